@@ -0,0 +1,80 @@
+//! Throughput of starting short commands, with and without the wrappers in this crate
+//!
+//! Run with `cargo bench --bench spawn`.
+
+use std::{
+    hint::black_box,
+    process::{Command, Stdio},
+    time::Instant,
+};
+
+use command_ext::{batch::run_many_status, bench::Bench, pool, CommandExtCheck, CommandWrap};
+
+const ITERATIONS: u32 = 500;
+
+fn main() {
+    let raw = Bench::new("std status").run(|| {
+        black_box(Command::new("true").status().ok());
+    });
+    println!("{raw}");
+
+    let output = Bench::new("std output").run(|| {
+        black_box(Command::new("true").output().ok());
+    });
+    println!("{output}");
+
+    let quiet = Bench::new("std status (no stdio)").run(|| {
+        black_box(
+            Command::new("true")
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .ok(),
+        );
+    });
+    println!("{quiet}");
+
+    let check = Bench::new("check").run(|| {
+        black_box(Command::new("true").check().ok());
+    });
+    println!("{check}");
+
+    #[cfg(feature = "log")]
+    {
+        use command_ext::CommandExtLog;
+
+        let log = Bench::new("log wrapper (disabled)").run(|| {
+            black_box(
+                Command::new("true")
+                    .log_args(log::Level::Trace)
+                    .status()
+                    .ok(),
+            );
+        });
+        println!("{log}");
+    }
+
+    let pooled = Bench::new("pooled wait").run(|| {
+        let child = Command::new("true").spawn();
+        black_box(
+            pool::spawn(move || child.and_then(|mut c| c.wait()).ok())
+                .join()
+                .ok(),
+        );
+    });
+    println!("{pooled}");
+
+    // Batches are measured as a whole, since their commands run concurrently
+    let start = Instant::now();
+    black_box(run_many_status(
+        (0..ITERATIONS).map(|_| Command::new("true")),
+    ));
+    let elapsed = start.elapsed();
+    println!(
+        "{:<28} {:>10.1}/s  mean {:>12.1?}",
+        "batch",
+        f64::from(ITERATIONS) / elapsed.as_secs_f64(),
+        elapsed / ITERATIONS
+    );
+}
@@ -0,0 +1,102 @@
+//! Latency and throughput of executing commands through the wrappers and layers in this
+//! crate, compared to the same commands executed with [`Command`] alone
+//!
+//! Each wrapped variant reports how much its median latency exceeds that of the plain
+//! command it wraps, which is the overhead of the wrap layer. Run with
+//! `cargo bench --bench wrap`.
+
+use std::{
+    hint::black_box,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use command_ext::{
+    bench::{Bench, BenchResult},
+    metrics, CommandExt, CommandExtCheck, CommandExtLog, CommandExtPrint, CommandExtRetry,
+    CommandExtTrace, CommandWrap,
+};
+
+/// Print `result` with its overhead over `baseline`
+fn report(result: &BenchResult, baseline: &BenchResult) {
+    println!("{result}  +{:.1?}", result.overhead(baseline));
+}
+
+/// A command which exits immediately without any output, so its execution is dominated by
+/// spawning it
+fn quick() -> Command {
+    let mut command = Command::new("true");
+    command.stdin(Stdio::null());
+    command
+}
+
+/// Spawn latency: the time until the child is running, not counting waiting for it
+fn spawn() {
+    let raw = Bench::new("spawn: raw").run_timed(|| {
+        let start = Instant::now();
+        let child = quick().spawn();
+        let elapsed = start.elapsed();
+        black_box(child.and_then(|mut c| c.wait()).ok());
+        elapsed
+    });
+    println!("{raw}");
+
+    let logged = Bench::new("spawn: log").run_timed(|| {
+        let mut command = quick();
+        let mut wrapper = command.log_args(log::Level::Trace);
+        let start = Instant::now();
+        let child = wrapper.spawn();
+        let elapsed = start.elapsed();
+        black_box(child.and_then(|mut c| c.wait()).ok());
+        elapsed
+    });
+    report(&logged, &raw);
+}
+
+/// Throughput: executing the command to completion and collecting its output
+fn output() {
+    let raw = Bench::new("output: raw").run(|| {
+        black_box(quick().output().ok());
+    });
+    println!("{raw}");
+
+    let results = [
+        Bench::new("output: check").run(|| {
+            black_box(quick().check().ok());
+        }),
+        Bench::new("output: log (disabled)").run(|| {
+            black_box(quick().log_args(log::Level::Trace).output().ok());
+        }),
+        Bench::new("output: trace (disabled)").run(|| {
+            black_box(quick().trace_args(tracing::Level::TRACE).output().ok());
+        }),
+        Bench::new("output: print").run(|| {
+            black_box(quick().print_args().print_to(std::io::sink()).output().ok());
+        }),
+        Bench::new("output: retry").run(|| {
+            black_box(quick().retry(1).check().ok());
+        }),
+        Bench::new("output: timeout").run(|| {
+            black_box(quick().with_timeout(Duration::from_secs(60)).check().ok());
+        }),
+        Bench::new("output: log + timeout").run(|| {
+            let mut command = quick();
+            let mut log = command.log_args(log::Level::Trace);
+            black_box(log.with_timeout(Duration::from_secs(60)).check().ok());
+        }),
+    ];
+    results.iter().for_each(|result| report(result, &raw));
+
+    metrics::enable();
+    let instrumented = Bench::new("output: log (metrics)").run(|| {
+        black_box(quick().log_args(log::Level::Trace).output().ok());
+    });
+    metrics::disable();
+    report(&instrumented, &raw);
+    println!("{:<28} {}", "  recorded overhead", metrics::total());
+}
+
+fn main() {
+    spawn();
+    output();
+}
@@ -3,7 +3,7 @@ use std::{ffi::OsStr, process::Command};
 use typed_builder::TypedBuilder;
 
 trait FriendlyCommandExt {
-    fn message(&mut self, message: impl Into<String>) -> FriendlyCommand;
+    fn message(&mut self, message: impl Into<String>) -> FriendlyCommand<'_>;
 }
 
 #[derive(TypedBuilder)]
@@ -38,7 +38,7 @@ impl<'a> CommandWrap for FriendlyCommand<'a> {
 }
 
 impl FriendlyCommandExt for Command {
-    fn message(&mut self, message: impl Into<String>) -> FriendlyCommand {
+    fn message(&mut self, message: impl Into<String>) -> FriendlyCommand<'_> {
         FriendlyCommand {
             command: self,
             message: message.into(),
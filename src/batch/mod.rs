@@ -0,0 +1,630 @@
+//! Running many short commands at once
+//!
+//! Rather than dedicating a thread to each child, the functions in this module wait for all
+//! running children from a single loop on the calling thread, starting a new child as soon
+//! as a slot frees up.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::batch::run_many_status;
+//! let statuses = run_many_status((0..16).map(|i| {
+//!     let mut command = Command::new("bash");
+//!     command.args(["-c", &format!("exit {}", i % 2)]);
+//!     command
+//! }));
+//!
+//! assert_eq!(statuses.len(), 16);
+//! assert!(statuses[0].as_ref().is_ok_and(|s| s.success()));
+//! assert!(statuses[1].as_ref().is_ok_and(|s| !s.success()));
+//! ```
+//!
+//! ## Deterministic output
+//!
+//! When commands run in parallel and inherit stdout, their output interleaves differently
+//! on every run. [`run_many_output_ordered`] instead captures each command's output and
+//! writes it in one piece, in the order the commands were given, as soon as it and every
+//! command before it have finished.
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::batch::run_many_output_ordered;
+//! let mut log = Vec::new();
+//! let outputs = run_many_output_ordered(
+//!     (0..4).map(|i| {
+//!         let mut command = Command::new("sh");
+//!         command.args(["-c", &format!("sleep 0.{}; echo {i}", 4 - i)]);
+//!         command
+//!     }),
+//!     4,
+//!     &mut log,
+//! );
+//!
+//! assert_eq!(outputs.len(), 4);
+//! assert_eq!(log, b"0\n1\n2\n3\n");
+//! ```
+//!
+//! ## Status line
+//!
+//! [`run_many_with_status`] reports progress on a [`StatusLine`] in the style of `ninja`:
+//! on a terminal, a single line like `[12/40] building foo... (3.1s, 1 failed)` is
+//! redrawn as commands start, and each command's output is printed above it as the command
+//! finishes. When the output is not a terminal, each status is written on its own line.
+//! At the end, the failed commands are listed again under `Failures:` with their command
+//! lines, exit statuses and the last lines of their stderr.
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::batch::{run_many_with_status, StatusLine};
+//! let mut status = StatusLine::stderr();
+//! let outputs = run_many_with_status(
+//!     ["foo", "bar"].map(|name| {
+//!         let mut command = Command::new("echo");
+//!         command.arg(name);
+//!         (format!("building {name}"), command)
+//!     }),
+//!     2,
+//!     &mut status,
+//! );
+//!
+//! assert_eq!(outputs.len(), 2);
+//! assert_eq!(status.failed(), 0);
+//! ```
+
+use std::{
+    env::var,
+    fmt::{Display, Formatter, Result as FmtResult},
+    io::{stderr, IsTerminal, Result as IoResult, Stderr, Write},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    sync::mpsc::channel,
+    thread::available_parallelism,
+    time::{Duration, Instant},
+};
+
+use crate::{capture, clock, pool, CommandExtLossy};
+
+/// The shortest pause between polls when no child has exited
+const MIN_BACKOFF: Duration = Duration::from_micros(50);
+/// The longest pause between polls when no child has exited
+const MAX_BACKOFF: Duration = Duration::from_millis(1);
+/// The number of lines from the end of a failed command's stderr kept for the failure
+/// summary by default
+pub const DEFAULT_TAIL_LINES: usize = 10;
+
+/// Execute every command, running as many at once as there are CPUs, and return their
+/// statuses in the order the commands were given
+pub fn run_many_status<I>(commands: I) -> Vec<IoResult<ExitStatus>>
+where
+    I: IntoIterator<Item = Command>,
+{
+    run_many_status_with_limit(
+        commands,
+        available_parallelism().map(|n| n.get()).unwrap_or(1),
+    )
+}
+
+/// Execute every command, running at most `limit` at once, and return their statuses in
+/// the order the commands were given. A `limit` of zero is treated as one.
+pub fn run_many_status_with_limit<I>(commands: I, limit: usize) -> Vec<IoResult<ExitStatus>>
+where
+    I: IntoIterator<Item = Command>,
+{
+    let limit = limit.max(1);
+    let mut commands = commands.into_iter().enumerate();
+    let mut results = Vec::new();
+    let mut running: Vec<(usize, Child)> = Vec::with_capacity(limit);
+    let clock = clock::current();
+    let mut backoff = MIN_BACKOFF;
+
+    loop {
+        while running.len() < limit {
+            let Some((index, mut command)) = commands.next() else {
+                break;
+            };
+            results.push(None);
+            match command.spawn() {
+                Ok(child) => running.push((index, child)),
+                Err(e) => results[index] = Some(Err(e)),
+            }
+        }
+
+        if running.is_empty() {
+            break;
+        }
+
+        let before = running.len();
+        running.retain_mut(|(index, child)| match child.try_wait() {
+            Ok(Some(status)) => {
+                results[*index] = Some(Ok(status));
+                false
+            }
+            Ok(None) => true,
+            Err(e) => {
+                results[*index] = Some(Err(e));
+                false
+            }
+        });
+
+        if running.len() < before {
+            backoff = MIN_BACKOFF;
+        } else {
+            clock.sleep(backoff);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    results
+        .into_iter()
+        .map(|r| r.expect("every command has a result"))
+        .collect()
+}
+
+/// Execute every command with its output captured, running at most `limit` at once, and
+/// return their outputs in the order the commands were given. Each command's stdout and
+/// then stderr are written to `out` in a single write once it and every command before it
+/// have finished, so the combined output is the same however the commands are scheduled.
+/// Errors writing to `out` are ignored. A `limit` of zero is treated as one.
+pub fn run_many_output_ordered<I, W>(commands: I, limit: usize, mut out: W) -> Vec<IoResult<Output>>
+where
+    I: IntoIterator<Item = Command>,
+    W: Write,
+{
+    let limit = limit.max(1);
+    let (sender, finished) = channel();
+    let mut commands = commands.into_iter().enumerate();
+    let mut results: Vec<Option<IoResult<Output>>> = Vec::new();
+    let mut flushed = 0;
+    let mut running = 0;
+
+    loop {
+        while running < limit {
+            let Some((index, mut command)) = commands.next() else {
+                break;
+            };
+            results.push(None);
+            match command
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+            {
+                Ok(child) => {
+                    let sender = sender.clone();
+                    pool::spawn(move || {
+                        sender.send((index, capture::output(child))).ok();
+                    });
+                    running += 1;
+                }
+                Err(e) => results[index] = Some(Err(e)),
+            }
+        }
+
+        // Flush every finished command which is not waiting on an earlier one
+        while let Some(Some(result)) = results.get(flushed) {
+            if let Ok(output) = result {
+                out.write_all(&[output.stdout.as_slice(), &output.stderr].concat())
+                    .and_then(|_| out.flush())
+                    .ok();
+            }
+            flushed += 1;
+        }
+
+        if running == 0 {
+            break;
+        }
+
+        let Ok((index, result)) = finished.recv() else {
+            break;
+        };
+        results[index] = Some(result);
+        running -= 1;
+    }
+
+    results
+        .into_iter()
+        .map(|r| r.expect("every command has a result"))
+        .collect()
+}
+
+/// A console status line for a batch of commands, like the one `ninja` prints. On a
+/// terminal, a single line is redrawn as commands start, showing how many have started, the
+/// most recent command's label, the elapsed time and the number of failures. Otherwise,
+/// a plain line is written as each command starts.
+#[derive(Debug)]
+pub struct StatusLine<W> {
+    out: W,
+    tty: bool,
+    width: usize,
+    total: usize,
+    started: usize,
+    start: Instant,
+    label: String,
+    tail_lines: usize,
+    failures: Vec<Failure>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A command which failed in a batch, as listed in the summary at the end of the batch
+pub struct Failure {
+    /// The command's label
+    pub label: String,
+    /// The command line, converted lossily to UTF-8
+    pub command: String,
+    /// The exit status, or the error which prevented the command from running
+    pub status: String,
+    /// The last lines of the command's stderr
+    pub stderr_tail: String,
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        writeln!(f, "{}: {}", self.label, self.status)?;
+        writeln!(f, "  $ {}", self.command)?;
+        self.stderr_tail
+            .lines()
+            .try_for_each(|line| writeln!(f, "  | {line}"))
+    }
+}
+
+/// The last `lines` lines of `stderr`
+fn tail(stderr: &[u8], lines: usize) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    let all = stderr.lines().collect::<Vec<_>>();
+    all[all.len().saturating_sub(lines)..].join("\n")
+}
+
+impl StatusLine<Stderr> {
+    /// A status line on stderr, which is redrawn in place if stderr is a terminal
+    pub fn stderr() -> Self {
+        let tty = stderr().is_terminal();
+        Self::new(stderr(), tty)
+    }
+}
+
+impl<W> StatusLine<W>
+where
+    W: Write,
+{
+    /// A status line written to `out`, which is redrawn in place if `tty` is set. The line
+    /// is kept within the width given by the `COLUMNS` environment variable, or 80 columns.
+    pub fn new(out: W, tty: bool) -> Self {
+        Self {
+            out,
+            tty,
+            width: var("COLUMNS")
+                .ok()
+                .and_then(|c| c.parse().ok())
+                .unwrap_or(80),
+            total: 0,
+            started: 0,
+            start: clock::current().now(),
+            label: String::new(),
+            tail_lines: DEFAULT_TAIL_LINES,
+            failures: Vec::new(),
+        }
+    }
+
+    /// Keep the last `lines` lines of each failed command's stderr for the failure summary,
+    /// instead of [`DEFAULT_TAIL_LINES`]
+    pub fn tail_lines(mut self, lines: usize) -> Self {
+        self.tail_lines = lines;
+        self
+    }
+
+    /// The commands which failed so far
+    pub fn failures(&self) -> &[Failure] {
+        &self.failures
+    }
+
+    /// The number of commands which failed so far
+    pub fn failed(&self) -> usize {
+        self.failures.len()
+    }
+
+    /// How long ago the first command started
+    fn elapsed(&self) -> Duration {
+        clock::current().now().duration_since(self.start)
+    }
+
+    /// The text of the status line
+    fn line(&self) -> String {
+        let mut line = format!(
+            "[{}/{}] {}... ({:.1}s",
+            self.started,
+            self.total,
+            self.label,
+            self.elapsed().as_secs_f64()
+        );
+        if !self.failures.is_empty() {
+            line.push_str(&format!(", {} failed", self.failures.len()));
+        }
+        line.push(')');
+
+        if self.tty && line.chars().count() > self.width {
+            line = line.chars().take(self.width.saturating_sub(3)).collect();
+            line.push_str("...");
+        }
+
+        line
+    }
+
+    /// Replace the status line on a terminal with `text`, or write `text` on its own line
+    fn write(&mut self, text: &str) {
+        let written = if self.tty {
+            write!(self.out, "\r\x1b[K{text}")
+        } else {
+            writeln!(self.out, "{text}")
+        };
+        written.and_then(|_| self.out.flush()).ok();
+    }
+
+    fn started(&mut self, label: &str) {
+        self.started += 1;
+        self.label = label.to_string();
+        let line = self.line();
+        self.write(&line);
+    }
+
+    /// Print the output of a finished command above the status line, and remember it for
+    /// the failure summary if it failed
+    fn finished(&mut self, label: &str, command: &str, result: &IoResult<Output>) {
+        let failure = match result {
+            Ok(output) if output.status.success() => None,
+            Ok(output) => Some((
+                output.status.to_string(),
+                tail(&output.stderr, self.tail_lines),
+            )),
+            Err(e) => Some((e.to_string(), String::new())),
+        };
+
+        let mut text = Vec::new();
+        if let Some((status, stderr_tail)) = failure {
+            writeln!(text, "FAILED: {label} ({status})").ok();
+            self.failures.push(Failure {
+                label: label.to_string(),
+                command: command.to_string(),
+                status,
+                stderr_tail,
+            });
+        }
+        if let Ok(output) = result {
+            text.extend_from_slice(&output.stdout);
+            text.extend_from_slice(&output.stderr);
+        }
+
+        if !text.is_empty() {
+            if self.tty {
+                write!(self.out, "\r\x1b[K").ok();
+            }
+            self.out.write_all(&text).ok();
+            if self.tty {
+                let line = self.line();
+                write!(self.out, "{line}").ok();
+            }
+            self.out.flush().ok();
+        }
+    }
+
+    /// Leave the final status on its own line, followed by a summary of the failures
+    fn done(&mut self) {
+        let mut line = format!(
+            "[{}/{}] done ({:.1}s",
+            self.started,
+            self.total,
+            self.elapsed().as_secs_f64()
+        );
+        if !self.failures.is_empty() {
+            line.push_str(&format!(", {} failed", self.failures.len()));
+        }
+        line.push(')');
+        self.write(&line);
+        if self.tty {
+            writeln!(self.out).ok();
+        }
+
+        if !self.failures.is_empty() {
+            writeln!(self.out, "Failures:").ok();
+            for failure in &self.failures {
+                write!(self.out, "{failure}").ok();
+            }
+        }
+        self.out.flush().ok();
+    }
+}
+
+/// Execute every labeled command with its output captured, running at most `limit` at
+/// once, and report progress on `status`. Each command's output is written above the
+/// status line when it finishes, along with a `FAILED` line naming it if it failed. Once
+/// every command has finished, a `Failures:` section lists each failed command's label,
+/// command line, exit status and the end of its stderr. Returns the outputs in the order
+/// the commands were given. A `limit` of zero is treated as one.
+pub fn run_many_with_status<I, L, W>(
+    commands: I,
+    limit: usize,
+    status: &mut StatusLine<W>,
+) -> Vec<IoResult<Output>>
+where
+    I: IntoIterator<Item = (L, Command)>,
+    L: Into<String>,
+    W: Write,
+{
+    let limit = limit.max(1);
+    let commands = commands
+        .into_iter()
+        .map(|(label, command)| (label.into(), command))
+        .collect::<Vec<(String, Command)>>();
+    let labels = commands
+        .iter()
+        .map(|(l, c)| (l.clone(), c.get_full_command_lossy().to_string()))
+        .collect::<Vec<_>>();
+    let (sender, finished) = channel();
+    let mut commands = commands.into_iter().enumerate();
+    let mut results: Vec<Option<IoResult<Output>>> = Vec::new();
+    let mut running = 0;
+
+    status.total = labels.len();
+    status.start = clock::current().now();
+
+    loop {
+        while running < limit {
+            let Some((index, (label, mut command))) = commands.next() else {
+                break;
+            };
+            results.push(None);
+            status.started(&label);
+            match command
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+            {
+                Ok(child) => {
+                    let sender = sender.clone();
+                    pool::spawn(move || {
+                        sender.send((index, capture::output(child))).ok();
+                    });
+                    running += 1;
+                }
+                Err(e) => {
+                    let result = Err(e);
+                    status.finished(&label, &labels[index].1, &result);
+                    results[index] = Some(result);
+                }
+            }
+        }
+
+        if running == 0 {
+            break;
+        }
+
+        let Ok((index, result)) = finished.recv() else {
+            break;
+        };
+        let (label, command) = &labels[index];
+        status.finished(label, command, &result);
+        results[index] = Some(result);
+        running -= 1;
+    }
+
+    status.done();
+
+    results
+        .into_iter()
+        .map(|r| r.expect("every command has a result"))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        process::Command,
+        time::{Duration, Instant},
+    };
+
+    use super::{
+        run_many_output_ordered, run_many_status_with_limit, run_many_with_status, StatusLine,
+    };
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_order_and_errors() {
+        let commands = vec![
+            Command::new("true"),
+            Command::new("asdfasdfasdfasdfjkljkljkl"),
+            Command::new("false"),
+        ];
+        let statuses = run_many_status_with_limit(commands, 2);
+
+        assert!(statuses[0].as_ref().is_ok_and(|s| s.success()));
+        assert!(statuses[1].is_err());
+        assert!(statuses[2].as_ref().is_ok_and(|s| !s.success()));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_limit() {
+        let start = Instant::now();
+        let statuses = run_many_status_with_limit(
+            (0..4).map(|_| {
+                let mut command = Command::new("sleep");
+                command.arg("0.2");
+                command
+            }),
+            4,
+        );
+
+        assert!(statuses
+            .iter()
+            .all(|s| s.as_ref().is_ok_and(|s| s.success())));
+        assert!(start.elapsed() < Duration::from_millis(700));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_output_ordered() {
+        let mut out = Vec::new();
+        let outputs = run_many_output_ordered(
+            (0..6)
+                .map(|i| {
+                    let mut command = Command::new("sh");
+                    command.args(["-c", &format!("sleep 0.{}; echo {i}; echo e{i} >&2", 6 - i)]);
+                    command
+                })
+                .chain([Command::new("asdfasdfasdfasdfjkljkljkl")]),
+            3,
+            &mut out,
+        );
+
+        assert_eq!(
+            String::from_utf8_lossy(&out),
+            "0\ne0\n1\ne1\n2\ne2\n3\ne3\n4\ne4\n5\ne5\n"
+        );
+        assert_eq!(outputs.len(), 7);
+        assert!(outputs[6].is_err());
+        assert!(outputs[..6]
+            .iter()
+            .all(|o| o.as_ref().is_ok_and(|o| o.status.success())));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_status_line() {
+        let commands = || {
+            [("ok", "echo x"), ("bad", "echo y >&2; exit 3")].map(|(label, script)| {
+                let mut command = Command::new("sh");
+                command.args(["-c", script]);
+                (label, command)
+            })
+        };
+
+        let mut plain = StatusLine::new(Vec::new(), false);
+        let outputs = run_many_with_status(commands(), 1, &mut plain);
+        assert!(outputs[0].as_ref().is_ok_and(|o| o.status.success()));
+        assert_eq!(plain.failed(), 1);
+        let text = String::from_utf8_lossy(&plain.out).into_owned();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert!(lines[0].starts_with("[1/2] ok... ("));
+        assert_eq!(lines[1], "x");
+        assert!(lines[2].starts_with("[2/2] bad... ("));
+        assert_eq!(lines[3], "FAILED: bad (exit status: 3)");
+        assert_eq!(lines[4], "y");
+        assert!(lines[5].starts_with("[2/2] done (") && lines[5].ends_with(", 1 failed)"));
+        assert_eq!(
+            lines[6..],
+            [
+                "Failures:",
+                "bad: exit status: 3",
+                "  $ sh -c echo y >&2; exit 3",
+                "  | y",
+            ]
+        );
+
+        let mut tty = StatusLine::new(Vec::new(), true);
+        run_many_with_status(commands(), 1, &mut tty);
+        let text = String::from_utf8_lossy(&tty.out).into_owned();
+        assert!(text.starts_with("\r\x1b[K[1/2] ok... ("));
+        assert_eq!(text.matches('\n').count(), 8);
+        assert!(
+            text.ends_with("Failures:\nbad: exit status: 3\n  $ sh -c echo y >&2; exit 3\n  | y\n")
+        );
+    }
+}
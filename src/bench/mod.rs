@@ -0,0 +1,201 @@
+//! A small harness for measuring the latency and throughput of executing commands
+//!
+//! The benchmarks in `benches/` use it to compare plain [`std::process::Command`]s with the
+//! wrappers and layers of this crate, so that the overhead of the wrap layer is tracked and
+//! regressions show up as a growing gap from the baseline. It is public so that users can
+//! measure their own wrapper stacks the same way. Every iteration is timed on its own, so
+//! results report the spread of latencies as well as the throughput.
+//!
+//! Timing uses the system's monotonic clock even when another [`Clock`](crate::Clock) is
+//! installed, since a mock clock would not measure anything.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{bench::Bench, CommandExtCheck};
+//! let raw = Bench::new("raw").iterations(20).run(|| {
+//!     Command::new("true").output().ok();
+//! });
+//! let checked = Bench::new("check").iterations(20).run(|| {
+//!     Command::new("true").check().ok();
+//! });
+//!
+//! println!("{raw}");
+//! println!("{checked} ({:?} over raw)", checked.overhead(&raw));
+//! assert_eq!(checked.iterations, 20);
+//! ```
+
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+/// The number of iterations run when none is given
+const DEFAULT_ITERATIONS: u32 = 500;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A benchmark of one way of executing commands
+pub struct Bench {
+    name: String,
+    iterations: u32,
+    warmup: Option<u32>,
+}
+
+impl Bench {
+    /// A benchmark called `name`, which runs 500 iterations after a warmup of a tenth of
+    /// that
+    pub fn new<S>(name: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Self {
+            name: name.into(),
+            iterations: DEFAULT_ITERATIONS,
+            warmup: None,
+        }
+    }
+
+    /// Set the number of measured iterations. At least one is always run.
+    pub fn iterations(&mut self, iterations: u32) -> &mut Self {
+        self.iterations = iterations.max(1);
+        self
+    }
+
+    /// Set the number of unmeasured iterations run first to warm up the page cache and the
+    /// shared [pool](crate::pool). Defaults to a tenth of the measured iterations.
+    pub fn warmup(&mut self, warmup: u32) -> &mut Self {
+        self.warmup = Some(warmup);
+        self
+    }
+
+    /// Get the name of the benchmark
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Run `f` for every iteration, measuring how long each takes. What `f` returns is
+    /// passed through [`black_box`], so the compiler cannot skip the work producing it.
+    pub fn run<T, F>(&self, mut f: F) -> BenchResult
+    where
+        F: FnMut() -> T,
+    {
+        self.run_timed(|| {
+            let start = Instant::now();
+            black_box(f());
+            start.elapsed()
+        })
+    }
+
+    /// Run `f` for every iteration, using the duration it returns as the iteration's
+    /// latency. This measures part of each iteration, such as spawning a child without
+    /// waiting for it to exit.
+    pub fn run_timed<F>(&self, mut f: F) -> BenchResult
+    where
+        F: FnMut() -> Duration,
+    {
+        let warmup = self.warmup.unwrap_or(self.iterations / 10);
+        (0..warmup).for_each(|_| {
+            black_box(f());
+        });
+
+        let mut latencies = (0..self.iterations).map(|_| f()).collect::<Vec<_>>();
+        latencies.sort_unstable();
+        BenchResult::new(self.name.clone(), &latencies)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The latencies measured by a [`Bench`]
+pub struct BenchResult {
+    /// The name of the benchmark
+    pub name: String,
+    /// The number of measured iterations
+    pub iterations: u32,
+    /// The sum of the latencies of every iteration
+    pub total: Duration,
+    /// The lowest latency
+    pub min: Duration,
+    /// The median latency
+    pub median: Duration,
+    /// The 95th percentile latency
+    pub p95: Duration,
+    /// The highest latency
+    pub max: Duration,
+}
+
+impl BenchResult {
+    /// Summarize sorted, non-empty `latencies`
+    fn new(name: String, latencies: &[Duration]) -> Self {
+        let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+
+        Self {
+            name,
+            iterations: latencies.len() as u32,
+            total: latencies.iter().sum(),
+            min: percentile(0),
+            median: percentile(50),
+            p95: percentile(95),
+            max: percentile(100),
+        }
+    }
+
+    /// The mean latency
+    pub fn mean(&self) -> Duration {
+        self.total / self.iterations
+    }
+
+    /// The number of iterations per second
+    pub fn throughput(&self) -> f64 {
+        f64::from(self.iterations) / self.total.as_secs_f64()
+    }
+
+    /// How much higher the median latency is than that of `baseline`, or zero if it is not
+    /// higher
+    pub fn overhead(&self, baseline: &BenchResult) -> Duration {
+        self.median.saturating_sub(baseline.median)
+    }
+}
+
+impl Display for BenchResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "{:<28} {:>10.1}/s  median {:>10.1?}  p95 {:>10.1?}  min {:>10.1?}",
+            self.name,
+            self.throughput(),
+            self.median,
+            self.p95,
+            self.min
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{Bench, BenchResult};
+
+    #[test]
+    /// Test that latencies are summarized by percentile and compared by median
+    fn test_result() {
+        let latencies = (1..=100).map(Duration::from_millis).collect::<Vec<_>>();
+        let result = BenchResult::new("x".to_string(), &latencies);
+        assert_eq!(result.min, Duration::from_millis(1));
+        assert_eq!(result.median, Duration::from_millis(50));
+        assert_eq!(result.p95, Duration::from_millis(95));
+        assert_eq!(result.max, Duration::from_millis(100));
+        assert_eq!(result.mean(), Duration::from_micros(50500));
+
+        let mut calls = 0;
+        let timed = Bench::new("timed").iterations(10).warmup(2).run_timed(|| {
+            calls += 1;
+            Duration::from_millis(60)
+        });
+        assert_eq!(calls, 12);
+        assert_eq!(timed.overhead(&result), Duration::from_millis(10));
+        assert_eq!(result.overhead(&timed), Duration::ZERO);
+    }
+}
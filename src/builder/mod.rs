@@ -0,0 +1,69 @@
+//! Hand-written builders for the reporting wrappers, used in place of `typed-builder` when
+//! its feature is disabled. The generated builders have the same methods as the derived
+//! ones: `command` must be called before `build`, which is checked at compile time by the
+//! builder's type parameter, and every other field falls back to its default.
+
+/// Define `$builder` and `$wrapper::builder()` for a wrapper with a `command` field, a
+/// `last_execution` field, a field named `$defaults` holding the ambient
+/// [defaults](crate::defaults), and the listed fields. The defaults are read once when the
+/// wrapper is built, and each `$default` may refer to them as `$defaults`. Each field's
+/// setter converts its argument into `$arg` and stores `$set`, computed from the argument
+/// bound to `$value`.
+macro_rules! builder {
+    (
+        $wrapper:ident, $builder:ident, $defaults:ident {
+            $( $field:ident : $ty:ty = $default:expr, |$value:ident: $arg:ty| $set:expr ),* $(,)?
+        }
+    ) => {
+        #[derive(Debug)]
+        #[doc = concat!("A builder for [`", stringify!($wrapper), "`]")]
+        pub struct $builder<C> {
+            command: C,
+            $( $field: Option<$ty>, )*
+        }
+
+        impl<'a> $wrapper<'a> {
+            #[doc = concat!("Create a builder for [`", stringify!($wrapper), "`]")]
+            pub fn builder() -> $builder<()> {
+                $builder {
+                    command: (),
+                    $( $field: None, )*
+                }
+            }
+        }
+
+        impl<C> $builder<C> {
+            /// Set the command to wrap
+            pub fn command(self, command: &mut Command) -> $builder<&mut Command> {
+                $builder {
+                    command,
+                    $( $field: self.$field, )*
+                }
+            }
+
+            $(
+                #[doc = concat!("Set `", stringify!($field), "`")]
+                pub fn $field<T: Into<$arg>>(mut self, $value: T) -> Self {
+                    let $value: $arg = $value.into();
+                    self.$field = Some($set);
+                    self
+                }
+            )*
+        }
+
+        impl<'a> $builder<&'a mut Command> {
+            #[doc = concat!("Build the [`", stringify!($wrapper), "`]")]
+            pub fn build(self) -> $wrapper<'a> {
+                let $defaults = crate::defaults::get();
+                $wrapper {
+                    command: self.command,
+                    $( $field: self.$field.unwrap_or_else(|| $default), )*
+                    $defaults,
+                    last_execution: None,
+                }
+            }
+        }
+    };
+}
+
+pub(crate) use builder;
@@ -0,0 +1,257 @@
+//! Deadlock-free capture of the output of spawned children
+//!
+//! A child whose stdout and stderr are both piped can block writing to one pipe while the
+//! parent blocks reading the other: once the pipe buffer (often 64KiB) is full, neither side
+//! makes progress. Reading the streams one after the other, for example with
+//! `read_to_end` on stdout followed by stderr, deadlocks as soon as the child writes enough
+//! to stderr before closing stdout.
+//!
+//! The functions in this module drain both pipes concurrently on the shared
+//! [`crate::pool`], so they never deadlock regardless of how much the child writes to each
+//! stream or in which order. They are the primitive the streaming and capturing features of
+//! this crate are built on.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::{Command, Stdio};
+//! # use command_ext::capture::read_both;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut child = Command::new("bash")
+//!     .args(["-c", "head -c 1000000 /dev/zero >&2; echo done"])
+//!     .stdout(Stdio::piped())
+//!     .stderr(Stdio::piped())
+//!     .spawn()?;
+//!
+//! let (stdout, stderr) = read_both(&mut child)?;
+//! child.wait()?;
+//! assert_eq!(stdout, b"done\n");
+//! assert_eq!(stderr.len(), 1000000);
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(any(feature = "async", feature = "priority", feature = "timeout"))]
+use std::process::{Command, Stdio};
+#[cfg(any(feature = "watch", feature = "timeout"))]
+use std::sync::mpsc::RecvTimeoutError;
+use std::{
+    io::{ErrorKind, Read, Result as IoResult},
+    process::{Child, Output},
+    sync::mpsc::{channel, Receiver, Sender},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    metrics::{self, Category},
+    pool,
+};
+
+/// The size of the chunks read from each pipe
+const CHUNK_SIZE: usize = 8192;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// One of the output streams of a child
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// A chunk of output read from one stream, or the error which ended reading it
+pub(crate) type Chunk = (Stream, IoResult<Vec<u8>>);
+
+/// The piped output streams of a child, drained concurrently in the background
+pub(crate) struct Pipes {
+    chunks: Receiver<Chunk>,
+}
+
+fn drain<R>(stream: Stream, mut reader: R, chunks: Sender<Chunk>)
+where
+    R: Read + Send + 'static,
+{
+    let program = metrics::current();
+    pool::spawn(move || {
+        let mut buf = vec![0; CHUNK_SIZE];
+        let mut copying = Duration::ZERO;
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let start = program.as_ref().map(|_| Instant::now());
+                    let sent = chunks.send((stream, Ok(buf[..n].to_vec())));
+                    copying += start.map(|s| s.elapsed()).unwrap_or_default();
+                    if sent.is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    chunks.send((stream, Err(e))).ok();
+                    break;
+                }
+            }
+        }
+
+        if let Some(program) = program {
+            metrics::record(&program, Category::Capture, copying);
+        }
+    });
+}
+
+impl Pipes {
+    /// Take the piped streams of `child`, if any, and start draining them
+    pub(crate) fn take(child: &mut Child) -> Self {
+        let (sender, chunks) = channel();
+
+        if let Some(stdout) = child.stdout.take() {
+            drain(Stream::Stdout, stdout, sender.clone());
+        }
+
+        if let Some(stderr) = child.stderr.take() {
+            drain(Stream::Stderr, stderr, sender);
+        }
+
+        Self { chunks }
+    }
+
+    /// The next chunk of output, or `None` once every stream is closed
+    pub(crate) fn recv(&self) -> Option<Chunk> {
+        self.chunks.recv().ok()
+    }
+
+    /// The next chunk of output, waiting at most `timeout` for one to arrive. Returns
+    /// `Ok(None)` once every stream is closed.
+    #[cfg(any(feature = "watch", feature = "timeout"))]
+    pub(crate) fn recv_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<Option<Chunk>, RecvTimeoutError> {
+        match self.chunks.recv_timeout(timeout) {
+            Ok(chunk) => Ok(Some(chunk)),
+            Err(RecvTimeoutError::Disconnected) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Drain the piped stdout and stderr of `child` concurrently, calling `f` with each chunk
+/// of output as it arrives. Streams which are not piped are ignored.
+pub fn drain_both<F>(child: &mut Child, mut f: F) -> IoResult<()>
+where
+    F: FnMut(Stream, &[u8]),
+{
+    let pipes = Pipes::take(child);
+
+    while let Some((stream, chunk)) = pipes.recv() {
+        f(stream, &chunk?);
+    }
+
+    Ok(())
+}
+
+/// Read the piped stdout and stderr of `child` to the end concurrently. Streams which are
+/// not piped are returned empty.
+pub fn read_both(child: &mut Child) -> IoResult<(Vec<u8>, Vec<u8>)> {
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+
+    drain_both(child, |stream, chunk| match stream {
+        Stream::Stdout => stdout.extend_from_slice(chunk),
+        Stream::Stderr => stderr.extend_from_slice(chunk),
+    })?;
+
+    Ok((stdout, stderr))
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Which standard streams of a command were configured through a wrapper. The standard
+/// library does not expose how the streams of a [`Command`] are configured, so wrappers
+/// which spawn a command in place of [`Command::output`] track the streams configured
+/// through them, and cannot see streams configured on the command before it was wrapped.
+#[cfg(any(feature = "async", feature = "priority", feature = "timeout"))]
+pub(crate) struct Configured {
+    pub(crate) stdin: bool,
+    pub(crate) stdout: bool,
+    pub(crate) stderr: bool,
+}
+
+/// Spawn `command` with the stdio [`Command::output`] uses for streams which are not
+/// `configured`: a null stdin and piped stdout and stderr
+#[cfg(any(feature = "async", feature = "priority"))]
+pub(crate) fn spawn_for_output(command: &mut Command, configured: Configured) -> IoResult<Child> {
+    configure_for_output(command, configured);
+    command.spawn()
+}
+
+/// Configure the stdio [`Command::output`] uses for streams of `command` which are not
+/// `configured`
+#[cfg(any(feature = "async", feature = "priority", feature = "timeout"))]
+pub(crate) fn configure_for_output(command: &mut Command, configured: Configured) {
+    if !configured.stdin {
+        command.stdin(Stdio::null());
+    }
+    if !configured.stdout {
+        command.stdout(Stdio::piped());
+    }
+    if !configured.stderr {
+        command.stderr(Stdio::piped());
+    }
+}
+
+/// Read the piped output of `child` and wait for it to exit, like
+/// [`Child::wait_with_output`]
+pub fn output(mut child: Child) -> IoResult<Output> {
+    drop(child.stdin.take());
+    let (stdout, stderr) = read_both(&mut child)?;
+    let status = child.wait()?;
+
+    Ok(Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::{Command, Stdio};
+
+    use super::{drain_both, output, Stream};
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that both streams are read at once, so neither fills its pipe
+    fn test_large_streams() -> anyhow::Result<()> {
+        let child = Command::new("bash")
+            .args([
+                "-c",
+                "head -c 500000 /dev/zero >&2; head -c 500000 /dev/zero; echo x >&2",
+            ])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let output = output(child)?;
+        assert!(output.status.success());
+        assert_eq!(output.stdout.len(), 500000);
+        assert_eq!(output.stderr.len(), 500002);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that only the streams which are piped are drained
+    fn test_drain_both() -> anyhow::Result<()> {
+        let mut child = Command::new("bash")
+            .args(["-c", "echo out; echo err >&2"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let mut streams = Vec::new();
+        drain_both(&mut child, |stream, _| streams.push(stream))?;
+        child.wait()?;
+        assert_eq!(streams, [Stream::Stdout]);
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+//! Extension trait to chain commands which are built from the output of the previous one
+//!
+//! A pipe feeds the output stream of one command into the next while both run. A chain
+//! instead waits for each command to finish and passes its [`Output`] to a closure which
+//! builds the next command, so a value printed by one command can become an argument of
+//! the next without an intermediate variable. Each command is checked with
+//! [`CommandExtCheck::check`], and so reported with the ambient defaults, and the chain
+//! stops at the first one which fails.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{CommandExtChain, CommandExtCheck, CommandExtError};
+//! # fn main() -> Result<(), CommandExtError> {
+//! let output = Command::new("echo")
+//!     .arg("/tmp")
+//!     .then_with(|dir| {
+//!         let mut ls = Command::new("ls");
+//!         ls.arg("-d").arg(String::from_utf8_lossy(&dir.stdout).trim());
+//!         ls
+//!     })
+//!     .check()?;
+//! assert_eq!(output.stdout, b"/tmp\n");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    process::{Command, Output},
+};
+
+use crate::{CommandExtCheck, CommandExtError};
+
+/// Builds the next command of a chain from the output of the previous one
+type Step<'a> = Box<dyn FnMut(&Output) -> Command + 'a>;
+
+pub struct CommandChain<'a> {
+    command: &'a mut Command,
+    steps: Vec<Step<'a>>,
+    outputs: Vec<Output>,
+}
+
+impl<'a> CommandChain<'a> {
+    /// Run the command built by `step` from the output of the last command of the chain
+    /// once it succeeds
+    pub fn then_with<F>(&mut self, step: F) -> &mut Self
+    where
+        F: FnMut(&Output) -> Command + 'a,
+    {
+        self.steps.push(Box::new(step));
+        self
+    }
+
+    /// The number of commands in the chain
+    pub fn len(&self) -> usize {
+        self.steps.len() + 1
+    }
+
+    /// Whether the chain has no commands. Always false, as a chain starts with a command.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// The outputs which were passed to a step in the last check of the chain, from first
+    /// to last. The output of the last command is returned by the check instead.
+    pub fn outputs(&self) -> &[Output] {
+        &self.outputs
+    }
+}
+
+impl Debug for CommandChain<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("CommandChain")
+            .field("command", &self.command)
+            .field("steps", &self.steps.len())
+            .field("outputs", &self.outputs)
+            .finish()
+    }
+}
+
+impl<'a> From<&'a mut Command> for CommandChain<'a> {
+    fn from(value: &'a mut Command) -> Self {
+        Self {
+            command: value,
+            steps: Vec::new(),
+            outputs: Vec::new(),
+        }
+    }
+}
+
+impl CommandExtCheck for CommandChain<'_> {
+    type Error = CommandExtError;
+
+    /// Check each command of the chain in turn, returning the output of the last one or the
+    /// error of the first one which fails
+    fn check(&mut self) -> Result<Output, Self::Error> {
+        self.outputs.clear();
+        let mut output = self.command.check()?;
+
+        for step in &mut self.steps {
+            let mut next = step(&output);
+            self.outputs.push(output);
+            output = next.check()?;
+        }
+
+        Ok(output)
+    }
+}
+
+pub trait CommandExtChain {
+    /// Run the command built by `step` from the output of this command once it succeeds
+    fn then_with<'a, F>(&'a mut self, step: F) -> CommandChain<'a>
+    where
+        F: FnMut(&Output) -> Command + 'a;
+}
+
+impl CommandExtChain for Command {
+    fn then_with<'a, F>(&'a mut self, step: F) -> CommandChain<'a>
+    where
+        F: FnMut(&Output) -> Command + 'a,
+    {
+        let mut chain = CommandChain::from(self);
+        chain.then_with(step);
+        chain
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use crate::{CommandExtChain, CommandExtCheck, CommandExtError};
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that each command is built from the previous output, and that the chain stops
+    /// at the first failure
+    fn test_chain() -> Result<(), CommandExtError> {
+        let mut command = Command::new("echo");
+        command.arg("3");
+        let mut chain = command.then_with(|output| {
+            let mut seq = Command::new("seq");
+            seq.arg(String::from_utf8_lossy(&output.stdout).trim());
+            seq
+        });
+        chain.then_with(|output| {
+            let mut wc = Command::new("echo");
+            wc.arg(output.stdout.len().to_string());
+            wc
+        });
+        assert_eq!(chain.len(), 3);
+        assert_eq!(chain.check()?.stdout, b"6\n");
+        assert_eq!(chain.outputs().len(), 2);
+        assert_eq!(chain.outputs()[1].stdout, b"1\n2\n3\n");
+
+        let mut command = Command::new("echo");
+        let mut chain = command.then_with(|_| Command::new("false"));
+        chain.then_with(|_| panic!("Unexpected step after a failure"));
+        assert!(matches!(chain.check(), Err(CommandExtError::Check { .. })));
+        assert_eq!(chain.outputs().len(), 1);
+        Ok(())
+    }
+}
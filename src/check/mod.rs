@@ -1,7 +1,17 @@
 //! Extension trait to check the output of a command
 
-use crate::error::CommandExtError;
-use std::process::{Command, Output};
+use crate::{
+    capture,
+    error::CommandExtError,
+    metrics,
+    wrap::{Execute, ExecutionInfo},
+    CommandWrap,
+};
+use std::{
+    io::Result as IoResult,
+    path::Path,
+    process::{Child, Command, Output},
+};
 
 /// Extension trait for [`std::process::Command`] to check the output of a command
 pub trait CommandExtCheck {
@@ -11,6 +21,198 @@ pub trait CommandExtCheck {
     /// Check the result of a command, returning an error containing the output and
     /// error stream content if the status is not success
     fn check(&mut self) -> Result<Output, Self::Error>;
+
+    /// Check the result of a command like [`CommandExtCheck::check`], converting the error
+    /// into the caller's own error type, so libraries do not need to expose
+    /// [`CommandExtError`] or write conversion boilerplate at every call
+    ///
+    /// ```rust
+    /// # use std::process::Command;
+    /// # use command_ext::{CommandExtCheck, CommandExtError};
+    /// #[derive(Debug)]
+    /// enum BuildError {
+    ///     Tool(String),
+    /// }
+    ///
+    /// impl From<CommandExtError> for BuildError {
+    ///     fn from(e: CommandExtError) -> Self {
+    ///         Self::Tool(e.to_string())
+    ///     }
+    /// }
+    ///
+    /// fn build() -> Result<(), BuildError> {
+    ///     Command::new("true").check_into::<BuildError>()?;
+    ///     Ok(())
+    /// }
+    /// # build().unwrap();
+    /// ```
+    fn check_into<E>(&mut self) -> Result<Output, E>
+    where
+        E: From<Self::Error>,
+    {
+        self.check().map_err(E::from)
+    }
+}
+
+/// Convert the result of executing a command into the result of checking it. The output is
+/// moved into the success or error value without copying the output streams.
+pub(crate) fn check_output(output: std::io::Result<Output>) -> Result<Output, CommandExtError> {
+    let output = output?;
+
+    if output.status.success() {
+        return Ok(output);
+    }
+
+    let lossy = |bytes: Vec<u8>| {
+        String::from_utf8(bytes)
+            .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+    };
+
+    Err(CommandExtError::Check {
+        status: output.status,
+        stdout: lossy(output.stdout),
+        stderr: lossy(output.stderr),
+    })
+}
+
+/// Check the result of executing a command like [`check_output`], first giving the
+/// [`CommandWrap::on_check`](crate::CommandWrap::on_check) hooks of the wrappers it was
+/// executed through the chance to fail it
+pub(crate) fn check_with<E>(
+    executor: &mut E,
+    output: IoResult<Output>,
+) -> Result<Output, CommandExtError>
+where
+    E: Execute,
+{
+    let output = output?;
+
+    match executor.finish_check(&output) {
+        Some(error) => Err(error),
+        None => check_output(Ok(output)),
+    }
+}
+
+/// Check a wrapper the way the wrappers in this crate check themselves: execute it for its
+/// output, give its [`CommandWrap::on_check`] hook the chance to fail it, and otherwise
+/// check its status. Custom wrappers can implement [`CommandExtCheck`] with this.
+///
+/// ```rust
+/// # use std::{io::Error, process::{Command, Output}};
+/// # use command_ext::{check::check_wrapper, CommandExtCheck, CommandExtError, CommandWrap, HasCommand};
+/// struct DenyWarnings<'a>(&'a mut Command);
+///
+/// impl HasCommand for DenyWarnings<'_> {
+///     fn command(&self) -> &Command {
+///         self.0
+///     }
+///
+///     fn command_mut(&mut self) -> &mut Command {
+///         self.0
+///     }
+/// }
+///
+/// impl CommandWrap for DenyWarnings<'_> {
+///     fn on_check(&mut self, output: &Output) -> Option<CommandExtError> {
+///         let stderr = String::from_utf8_lossy(&output.stderr);
+///         stderr
+///             .lines()
+///             .find(|l| l.contains("WARNING"))
+///             .map(|line| CommandExtError::StdIoError(Error::other(format!("denied: {line}"))))
+///     }
+/// }
+///
+/// impl CommandExtCheck for DenyWarnings<'_> {
+///     type Error = CommandExtError;
+///
+///     fn check(&mut self) -> Result<Output, Self::Error> {
+///         check_wrapper(self)
+///     }
+/// }
+///
+/// let mut command = Command::new("bash");
+/// command.args(["-c", "echo 'WARNING: deprecated' >&2"]);
+/// assert!(DenyWarnings(&mut command).check().is_err());
+/// ```
+pub fn check_wrapper<W>(wrapper: &mut W) -> Result<Output, CommandExtError>
+where
+    W: CommandWrap,
+{
+    preflight(wrapper.command())?;
+    let output = wrapper.output();
+    check_with(wrapper, output)
+}
+
+/// Finish a child which was spawned from `executor` and interacted with by the caller:
+/// read the rest of its piped output after `stdout` and `stderr`, which the caller already
+/// read from it, wait for it, run the hooks `executor` runs after its output, and check the
+/// result like [`check_with`]
+pub(crate) fn wait_checked<E>(
+    executor: &mut E,
+    mut child: Child,
+    mut stdout: Vec<u8>,
+    mut stderr: Vec<u8>,
+) -> Result<Output, CommandExtError>
+where
+    E: Execute,
+{
+    let _execution = metrics::execution(executor.command());
+    let pid = child.id();
+    drop(child.stdin.take());
+
+    let (output, mut info) = ExecutionInfo::measure(|| {
+        let (rest_stdout, rest_stderr) = capture::read_both(&mut child)?;
+        let status = child.wait()?;
+        stdout.extend(rest_stdout);
+        stderr.extend(rest_stderr);
+
+        Ok(Output {
+            status,
+            stdout,
+            stderr,
+        })
+    });
+    info.pid = Some(pid);
+    let info = info.with_output(&output);
+
+    let output = executor.finish_output(output, &info);
+    check_with(executor, output)
+}
+
+/// Check for the two configurations which make spawning fail with an error that does not
+/// say which path is wrong: a working directory which does not exist, and a program path
+/// which names a directory
+pub(crate) fn preflight(command: &Command) -> Result<(), CommandExtError> {
+    let dir = command.get_current_dir();
+
+    if let Some(dir) = dir.filter(|d| !d.is_dir()) {
+        return Err(CommandExtError::CwdNotFound {
+            path: dir.to_path_buf(),
+        });
+    }
+
+    let program = Path::new(command.get_program());
+    if program.components().count() > 1 {
+        let path = match dir {
+            Some(dir) => dir.join(program),
+            None => program.to_path_buf(),
+        };
+
+        if path.is_dir() {
+            return Err(CommandExtError::ProgramIsDirectory { path });
+        }
+    }
+
+    Ok(())
+}
+
+/// Execute a plain command for its output, reporting it with the ambient defaults when a
+/// reporting backend is enabled
+pub(crate) fn default_output(command: &mut Command) -> std::io::Result<Output> {
+    #[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+    return crate::defaults::output(command);
+    #[cfg(not(any(feature = "log", feature = "print", feature = "tracing")))]
+    return command.output();
 }
 
 impl CommandExtCheck for Command {
@@ -19,22 +221,14 @@ impl CommandExtCheck for Command {
     /// Check the result of a command, returning an error containing the status, output
     /// and error stream content if the status is not success
     fn check(&mut self) -> Result<Output, Self::Error> {
-        self.output().map_err(CommandExtError::from).and_then(|r| {
-            r.status
-                .success()
-                .then_some(r.clone())
-                .ok_or_else(|| CommandExtError::Check {
-                    status: r.status,
-                    stdout: String::from_utf8_lossy(&r.stdout).to_string(),
-                    stderr: String::from_utf8_lossy(&r.stderr).to_string(),
-                })
-        })
+        preflight(self)?;
+        check_output(default_output(self))
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::process::Command;
+    use std::{path::Path, process::Command};
 
     use crate::{CommandExtCheck, CommandExtError};
 
@@ -65,6 +259,47 @@ mod test {
         }
     }
 
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a missing working directory or a directory program is reported by path
+    fn test_preflight() {
+        match Command::new("true").current_dir("/nonexistent").check() {
+            Err(CommandExtError::CwdNotFound { path }) => {
+                assert_eq!(path, Path::new("/nonexistent"))
+            }
+            other => panic!("Unexpected result from command: {:?}", other),
+        }
+
+        match Command::new("./src").check() {
+            Err(CommandExtError::ProgramIsDirectory { path }) => {
+                assert_eq!(path, Path::new("./src"))
+            }
+            other => panic!("Unexpected result from command: {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "log", feature = "stdin"))]
+    #[cfg_attr(miri, ignore)]
+    /// Test that checking through a wrapper reports a missing working directory by path
+    fn test_preflight_wrapper() {
+        use log::Level;
+
+        use crate::{CommandExtLog, CommandExtStdin};
+
+        let result = Command::new("true")
+            .current_dir("/nonexistent")
+            .log_args(Level::Debug)
+            .check();
+        assert!(matches!(result, Err(CommandExtError::CwdNotFound { .. })));
+
+        let result = Command::new("cat")
+            .current_dir("/nonexistent")
+            .stdin_bytes("x")
+            .check();
+        assert!(matches!(result, Err(CommandExtError::CwdNotFound { .. })));
+    }
+
     #[test]
     #[cfg_attr(miri, ignore)]
     /// Test that a command which fails by returning a nonzero status code returns a check error
@@ -83,4 +318,91 @@ mod test {
             )),
         }
     }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that the output streams of a failed command are carried by the check error
+    fn test_failure_output() {
+        let output = Command::new("bash")
+            .args(["-c", "echo x; echo y 1>&2; exit 1"])
+            .check();
+
+        match output {
+            Ok(output) => panic!("Unexpected success from command: {:?}", output),
+            Err(CommandExtError::Check { stdout, stderr, .. }) => {
+                assert_eq!(stdout, "x\n");
+                assert_eq!(stderr, "y\n");
+            }
+            Err(e) => panic!("Unexpected error from command: {}", e),
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that the check error is converted into the requested error type
+    fn test_check_into() {
+        #[derive(Debug)]
+        struct Wrapped(CommandExtError);
+
+        impl From<CommandExtError> for Wrapped {
+            fn from(e: CommandExtError) -> Self {
+                Self(e)
+            }
+        }
+
+        assert!(Command::new("true").check_into::<Wrapped>().is_ok());
+        match Command::new("false").check_into::<Wrapped>() {
+            Err(Wrapped(CommandExtError::Check { .. })) => {}
+            other => panic!("Unexpected result from command: {:?}", other),
+        }
+
+        let io = Command::new("false").check_into::<std::io::Error>();
+        assert!(io.is_err());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that the check hook of a wrapper can fail a successful command, and otherwise
+    /// leaves the decision to the status
+    fn test_on_check() {
+        use std::process::Output;
+
+        use crate::{check::check_wrapper, CommandWrap, HasCommand};
+
+        struct NonEmpty<'a>(&'a mut Command);
+
+        impl HasCommand for NonEmpty<'_> {
+            fn command(&self) -> &Command {
+                self.0
+            }
+
+            fn command_mut(&mut self) -> &mut Command {
+                self.0
+            }
+        }
+
+        impl CommandWrap for NonEmpty<'_> {
+            fn on_check(&mut self, output: &Output) -> Option<CommandExtError> {
+                output.stdout.is_empty().then(|| CommandExtError::Skipped {
+                    reason: "no output".to_string(),
+                })
+            }
+        }
+
+        let mut command = Command::new("true");
+        assert!(matches!(
+            check_wrapper(&mut NonEmpty(&mut command)),
+            Err(CommandExtError::Skipped { .. })
+        ));
+
+        let mut command = Command::new("echo");
+        assert!(check_wrapper(&mut NonEmpty(&mut command)).is_ok());
+
+        let mut command = Command::new("bash");
+        command.args(["-c", "echo x; exit 1"]);
+        assert!(matches!(
+            check_wrapper(&mut NonEmpty(&mut command)),
+            Err(CommandExtError::Check { .. })
+        ));
+    }
 }
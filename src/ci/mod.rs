@@ -0,0 +1,157 @@
+//! Detection of continuous integration systems and presets for running under them
+//!
+//! [`detect`] reports which CI system, if any, the process is running under. When a
+//! reporting backend is enabled, [`install`] defines the [`PROFILE`] profile with settings
+//! suited to CI logs, and that profile is applied to every command whenever a CI system is
+//! detected and no other profile is selected with `COMMAND_EXT_PROFILE`.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::{process::Command, time::Duration};
+//! # use command_ext::{ci, CommandExtCheck};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! if let Some(ci) = ci::install() {
+//!     println!("running under {ci}");
+//! }
+//!
+//! // Runs with colors disabled and verbose reporting when under CI
+//! Command::new("echo").arg("x").check()?;
+//!
+//! // Slow CI runners get three times as long
+//! let timeout = ci::timeout(Duration::from_secs(10));
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{env::var, fmt::Display, time::Duration};
+
+/// The name of the profile applied to commands when a CI system is detected
+pub const PROFILE: &str = "ci";
+
+/// How much longer timeouts are under CI, see [`timeout`]
+pub const TIMEOUT_FACTOR: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+/// A continuous integration system
+pub enum Ci {
+    GitHubActions,
+    GitLab,
+    Jenkins,
+    CircleCi,
+    Travis,
+    AzurePipelines,
+    Buildkite,
+    TeamCity,
+    /// A CI system which only sets the conventional `CI` variable
+    Other,
+}
+
+impl Ci {
+    /// The name of the CI system
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::GitHubActions => "GitHub Actions",
+            Self::GitLab => "GitLab CI",
+            Self::Jenkins => "Jenkins",
+            Self::CircleCi => "CircleCI",
+            Self::Travis => "Travis CI",
+            Self::AzurePipelines => "Azure Pipelines",
+            Self::Buildkite => "Buildkite",
+            Self::TeamCity => "TeamCity",
+            Self::Other => "CI",
+        }
+    }
+}
+
+impl Display for Ci {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The variables identifying each CI system, checked in order
+const VARIABLES: &[(&str, Ci)] = &[
+    ("GITHUB_ACTIONS", Ci::GitHubActions),
+    ("GITLAB_CI", Ci::GitLab),
+    ("JENKINS_URL", Ci::Jenkins),
+    ("CIRCLECI", Ci::CircleCi),
+    ("TRAVIS", Ci::Travis),
+    ("TF_BUILD", Ci::AzurePipelines),
+    ("BUILDKITE", Ci::Buildkite),
+    ("TEAMCITY_VERSION", Ci::TeamCity),
+    ("CI", Ci::Other),
+];
+
+fn detect_with<F>(lookup: F) -> Option<Ci>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    VARIABLES.iter().find_map(|(name, ci)| {
+        lookup(name)
+            .filter(|v| !v.is_empty() && v != "0" && !v.eq_ignore_ascii_case("false"))
+            .map(|_| *ci)
+    })
+}
+
+/// The CI system the process is running under, if any
+pub fn detect() -> Option<Ci> {
+    detect_with(|name| var(name).ok())
+}
+
+/// Scale `base` by [`TIMEOUT_FACTOR`] when running under CI, where runners are often
+/// slower and more heavily loaded than developer machines
+pub fn timeout(base: Duration) -> Duration {
+    match detect() {
+        Some(_) => base * TIMEOUT_FACTOR,
+        None => base,
+    }
+}
+
+#[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+/// Configure `profile` for CI logs: colors are disabled for tools which honor
+/// `NO_COLOR`, `CLICOLOR`, or `CARGO_TERM_COLOR`, and commands are reported verbosely
+pub fn preset(profile: &mut crate::Profile) -> &mut crate::Profile {
+    profile
+        .reporting(|d| d.verbosity(crate::Verbosity::Verbose))
+        .env("NO_COLOR", "1")
+        .env("CLICOLOR", "0")
+        .env("CARGO_TERM_COLOR", "never")
+}
+
+#[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+/// Define the [`PROFILE`] profile with the [`preset`] settings, replacing any existing
+/// definition, and return the detected CI system
+pub fn install() -> Option<Ci> {
+    crate::profile::define(PROFILE, preset);
+    detect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{detect_with, Ci};
+
+    #[test]
+    fn test_detect() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(k, _)| *k == name)
+                    .map(|(_, v)| v.to_string())
+            }
+        };
+
+        assert_eq!(detect_with(env(&[])), None);
+        assert_eq!(detect_with(env(&[("CI", "false")])), None);
+        assert_eq!(detect_with(env(&[("CI", "true")])), Some(Ci::Other));
+        assert_eq!(
+            detect_with(env(&[("CI", "true"), ("GITLAB_CI", "true")])),
+            Some(Ci::GitLab)
+        );
+        assert_eq!(
+            detect_with(env(&[("TEAMCITY_VERSION", "2023.05")])),
+            Some(Ci::TeamCity)
+        );
+    }
+}
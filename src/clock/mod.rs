@@ -0,0 +1,225 @@
+//! The clock used to measure executions, wait between retries, time out commands and
+//! schedule heartbeats
+//!
+//! Everything in this crate which reads the time or waits asks the current [`Clock`]
+//! instead of using [`Instant`], [`SystemTime`] and [`std::thread::sleep`] directly, except
+//! for the overhead [metrics](crate::metrics) and benchmarks, which measure the real time
+//! spent in the process. The current clock is the [`SystemClock`] unless another one is
+//! installed for the whole process with [`set`], or for a closure on the current thread
+//! with [`with`]. Installing a [`MockClock`] makes timeout, retry and inactivity logic
+//! deterministic: sleeping on it advances it immediately instead of waiting, and tests can
+//! advance it by hand.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::{process::Command, sync::Arc, time::Duration};
+//! # use command_ext::{clock::{self, MockClock}, CommandExtCheck, CommandExtRetry};
+//! let mock = MockClock::new();
+//! let result = clock::with(Arc::new(mock.clone()), || {
+//!     Command::new("false")
+//!         .retry(4)
+//!         .delay(Duration::from_secs(60))
+//!         .check()
+//! });
+//!
+//! // Seven minutes of backoff passed without any real waiting
+//! assert!(result.is_err());
+//! assert_eq!(mock.elapsed(), Duration::from_secs(60 + 120 + 240));
+//! assert_eq!(mock.sleeps().len(), 3);
+//! ```
+
+use std::{
+    cell::RefCell,
+    fmt::Debug,
+    sync::{Arc, Mutex, MutexGuard, RwLock},
+    thread::{sleep, yield_now},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// A source of the current time which can be waited on
+pub trait Clock: Debug + Send + Sync {
+    /// The current monotonic time
+    fn now(&self) -> Instant;
+
+    /// The current wall-clock time
+    fn system_now(&self) -> SystemTime;
+
+    /// Wait for `duration` to pass
+    fn sleep(&self, duration: Duration);
+
+    /// How long to block waiting for something other than the clock, such as a child's
+    /// output, when the clock says `wait` remains before the next deadline. Real time
+    /// passes while blocking, so clocks which do not follow real time return a short
+    /// interval, after which the deadline is checked against the clock again.
+    fn poll_interval(&self, wait: Duration) -> Duration {
+        wait
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+/// The real time of the system
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        sleep(duration)
+    }
+}
+
+/// How long a [`MockClock`] lets other waits block before checking its time again
+const MOCK_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Debug)]
+struct MockTime {
+    elapsed: Duration,
+    sleeps: Vec<Duration>,
+}
+
+#[derive(Debug, Clone)]
+/// A clock which only advances when it is slept on or advanced by hand. Clones share the
+/// same time, so a test can keep one and install another.
+pub struct MockClock {
+    instant: Instant,
+    system: SystemTime,
+    time: Arc<Mutex<MockTime>>,
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockClock {
+    /// Create a clock starting at the current real time
+    pub fn new() -> Self {
+        Self {
+            instant: Instant::now(),
+            system: SystemTime::now(),
+            time: Arc::new(Mutex::new(MockTime {
+                elapsed: Duration::ZERO,
+                sleeps: Vec::new(),
+            })),
+        }
+    }
+
+    fn time(&self) -> MutexGuard<'_, MockTime> {
+        self.time.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Move the clock forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        self.time().elapsed += duration;
+    }
+
+    /// How far the clock has moved since it was created
+    pub fn elapsed(&self) -> Duration {
+        self.time().elapsed
+    }
+
+    /// The durations the clock was slept for, in order
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.time().sleeps.clone()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.instant + self.elapsed()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.system + self.elapsed()
+    }
+
+    /// Advance the clock by `duration` without waiting
+    fn sleep(&self, duration: Duration) {
+        let mut time = self.time();
+        time.elapsed += duration;
+        time.sleeps.push(duration);
+        drop(time);
+        // Busy loops which poll a child through the clock still give it time to run
+        yield_now();
+    }
+
+    fn poll_interval(&self, wait: Duration) -> Duration {
+        wait.min(MOCK_POLL_INTERVAL)
+    }
+}
+
+static GLOBAL: RwLock<Option<Arc<dyn Clock>>> = RwLock::new(None);
+
+thread_local! {
+    static SCOPED: RefCell<Option<Arc<dyn Clock>>> = const { RefCell::new(None) };
+}
+
+/// Install `clock` as the clock for the whole process
+pub fn set(clock: Arc<dyn Clock>) {
+    *GLOBAL.write().unwrap_or_else(|e| e.into_inner()) = Some(clock);
+}
+
+/// Restore the system clock for the whole process
+pub fn reset() {
+    *GLOBAL.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Run `f` with `clock` as the clock of the current thread, taking precedence over the
+/// clock installed with [`set`]. Threads spawned by `f` use the process's clock.
+pub fn with<T, F>(clock: Arc<dyn Clock>, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    /// Restores the previous clock of the thread, even if `f` panics
+    struct Restore(Option<Arc<dyn Clock>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            SCOPED.with(|scoped| *scoped.borrow_mut() = self.0.take());
+        }
+    }
+
+    let _restore = Restore(SCOPED.with(|scoped| scoped.borrow_mut().replace(clock)));
+    f()
+}
+
+/// The clock of the current thread: the one installed with [`with`], then the one installed
+/// with [`set`], then the [`SystemClock`]
+pub fn current() -> Arc<dyn Clock> {
+    SCOPED
+        .with(|scoped| scoped.borrow().clone())
+        .or_else(|| GLOBAL.read().unwrap_or_else(|e| e.into_inner()).clone())
+        .unwrap_or_else(|| Arc::new(SystemClock))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{current, with, Clock, MockClock};
+
+    #[test]
+    /// Test that a mock clock advances when slept on and is only current inside `with`
+    fn test_mock_clock() {
+        let mock = MockClock::new();
+        let start = mock.now();
+
+        with(Arc::new(mock.clone()), || {
+            let clock = current();
+            clock.sleep(Duration::from_secs(5));
+            mock.advance(Duration::from_secs(1));
+            assert_eq!(clock.now() - start, Duration::from_secs(6));
+        });
+
+        assert_eq!(mock.sleeps(), [Duration::from_secs(5)]);
+        assert!(current().now() - start < Duration::from_secs(6));
+    }
+}
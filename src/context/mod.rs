@@ -0,0 +1,213 @@
+//! Extension traits to attach a description of the operation to command errors
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{CommandExtCheck, CommandExtContext, CommandExtError};
+//! let error = Command::new("false")
+//!     .context("while building frontend")
+//!     .check()
+//!     .unwrap_err();
+//!
+//! assert!(error
+//!     .to_string()
+//!     .starts_with("while building frontend: Command failed"));
+//!
+//! // Let the top-level script exit with a specific code when this command fails
+//! let error = Command::new("false").fail_with_code(42).check().unwrap_err();
+//! assert_eq!(error.exit_code_hint(), 42);
+//! ```
+
+use std::process::Output;
+
+use crate::{CommandExtCheck, CommandExtError};
+
+/// Extension trait to attach context to the error of a command result
+pub trait CommandResultExt<T> {
+    /// Wrap the error, if any, with a description of the operation which failed
+    fn context<C: Into<String>>(self, context: C) -> Result<T, CommandExtError>;
+
+    /// Wrap the error, if any, with a lazily computed description of the operation
+    fn with_context<C, F>(self, f: F) -> Result<T, CommandExtError>
+    where
+        C: Into<String>,
+        F: FnOnce() -> C;
+}
+
+impl<T> CommandResultExt<T> for Result<T, CommandExtError> {
+    fn context<C: Into<String>>(self, context: C) -> Result<T, CommandExtError> {
+        self.map_err(|e| e.context(context))
+    }
+
+    fn with_context<C, F>(self, f: F) -> Result<T, CommandExtError>
+    where
+        C: Into<String>,
+        F: FnOnce() -> C,
+    {
+        self.map_err(|e| e.context(f()))
+    }
+}
+
+#[derive(Debug)]
+/// A checked command or wrapper whose errors are annotated with a description and/or an
+/// exit code
+pub struct CommandContext<'a, C> {
+    inner: &'a mut C,
+    /// The descriptions, innermost first
+    contexts: Vec<String>,
+    code: Option<u8>,
+}
+
+impl<'a, C> CommandContext<'a, C> {
+    /// Add another, outer, description of the operation. Each description wraps the error
+    /// separately, as with [`CommandResultExt::context`].
+    pub fn context<S: Into<String>>(&mut self, context: S) -> &mut Self {
+        self.contexts.push(context.into());
+        self
+    }
+
+    /// Set the exit code the parent process should use if this command fails
+    pub fn fail_with_code(&mut self, code: u8) -> &mut Self {
+        self.code = Some(code);
+        self
+    }
+}
+
+impl<'a, C> From<&'a mut C> for CommandContext<'a, C> {
+    fn from(value: &'a mut C) -> Self {
+        Self {
+            inner: value,
+            contexts: Vec::new(),
+            code: None,
+        }
+    }
+}
+
+pub trait CommandExtContext: Sized {
+    fn context<S: Into<String>>(&mut self, context: S) -> CommandContext<'_, Self>;
+    fn fail_with_code(&mut self, code: u8) -> CommandContext<'_, Self>;
+}
+
+impl<T> CommandExtContext for T
+where
+    T: CommandExtCheck<Error = CommandExtError>,
+{
+    fn context<S: Into<String>>(&mut self, context: S) -> CommandContext<'_, Self> {
+        let mut context_wrapper = CommandContext::from(self);
+        context_wrapper.context(context);
+        context_wrapper
+    }
+
+    fn fail_with_code(&mut self, code: u8) -> CommandContext<'_, Self> {
+        let mut context_wrapper = CommandContext::from(self);
+        context_wrapper.fail_with_code(code);
+        context_wrapper
+    }
+}
+
+impl<'a, C> CommandExtCheck for CommandContext<'a, C>
+where
+    C: CommandExtCheck<Error = CommandExtError>,
+{
+    type Error = CommandExtError;
+
+    fn check(&mut self) -> Result<Output, Self::Error> {
+        self.inner.check().map_err(|e| {
+            let e = match self.code {
+                Some(code) => e.with_exit_code(code),
+                None => e,
+            };
+
+            self.contexts
+                .iter()
+                .fold(e, |e, context| e.context(context.clone()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use super::CommandResultExt;
+    use crate::{CommandExtCheck, CommandExtContext, CommandExtError};
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that contexts are shown outermost first and the root cause is kept
+    fn test_context() {
+        let error = Command::new("false")
+            .context("inner")
+            .context("outer")
+            .check()
+            .unwrap_err();
+
+        assert_eq!(error.contexts(), ["outer", "inner"]);
+        assert!(error
+            .to_string()
+            .starts_with("outer: inner: Command failed"));
+        assert!(matches!(error.root_cause(), CommandExtError::Check { .. }));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that contexts can be added to the result of a check
+    fn test_result_context() {
+        let error = Command::new("false")
+            .check()
+            .context("inner")
+            .with_context(|| "outer")
+            .unwrap_err();
+
+        assert_eq!(error.contexts(), ["outer", "inner"]);
+        assert!(error
+            .to_string()
+            .starts_with("outer: inner: Command failed"));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that an exit code chosen for a failure is kept under its context
+    fn test_fail_with_code() {
+        let error = Command::new("false")
+            .fail_with_code(3)
+            .context("outer")
+            .check()
+            .unwrap_err();
+
+        assert_eq!(error.exit_code_hint(), 3);
+        assert_eq!(error.contexts(), ["outer"]);
+        assert!(error.to_string().starts_with("outer: Command failed"));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that the exit code hint follows the command's status or category
+    fn test_exit_code_hint() {
+        use crate::error::ErrorCategory;
+
+        let error = Command::new("bash")
+            .args(["-c", "exit 7"])
+            .check()
+            .unwrap_err();
+        assert_eq!(error.category(), ErrorCategory::Failed);
+        assert_eq!(error.exit_code_hint(), 7);
+
+        let error = Command::new("asdfasdfasdfasdfjkljkljkl")
+            .context("missing")
+            .check()
+            .unwrap_err();
+        assert_eq!(error.category(), ErrorCategory::NotFound);
+        assert_eq!(error.exit_code_hint(), 127);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a successful command is passed through unchanged
+    fn test_success() -> anyhow::Result<()> {
+        let output = Command::new("echo").arg("x").context("unused").check()?;
+        assert_eq!(output.stdout, b"x\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,344 @@
+//! Extension trait to start daemons which fork into the background and exit
+//!
+//! Many daemons are started by a launcher process which forks, writes the PID of the real
+//! daemon to a pidfile, and exits. Waiting on the launcher says nothing about the daemon, so
+//! [`CommandPidfile::start`] waits for the pidfile instead and returns a [`Daemon`] handle
+//! which manages the process it names. Dropping the handle leaves the daemon running.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::{env::temp_dir, process::Command, time::Duration};
+//! # use command_ext::CommandExtPidfile;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let pidfile = temp_dir().join(format!("command-ext-doc-{}.pid", std::process::id()));
+//! let daemon = Command::new("bash")
+//!     .args(["-c", "sleep 30 & echo $! > \"$0\"", &pidfile.to_string_lossy()])
+//!     .pidfile(&pidfile, Duration::from_secs(5))
+//!     .start()?;
+//!
+//! assert!(daemon.is_running());
+//! daemon.shutdown(Duration::from_secs(5))?;
+//! assert!(!daemon.is_running());
+//! # std::fs::remove_file(pidfile)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    fs::read_to_string,
+    io::{Error, ErrorKind, Result as IoResult},
+    path::{Path, PathBuf},
+    process::{Child, Command},
+    time::Duration,
+};
+
+use crate::{clock, pool, CommandExtError};
+
+/// How often the pidfile and the daemon are polled
+const POLL_INTERVAL: Duration = Duration::from_millis(25);
+
+/// Read the PID in a pidfile. PIDs 0 and 1 are rejected: signalling 0 signals the caller's
+/// whole process group, and 1 is init, so neither can name a daemon to manage.
+fn read_pid(path: &Path) -> Option<u32> {
+    read_to_string(path)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .filter(|pid| *pid > 1)
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::io::{Error, Result as IoResult};
+
+    extern "C" {
+        fn kill(pid: i32, sig: i32) -> i32;
+    }
+
+    const SIGKILL: i32 = 9;
+    const SIGTERM: i32 = 15;
+
+    fn signal(pid: u32, sig: i32) -> IoResult<()> {
+        let pid = i32::try_from(pid).map_err(Error::other)?;
+        // SAFETY: kill has no memory safety requirements
+        match unsafe { kill(pid, sig) } {
+            0 => Ok(()),
+            _ => Err(Error::last_os_error()),
+        }
+    }
+
+    pub(super) fn is_running(pid: u32) -> bool {
+        signal(pid, 0).is_ok_and(|()| {
+            // Zombies accept signals but are no longer running
+            std::fs::read_to_string(format!("/proc/{pid}/stat")).map_or(true, |stat| {
+                stat.rsplit_once(") ")
+                    .is_none_or(|(_, rest)| !rest.starts_with('Z'))
+            })
+        })
+    }
+
+    pub(super) fn terminate(pid: u32) -> IoResult<()> {
+        signal(pid, SIGTERM)
+    }
+
+    pub(super) fn kill_now(pid: u32) -> IoResult<()> {
+        signal(pid, SIGKILL)
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::{
+        io::{Error, Result as IoResult},
+        process::{Command, Stdio},
+    };
+
+    fn taskkill(pid: u32, force: bool) -> IoResult<()> {
+        let mut command = Command::new("taskkill");
+        command.args(["/PID", &pid.to_string()]);
+        if force {
+            command.arg("/F");
+        }
+        match command
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()?
+        {
+            status if status.success() => Ok(()),
+            status => Err(Error::other(format!("taskkill failed with {status}"))),
+        }
+    }
+
+    pub(super) fn is_running(pid: u32) -> bool {
+        Command::new("tasklist")
+            .args(["/NH", "/FI", &format!("PID eq {pid}")])
+            .output()
+            .is_ok_and(|o| String::from_utf8_lossy(&o.stdout).contains(&pid.to_string()))
+    }
+
+    pub(super) fn terminate(pid: u32) -> IoResult<()> {
+        taskkill(pid, false)
+    }
+
+    pub(super) fn kill_now(pid: u32) -> IoResult<()> {
+        taskkill(pid, true)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A handle to a daemon found through its pidfile
+pub struct Daemon {
+    pid: u32,
+    pidfile: PathBuf,
+}
+
+impl Daemon {
+    /// The PID read from the pidfile
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// The pidfile the daemon was found through
+    pub fn pidfile(&self) -> &Path {
+        &self.pidfile
+    }
+
+    /// Whether the daemon is still running
+    pub fn is_running(&self) -> bool {
+        sys::is_running(self.pid)
+    }
+
+    /// Ask the daemon to exit (`SIGTERM` on unix)
+    pub fn terminate(&self) -> IoResult<()> {
+        sys::terminate(self.pid)
+    }
+
+    /// Kill the daemon immediately (`SIGKILL` on unix)
+    pub fn kill(&self) -> IoResult<()> {
+        sys::kill_now(self.pid)
+    }
+
+    /// Wait up to `timeout` for the daemon to exit, returning whether it did
+    pub fn wait_for_exit(&self, timeout: Duration) -> bool {
+        let clock = clock::current();
+        let start = clock.now();
+        while self.is_running() {
+            if clock.now().duration_since(start) >= timeout {
+                return false;
+            }
+            clock.sleep(POLL_INTERVAL);
+        }
+        true
+    }
+
+    /// Ask the daemon to exit, and kill it if it is still running after `grace`
+    pub fn shutdown(&self, grace: Duration) -> IoResult<()> {
+        if !self.is_running() {
+            return Ok(());
+        }
+
+        self.terminate()?;
+        if !self.wait_for_exit(grace) {
+            self.kill()?;
+            self.wait_for_exit(grace);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+/// A command which launches a daemon that records its PID in a pidfile
+pub struct CommandPidfile<'a> {
+    command: &'a mut Command,
+    path: PathBuf,
+    timeout: Duration,
+}
+
+impl<'a> CommandPidfile<'a> {
+    /// The pidfile the daemon writes its PID to
+    pub fn get_pidfile(&self) -> &Path {
+        &self.path
+    }
+
+    /// How long to wait for the pidfile to name a running process
+    pub fn get_timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Run the launcher and wait for the pidfile to name a running process. A PID left in
+    /// the pidfile by an earlier run is ignored. Fails if the launcher exits unsuccessfully
+    /// or the pidfile does not appear within the timeout. A launcher which is still running
+    /// when this returns is reaped in the background.
+    pub fn start(&mut self) -> Result<Daemon, CommandExtError> {
+        let stale = read_pid(&self.path);
+        let mut launcher = self.command.spawn()?;
+        let mut launcher_exited = false;
+        let result = self.wait_for_daemon(stale, &mut launcher, &mut launcher_exited);
+
+        if !launcher_exited {
+            drop(pool::spawn(move || launcher.wait()));
+        }
+
+        result
+    }
+
+    /// Poll the pidfile for a running process other than `stale`, reaping the launcher and
+    /// failing if it exits unsuccessfully
+    fn wait_for_daemon(
+        &self,
+        stale: Option<u32>,
+        launcher: &mut Child,
+        launcher_exited: &mut bool,
+    ) -> Result<Daemon, CommandExtError> {
+        let clock = clock::current();
+        let start = clock.now();
+
+        loop {
+            if let Some(pid) = read_pid(&self.path).filter(|pid| Some(*pid) != stale) {
+                if sys::is_running(pid) {
+                    return Ok(Daemon {
+                        pid,
+                        pidfile: self.path.clone(),
+                    });
+                }
+            }
+
+            if !*launcher_exited {
+                if let Some(status) = launcher.try_wait()? {
+                    *launcher_exited = true;
+                    if !status.success() {
+                        return Err(CommandExtError::Check {
+                            status,
+                            stdout: String::new(),
+                            stderr: String::new(),
+                        });
+                    }
+                }
+            }
+
+            if clock.now().duration_since(start) >= self.timeout {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!(
+                        "pidfile {} did not name a running process within {:?}",
+                        self.path.display(),
+                        self.timeout
+                    ),
+                )
+                .into());
+            }
+
+            clock.sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Extension trait for [`std::process::Command`] to launch a daemon which records its PID in
+/// a pidfile
+pub trait CommandExtPidfile {
+    /// Launch a daemon with this command once [started](CommandPidfile::start), waiting up
+    /// to `timeout` for `path` to name a running process
+    fn pidfile<P: AsRef<Path>>(&mut self, path: P, timeout: Duration) -> CommandPidfile<'_>;
+}
+
+impl CommandExtPidfile for Command {
+    fn pidfile<P: AsRef<Path>>(&mut self, path: P, timeout: Duration) -> CommandPidfile<'_> {
+        CommandPidfile {
+            command: self,
+            path: path.as_ref().to_path_buf(),
+            timeout,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env::temp_dir, process::Command, time::Duration};
+
+    use crate::{error::ErrorCategory, CommandExtPidfile};
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a pidfile which never appears times out
+    fn test_timeout() {
+        let path = temp_dir().join(format!("command-ext-missing-{}.pid", std::process::id()));
+        let error = Command::new("true")
+            .pidfile(path, Duration::from_millis(100))
+            .start()
+            .unwrap_err();
+
+        assert_eq!(error.category(), ErrorCategory::TimedOut);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a pidfile naming PID 0, which would signal the caller's process group, is
+    /// never accepted
+    fn test_pid_zero() {
+        let path = temp_dir().join(format!("command-ext-zero-{}.pid", std::process::id()));
+        let error = Command::new("bash")
+            .args(["-c", "echo 0 > \"$0\"", &path.to_string_lossy()])
+            .pidfile(&path, Duration::from_millis(200))
+            .start()
+            .unwrap_err();
+
+        assert_eq!(error.category(), ErrorCategory::TimedOut);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a launcher which exits unsuccessfully fails the start
+    fn test_launcher_failure() {
+        let path = temp_dir().join(format!("command-ext-failed-{}.pid", std::process::id()));
+        let error = Command::new("false")
+            .pidfile(path, Duration::from_secs(5))
+            .start()
+            .unwrap_err();
+
+        assert_eq!(error.category(), ErrorCategory::Failed);
+    }
+}
@@ -0,0 +1,404 @@
+//! Ambient reporting defaults applied to every command executed through this crate
+//!
+//! Defaults are installed once, usually at the start of `main`, and apply to every
+//! [`crate::CommandExtCheck::check`] call on a plain [`Command`] as well as to every
+//! print, log, and trace wrapper, unless the wrapper overrides them locally. When the
+//! `COMMAND_EXT_PROFILE` environment variable selects a [`crate::profile::Profile`], the
+//! reporting settings of that profile take the place of the installed defaults.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{defaults, CommandExtCheck};
+//! # use log::Level;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! defaults::set(|cfg| cfg.log_args(Level::Debug).log_status(Level::Info));
+//! // Logs its arguments and status without any further configuration
+//! Command::new("echo").arg("x").check()?;
+//! # defaults::reset();
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::Result as IoResult,
+    process::{Command, Output},
+    sync::RwLock,
+};
+
+#[cfg(feature = "log")]
+use crate::log::CommandLog;
+#[cfg(feature = "print")]
+use crate::print::CommandPrint;
+#[cfg(feature = "tracing")]
+use crate::trace::CommandTrace;
+use crate::{wrap::ExecutionInfo, CommandWrap, Verbosity};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Reporting settings applied to commands which do not override them
+pub struct Defaults {
+    #[cfg(feature = "log")]
+    pub(crate) log_args: Option<log::Level>,
+    #[cfg(feature = "log")]
+    pub(crate) log_envs: Option<log::Level>,
+    #[cfg(feature = "log")]
+    pub(crate) log_current_dir: Option<log::Level>,
+    #[cfg(feature = "log")]
+    pub(crate) log_status: Option<log::Level>,
+    #[cfg(feature = "log")]
+    pub(crate) log_stdout: Option<log::Level>,
+    #[cfg(feature = "log")]
+    pub(crate) log_stderr: Option<log::Level>,
+    #[cfg(feature = "tracing")]
+    pub(crate) trace_args: Option<tracing::Level>,
+    #[cfg(feature = "tracing")]
+    pub(crate) trace_envs: Option<tracing::Level>,
+    #[cfg(feature = "tracing")]
+    pub(crate) trace_current_dir: Option<tracing::Level>,
+    #[cfg(feature = "tracing")]
+    pub(crate) trace_status: Option<tracing::Level>,
+    #[cfg(feature = "tracing")]
+    pub(crate) trace_stdout: Option<tracing::Level>,
+    #[cfg(feature = "tracing")]
+    pub(crate) trace_stderr: Option<tracing::Level>,
+    #[cfg(feature = "print")]
+    pub(crate) print_args: bool,
+    #[cfg(feature = "print")]
+    pub(crate) print_envs: bool,
+    #[cfg(feature = "print")]
+    pub(crate) print_current_dir: bool,
+    #[cfg(feature = "print")]
+    pub(crate) print_status: bool,
+    #[cfg(feature = "print")]
+    pub(crate) print_stdout: bool,
+    #[cfg(feature = "print")]
+    pub(crate) print_stderr: bool,
+}
+
+impl Defaults {
+    /// Defaults which report nothing
+    pub const fn new() -> Self {
+        Self {
+            #[cfg(feature = "log")]
+            log_args: None,
+            #[cfg(feature = "log")]
+            log_envs: None,
+            #[cfg(feature = "log")]
+            log_current_dir: None,
+            #[cfg(feature = "log")]
+            log_status: None,
+            #[cfg(feature = "log")]
+            log_stdout: None,
+            #[cfg(feature = "log")]
+            log_stderr: None,
+            #[cfg(feature = "tracing")]
+            trace_args: None,
+            #[cfg(feature = "tracing")]
+            trace_envs: None,
+            #[cfg(feature = "tracing")]
+            trace_current_dir: None,
+            #[cfg(feature = "tracing")]
+            trace_status: None,
+            #[cfg(feature = "tracing")]
+            trace_stdout: None,
+            #[cfg(feature = "tracing")]
+            trace_stderr: None,
+            #[cfg(feature = "print")]
+            print_args: false,
+            #[cfg(feature = "print")]
+            print_envs: false,
+            #[cfg(feature = "print")]
+            print_current_dir: false,
+            #[cfg(feature = "print")]
+            print_status: false,
+            #[cfg(feature = "print")]
+            print_stdout: false,
+            #[cfg(feature = "print")]
+            print_stderr: false,
+        }
+    }
+
+    /// Whether these defaults report nothing
+    pub fn is_empty(&self) -> bool {
+        *self == Self::new()
+    }
+
+    /// Report everything shown at the given verbosity with every enabled backend
+    pub fn verbosity(&mut self, verbosity: Verbosity) -> &mut Self {
+        let selection = verbosity.selection();
+
+        #[cfg(feature = "log")]
+        {
+            let level = verbosity.log_level();
+            self.log_args = selection.args.then_some(level);
+            self.log_envs = selection.envs.then_some(level);
+            self.log_current_dir = selection.current_dir.then_some(level);
+            self.log_status = selection.status.then_some(level);
+            self.log_stdout = selection.stdout.then_some(level);
+            self.log_stderr = selection.stderr.then_some(level);
+        }
+
+        #[cfg(feature = "tracing")]
+        {
+            let level = verbosity.trace_level();
+            self.trace_args = selection.args.then_some(level);
+            self.trace_envs = selection.envs.then_some(level);
+            self.trace_current_dir = selection.current_dir.then_some(level);
+            self.trace_status = selection.status.then_some(level);
+            self.trace_stdout = selection.stdout.then_some(level);
+            self.trace_stderr = selection.stderr.then_some(level);
+        }
+
+        #[cfg(feature = "print")]
+        {
+            self.print_args = selection.args;
+            self.print_envs = selection.envs;
+            self.print_current_dir = selection.current_dir;
+            self.print_status = selection.status;
+            self.print_stdout = selection.stdout;
+            self.print_stderr = selection.stderr;
+        }
+
+        self
+    }
+}
+
+#[cfg(feature = "log")]
+impl Defaults {
+    pub fn log_args<L: Into<log::Level>>(&mut self, filter: L) -> &mut Self {
+        self.log_args = Some(filter.into());
+        self
+    }
+
+    pub fn log_envs<L: Into<log::Level>>(&mut self, filter: L) -> &mut Self {
+        self.log_envs = Some(filter.into());
+        self
+    }
+
+    pub fn log_current_dir<L: Into<log::Level>>(&mut self, filter: L) -> &mut Self {
+        self.log_current_dir = Some(filter.into());
+        self
+    }
+
+    pub fn log_status<L: Into<log::Level>>(&mut self, filter: L) -> &mut Self {
+        self.log_status = Some(filter.into());
+        self
+    }
+
+    pub fn log_stdout<L: Into<log::Level>>(&mut self, filter: L) -> &mut Self {
+        self.log_stdout = Some(filter.into());
+        self
+    }
+
+    pub fn log_stderr<L: Into<log::Level>>(&mut self, filter: L) -> &mut Self {
+        self.log_stderr = Some(filter.into());
+        self
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl Defaults {
+    pub fn trace_args<L: Into<tracing::Level>>(&mut self, filter: L) -> &mut Self {
+        self.trace_args = Some(filter.into());
+        self
+    }
+
+    pub fn trace_envs<L: Into<tracing::Level>>(&mut self, filter: L) -> &mut Self {
+        self.trace_envs = Some(filter.into());
+        self
+    }
+
+    pub fn trace_current_dir<L: Into<tracing::Level>>(&mut self, filter: L) -> &mut Self {
+        self.trace_current_dir = Some(filter.into());
+        self
+    }
+
+    pub fn trace_status<L: Into<tracing::Level>>(&mut self, filter: L) -> &mut Self {
+        self.trace_status = Some(filter.into());
+        self
+    }
+
+    pub fn trace_stdout<L: Into<tracing::Level>>(&mut self, filter: L) -> &mut Self {
+        self.trace_stdout = Some(filter.into());
+        self
+    }
+
+    pub fn trace_stderr<L: Into<tracing::Level>>(&mut self, filter: L) -> &mut Self {
+        self.trace_stderr = Some(filter.into());
+        self
+    }
+}
+
+#[cfg(feature = "print")]
+impl Defaults {
+    pub fn print_args(&mut self) -> &mut Self {
+        self.print_args = true;
+        self
+    }
+
+    pub fn print_envs(&mut self) -> &mut Self {
+        self.print_envs = true;
+        self
+    }
+
+    pub fn print_current_dir(&mut self) -> &mut Self {
+        self.print_current_dir = true;
+        self
+    }
+
+    pub fn print_status(&mut self) -> &mut Self {
+        self.print_status = true;
+        self
+    }
+
+    pub fn print_stdout(&mut self) -> &mut Self {
+        self.print_stdout = true;
+        self
+    }
+
+    pub fn print_stderr(&mut self) -> &mut Self {
+        self.print_stderr = true;
+        self
+    }
+}
+
+static DEFAULTS: RwLock<Defaults> = RwLock::new(Defaults::new());
+
+/// Install ambient defaults, replacing any previously installed defaults
+pub fn set<F>(f: F)
+where
+    F: FnOnce(&mut Defaults) -> &mut Defaults,
+{
+    let mut defaults = Defaults::new();
+    f(&mut defaults);
+    *DEFAULTS.write().unwrap_or_else(|e| e.into_inner()) = defaults;
+}
+
+/// The currently installed ambient defaults, or the reporting settings of the profile
+/// selected by the environment
+pub fn get() -> Defaults {
+    crate::profile::active()
+        .map(|p| *p.reporting_defaults())
+        .unwrap_or_else(|| *DEFAULTS.read().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// Remove all ambient defaults
+pub fn reset() {
+    set(|d| d);
+}
+
+/// Execute `command` for its output, reporting it with the given defaults
+pub fn output_with(command: &mut Command, defaults: &Defaults) -> IoResult<Output> {
+    output_reported(command, defaults, Command::output)
+}
+
+/// Execute `command` for its output with `execute`, reporting it with the given defaults
+pub(crate) fn output_reported<F>(
+    command: &mut Command,
+    defaults: &Defaults,
+    execute: F,
+) -> IoResult<Output>
+where
+    F: FnOnce(&mut Command) -> IoResult<Output>,
+{
+    if defaults.is_empty() {
+        return execute(command);
+    }
+
+    report_start(command, defaults);
+    let (output, info) = ExecutionInfo::measure(|| execute(&mut *command));
+    report_output(command, defaults, &output, &info);
+    output
+}
+
+/// Report that `command` is starting, with the given defaults
+pub(crate) fn report_start(command: &mut Command, defaults: &Defaults) {
+    if defaults.is_empty() {
+        return;
+    }
+
+    #[cfg(feature = "print")]
+    CommandPrint::from(&mut *command)
+        .with_defaults(defaults)
+        .on_output();
+    #[cfg(feature = "log")]
+    CommandLog::from(&mut *command)
+        .with_defaults(defaults)
+        .on_output();
+    #[cfg(feature = "tracing")]
+    CommandTrace::from(&mut *command)
+        .with_defaults(defaults)
+        .on_output();
+}
+
+/// Report the result of an execution of `command` started with [`report_start`], with the
+/// given defaults
+pub(crate) fn report_output(
+    command: &mut Command,
+    defaults: &Defaults,
+    output: &IoResult<Output>,
+    info: &ExecutionInfo,
+) {
+    if defaults.is_empty() {
+        return;
+    }
+
+    #[cfg(feature = "print")]
+    CommandPrint::from(&mut *command)
+        .with_defaults(defaults)
+        .after_output(output, info);
+    #[cfg(feature = "log")]
+    CommandLog::from(&mut *command)
+        .with_defaults(defaults)
+        .after_output(output, info);
+    #[cfg(feature = "tracing")]
+    CommandTrace::from(&mut *command)
+        .with_defaults(defaults)
+        .after_output(output, info);
+}
+
+/// Execute `command` for its output, reporting it with the ambient defaults. If a profile
+/// is selected by the environment, its environment settings are applied as well.
+pub fn output(command: &mut Command) -> IoResult<Output> {
+    match crate::profile::active() {
+        Some(profile) => profile.output(command),
+        None => output_with(command, &get()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use super::{output_with, Defaults};
+    use crate::Verbosity;
+
+    #[test]
+    #[cfg(feature = "log")]
+    fn test_set() {
+        use super::{get, reset, set};
+
+        set(|d| d.log_args(log::Level::Debug));
+        assert_eq!(get().log_args, Some(log::Level::Debug));
+        reset();
+        assert!(get().is_empty());
+    }
+
+    #[test]
+    fn test_verbosity() {
+        assert!(Defaults::new().verbosity(Verbosity::Quiet).is_empty());
+        assert!(!Defaults::new().verbosity(Verbosity::Normal).is_empty());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_output_with() -> anyhow::Result<()> {
+        let output = output_with(
+            Command::new("echo").arg("x"),
+            Defaults::new().verbosity(Verbosity::Debug),
+        )?;
+        assert_eq!(output.stdout, b"x\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,324 @@
+//! Lazily formatted views of commands, used by the reporting backends to avoid building
+//! strings for messages which are never emitted, and owned UTF-8 views for display
+
+#[cfg(any(feature = "log", feature = "tracing"))]
+use std::borrow::Cow;
+use std::{
+    ffi::OsStr,
+    fmt::{Display, Formatter, Result as FmtResult},
+    ops::Deref,
+    process::Command,
+};
+
+#[cfg(any(feature = "log", feature = "tracing"))]
+use crate::wrap::ExecutionInfo;
+use crate::CommandWrap;
+
+#[cfg(any(
+    feature = "log",
+    feature = "print",
+    feature = "tracing",
+    feature = "expr",
+    feature = "transaction"
+))]
+/// Displays the program and arguments of a command, separated by spaces
+pub(crate) struct CommandLine<'a>(pub(crate) &'a Command);
+
+#[cfg(any(
+    feature = "log",
+    feature = "print",
+    feature = "tracing",
+    feature = "expr",
+    feature = "transaction"
+))]
+impl Display for CommandLine<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self.0.get_program().to_string_lossy())?;
+        self.0
+            .get_args()
+            .try_for_each(|arg| write!(f, " {}", arg.to_string_lossy()))
+    }
+}
+
+#[cfg(any(feature = "log", feature = "tracing"))]
+/// Displays the names of the environment variables a command sets and removes, and the
+/// number and total length of its arguments, without any of their values
+pub(crate) struct EnvSummary<'a>(pub(crate) &'a Command);
+
+#[cfg(any(feature = "log", feature = "tracing"))]
+impl Display for EnvSummary<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let (set, removed): (Vec<_>, Vec<_>) = self.0.get_envs().partition(|(_, v)| v.is_some());
+        let names = |vars: Vec<(&OsStr, Option<&OsStr>)>| {
+            vars.into_iter()
+                .map(|(k, _)| k.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        write!(f, "{} envs set ({})", set.len(), names(set))?;
+        if !removed.is_empty() {
+            write!(f, ", {} removed ({})", removed.len(), names(removed))?;
+        }
+
+        let length = self.0.get_args().map(OsStr::len).sum::<usize>();
+        write!(f, ", {} args of {length} bytes", self.0.get_args().len())
+    }
+}
+
+#[cfg(any(feature = "log", feature = "tracing"))]
+/// Displays one execution of a command as `key=value` pairs on a single line, quoting
+/// values which contain whitespace or quotes
+pub(crate) struct ExecutionSummary<'a>(pub(crate) &'a Command, pub(crate) &'a ExecutionInfo);
+
+#[cfg(any(feature = "log", feature = "tracing"))]
+impl Display for ExecutionSummary<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let value = |value: &str| {
+            if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"') {
+                format!("{value:?}")
+            } else {
+                value.to_string()
+            }
+        };
+        let (command, info) = (self.0, self.1);
+        let args = command
+            .get_args()
+            .map(OsStr::to_string_lossy)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let cwd = command
+            .get_current_dir()
+            .map(|d| d.to_path_buf())
+            .or_else(|| std::env::current_dir().ok())
+            .unwrap_or_default();
+        let status = match info.status {
+            Some(status) => status
+                .code()
+                .map_or_else(|| "signaled".to_string(), |c| c.to_string()),
+            None => "none".to_string(),
+        };
+
+        write!(
+            f,
+            "command={} args={} cwd={} status={status} duration={:.3}s",
+            value(&command.get_program().to_string_lossy()),
+            value(&args),
+            value(&cwd.to_string_lossy()),
+            info.duration.as_secs_f64()
+        )?;
+        if let Some(len) = info.stdout_len {
+            write!(f, " stdout_bytes={len}")?;
+        }
+        if let Some(len) = info.stderr_len {
+            write!(f, " stderr_bytes={len}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "log", feature = "tracing"))]
+/// Keep only the first `head` and last `tail` lines of `text`, replacing the rest with a
+/// marker saying how many lines were omitted
+pub(crate) fn fold_lines(text: &str, head: usize, tail: usize) -> Cow<'_, str> {
+    let lines = text.lines().collect::<Vec<_>>();
+    if lines.len() <= head + tail {
+        return Cow::Borrowed(text);
+    }
+
+    let omitted = match lines.len() - head - tail {
+        1 => "... (1 line omitted)".to_string(),
+        n => format!("... ({n} lines omitted)"),
+    };
+    let folded = lines[..head]
+        .iter()
+        .copied()
+        .chain([omitted.as_str()])
+        .chain(lines[lines.len() - tail..].iter().copied())
+        .collect::<Vec<_>>();
+    Cow::Owned(folded.join("\n"))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// A value converted to UTF-8, and whether any invalid UTF-8 was replaced with
+/// `U+FFFD REPLACEMENT CHARACTER` while converting it
+pub struct Lossy<T> {
+    /// The converted value
+    pub value: T,
+    /// Whether the conversion replaced any invalid UTF-8
+    pub lossy: bool,
+}
+
+impl<T> Deref for Lossy<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl Display for Lossy<String> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self.value)
+    }
+}
+
+/// Convert `value` to UTF-8, recording in `lossy` if it was not valid
+fn lossy(value: &OsStr, lossy: &mut bool) -> String {
+    *lossy |= value.to_str().is_none();
+    value.to_string_lossy().into_owned()
+}
+
+/// Extension trait for [`std::process::Command`] to get its program and arguments as
+/// owned UTF-8 strings for display
+pub trait CommandExtLossy {
+    /// The arguments, converted lossily to UTF-8
+    fn get_args_lossy(&self) -> Lossy<Vec<String>>;
+
+    /// The program and arguments separated by spaces, converted lossily to UTF-8. The
+    /// arguments are not quoted, so this is for display and not for running in a shell.
+    fn get_full_command_lossy(&self) -> Lossy<String>;
+}
+
+impl CommandExtLossy for Command {
+    fn get_args_lossy(&self) -> Lossy<Vec<String>> {
+        let mut replaced = false;
+        let value = self.get_args().map(|a| lossy(a, &mut replaced)).collect();
+
+        Lossy {
+            value,
+            lossy: replaced,
+        }
+    }
+
+    fn get_full_command_lossy(&self) -> Lossy<String> {
+        let mut replaced = false;
+        let value =
+            self.get_args()
+                .fold(lossy(self.get_program(), &mut replaced), |mut line, arg| {
+                    line.push(' ');
+                    line.push_str(&lossy(arg, &mut replaced));
+                    line
+                });
+
+        Lossy {
+            value,
+            lossy: replaced,
+        }
+    }
+}
+
+impl<T> CommandExtLossy for T
+where
+    T: CommandWrap,
+{
+    fn get_args_lossy(&self) -> Lossy<Vec<String>> {
+        self.command().get_args_lossy()
+    }
+
+    fn get_full_command_lossy(&self) -> Lossy<String> {
+        self.command().get_full_command_lossy()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    #[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+    use super::CommandLine;
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    use super::{fold_lines, EnvSummary, ExecutionSummary};
+    use crate::CommandExtLossy;
+
+    #[test]
+    #[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+    fn test_command_line() {
+        let mut command = Command::new("echo");
+        assert_eq!(CommandLine(&command).to_string(), "echo");
+        command.args(["x", "y z"]);
+        assert_eq!(CommandLine(&command).to_string(), "echo x y z");
+    }
+
+    #[test]
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn test_env_summary() {
+        let mut command = Command::new("echo");
+        command.args(["x", "y z"]).env("B", "secret").env("A", "1");
+        assert_eq!(
+            EnvSummary(&command).to_string(),
+            "2 envs set (A, B), 2 args of 4 bytes"
+        );
+        command.env_remove("C");
+        assert_eq!(
+            EnvSummary(&command).to_string(),
+            "2 envs set (A, B), 1 removed (C), 2 args of 4 bytes"
+        );
+    }
+
+    #[test]
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn test_execution_summary() {
+        use std::time::{Duration, SystemTime};
+
+        let mut command = Command::new("git");
+        command.args(["log", "--oneline"]).current_dir("/tmp");
+        let mut info = crate::wrap::ExecutionInfo {
+            started_at: SystemTime::now(),
+            duration: Duration::from_millis(1200),
+            pid: None,
+            status: None,
+            stdout_len: Some(123),
+            stderr_len: Some(0),
+        };
+        assert_eq!(
+            ExecutionSummary(&command, &info).to_string(),
+            "command=git args=\"log --oneline\" cwd=/tmp status=none duration=1.200s \
+             stdout_bytes=123 stderr_bytes=0"
+        );
+
+        info.stdout_len = None;
+        info.stderr_len = None;
+        command.args(["--format", "%H"]);
+        info.status = std::process::Command::new("true").status().ok();
+        let summary = ExecutionSummary(&command, &info).to_string();
+        assert!(summary.contains(" status=0 duration=1.200s"), "{summary}");
+        assert!(summary.ends_with('s'), "{summary}");
+    }
+
+    #[test]
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn test_fold_lines() {
+        let text = (1..=10)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(fold_lines(&text, 2, 1), "1\n2\n... (7 lines omitted)\n10");
+        assert_eq!(fold_lines(&text, 0, 0), "... (10 lines omitted)");
+        assert_eq!(
+            fold_lines(&text, 9, 0),
+            "1\n2\n3\n4\n5\n6\n7\n8\n9\n... (1 line omitted)"
+        );
+        assert_eq!(fold_lines(&text, 5, 5), text);
+    }
+
+    #[test]
+    fn test_lossy() {
+        let mut command = Command::new("echo");
+        command.args(["x", "y z"]);
+        assert_eq!(*command.get_args_lossy(), ["x", "y z"]);
+        assert_eq!(command.get_full_command_lossy().to_string(), "echo x y z");
+        assert!(!command.get_full_command_lossy().lossy);
+
+        #[cfg(unix)]
+        {
+            use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+            command.arg(OsStr::from_bytes(b"a\xffb"));
+            let args = command.get_args_lossy();
+            assert!(args.lossy);
+            assert_eq!(args[2], "a\u{fffd}b");
+            assert!(command.get_full_command_lossy().lossy);
+        }
+    }
+}
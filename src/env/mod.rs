@@ -0,0 +1,319 @@
+//! Extension trait to layer environment variables from several sources and trace where
+//! each variable came from
+//!
+//! Environment variables reach a command from the inherited environment, profiles, env
+//! files and explicit calls, and a later source silently replaces an earlier one. A
+//! [`CommandEnv`] applies layers in order, remembers the source of every variable, and logs
+//! each override at debug level, so the effective environment and the reason for each value
+//! can be inspected with [`CommandEnv::effective_env_with_sources`].
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::{env::temp_dir, ffi::OsStr, fs::write, process::Command};
+//! # use command_ext::{env::EnvSource, CommandExtEnv, CommandWrap};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! # let path = temp_dir().join(format!("command-ext-doc-{}.env", std::process::id()));
+//! write(&path, "# settings\nMODE=debug\nexport NAME=\"app\"\n")?;
+//!
+//! let mut command = Command::new("printenv");
+//! let mut env = command.layered_env();
+//! env.layer("defaults", [("MODE", "release"), ("JOBS", "4")])
+//!     .env_file(&path)?
+//!     .env("JOBS", "8");
+//!
+//! let effective = env.effective_env_with_sources();
+//! assert_eq!(effective[OsStr::new("MODE")], ("debug".into(), EnvSource::EnvFile(path.clone())));
+//! assert_eq!(effective[OsStr::new("JOBS")], ("8".into(), EnvSource::Explicit));
+//! # std::fs::remove_file(path)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    env::vars_os,
+    ffi::{OsStr, OsString},
+    fmt::{Display, Formatter, Result as FmtResult},
+    fs::read_to_string,
+    io::{Error, ErrorKind, Result as IoResult},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{wrap::HasCommand, CommandWrap};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Where the value of an environment variable came from
+pub enum EnvSource {
+    /// Inherited from the environment of the current process
+    Inherited,
+    /// Set by a named layer
+    Layer(String),
+    /// Read from an env file
+    EnvFile(PathBuf),
+    /// Set by a call to `env`, `envs` or `env_remove`
+    Explicit,
+}
+
+impl Display for EnvSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Inherited => write!(f, "the inherited environment"),
+            Self::Layer(name) => write!(f, "layer {name}"),
+            Self::EnvFile(path) => write!(f, "env file {}", path.display()),
+            Self::Explicit => write!(f, "an explicit call"),
+        }
+    }
+}
+
+fn debug(message: String) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!("{message}");
+    #[cfg(all(feature = "log", not(feature = "tracing")))]
+    log::debug!("{message}");
+    #[cfg(not(any(feature = "log", feature = "tracing")))]
+    drop(message);
+}
+
+/// Parse the `KEY=VALUE` lines of an env file. Blank lines, `#` comments and an `export `
+/// prefix are ignored, and values may be wrapped in single or double quotes.
+pub fn parse_env_file(contents: &str) -> IoResult<Vec<(String, String)>> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (key, value) = line.split_once('=').ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("line {number} of env file is not KEY=VALUE"),
+                )
+            })?;
+            let value = value.trim();
+            let value = ['"', '\'']
+                .iter()
+                .find_map(|q| value.strip_prefix(*q)?.strip_suffix(*q))
+                .unwrap_or(value);
+
+            Ok((key.trim().to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+/// A command whose environment is built from ordered layers with tracked sources
+pub struct CommandEnv<'a> {
+    command: &'a mut Command,
+    cleared: bool,
+    sources: BTreeMap<OsString, (Option<OsString>, EnvSource)>,
+}
+
+impl<'a> CommandEnv<'a> {
+    fn record(&mut self, key: &OsStr, value: Option<&OsStr>, source: EnvSource) {
+        if let Some((old, old_source)) = self.sources.get(key) {
+            if old.as_deref() != value {
+                debug(format!(
+                    "env {} from {source} overrides the value from {old_source}",
+                    key.to_string_lossy()
+                ));
+            }
+        }
+
+        match value {
+            Some(value) => self.command.env(key, value),
+            None => self.command.env_remove(key),
+        };
+        self.sources
+            .insert(key.to_owned(), (value.map(OsStr::to_owned), source));
+    }
+
+    /// Apply a named layer of variables, overriding the layers applied before it
+    pub fn layer<S, I, K, V>(&mut self, name: S, vars: I) -> &mut Self
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        let source = EnvSource::Layer(name.into());
+        vars.into_iter()
+            .for_each(|(k, v)| self.record(k.as_ref(), Some(v.as_ref()), source.clone()));
+        self
+    }
+
+    /// Apply the variables of the env file at `path`, overriding the layers applied before it
+    pub fn env_file<P: AsRef<Path>>(&mut self, path: P) -> IoResult<&mut Self> {
+        let path = path.as_ref();
+        let source = EnvSource::EnvFile(path.to_path_buf());
+        parse_env_file(&read_to_string(path)?)?
+            .into_iter()
+            .for_each(|(k, v)| self.record(k.as_ref(), Some(v.as_ref()), source.clone()));
+        Ok(self)
+    }
+
+    #[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+    /// Apply the environment settings of the profile `name`, overriding the layers applied
+    /// before it
+    pub fn profile(&mut self, name: &str) -> IoResult<&mut Self> {
+        let profile = crate::profile::get(name).ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("no profile named {name} is defined"),
+            )
+        })?;
+        let source = EnvSource::Layer(format!("profile {name}"));
+        profile
+            .envs()
+            .iter()
+            .for_each(|(k, v)| self.record(k, v.as_deref(), source.clone()));
+        Ok(self)
+    }
+
+    /// The source of the current value of `key`
+    pub fn source<K: AsRef<OsStr>>(&self, key: K) -> Option<EnvSource> {
+        let key = key.as_ref();
+        match self.sources.get(key) {
+            Some((Some(_), source)) => Some(source.clone()),
+            Some((None, _)) => None,
+            None => {
+                (!self.cleared && vars_os().any(|(k, _)| k == key)).then_some(EnvSource::Inherited)
+            }
+        }
+    }
+
+    /// The environment the command will run with, and the source of each variable
+    pub fn effective_env_with_sources(&self) -> BTreeMap<OsString, (OsString, EnvSource)> {
+        let mut env = match self.cleared {
+            true => BTreeMap::new(),
+            false => vars_os()
+                .map(|(k, v)| (k, (v, EnvSource::Inherited)))
+                .collect(),
+        };
+
+        self.sources.iter().for_each(|(k, (v, source))| match v {
+            Some(v) => {
+                env.insert(k.clone(), (v.clone(), source.clone()));
+            }
+            None => {
+                env.remove(k);
+            }
+        });
+
+        env
+    }
+}
+
+impl<'a> HasCommand for CommandEnv<'a> {
+    fn command(&self) -> &Command {
+        self.command
+    }
+
+    fn command_mut(&mut self) -> &mut Command {
+        self.command
+    }
+}
+
+impl<'a> CommandWrap for CommandEnv<'a> {
+    fn env<K, V>(&mut self, key: K, val: V) -> &mut Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.record(key.as_ref(), Some(val.as_ref()), EnvSource::Explicit);
+        self
+    }
+
+    fn envs<I, K, V>(&mut self, vars: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        vars.into_iter()
+            .for_each(|(k, v)| self.record(k.as_ref(), Some(v.as_ref()), EnvSource::Explicit));
+        self
+    }
+
+    fn env_remove<K: AsRef<OsStr>>(&mut self, key: K) -> &mut Self {
+        self.record(key.as_ref(), None, EnvSource::Explicit);
+        self
+    }
+
+    fn env_clear(&mut self) -> &mut Self {
+        self.command.env_clear();
+        self.cleared = true;
+        self.sources.clear();
+        self
+    }
+}
+
+impl<'a> From<&'a mut Command> for CommandEnv<'a> {
+    /// Wrap `value`, treating the environment changes already made to it as explicit
+    fn from(value: &'a mut Command) -> Self {
+        let sources = value
+            .get_envs()
+            .map(|(k, v)| (k.to_owned(), (v.map(OsStr::to_owned), EnvSource::Explicit)))
+            .collect();
+
+        Self {
+            command: value,
+            cleared: false,
+            sources,
+        }
+    }
+}
+
+pub trait CommandExtEnv {
+    fn layered_env(&mut self) -> CommandEnv<'_>;
+}
+
+impl CommandExtEnv for Command {
+    fn layered_env(&mut self) -> CommandEnv<'_> {
+        CommandEnv::from(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use super::{parse_env_file, EnvSource};
+    use crate::{CommandExtEnv, CommandWrap};
+
+    #[test]
+    fn test_parse_env_file() {
+        let vars = parse_env_file("A=1\n\n# comment\nexport B = 'two words'\nC=\"\"\n").unwrap();
+        assert_eq!(
+            vars,
+            [
+                ("A".to_string(), "1".to_string()),
+                ("B".to_string(), "two words".to_string()),
+                ("C".to_string(), String::new())
+            ]
+        );
+        assert!(parse_env_file("A").is_err());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_layers() -> anyhow::Result<()> {
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo $A $B"]).env("A", "explicit");
+        let mut env = command.layered_env();
+        env.layer("base", [("A", "base"), ("B", "base")])
+            .env_remove("PATH");
+
+        assert_eq!(env.source("A"), Some(EnvSource::Layer("base".to_string())));
+        assert_eq!(env.source("PATH"), None);
+        assert!(!env
+            .effective_env_with_sources()
+            .contains_key(std::ffi::OsStr::new("PATH")));
+
+        env.env("PATH", "/bin:/usr/bin");
+        assert_eq!(env.output()?.stdout, b"base base\n");
+        Ok(())
+    }
+}
@@ -1,16 +1,702 @@
-use std::process::ExitStatus;
+use std::{
+    error::Error as StdError,
+    fmt::{Display, Formatter, Result as FmtResult},
+    io::{Error, ErrorKind},
+    path::PathBuf,
+    process::{ExitCode, ExitStatus, Termination},
+    time::Duration,
+};
 
-use thiserror::Error;
-
-#[derive(Error, Debug)]
+#[derive(Debug)]
+#[non_exhaustive]
 /// An error when checking the result of a command
+///
+/// The enum and its variants are non-exhaustive, so that variants and fields can be added
+/// without breaking matches on them outside this crate.
 pub enum CommandExtError {
-    #[error("Command failed with status ({status}), stdout ({stdout}), stderr ({stderr})")]
+    #[non_exhaustive]
     Check {
         status: ExitStatus,
         stdout: String,
         stderr: String,
     },
-    #[error(transparent)]
-    StdIoError(#[from] std::io::Error),
+    #[non_exhaustive]
+    Skipped {
+        reason: String,
+    },
+    #[non_exhaustive]
+    UnknownCommand {
+        name: String,
+    },
+    #[non_exhaustive]
+    UnknownProfile {
+        name: String,
+    },
+    #[non_exhaustive]
+    SnapshotMismatch {
+        path: PathBuf,
+        diff: String,
+    },
+    #[non_exhaustive]
+    OutputMatched {
+        pattern: String,
+        line: String,
+        killed: bool,
+        stdout: String,
+        stderr: String,
+    },
+    #[non_exhaustive]
+    Inactive {
+        silence: Duration,
+        last_line: Option<String>,
+        stdout: String,
+        stderr: String,
+    },
+    #[non_exhaustive]
+    NotHonored {
+        variable: String,
+        value: String,
+        reason: String,
+    },
+    #[non_exhaustive]
+    AlreadyExecuted {
+        program: String,
+        executions: usize,
+    },
+    #[non_exhaustive]
+    InsufficientSpace {
+        path: PathBuf,
+        available: u64,
+        required: u64,
+    },
+    #[non_exhaustive]
+    ToolsUnavailable {
+        report: String,
+    },
+    #[non_exhaustive]
+    InvalidCommandLine {
+        line: String,
+        reason: String,
+    },
+    #[non_exhaustive]
+    TimedOut {
+        timeout: Duration,
+        stdout: String,
+        stderr: String,
+    },
+    #[non_exhaustive]
+    UnexpectedStderr {
+        stderr: String,
+    },
+    #[non_exhaustive]
+    ParseFailed {
+        format: String,
+        reason: String,
+    },
+    #[non_exhaustive]
+    NoMatch {
+        pattern: String,
+    },
+    #[non_exhaustive]
+    CircuitOpen {
+        key: String,
+        failures: u32,
+        remaining: Duration,
+        trial: bool,
+    },
+    #[non_exhaustive]
+    HookPanicked {
+        hook: String,
+        message: String,
+    },
+    #[non_exhaustive]
+    CwdNotFound {
+        path: PathBuf,
+    },
+    #[non_exhaustive]
+    ProgramIsDirectory {
+        path: PathBuf,
+    },
+    #[non_exhaustive]
+    RetryRefused {
+        program: String,
+        reason: String,
+        source: Box<CommandExtError>,
+    },
+    #[non_exhaustive]
+    Context {
+        context: String,
+        source: Box<CommandExtError>,
+    },
+    #[non_exhaustive]
+    RolledBack {
+        failed: String,
+        rollbacks: Vec<(String, Result<(), String>)>,
+        source: Box<CommandExtError>,
+    },
+    #[non_exhaustive]
+    ExitCode {
+        code: u8,
+        source: Box<CommandExtError>,
+    },
+    StdIoError(std::io::Error),
+}
+
+impl Display for CommandExtError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Check {
+                status,
+                stdout,
+                stderr,
+            } => write!(
+                f,
+                "Command failed with status ({status}), stdout ({stdout}), stderr ({stderr})"
+            ),
+            Self::Skipped { reason } => write!(f, "Command skipped: {reason}"),
+            Self::UnknownCommand { name } => write!(f, "No command named {name} is registered"),
+            Self::UnknownProfile { name } => write!(f, "No profile named {name} is defined"),
+            Self::SnapshotMismatch { path, diff } => write!(
+                f,
+                "Output does not match snapshot {} (set COMMAND_EXT_UPDATE_SNAPSHOTS=1 to update it):\n{diff}",
+                path.display()
+            ),
+            Self::OutputMatched {
+                pattern,
+                line,
+                killed,
+                ..
+            } => write!(
+                f,
+                "Command output matched {pattern}{}: {line}",
+                if *killed { " and was killed" } else { "" }
+            ),
+            Self::Inactive {
+                silence, last_line, ..
+            } => {
+                write!(f, "Command produced no output for {silence:?}")?;
+                match last_line {
+                    Some(l) => write!(f, ", last output: {l}"),
+                    None => Ok(()),
+                }
+            }
+            Self::NotHonored {
+                variable,
+                value,
+                reason,
+            } => write!(f, "Command did not honor {variable}={value}: {reason}"),
+            Self::AlreadyExecuted {
+                program,
+                executions,
+            } => write!(
+                f,
+                "Command {program} was marked one-shot but already ran {executions} time(s)"
+            ),
+            Self::InsufficientSpace {
+                path,
+                available,
+                required,
+            } => write!(
+                f,
+                "Not enough free space at {}: {available} bytes available, {required} required",
+                path.display()
+            ),
+            Self::ToolsUnavailable { report } => write!(
+                f,
+                "Required tools are missing or do not match their requirements:\n{report}"
+            ),
+            Self::InvalidCommandLine { line, reason } => {
+                write!(f, "Cannot split command line {line:?}: {reason}")
+            }
+            Self::TimedOut { timeout, .. } => write!(f, "Command timed out after {timeout:?}"),
+            Self::UnexpectedStderr { stderr } => {
+                write!(f, "Command succeeded but wrote to stderr ({stderr})")
+            }
+            Self::ParseFailed { format, reason } => {
+                write!(f, "Cannot parse output as {format}: {reason}")
+            }
+            Self::NoMatch { pattern } => write!(f, "No match for {pattern:?} in the output"),
+            Self::CircuitOpen {
+                key,
+                failures,
+                trial: true,
+                ..
+            } => write!(
+                f,
+                "Command {key} failed {failures} time(s) in a row and is not run while a trial invocation is running"
+            ),
+            Self::CircuitOpen {
+                key,
+                failures,
+                remaining,
+                ..
+            } => write!(
+                f,
+                "Command {key} failed {failures} time(s) in a row and is not run for another {remaining:?}"
+            ),
+            Self::HookPanicked { hook, message } => {
+                write!(f, "Wrapper hook {hook} panicked: {message}")
+            }
+            Self::CwdNotFound { path } => {
+                write!(f, "Working directory {} does not exist", path.display())
+            }
+            Self::ProgramIsDirectory { path } => {
+                write!(f, "Program {} is a directory", path.display())
+            }
+            Self::RetryRefused {
+                program,
+                reason,
+                source,
+            } => write!(f, "Command {program} was not retried because {reason}: {source}"),
+            Self::Context { context, source } => write!(f, "{context}: {source}"),
+            Self::RolledBack {
+                failed,
+                rollbacks,
+                source,
+            } => {
+                if rollbacks.is_empty() {
+                    return write!(f, "Command {failed} failed with nothing to roll back: {source}");
+                }
+
+                write!(
+                    f,
+                    "Command {failed} failed and {} commands were rolled back",
+                    rollbacks.len()
+                )?;
+                let failures = rollbacks
+                    .iter()
+                    .filter_map(|(command, result)| {
+                        result.as_ref().err().map(|e| format!("{command}: {e}"))
+                    })
+                    .collect::<Vec<_>>();
+                if !failures.is_empty() {
+                    write!(
+                        f,
+                        ", {} unsuccessfully ({})",
+                        failures.len(),
+                        failures.join("; ")
+                    )?;
+                }
+                write!(f, ": {source}")
+            }
+            Self::ExitCode { source, .. } => write!(f, "{source}"),
+            Self::StdIoError(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl StdError for CommandExtError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            // The wrapped error is displayed as part of this error, so reporting it as the
+            // source would print it twice. Its own source is this error's source instead;
+            // use [`CommandExtError::root_cause`] to reach it.
+            Self::Context { source, .. }
+            | Self::ExitCode { source, .. }
+            | Self::RetryRefused { source, .. }
+            | Self::RolledBack { source, .. } => source.source(),
+            // I/O errors are displayed in place of this error, so their source is this
+            // error's source
+            Self::StdIoError(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for CommandExtError {
+    /// Convert from an I/O error, unwrapping errors from this crate which were converted
+    /// into I/O errors, for example to be returned from [`crate::CommandWrap::output`]
+    fn from(value: Error) -> Self {
+        if !value.get_ref().is_some_and(|e| e.is::<CommandExtError>()) {
+            return Self::StdIoError(value);
+        }
+
+        match value.into_inner().map(|e| e.downcast::<CommandExtError>()) {
+            Some(Ok(e)) => *e,
+            _ => unreachable!("the inner error was checked to be a CommandExtError"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+/// The mechanism which terminated a command, or stopped it from running
+pub enum TerminationReason {
+    /// Killed by a [timeout](crate::timeout)
+    Timeout,
+    /// Killed by a [watchdog](crate::watch) which matched its output
+    Watchdog,
+    /// Killed by a [watchdog](crate::watch) because it produced no output for too long
+    Inactivity,
+    /// Not run because the [circuit breaker](crate::Runner::circuit_breaker) of its label
+    /// is open
+    CircuitBreaker,
+    /// Interrupted by `SIGINT`, as sent by ctrl-c
+    Interrupted,
+    /// Cancelled by `SIGTERM` or `SIGKILL`, as sent to stop a command explicitly
+    Cancelled,
+}
+
+impl Display for TerminationReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Timeout => write!(f, "timeout"),
+            Self::Watchdog => write!(f, "watchdog"),
+            Self::Inactivity => write!(f, "inactivity"),
+            Self::CircuitBreaker => write!(f, "circuit breaker"),
+            Self::Interrupted => write!(f, "interrupted"),
+            Self::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+/// A broad classification of a [`CommandExtError`]
+///
+/// Categories may be added as new kinds of failure are reported, so matches on it outside
+/// this crate need a wildcard arm.
+pub enum ErrorCategory {
+    /// The program or a path it needs could not be found
+    NotFound,
+    /// The program could not be executed due to permissions
+    PermissionDenied,
+    /// The command ran and exited with a failure status
+    Failed,
+    /// The command was terminated by a signal
+    Signaled,
+    /// The command was killed because it ran or stayed silent for too long
+    TimedOut,
+    /// The command was intentionally not executed
+    Skipped,
+    /// The command was misconfigured or referred to something undefined
+    Usage,
+    /// Any other I/O error while executing the command
+    Io,
+}
+
+impl CommandExtError {
+    /// Wrap this error with a description of the operation which failed
+    pub fn context<C: Into<String>>(self, context: C) -> Self {
+        Self::Context {
+            context: context.into(),
+            source: Box::new(self),
+        }
+    }
+
+    /// Attach the exit code the parent process should use if it fails because of this error
+    pub fn with_exit_code(self, code: u8) -> Self {
+        Self::ExitCode {
+            code,
+            source: Box::new(self),
+        }
+    }
+
+    /// The innermost error, with all context removed
+    pub fn root_cause(&self) -> &Self {
+        match self {
+            Self::Context { source, .. } | Self::ExitCode { source, .. } => source.root_cause(),
+            e => e,
+        }
+    }
+
+    /// The descriptions attached to this error, outermost first
+    pub fn contexts(&self) -> Vec<&str> {
+        let mut contexts = Vec::new();
+        let mut error = self;
+
+        loop {
+            match error {
+                Self::Context { context, source } => {
+                    contexts.push(context.as_str());
+                    error = source;
+                }
+                Self::ExitCode { source, .. } => error = source,
+                _ => break,
+            }
+        }
+
+        contexts
+    }
+
+    /// The mechanism which terminated the command, or stopped it from running, looking
+    /// through any context. Commands which failed on their own have none.
+    pub fn termination_reason(&self) -> Option<TerminationReason> {
+        match self {
+            Self::TimedOut { .. } => Some(TerminationReason::Timeout),
+            Self::OutputMatched { killed: true, .. } => Some(TerminationReason::Watchdog),
+            Self::Inactive { .. } => Some(TerminationReason::Inactivity),
+            Self::CircuitOpen { .. } => Some(TerminationReason::CircuitBreaker),
+            Self::Check { status, .. } => match signal(status) {
+                Some(SIGINT) => Some(TerminationReason::Interrupted),
+                Some(SIGTERM | SIGKILL) => Some(TerminationReason::Cancelled),
+                _ => None,
+            },
+            Self::Context { source, .. }
+            | Self::ExitCode { source, .. }
+            | Self::RetryRefused { source, .. }
+            | Self::RolledBack { source, .. } => source.termination_reason(),
+            _ => None,
+        }
+    }
+
+    /// The output the command wrote before it failed or was terminated, as its standard
+    /// output and error streams, looking through any context. Streams which were not
+    /// captured are empty.
+    pub fn partial_output(&self) -> Option<(&str, &str)> {
+        match self {
+            Self::Check { stdout, stderr, .. }
+            | Self::TimedOut { stdout, stderr, .. }
+            | Self::OutputMatched { stdout, stderr, .. }
+            | Self::Inactive { stdout, stderr, .. } => Some((stdout, stderr)),
+            Self::Context { source, .. }
+            | Self::ExitCode { source, .. }
+            | Self::RetryRefused { source, .. }
+            | Self::RolledBack { source, .. } => source.partial_output(),
+            _ => None,
+        }
+    }
+
+    /// Classify this error, looking through any context
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            Self::Check { status, .. } => match status.code() {
+                Some(_) => ErrorCategory::Failed,
+                None => ErrorCategory::Signaled,
+            },
+            Self::Skipped { .. } => ErrorCategory::Skipped,
+            Self::CwdNotFound { .. } | Self::ToolsUnavailable { .. } => ErrorCategory::NotFound,
+            Self::ProgramIsDirectory { .. } => ErrorCategory::PermissionDenied,
+            Self::Inactive { .. } | Self::TimedOut { .. } => ErrorCategory::TimedOut,
+            Self::InsufficientSpace { .. } | Self::HookPanicked { .. } => ErrorCategory::Io,
+            Self::SnapshotMismatch { .. }
+            | Self::OutputMatched { .. }
+            | Self::UnexpectedStderr { .. }
+            | Self::ParseFailed { .. }
+            | Self::NoMatch { .. }
+            | Self::CircuitOpen { .. } => ErrorCategory::Failed,
+            Self::UnknownCommand { .. }
+            | Self::UnknownProfile { .. }
+            | Self::NotHonored { .. }
+            | Self::AlreadyExecuted { .. }
+            | Self::InvalidCommandLine { .. } => ErrorCategory::Usage,
+            Self::Context { source, .. }
+            | Self::ExitCode { source, .. }
+            | Self::RetryRefused { source, .. }
+            | Self::RolledBack { source, .. } => source.category(),
+            Self::StdIoError(e) => match e.kind() {
+                ErrorKind::NotFound => ErrorCategory::NotFound,
+                ErrorKind::TimedOut => ErrorCategory::TimedOut,
+                ErrorKind::PermissionDenied => ErrorCategory::PermissionDenied,
+                ErrorKind::InvalidInput => ErrorCategory::Usage,
+                _ => ErrorCategory::Io,
+            },
+        }
+    }
+
+    /// A suggested exit code for the parent process when it fails because of this error.
+    ///
+    /// An explicit code attached with [`crate::CommandExtContext::fail_with_code`] takes
+    /// precedence. Otherwise, a failed command's own exit code is propagated, commands killed
+    /// by a signal map to `128 + signal`, and other errors follow shell and `sysexits.h`
+    /// conventions. The hint is never zero, so an error never exits successfully; a skipped
+    /// command maps to `EX_UNAVAILABLE`.
+    pub fn exit_code_hint(&self) -> u8 {
+        match self {
+            Self::ExitCode { code, .. } => *code,
+            Self::Context { source, .. }
+            | Self::RetryRefused { source, .. }
+            | Self::RolledBack { source, .. } => source.exit_code_hint(),
+            Self::Check { status, .. } => match status.code() {
+                Some(code) => u8::try_from(code).ok().filter(|c| *c != 0).unwrap_or(1),
+                None => signal_exit_code(status),
+            },
+            _ => match self.category() {
+                ErrorCategory::NotFound => 127,
+                ErrorCategory::PermissionDenied => 126,
+                ErrorCategory::TimedOut => 124,
+                ErrorCategory::Skipped => 69,
+                ErrorCategory::Usage => 64,
+                ErrorCategory::Io => 74,
+                ErrorCategory::Failed | ErrorCategory::Signaled => 1,
+            },
+        }
+    }
+}
+
+const SIGINT: i32 = 2;
+const SIGKILL: i32 = 9;
+const SIGTERM: i32 = 15;
+
+/// The signal which terminated a command
+#[cfg(unix)]
+fn signal(status: &ExitStatus) -> Option<i32> {
+    std::os::unix::process::ExitStatusExt::signal(status)
+}
+
+#[cfg(not(unix))]
+fn signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+fn signal_exit_code(status: &ExitStatus) -> u8 {
+    signal(status)
+        .and_then(|s| u8::try_from(128 + s).ok())
+        .unwrap_or(1)
+}
+
+impl From<CommandExtError> for Error {
+    /// Convert into an I/O error. I/O errors are unwrapped, and other errors are wrapped in an
+    /// I/O error whose kind reflects their [`ErrorCategory`].
+    fn from(value: CommandExtError) -> Self {
+        match value {
+            CommandExtError::StdIoError(e) => e,
+            e => {
+                let kind = match e.category() {
+                    ErrorCategory::NotFound => ErrorKind::NotFound,
+                    ErrorCategory::PermissionDenied => ErrorKind::PermissionDenied,
+                    ErrorCategory::TimedOut => ErrorKind::TimedOut,
+                    ErrorCategory::Skipped => ErrorKind::Unsupported,
+                    ErrorCategory::Usage => ErrorKind::InvalidInput,
+                    ErrorCategory::Failed | ErrorCategory::Signaled | ErrorCategory::Io => {
+                        ErrorKind::Other
+                    }
+                };
+                Error::new(kind, e)
+            }
+        }
+    }
+}
+
+impl From<&CommandExtError> for ExitCode {
+    fn from(value: &CommandExtError) -> Self {
+        ExitCode::from(value.exit_code_hint())
+    }
+}
+
+impl From<CommandExtError> for ExitCode {
+    fn from(value: CommandExtError) -> Self {
+        ExitCode::from(&value)
+    }
+}
+
+#[derive(Debug)]
+/// A [`Termination`] result for `main` which prints the error, if any, and exits with its
+/// [`CommandExtError::exit_code_hint`]
+///
+/// ```rust,no_run
+/// # use std::process::Command;
+/// # use command_ext::{CommandExtCheck, CommandExtError, Exit};
+/// fn run() -> Result<(), CommandExtError> {
+///     Command::new("cargo").arg("build").check()?;
+///     Ok(())
+/// }
+///
+/// fn main() -> Exit {
+///     run().into()
+/// }
+/// ```
+pub struct Exit(pub Result<(), CommandExtError>);
+
+impl From<Result<(), CommandExtError>> for Exit {
+    fn from(value: Result<(), CommandExtError>) -> Self {
+        Self(value)
+    }
+}
+
+impl Termination for Exit {
+    fn report(self) -> ExitCode {
+        match self.0 {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error: {e}");
+                ExitCode::from(&e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Error, ErrorKind};
+
+    use super::CommandExtError;
+
+    #[test]
+    fn test_into_io_error() {
+        let error = Error::from(CommandExtError::StdIoError(Error::from(
+            ErrorKind::NotFound,
+        )));
+        assert_eq!(error.kind(), ErrorKind::NotFound);
+
+        let error = Error::from(CommandExtError::UnknownCommand {
+            name: "x".to_string(),
+        });
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert_eq!(error.to_string(), "No command named x is registered");
+    }
+
+    #[test]
+    /// Test that a skipped command does not make the process exit successfully
+    fn test_skipped_exit_code() {
+        let error = CommandExtError::Skipped {
+            reason: "dry run".to_string(),
+        };
+        assert_eq!(error.exit_code_hint(), 69);
+        assert_ne!(error.context("deploy").exit_code_hint(), 0);
+    }
+
+    #[test]
+    /// Test that printing an error with its chain of sources prints each cause once
+    fn test_source_chain() {
+        use std::error::Error as StdError;
+
+        let error = CommandExtError::StdIoError(Error::other(CommandExtError::UnknownCommand {
+            name: "x".to_string(),
+        }))
+        .context("inner")
+        .with_exit_code(3)
+        .context("outer");
+
+        let mut chain = vec![error.to_string()];
+        let mut source = error.source();
+        while let Some(cause) = source {
+            chain.push(cause.to_string());
+            source = cause.source();
+        }
+
+        assert_eq!(chain, ["outer: inner: No command named x is registered"]);
+        assert!(matches!(error.root_cause(), CommandExtError::StdIoError(_)));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    /// Test that the termination reason is found through context, and only for signals
+    /// which stop a command from outside
+    fn test_termination_reason() {
+        use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+
+        use super::TerminationReason;
+
+        let signaled = |signal| CommandExtError::Check {
+            status: ExitStatus::from_raw(signal),
+            stdout: "partial".to_string(),
+            stderr: String::new(),
+        };
+        let interrupted = CommandExtError::Context {
+            context: "building".to_string(),
+            source: Box::new(signaled(2)),
+        };
+        assert_eq!(
+            interrupted.termination_reason(),
+            Some(TerminationReason::Interrupted)
+        );
+        assert_eq!(interrupted.partial_output(), Some(("partial", "")));
+        assert_eq!(
+            signaled(15).termination_reason(),
+            Some(TerminationReason::Cancelled)
+        );
+        assert_eq!(signaled(11).termination_reason(), None);
+        assert_eq!(signaled(1 << 8).termination_reason(), None);
+    }
 }
@@ -0,0 +1,227 @@
+//! Extension trait to describe what a configured command will do when it executes
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::{Command, Stdio};
+//! # use command_ext::{CommandExtExplain, CommandExtWatch};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut command = Command::new("echo");
+//! command.arg("x").env("A", "b").stdout(Stdio::piped());
+//! println!("{}", command.explain());
+//!
+//! // Wrappers describe how they change the execution
+//! println!("{}", command.kill_if_output_matches("panic").explain());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! This prints something like:
+//!
+//! ```txt
+//! program: echo (/usr/bin/echo)
+//! args: "x"
+//! env: inherited
+//!   set A="b"
+//! cwd: inherited
+//! stdio: stdin unknown, stdout unknown, stderr unknown
+//! ```
+//!
+//! A [`Command`] does not expose whether its environment was cleared or how its streams are
+//! configured, so those are reported as unknown. Explain a [`CommandSpec`] to see them:
+//!
+//! ```rust
+//! # use command_ext::{explain::StdioConfig, CommandExtExplain, CommandSpec};
+//! let mut spec = CommandSpec::new("echo");
+//! spec.arg("x").env_clear();
+//! spec.stdout = StdioConfig::Piped;
+//!
+//! let explanation = spec.explain();
+//! assert_eq!(explanation.env_cleared, Some(true));
+//! assert_eq!(explanation.stdout, StdioConfig::Piped);
+//! ```
+
+use std::{
+    env::var_os,
+    ffi::OsString,
+    fmt::{Display, Formatter, Result as FmtResult},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+pub use crate::spec::StdioConfig;
+
+use crate::{host::which_in, CommandSpec, CommandWrap};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A description of what a configured command will do when it executes
+pub struct Explanation {
+    /// The program as configured
+    pub program: OsString,
+    /// The path the program resolves to with the search path the command will use, if it
+    /// can be found
+    pub resolved: Option<PathBuf>,
+    /// The arguments, not including the program
+    pub args: Vec<OsString>,
+    /// Whether the environment is cleared instead of inherited from the current process, or
+    /// `None` if that is unknown because a [`Command`] does not expose it
+    pub env_cleared: Option<bool>,
+    /// The environment variables set (`Some`) or removed (`None`) on top of the inherited
+    /// or cleared environment
+    pub envs: Vec<(OsString, Option<OsString>)>,
+    /// The working directory, if it is set
+    pub current_dir: Option<PathBuf>,
+    /// The configuration of the standard input stream
+    pub stdin: StdioConfig,
+    /// The configuration of the standard output stream
+    pub stdout: StdioConfig,
+    /// The configuration of the standard error stream
+    pub stderr: StdioConfig,
+    /// Descriptions of the wrappers which change how the command executes
+    pub wrappers: Vec<String>,
+}
+
+impl Explanation {
+    /// Describe `command` as it is currently configured
+    pub fn new(command: &Command) -> Self {
+        let mut explanation = Self::from_spec(&CommandSpec::from(command));
+        explanation.env_cleared = None;
+        explanation
+    }
+
+    /// Describe the command `spec` builds
+    pub fn from_spec(spec: &CommandSpec) -> Self {
+        let path = match spec.envs.iter().find(|(k, _)| k == "PATH") {
+            Some((_, path)) => path.clone(),
+            None if spec.env_clear => None,
+            None => var_os("PATH"),
+        };
+        let program = spec.program.as_os_str();
+        let resolved = match &spec.current_dir {
+            Some(dir) if Path::new(program).components().count() > 1 => {
+                which_in(dir.join(program), "")
+            }
+            _ => which_in(program, path.unwrap_or_default()),
+        };
+
+        Self {
+            program: spec.program.clone(),
+            resolved,
+            args: spec.args.clone(),
+            env_cleared: Some(spec.env_clear),
+            envs: spec.envs.clone(),
+            current_dir: spec.current_dir.clone(),
+            stdin: spec.stdin.clone(),
+            stdout: spec.stdout.clone(),
+            stderr: spec.stderr.clone(),
+            wrappers: Vec::new(),
+        }
+    }
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "program: {}", self.program.to_string_lossy())?;
+        match &self.resolved {
+            Some(path) => writeln!(f, " ({})", path.display())?,
+            None => writeln!(f, " (not found)")?,
+        }
+
+        write!(f, "args:")?;
+        self.args.iter().try_for_each(|a| write!(f, " {a:?}"))?;
+        writeln!(f)?;
+
+        match self.env_cleared {
+            Some(true) => writeln!(f, "env: cleared")?,
+            Some(false) => writeln!(f, "env: inherited")?,
+            None => writeln!(f, "env: inherited unless cleared on the command")?,
+        }
+        self.envs.iter().try_for_each(|(k, v)| match v {
+            Some(v) => writeln!(f, "  set {}={v:?}", k.to_string_lossy()),
+            None => writeln!(f, "  remove {}", k.to_string_lossy()),
+        })?;
+
+        match &self.current_dir {
+            Some(dir) => writeln!(f, "cwd: {}", dir.display())?,
+            None => writeln!(f, "cwd: inherited")?,
+        }
+
+        write!(
+            f,
+            "stdio: stdin {}, stdout {}, stderr {}",
+            self.stdin, self.stdout, self.stderr
+        )?;
+
+        self.wrappers
+            .iter()
+            .try_for_each(|w| write!(f, "\nwrapper: {w}"))
+    }
+}
+
+pub trait CommandExtExplain {
+    /// Describe what the command will do when it executes
+    fn explain(&self) -> Explanation;
+}
+
+impl CommandExtExplain for Command {
+    fn explain(&self) -> Explanation {
+        Explanation::new(self)
+    }
+}
+
+impl CommandExtExplain for CommandSpec {
+    fn explain(&self) -> Explanation {
+        Explanation::from_spec(self)
+    }
+}
+
+impl<T> CommandExtExplain for T
+where
+    T: CommandWrap,
+{
+    fn explain(&self) -> Explanation {
+        let mut explanation = Explanation::new(self.command());
+        explanation.wrappers.extend(self.describe());
+        explanation
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::{Command, Stdio};
+
+    use super::StdioConfig;
+    use crate::{CommandExtExplain, CommandSpec};
+
+    #[test]
+    fn test_explain() {
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", "true"])
+            .env("A", "b")
+            .env_remove("C")
+            .current_dir("/")
+            .stdout(Stdio::null());
+        let explanation = command.explain();
+
+        assert_eq!(explanation.args, ["-c", "true"]);
+        assert_eq!(explanation.envs.len(), 2);
+        assert_eq!(explanation.env_cleared, None);
+        assert_eq!(explanation.stdout, StdioConfig::Unknown);
+        assert!(explanation.to_string().contains("  remove C\n"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_explain_cleared_path() {
+        let mut spec = CommandSpec::new("sh");
+        spec.env_clear();
+        let explanation = spec.explain();
+
+        assert_eq!(explanation.env_cleared, Some(true));
+        assert_eq!(explanation.resolved, None);
+
+        spec.env("PATH", "/bin:/usr/bin");
+        assert!(spec.explain().resolved.is_some());
+    }
+}
@@ -0,0 +1,601 @@
+//! Expressions which compose commands, pipes, redirections and checking
+//!
+//! An [`Expression`] is an owned, cloneable description of one command or a pipeline of
+//! commands, built with [`cmd!`](crate::cmd) and combined with [`Expression::pipe`].
+//! Redirections and checking are set on the expression as a whole, and nothing runs until
+//! [`Expression::run`] or [`Expression::read`] is called. Each command is stored as a
+//! [`CommandSpec`], so an already configured [`Command`] can be turned into an expression.
+//!
+//! A pipeline fails if any of its commands fails, like `set -o pipefail` in a shell, and
+//! failures are reported with the same [`CommandExtError::Check`] error as
+//! [`crate::CommandExtCheck::check`]. Like a plain command run with `check`, each command
+//! is checked for a missing working directory before it is spawned, and is reported with
+//! the ambient [defaults](crate::defaults) or the reporting and environment settings of the
+//! [profile](crate::profile) selected by the environment.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use command_ext::cmd;
+//! # fn main() -> Result<(), command_ext::CommandExtError> {
+//! let output = cmd!("echo", "a\nb\nc")
+//!     .pipe(cmd!("grep", "-v", "b"))
+//!     .stdout_capture()
+//!     .run()?;
+//! assert_eq!(output.stdout, b"a\nc\n");
+//!
+//! let status = cmd!("false").pipe(cmd!("cat")).unchecked().run()?.status;
+//! assert!(!status.success());
+//!
+//! assert_eq!(cmd!("echo", "x").read()?, "x");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    ffi::OsStr,
+    fmt::{Display, Formatter, Result as FmtResult},
+    io::{Error, ErrorKind, Read, Result as IoResult, Write},
+    path::Path,
+    process::{Child, Command, Output, Stdio},
+    thread::scope,
+};
+
+use crate::{
+    check::{check_output, preflight},
+    display::CommandLine,
+    spec::{CommandSpec, StdioConfig},
+    wrap::ExecutionInfo,
+    CommandExtError,
+};
+#[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+use crate::{defaults::Defaults, profile::Profile};
+
+/// Build an [`Expression`] running a program with arguments
+///
+/// ```rust
+/// # use command_ext::cmd;
+/// let expression = cmd!("git", "log", "--oneline");
+/// assert_eq!(expression.to_string(), "git log --oneline");
+/// ```
+#[macro_export]
+macro_rules! cmd {
+    ($program:expr $(, $arg:expr)* $(,)?) => {
+        $crate::expr::Expression::new($program)$(.arg($arg))*
+    };
+}
+
+#[derive(Debug, Clone, Default)]
+/// Where the first command of an expression reads its input from
+enum Input {
+    #[default]
+    /// Inherited from the current process
+    Inherit,
+    /// The null device
+    Null,
+    /// Bytes written to the command by the expression
+    Bytes(Vec<u8>),
+}
+
+#[derive(Debug, Default)]
+/// How the commands of an expression are reported, chosen when it runs the same way as for
+/// plain commands
+struct Reporting {
+    #[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+    profile: Option<Profile>,
+    #[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+    defaults: Defaults,
+}
+
+#[cfg_attr(
+    not(any(feature = "log", feature = "print", feature = "tracing")),
+    allow(unused_variables)
+)]
+impl Reporting {
+    /// The profile selected by the environment, or the installed defaults if there is none
+    fn ambient() -> Self {
+        #[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+        return match crate::profile::active() {
+            Some(profile) => Self {
+                defaults: *profile.reporting_defaults(),
+                profile: Some(profile),
+            },
+            None => Self {
+                profile: None,
+                defaults: crate::defaults::get(),
+            },
+        };
+        #[cfg(not(any(feature = "log", feature = "print", feature = "tracing")))]
+        return Self::default();
+    }
+
+    /// Apply the environment settings of the profile to `command`
+    fn apply(&self, command: &mut Command) {
+        #[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+        if let Some(profile) = &self.profile {
+            profile.apply(command);
+        }
+    }
+
+    /// Report that `command` is starting
+    fn start(&self, command: &mut Command) {
+        #[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+        crate::defaults::report_start(command, &self.defaults);
+    }
+
+    /// Report how `command` finished
+    fn finish(&self, command: &mut Command, output: &IoResult<Output>, info: &ExecutionInfo) {
+        #[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+        crate::defaults::report_output(command, &self.defaults, output, info);
+    }
+}
+
+#[derive(Debug, Clone)]
+/// One command, or a pipeline of commands, with the redirections and checking which apply
+/// to it as a whole. See the [module documentation](self).
+pub struct Expression {
+    /// The commands of the pipeline, from first to last
+    stages: Vec<CommandSpec>,
+    /// The input of the first command
+    stdin: Input,
+    /// The output of the last command
+    stdout: StdioConfig,
+    /// The error output of every command
+    stderr: StdioConfig,
+    /// Whether a failure is returned as an error
+    checked: bool,
+}
+
+impl Expression {
+    /// An expression running `program` with no arguments
+    pub fn new<S>(program: S) -> Self
+    where
+        S: AsRef<OsStr>,
+    {
+        Self::from(CommandSpec::new(program))
+    }
+
+    /// Add an argument to the last command of the expression
+    pub fn arg<S>(mut self, arg: S) -> Self
+    where
+        S: AsRef<OsStr>,
+    {
+        self.last().arg(arg);
+        self
+    }
+
+    /// Add arguments to the last command of the expression
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.last().args(args);
+        self
+    }
+
+    /// Set an environment variable for every command of the expression
+    pub fn env<K, V>(mut self, key: K, val: V) -> Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.stages.iter_mut().for_each(|s| {
+            s.env(&key, &val);
+        });
+        self
+    }
+
+    /// Run every command of the expression in `dir`
+    pub fn dir<P>(mut self, dir: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        self.stages.iter_mut().for_each(|s| {
+            s.current_dir(&dir);
+        });
+        self
+    }
+
+    /// Connect the output of this expression to the input of `other`. The pipeline reads
+    /// its input like this expression, writes its output like `other`, and is checked
+    /// unless either side is [unchecked](Expression::unchecked).
+    pub fn pipe(mut self, other: Expression) -> Self {
+        self.stages.extend(other.stages);
+        self.stdout = other.stdout;
+        self.stderr = other.stderr;
+        self.checked &= other.checked;
+        self
+    }
+
+    /// Write `input` to the first command
+    pub fn stdin_bytes<B>(mut self, input: B) -> Self
+    where
+        B: Into<Vec<u8>>,
+    {
+        self.stdin = Input::Bytes(input.into());
+        self
+    }
+
+    /// Connect the input of the first command to the null device
+    pub fn stdin_null(mut self) -> Self {
+        self.stdin = Input::Null;
+        self
+    }
+
+    /// Capture the output of the last command into the result of [`Expression::run`]
+    pub fn stdout_capture(mut self) -> Self {
+        self.stdout = StdioConfig::Piped;
+        self
+    }
+
+    /// Discard the output of the last command
+    pub fn stdout_null(mut self) -> Self {
+        self.stdout = StdioConfig::Null;
+        self
+    }
+
+    /// Capture the error output of every command into the result of [`Expression::run`],
+    /// in the order of the pipeline
+    pub fn stderr_capture(mut self) -> Self {
+        self.stderr = StdioConfig::Piped;
+        self
+    }
+
+    /// Discard the error output of every command
+    pub fn stderr_null(mut self) -> Self {
+        self.stderr = StdioConfig::Null;
+        self
+    }
+
+    /// Return the output of a failed expression instead of an error
+    pub fn unchecked(mut self) -> Self {
+        self.checked = false;
+        self
+    }
+
+    /// The commands of the pipeline, from first to last
+    pub fn get_stages(&self) -> &[CommandSpec] {
+        &self.stages
+    }
+
+    /// Whether a failure is returned as an error
+    pub fn get_checked(&self) -> bool {
+        self.checked
+    }
+
+    fn last(&mut self) -> &mut CommandSpec {
+        self.stages
+            .last_mut()
+            .expect("an expression has at least one command")
+    }
+
+    /// Run the expression and wait for every command to exit. The status of the result is
+    /// that of the last command which failed, or of the last command if none did. Output
+    /// which was not captured is empty.
+    pub fn run(&self) -> Result<Output, CommandExtError> {
+        let reporting = Reporting::ambient();
+        let (stages, info) = ExecutionInfo::measure(|| self.execute(&reporting));
+
+        let outputs = stages?
+            .into_iter()
+            .map(|(mut command, pid, output)| {
+                let output = Ok(output);
+                let info = ExecutionInfo {
+                    pid: Some(pid),
+                    ..info.with_output(&output)
+                };
+                reporting.finish(&mut command, &output, &info);
+                output
+            })
+            .collect::<IoResult<Vec<_>>>()?;
+
+        let status = outputs
+            .iter()
+            .rev()
+            .map(|o| o.status)
+            .find(|s| !s.success())
+            .or(outputs.last().map(|o| o.status))
+            .expect("an expression has at least one command");
+        let stderr = outputs.iter().flat_map(|o| o.stderr.clone()).collect();
+        let stdout = outputs
+            .into_iter()
+            .last()
+            .map(|o| o.stdout)
+            .unwrap_or_default();
+
+        let output = Output {
+            status,
+            stdout,
+            stderr,
+        };
+
+        if self.checked {
+            check_output(Ok(output))
+        } else {
+            Ok(output)
+        }
+    }
+
+    /// Run the expression, capturing the output of the last command, and return it as a
+    /// string with trailing newlines removed
+    pub fn read(&self) -> Result<String, CommandExtError> {
+        let output = self.clone().stdout_capture().run()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(stdout.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    /// Run every command of the pipeline, returning each command with the process ID of
+    /// its child and what it output. Only the last command has captured stdout.
+    fn execute(
+        &self,
+        reporting: &Reporting,
+    ) -> Result<Vec<(Command, u32, Output)>, CommandExtError> {
+        let (commands, mut children): (Vec<_>, Vec<_>) = self.spawn(reporting)?.into_iter().unzip();
+        let stdin = children.first_mut().and_then(|c| c.stdin.take());
+        let stdout = children.last_mut().and_then(|c| c.stdout.take());
+        let stderrs = children
+            .iter_mut()
+            .map(|c| c.stderr.take())
+            .collect::<Vec<_>>();
+
+        let streams = scope(|s| {
+            if let (Some(mut pipe), Input::Bytes(input)) = (stdin, &self.stdin) {
+                // The command may exit without reading all of its input
+                s.spawn(move || pipe.write_all(input).ok());
+            }
+
+            let stderrs = stderrs
+                .into_iter()
+                .map(|pipe| pipe.map(|pipe| s.spawn(move || read_all(pipe))))
+                .collect::<Vec<_>>();
+            let stdout = stdout.map(read_all).transpose()?.unwrap_or_default();
+
+            let stderrs = stderrs
+                .into_iter()
+                .map(|h| match h {
+                    Some(h) => h.join().expect("reading stderr does not panic"),
+                    None => Ok(Vec::new()),
+                })
+                .collect::<IoResult<Vec<_>>>()?;
+
+            Ok::<_, Error>((stdout, stderrs))
+        });
+
+        let statuses = children
+            .iter_mut()
+            .map(Child::wait)
+            .collect::<IoResult<Vec<_>>>()?;
+        let (mut stdout, stderrs) = streams?;
+
+        let last = commands.len() - 1;
+        Ok(commands
+            .into_iter()
+            .zip(children.iter().map(Child::id))
+            .zip(statuses.into_iter().zip(stderrs))
+            .enumerate()
+            .map(|(i, ((command, pid), (status, stderr)))| {
+                let stdout = if i == last {
+                    std::mem::take(&mut stdout)
+                } else {
+                    Vec::new()
+                };
+                let output = Output {
+                    status,
+                    stdout,
+                    stderr,
+                };
+                (command, pid, output)
+            })
+            .collect())
+    }
+
+    /// Spawn every command of the pipeline, connecting each to the next and reporting it as
+    /// it starts. If a command cannot be spawned, the commands spawned before it are killed.
+    fn spawn(&self, reporting: &Reporting) -> Result<Vec<(Command, Child)>, CommandExtError> {
+        let mut stages: Vec<(Command, Child)> = Vec::with_capacity(self.stages.len());
+        let kill = |stages: &mut Vec<(Command, Child)>| {
+            stages.iter_mut().for_each(|(_, c)| {
+                c.kill().ok();
+                c.wait().ok();
+            });
+        };
+
+        for (i, stage) in self.stages.iter().enumerate() {
+            let mut command = stage.to_command();
+            reporting.apply(&mut command);
+            if let Err(e) = preflight(&command) {
+                kill(&mut stages);
+                return Err(e);
+            }
+
+            self.configure(i, &mut command, stages.last_mut().map(|(_, c)| c));
+            reporting.start(&mut command);
+            let (child, info) = ExecutionInfo::measure(|| command.spawn());
+
+            match child {
+                Ok(child) => stages.push((command, child)),
+                Err(e) => {
+                    let reported = Err(Error::new(e.kind(), e.to_string()));
+                    reporting.finish(&mut command, &reported, &info);
+                    kill(&mut stages);
+                    return Err(e.into());
+                }
+            }
+        }
+
+        Ok(stages)
+    }
+
+    /// Configure the stdio of the command at `index` of the pipeline, which reads from the
+    /// output of `previous` if it is not the first
+    fn configure(&self, index: usize, command: &mut Command, previous: Option<&mut Child>) {
+        match previous.and_then(|p| p.stdout.take()) {
+            Some(pipe) => command.stdin(pipe),
+            None => command.stdin(match self.stdin {
+                Input::Inherit => Stdio::inherit(),
+                Input::Null => Stdio::null(),
+                Input::Bytes(_) => Stdio::piped(),
+            }),
+        };
+
+        if index + 1 < self.stages.len() {
+            command.stdout(Stdio::piped());
+        } else {
+            command.stdout(self.stdout.stdio().unwrap_or_else(Stdio::inherit));
+        }
+
+        command.stderr(self.stderr.stdio().unwrap_or_else(Stdio::inherit));
+    }
+}
+
+/// Read a pipe to its end. A pipe closed early by the command reads as empty.
+fn read_all<R: Read>(mut pipe: R) -> IoResult<Vec<u8>> {
+    let mut buffer = Vec::new();
+    match pipe.read_to_end(&mut buffer) {
+        Err(e) if e.kind() != ErrorKind::BrokenPipe => Err(e),
+        _ => Ok(buffer),
+    }
+}
+
+impl From<CommandSpec> for Expression {
+    fn from(spec: CommandSpec) -> Self {
+        Self {
+            stages: vec![spec],
+            stdin: Input::default(),
+            stdout: StdioConfig::Default,
+            stderr: StdioConfig::Default,
+            checked: true,
+        }
+    }
+}
+
+impl From<&Command> for Expression {
+    /// An expression running a copy of `command`. Its stdio configuration is replaced by
+    /// that of the expression.
+    fn from(command: &Command) -> Self {
+        Self::from(CommandSpec::from(command))
+    }
+}
+
+impl From<&mut Command> for Expression {
+    fn from(command: &mut Command) -> Self {
+        Self::from(&*command)
+    }
+}
+
+impl Display for Expression {
+    /// The commands of the pipeline separated by `|`
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        self.stages.iter().enumerate().try_for_each(|(i, stage)| {
+            if i > 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{}", CommandLine(&stage.to_command()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use super::Expression;
+    #[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+    use super::Reporting;
+    use crate::CommandExtError;
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a pipeline connects its commands and captures the output of the last
+    fn test_pipe() -> anyhow::Result<()> {
+        let output = cmd!("printf", "b\\na\\nc\\n")
+            .pipe(cmd!("sort"))
+            .pipe(cmd!("head", "-n", "2"))
+            .stdout_capture()
+            .run()?;
+        assert_eq!(output.stdout, b"a\nb\n");
+        assert!(output.stderr.is_empty());
+
+        let input = cmd!("tr", "a-z", "A-Z").stdin_bytes("abc").read()?;
+        assert_eq!(input, "ABC");
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that any failing command fails the pipeline unless it is unchecked
+    fn test_checked() -> anyhow::Result<()> {
+        let failed = cmd!("bash", "-c", "echo x >&2; exit 3")
+            .pipe(cmd!("cat"))
+            .stderr_capture()
+            .run();
+        match failed {
+            Err(CommandExtError::Check { status, stderr, .. }) => {
+                assert_eq!(status.code(), Some(3));
+                assert_eq!(stderr, "x\n");
+            }
+            other => panic!("Unexpected result from pipeline: {:?}", other),
+        }
+
+        let output = cmd!("true").pipe(cmd!("false")).unchecked().run()?;
+        assert_eq!(output.status.code(), Some(1));
+
+        let output = cmd!("false").pipe(cmd!("true").unchecked()).run()?;
+        assert_eq!(output.status.code(), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a missing program fails without leaving earlier commands running
+    fn test_spawn_failure() {
+        let result = cmd!("sleep", "10")
+            .pipe(cmd!("asdfasdfasdfasdfjkljkljkl"))
+            .run();
+        assert!(matches!(result, Err(CommandExtError::StdIoError(_))));
+
+        let result = cmd!("sleep", "10")
+            .pipe(cmd!("true").dir("/asdfasdfasdfasdfjkljkljkl"))
+            .run();
+        assert!(matches!(result, Err(CommandExtError::CwdNotFound { .. })));
+    }
+
+    #[test]
+    #[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+    #[cfg_attr(miri, ignore)]
+    /// Test that every command is executed with the environment settings of the profile
+    fn test_profile() -> anyhow::Result<()> {
+        crate::profile::define("test_expr_profile", |p| p.env("EXPR_VAR", "x"));
+        let reporting = Reporting {
+            profile: crate::profile::get("test_expr_profile"),
+            defaults: Default::default(),
+        };
+
+        let stages = cmd!("sh", "-c", "echo $EXPR_VAR")
+            .pipe(cmd!("sh", "-c", "cat; echo $EXPR_VAR"))
+            .stdout_capture()
+            .execute(&reporting)?;
+        assert_eq!(stages.len(), 2);
+        assert!(stages[0].2.stdout.is_empty());
+        assert_eq!(stages[1].2.stdout, b"x\nx\n");
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that configured commands become expressions, and that settings apply to all
+    fn test_from_command() -> anyhow::Result<()> {
+        let mut command = Command::new("bash");
+        command.args(["-c", "echo $X; pwd"]);
+
+        let expression = Expression::from(&command)
+            .pipe(cmd!("cat"))
+            .env("X", "y")
+            .dir("/");
+        assert_eq!(expression.to_string(), "bash -c echo $X; pwd | cat");
+        assert_eq!(expression.get_stages().len(), 2);
+        assert_eq!(expression.read()?, "y\n/");
+        Ok(())
+    }
+}
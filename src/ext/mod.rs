@@ -0,0 +1,326 @@
+//! The umbrella extension trait, providing the most common operations with one import
+//!
+//! [`CommandExt`] is implemented for [`Command`] and for every wrapper and layer which can
+//! be checked. It builds on [`CommandExtCheck`], whose `check` it does not repeat, so
+//! import both traits, or the [prelude](crate::prelude), to also call `check`.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{CommandExt, CommandExtCheck, CommandExtError};
+//! # fn main() -> Result<(), CommandExtError> {
+//! let version = Command::new("echo").arg("1.2.3").checked_stdout()?;
+//! assert_eq!(version, "1.2.3");
+//!
+//! Command::new("true").run()?;
+//! assert!(Command::new("false").check().is_err());
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(feature = "regex")]
+use std::collections::HashMap;
+use std::process::{Child, Output};
+#[cfg(feature = "timeout")]
+use std::time::Duration;
+
+#[cfg(feature = "parse")]
+use crate::parse::{self, ParseOutput};
+#[cfg(feature = "timeout")]
+use crate::timeout::CommandTimeout;
+#[cfg(feature = "regex")]
+use regex::Regex;
+
+use crate::{
+    check::{self, preflight},
+    wrap::Execute,
+    CommandExtCheck, CommandExtError,
+};
+
+/// The most common operations on a [`std::process::Command`] or any wrapper around one
+pub trait CommandExt: Execute + CommandExtCheck<Error = CommandExtError> + Sized {
+    /// Execute the command with its streams inherited, returning an error if it does not
+    /// succeed
+    fn run(&mut self) -> Result<(), CommandExtError> {
+        preflight(self.command())?;
+        let status = self.execute_status()?;
+        if status.success() {
+            return Ok(());
+        }
+
+        Err(CommandExtError::Check {
+            status,
+            stdout: String::new(),
+            stderr: String::new(),
+        })
+    }
+
+    /// Check the command and return its output stream, converted lossily to UTF-8 and with
+    /// trailing newlines removed
+    fn checked_stdout(&mut self) -> Result<String, CommandExtError> {
+        let output = CommandExtCheck::check(self)?;
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .trim_end_matches(['\n', '\r'])
+            .to_string())
+    }
+
+    /// Wait for a `child` spawned from this command and check it like
+    /// [`CommandExtCheck::check`], for commands which are spawned to be interacted with instead
+    /// of executed for their output. The rest of the child's piped output is read, its stdin
+    /// is closed, and the hooks which run after the command's output run on the result, so
+    /// a failure is the same structured error checking returns.
+    ///
+    /// ```rust
+    /// # use std::{io::Write, process::{Command, Stdio}};
+    /// # use command_ext::{CommandExt, CommandExtError};
+    /// # fn main() -> Result<(), CommandExtError> {
+    /// let mut command = Command::new("bash");
+    /// command
+    ///     .args(["-c", "read line; echo \"got $line\"; exit 3"])
+    ///     .stdin(Stdio::piped())
+    ///     .stdout(Stdio::piped());
+    /// let mut child = command.spawn()?;
+    /// writeln!(child.stdin.as_mut().unwrap(), "input")?;
+    ///
+    /// match command.wait_checked(child) {
+    ///     Err(CommandExtError::Check { stdout, .. }) => assert_eq!(stdout, "got input\n"),
+    ///     other => panic!("unexpected result: {other:?}"),
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn wait_checked(&mut self, child: Child) -> Result<Output, CommandExtError> {
+        check::wait_checked(self, child, Vec::new(), Vec::new())
+    }
+
+    /// Wait for a `child` spawned from this command and check it like
+    /// [`CommandExt::wait_checked`], where `stdout` and `stderr` are the output the caller
+    /// already read from its pipes while interacting with it. They are prepended to the rest
+    /// of its output, so the error carries everything the child wrote.
+    fn wait_checked_with(
+        &mut self,
+        child: Child,
+        stdout: &[u8],
+        stderr: &[u8],
+    ) -> Result<Output, CommandExtError> {
+        check::wait_checked(self, child, stdout.to_vec(), stderr.to_vec())
+    }
+
+    #[cfg(feature = "parse")]
+    /// Check the command and parse its output stream as `T`, such as a
+    /// [`GitStatus`](crate::parse::GitStatus) or the messages of
+    /// `cargo --message-format=json`
+    fn check_parsed<T>(&mut self) -> Result<T, CommandExtError>
+    where
+        T: ParseOutput,
+    {
+        let output = CommandExtCheck::check(self)?;
+        parse::parse(&output.stdout)
+    }
+
+    #[cfg(feature = "regex")]
+    /// Check the command and match `pattern` against its output stream, returning the
+    /// named captures of the first match which took part in it, for the common case of
+    /// running a tool to read one value from its output
+    ///
+    /// ```rust
+    /// # use std::process::Command;
+    /// # use command_ext::CommandExt;
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let pattern = regex::Regex::new(r"(?m)^version: (?P<version>\S+)$")?;
+    /// let values = Command::new("echo").arg("version: 1.2.3").extract(&pattern)?;
+    /// assert_eq!(values["version"], "1.2.3");
+    /// # Ok(())
+    /// # }
+    /// ```
+    fn extract(&mut self, pattern: &Regex) -> Result<HashMap<String, String>, CommandExtError> {
+        let output = CommandExtCheck::check(self)?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let captures = pattern
+            .captures(&stdout)
+            .ok_or_else(|| CommandExtError::NoMatch {
+                pattern: pattern.to_string(),
+            })?;
+
+        Ok(pattern
+            .capture_names()
+            .flatten()
+            .filter_map(|name| {
+                captures
+                    .name(name)
+                    .map(|m| (name.to_string(), m.as_str().to_string()))
+            })
+            .collect())
+    }
+
+    #[cfg(feature = "timeout")]
+    /// Kill the command if it runs for longer than `timeout`
+    fn with_timeout(&mut self, timeout: Duration) -> CommandTimeout<'_, Self> {
+        CommandTimeout::new(self, timeout)
+    }
+}
+
+impl<T> CommandExt for T where T: Execute + CommandExtCheck<Error = CommandExtError> {}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use crate::{CommandExt, CommandExtError};
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that importing every trait does not make `check` ambiguous
+    fn test_glob_import() {
+        use crate::*;
+
+        assert!(Command::new("true").check().is_ok());
+        assert!(Command::new("true").run().is_ok());
+    }
+
+    #[test]
+    #[cfg(all(feature = "timeout", feature = "log"))]
+    #[cfg_attr(miri, ignore)]
+    /// Test that the facade works on plain commands, wrappers and layers
+    fn test_facade() -> Result<(), CommandExtError> {
+        use std::time::Duration;
+
+        use crate::CommandExtLog;
+
+        assert_eq!(Command::new("echo").arg("x").checked_stdout()?, "x");
+        assert!(matches!(
+            Command::new("false").run(),
+            Err(CommandExtError::Check { .. })
+        ));
+
+        let mut command = Command::new("echo");
+        command.arg("logged");
+        let mut log = command.log_status(log::Level::Debug);
+        assert_eq!(log.checked_stdout()?, "logged");
+        assert_eq!(
+            log.with_timeout(Duration::from_secs(10)).checked_stdout()?,
+            "logged"
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that waiting for an interacted-with child checks it with the partial output the
+    /// caller read and the hooks of the wrapper it was spawned from
+    fn test_wait_checked() -> anyhow::Result<()> {
+        use std::{
+            io::{BufRead, BufReader, Write},
+            process::{Output, Stdio},
+        };
+
+        use crate::{CommandWrap, HasCommand};
+
+        let mut command = Command::new("bash");
+        command
+            .args([
+                "-c",
+                "read line; echo \"got $line\"; read; echo done; exit 2",
+            ])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped());
+        let mut child = command.spawn()?;
+        let mut stdin = child.stdin.take().unwrap();
+        writeln!(stdin, "x")?;
+        let mut first = String::new();
+        BufReader::new(child.stdout.as_mut().unwrap()).read_line(&mut first)?;
+        assert_eq!(first, "got x\n");
+        child.stdin = Some(stdin);
+
+        match command.wait_checked_with(child, first.as_bytes(), b"") {
+            Err(CommandExtError::Check { status, stdout, .. }) => {
+                assert_eq!(status.code(), Some(2));
+                assert_eq!(stdout, "got x\ndone\n");
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        struct Quiet<'a>(&'a mut Command);
+
+        impl HasCommand for Quiet<'_> {
+            fn command(&self) -> &Command {
+                self.0
+            }
+
+            fn command_mut(&mut self) -> &mut Command {
+                self.0
+            }
+        }
+
+        impl CommandWrap for Quiet<'_> {
+            fn on_check(&mut self, output: &Output) -> Option<CommandExtError> {
+                (!output.stdout.is_empty()).then(|| CommandExtError::Skipped {
+                    reason: "not quiet".to_string(),
+                })
+            }
+        }
+
+        impl crate::CommandExtCheck for Quiet<'_> {
+            type Error = CommandExtError;
+
+            fn check(&mut self) -> Result<Output, Self::Error> {
+                crate::check::check_wrapper(self)
+            }
+        }
+
+        let mut command = Command::new("echo");
+        command.stdout(Stdio::piped());
+        let mut quiet = Quiet(&mut command);
+        let child = CommandWrap::spawn(&mut quiet)?;
+        assert!(matches!(
+            quiet.wait_checked(child),
+            Err(CommandExtError::Skipped { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "parse")]
+    #[cfg_attr(miri, ignore)]
+    /// Test that checked output is parsed, and that output which cannot be parsed is an error
+    fn test_check_parsed() -> Result<(), CommandExtError> {
+        use crate::parse::{GitStatus, Json};
+
+        let status = Command::new("printf")
+            .arg("# branch.head main\\n? new.txt\\n")
+            .check_parsed::<GitStatus>()?;
+        assert_eq!(status.head.as_deref(), Some("main"));
+        assert_eq!(status.entries[0].path, "new.txt");
+
+        assert!(matches!(
+            Command::new("echo").arg("{").check_parsed::<Json>(),
+            Err(CommandExtError::ParseFailed { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    #[cfg_attr(miri, ignore)]
+    /// Test that named captures are extracted, and that no match is an error
+    fn test_extract() -> anyhow::Result<()> {
+        let pattern = regex::Regex::new(r"(?m)^(?P<key>\w+)=(?P<value>\w+)(?P<rest> .*)?$")?;
+        let values = Command::new("printf")
+            .arg("noise\\nname=demo\\nother=x\\n")
+            .extract(&pattern)?;
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["key"], "name");
+        assert_eq!(values["value"], "demo");
+
+        assert!(matches!(
+            Command::new("echo").extract(&pattern),
+            Err(CommandExtError::NoMatch { .. })
+        ));
+        assert!(matches!(
+            Command::new("false").extract(&pattern),
+            Err(CommandExtError::Check { .. })
+        ));
+        Ok(())
+    }
+}
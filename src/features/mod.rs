@@ -0,0 +1,131 @@
+//! Which optional features of this crate were compiled in
+//!
+//! Higher-level tools built on this crate can check for an optional subsystem at runtime,
+//! to adapt their behavior or explain why something they offer is unavailable.
+//!
+//! # Example
+//!
+//! ```rust
+//! let features = command_ext::features();
+//! if !features.enabled("tracing") {
+//!     eprintln!("built without tracing support, --trace is ignored");
+//! }
+//! println!("command-ext features: {features}");
+//! ```
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+/// Every optional feature of this crate, and whether it was compiled in. Features which only
+/// exist on some platforms count as compiled in only on those platforms.
+const FEATURES: &[(&str, bool)] = &[
+    ("core", cfg!(feature = "core")),
+    ("typed-builder", cfg!(feature = "typed-builder")),
+    ("tracing", cfg!(feature = "tracing")),
+    ("check", cfg!(feature = "check")),
+    ("log", cfg!(feature = "log")),
+    ("print", cfg!(feature = "print")),
+    ("host", cfg!(feature = "host")),
+    ("registry", cfg!(feature = "registry")),
+    ("runner", cfg!(feature = "runner")),
+    ("snapshot", cfg!(feature = "snapshot")),
+    ("fuzz", cfg!(feature = "fuzz")),
+    ("watch", cfg!(feature = "watch")),
+    ("regex", cfg!(feature = "regex")),
+    ("daemon", cfg!(feature = "daemon")),
+    ("reap", cfg!(feature = "reap")),
+    ("ci", cfg!(feature = "ci")),
+    ("section", cfg!(feature = "section")),
+    ("explain", cfg!(feature = "explain")),
+    ("validate", cfg!(feature = "validate")),
+    ("shebang", cfg!(feature = "shebang")),
+    ("script", cfg!(feature = "script")),
+    ("stdin", cfg!(feature = "stdin")),
+    ("locale", cfg!(feature = "locale")),
+    ("env", cfg!(feature = "env")),
+    ("oneshot", cfg!(feature = "oneshot")),
+    ("async", cfg!(feature = "async")),
+    ("scope", cfg!(feature = "scope")),
+    ("priority", cfg!(feature = "priority")),
+    ("space", cfg!(feature = "space")),
+    ("jobserver", cfg!(all(feature = "jobserver", unix))),
+    ("tools", cfg!(feature = "tools")),
+    ("quote", cfg!(feature = "quote")),
+    ("retry", cfg!(feature = "retry")),
+    ("timeout", cfg!(feature = "timeout")),
+    ("expr", cfg!(feature = "expr")),
+    ("parse", cfg!(feature = "parse")),
+    ("chain", cfg!(feature = "chain")),
+    ("transaction", cfg!(feature = "transaction")),
+    ("bench", cfg!(feature = "bench")),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The optional features this crate was compiled with. See [`features`].
+pub struct Features(&'static [(&'static str, bool)]);
+
+impl Features {
+    /// Whether the feature `name` was compiled in. Features this crate does not have, for
+    /// example from a newer version, are reported as not compiled in.
+    pub fn enabled(&self, name: &str) -> bool {
+        self.0.iter().any(|(n, enabled)| *n == name && *enabled)
+    }
+
+    /// The names of the features which were compiled in
+    pub fn iter_enabled(&self) -> impl Iterator<Item = &'static str> {
+        self.0.iter().filter(|(_, e)| *e).map(|(n, _)| *n)
+    }
+
+    /// The names of the features which were not compiled in
+    pub fn iter_disabled(&self) -> impl Iterator<Item = &'static str> {
+        self.0.iter().filter(|(_, e)| !*e).map(|(n, _)| *n)
+    }
+}
+
+impl Display for Features {
+    /// Each feature prefixed with `+` if it was compiled in and `-` if it was not
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        self.0
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, (name, enabled))| {
+                let separator = if i == 0 { "" } else { " " };
+                let sign = if *enabled { '+' } else { '-' };
+                write!(f, "{separator}{sign}{name}")
+            })
+    }
+}
+
+/// The optional features this crate was compiled with
+pub fn features() -> Features {
+    Features(FEATURES)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{features, FEATURES};
+
+    #[test]
+    /// Test that every feature in the manifest is reported
+    fn test_features_match_manifest() {
+        let manifest = include_str!("../../Cargo.toml");
+        let section = manifest
+            .split("[features]")
+            .nth(1)
+            .and_then(|s| s.split("\n[").next())
+            .expect("manifest has a features section");
+        let declared = section
+            .lines()
+            .filter_map(|l| l.split_once(" = "))
+            .map(|(name, _)| name)
+            .filter(|name| *name != "default")
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            declared,
+            FEATURES.iter().map(|(n, _)| *n).collect::<Vec<_>>()
+        );
+        assert_eq!(features().enabled("check"), cfg!(feature = "check"));
+        assert!(!features().enabled("sandbox"));
+        assert_eq!(features().to_string().split(' ').count(), FEATURES.len());
+    }
+}
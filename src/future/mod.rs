@@ -0,0 +1,902 @@
+//! Extension trait to execute commands from asynchronous code
+//!
+//! The futures returned here do not depend on any async runtime. The child is spawned
+//! when the future is created, and waiting for it and reading its output is done on the
+//! shared [`pool`](crate::pool), which wakes the future when the child exits. They can be
+//! awaited on tokio, async-std, smol or any other executor, so downstream code written
+//! against [`CommandExtAsync`] does not choose a runtime for its users.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{future::block_on, CommandExtAsync};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! async fn version() -> std::io::Result<String> {
+//!     let output = Command::new("echo").arg("1.0").output_async().await?;
+//!     Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+//! }
+//!
+//! // Any executor can drive the future; `block_on` is a minimal one for synchronous code
+//! assert_eq!(block_on(version())?, "1.0");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! ## Streaming output
+//!
+//! [`CommandExtAsync::stdout_lines_stream`] and [`CommandExtAsync::events_stream`] yield
+//! output line by line while the command runs, and `next` returns a future for the next
+//! item. Only a small number of items are buffered: when the consumer falls behind,
+//! reading from the child pauses, and the child blocks on its next write until the
+//! consumer catches up.
+//!
+//! This crate does not depend on `futures-core`, so the streams do not implement
+//! `futures::Stream` themselves. Their `poll_next` methods have the same signature as
+//! `futures::Stream::poll_next`, and every stream is [`Unpin`], so a newtype in the
+//! consuming crate implements the trait by forwarding to them:
+//!
+//! ```rust,ignore
+//! struct Lines(command_ext::future::LineStream);
+//!
+//! impl futures::Stream for Lines {
+//!     type Item = std::io::Result<String>;
+//!
+//!     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+//!         Pin::new(&mut self.0).poll_next(cx)
+//!     }
+//! }
+//! ```
+//!
+//! Dropping a stream kills its child. Timeouts and cancellation tokens from any runtime
+//! therefore work by dropping the stream, for example when a `select!` picks another
+//! branch or a timeout future wrapping the consumer expires.
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{future::{block_on, Event}, CommandExtAsync};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut events = Command::new("sh")
+//!     .args(["-c", "echo out; echo err >&2"])
+//!     .events_stream()?;
+//!
+//! block_on(async {
+//!     while let Some(event) = events.next().await {
+//!         match event? {
+//!             Event::Stdout(line) => println!("stdout: {line}"),
+//!             Event::Stderr(line) => println!("stderr: {line}"),
+//!             Event::Exit(status) => println!("exited: {status}"),
+//!         }
+//!     }
+//!     std::io::Result::Ok(())
+//! })?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! ## Running many commands
+//!
+//! [`join_all_checked`] and [`try_stream`] run a batch of checked commands with bounded
+//! concurrency, like [`crate::batch`] does for blocking code, resolving to every output
+//! or streaming each result as its command finishes.
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::future::{block_on, join_all_checked, try_stream};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let commands = || {
+//!     (0..4).map(|i| {
+//!         let mut command = Command::new("echo");
+//!         command.arg(i.to_string());
+//!         command
+//!     })
+//! };
+//!
+//! let outputs = block_on(join_all_checked(commands(), 2))?;
+//! assert_eq!(outputs[3].stdout, b"3\n");
+//!
+//! let mut results = try_stream(commands(), 2);
+//! block_on(async {
+//!     while let Some((index, result)) = results.next().await {
+//!         println!("command {index}: {:?}", result.map(|o| o.status));
+//!     }
+//! });
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    io::{BufRead, BufReader, Read, Result as IoResult},
+    panic::{catch_unwind, resume_unwind, AssertUnwindSafe},
+    pin::{pin, Pin},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    task::{Context, Poll, Wake, Waker},
+    thread::{current, park, Result as ThreadResult, Thread},
+    time::Duration,
+};
+
+use crate::{capture, clock, pool};
+#[cfg(feature = "check")]
+use crate::{
+    check::{check_output, preflight},
+    CommandExtError,
+};
+
+struct Shared<T> {
+    result: Option<ThreadResult<T>>,
+    waker: Option<Waker>,
+}
+
+enum State<T> {
+    Ready(Option<T>),
+    Pending(Arc<Mutex<Shared<T>>>),
+}
+
+/// A future resolved by blocking work running on the shared pool. It does not depend on
+/// any async runtime. If the work panics, the panic is resumed when the future is polled.
+pub struct CommandFuture<T> {
+    state: State<T>,
+}
+
+impl<T> CommandFuture<T> {
+    /// A future which is already resolved to `value`
+    pub fn ready(value: T) -> Self {
+        Self {
+            state: State::Ready(Some(value)),
+        }
+    }
+}
+
+// The result is never pinned, so the future can be moved even while it is polled
+impl<T> Unpin for CommandFuture<T> {}
+
+impl<T> Future for CommandFuture<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let this = self.get_mut();
+        let shared = match &mut this.state {
+            State::Ready(value) => {
+                return Poll::Ready(value.take().expect("future polled after completion"))
+            }
+            State::Pending(shared) => shared,
+        };
+
+        let mut shared = shared.lock().unwrap_or_else(|e| e.into_inner());
+        match shared.result.take() {
+            Some(Ok(value)) => {
+                drop(shared);
+                this.state = State::Ready(None);
+                Poll::Ready(value)
+            }
+            Some(Err(panic)) => resume_unwind(panic),
+            None => {
+                if !shared
+                    .waker
+                    .as_ref()
+                    .is_some_and(|w| w.will_wake(cx.waker()))
+                {
+                    shared.waker = Some(cx.waker().clone());
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Run the blocking function `f` on the shared pool, returning a future for its result
+pub fn spawn_blocking<F, T>(f: F) -> CommandFuture<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let shared = Arc::new(Mutex::new(Shared {
+        result: None,
+        waker: None,
+    }));
+
+    let completion = shared.clone();
+    pool::spawn(move || {
+        let result = catch_unwind(AssertUnwindSafe(f));
+        let mut shared = completion.lock().unwrap_or_else(|e| e.into_inner());
+        shared.result = Some(result);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+    });
+
+    CommandFuture {
+        state: State::Pending(shared),
+    }
+}
+
+/// Wait for `child` to exit without blocking the calling task
+pub fn wait(mut child: Child) -> CommandFuture<IoResult<ExitStatus>> {
+    spawn_blocking(move || child.wait())
+}
+
+/// Wakes a thread blocked in [`block_on`]
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Drive `future` to completion on the calling thread. This is a minimal executor for
+/// calling asynchronous code from synchronous code and tests, not a replacement for a
+/// runtime.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(Unpark(current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(value) => return value,
+            Poll::Pending => park(),
+        }
+    }
+}
+
+/// The number of lines or events buffered by a stream before reading from the child
+/// pauses
+pub const STREAM_CAPACITY: usize = 64;
+
+/// How often a streamed child is checked for exit once its output has ended
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+struct Queue<T> {
+    items: VecDeque<T>,
+    senders: usize,
+    receiving: bool,
+    waker: Option<Waker>,
+}
+
+/// A bounded queue between blocking producers on the pool and one asynchronous consumer
+struct Channel<T> {
+    queue: Mutex<Queue<T>>,
+    space: Condvar,
+}
+
+impl<T> Channel<T> {
+    fn lock(&self) -> MutexGuard<'_, Queue<T>> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The producing end of a [`Channel`], which blocks while the queue is full
+struct Sender<T>(Arc<Channel<T>>);
+
+impl<T> Sender<T> {
+    /// Queue `item`, returning false if the consumer is gone
+    fn send(&self, item: T) -> bool {
+        let mut queue = self.0.lock();
+        while queue.receiving && queue.items.len() >= STREAM_CAPACITY {
+            queue = self.0.space.wait(queue).unwrap_or_else(|e| e.into_inner());
+        }
+
+        if !queue.receiving {
+            return false;
+        }
+
+        queue.items.push_back(item);
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.0.lock().senders += 1;
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut queue = self.0.lock();
+        queue.senders -= 1;
+        if queue.senders == 0 {
+            if let Some(waker) = queue.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// The consuming end of a [`Channel`]
+struct Receiver<T>(Arc<Channel<T>>);
+
+impl<T> Receiver<T> {
+    fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut queue = self.0.lock();
+        if let Some(item) = queue.items.pop_front() {
+            self.0.space.notify_one();
+            return Poll::Ready(Some(item));
+        }
+
+        if queue.senders == 0 {
+            return Poll::Ready(None);
+        }
+
+        queue.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.0.lock().receiving = false;
+        self.0.space.notify_all();
+    }
+}
+
+fn bounded<T>() -> (Sender<T>, Receiver<T>) {
+    let channel = Arc::new(Channel {
+        queue: Mutex::new(Queue {
+            items: VecDeque::new(),
+            senders: 1,
+            receiving: true,
+            waker: None,
+        }),
+        space: Condvar::new(),
+    });
+
+    (Sender(channel.clone()), Receiver(channel))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Something which happened while a streamed command was running
+pub enum Event {
+    /// A line written to stdout, without its line ending
+    Stdout(String),
+    /// A line written to stderr, without its line ending
+    Stderr(String),
+    /// The command exited. This is always the last event.
+    Exit(ExitStatus),
+}
+
+/// Send each line read from `reader` as an event, stopping when the consumer is gone
+fn send_lines<R, F>(reader: R, sender: Sender<IoResult<Event>>, event: F) -> pool::Task<()>
+where
+    R: Read + Send + 'static,
+    F: Fn(String) -> Event + Send + 'static,
+{
+    pool::spawn(move || {
+        for line in BufReader::new(reader).split(b'\n') {
+            let line = line.map(|line| {
+                let line = line.strip_suffix(b"\r").unwrap_or(&line);
+                event(String::from_utf8_lossy(line).into_owned())
+            });
+            let failed = line.is_err();
+            if !sender.send(line) || failed {
+                return;
+            }
+        }
+    })
+}
+
+/// A stream of the output lines and exit of a running command. Dropping the stream kills
+/// the command if it is still running.
+pub struct EventStream {
+    events: Receiver<IoResult<Event>>,
+    child: Arc<Mutex<Child>>,
+    pid: u32,
+    done: bool,
+}
+
+impl EventStream {
+    /// Start streaming the piped output of `child`
+    fn new(mut child: Child) -> Self {
+        let (sender, events) = bounded();
+        let readers = [
+            child
+                .stdout
+                .take()
+                .map(|r| send_lines(r, sender.clone(), Event::Stdout)),
+            child
+                .stderr
+                .take()
+                .map(|r| send_lines(r, sender.clone(), Event::Stderr)),
+        ];
+
+        let pid = child.id();
+        let child = Arc::new(Mutex::new(child));
+        let waited = child.clone();
+        pool::spawn(move || {
+            readers.into_iter().flatten().for_each(|r| {
+                r.join().ok();
+            });
+
+            // Poll rather than block in `wait`, so the child stays available to `kill`
+            let clock = clock::current();
+            let status = loop {
+                match waited.lock().unwrap_or_else(|e| e.into_inner()).try_wait() {
+                    Ok(Some(status)) => break Ok(Event::Exit(status)),
+                    Ok(None) => {}
+                    Err(e) => break Err(e),
+                }
+                clock.sleep(EXIT_POLL_INTERVAL);
+            };
+            sender.send(status);
+        });
+
+        Self {
+            events,
+            child,
+            pid,
+            done: false,
+        }
+    }
+
+    /// The process ID of the command
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    /// Kill the command. Its remaining output and exit are still delivered.
+    pub fn kill(&self) -> IoResult<()> {
+        self.child.lock().unwrap_or_else(|e| e.into_inner()).kill()
+    }
+
+    /// Poll for the next event, with the same signature as `futures::Stream::poll_next`
+    pub fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<IoResult<Event>>> {
+        let this = self.get_mut();
+        let event = this.events.poll_recv(cx);
+        if let Poll::Ready(Some(Ok(Event::Exit(_))) | None) = &event {
+            this.done = true;
+        }
+        event
+    }
+
+    /// The next event, or `None` after the command has exited
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Next<'_, Self> {
+        Next(self)
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        if !self.done {
+            let mut child = self.child.lock().unwrap_or_else(|e| e.into_inner());
+            if let Ok(None) = child.try_wait() {
+                child.kill().ok();
+            }
+        }
+    }
+}
+
+/// A stream of the lines a running command writes to stdout. Dropping the stream kills
+/// the command if it is still running.
+pub struct LineStream {
+    events: EventStream,
+    status: Option<ExitStatus>,
+}
+
+impl LineStream {
+    /// The process ID of the command
+    pub fn id(&self) -> u32 {
+        self.events.id()
+    }
+
+    /// Kill the command. Lines it already wrote are still delivered.
+    pub fn kill(&self) -> IoResult<()> {
+        self.events.kill()
+    }
+
+    /// The exit status of the command, once the stream has ended
+    pub fn status(&self) -> Option<ExitStatus> {
+        self.status
+    }
+
+    /// Poll for the next line, with the same signature as `futures::Stream::poll_next`
+    pub fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<IoResult<String>>> {
+        let this = self.get_mut();
+        loop {
+            return match Pin::new(&mut this.events).poll_next(cx) {
+                Poll::Ready(Some(Ok(Event::Stdout(line)))) => Poll::Ready(Some(Ok(line))),
+                Poll::Ready(Some(Ok(Event::Stderr(_)))) => continue,
+                Poll::Ready(Some(Ok(Event::Exit(status)))) => {
+                    this.status = Some(status);
+                    Poll::Ready(None)
+                }
+                Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => Poll::Ready(None),
+                Poll::Pending => Poll::Pending,
+            };
+        }
+    }
+
+    /// The next line, or `None` after the command has exited
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Next<'_, Self> {
+        Next(self)
+    }
+}
+
+/// A future for the next item of an [`EventStream`] or [`LineStream`]
+pub struct Next<'a, S>(&'a mut S);
+
+impl Future for Next<'_, EventStream> {
+    type Output = Option<IoResult<Event>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.get_mut().0).poll_next(cx)
+    }
+}
+
+impl Future for Next<'_, LineStream> {
+    type Output = Option<IoResult<String>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.get_mut().0).poll_next(cx)
+    }
+}
+
+/// Extension trait for [`std::process::Command`] to execute it from asynchronous code.
+/// The child is spawned immediately, and the returned future resolves when it exits.
+pub trait CommandExtAsync {
+    /// Execute the command and collect its output, like [`Command::output`]. The streams
+    /// are set up as `output` sets up streams which are not configured, a null stdin and
+    /// piped stdout and stderr, and stay configured that way on the command. This replaces
+    /// any stdio configured on the command, since the standard library does not expose it;
+    /// use [`CommandExtAsync::status_async`] to keep it.
+    fn output_async(&mut self) -> CommandFuture<IoResult<Output>>;
+
+    /// Execute the command and wait for its status, like [`Command::status`]
+    fn status_async(&mut self) -> CommandFuture<IoResult<ExitStatus>>;
+
+    #[cfg(feature = "check")]
+    /// Execute and check the command, like [`crate::CommandExtCheck::check`]. The streams
+    /// are set up as for [`CommandExtAsync::output_async`].
+    fn check_async(&mut self) -> CommandFuture<Result<Output, CommandExtError>>;
+
+    /// Execute the command with stdout piped, streaming the lines it writes. Stdin and
+    /// stderr are left as they are configured.
+    fn stdout_lines_stream(&mut self) -> IoResult<LineStream>;
+
+    /// Execute the command with stdout and stderr piped, streaming the lines it writes to
+    /// each and finally its exit. Stdin is left as it is configured.
+    fn events_stream(&mut self) -> IoResult<EventStream>;
+}
+
+impl CommandExtAsync for Command {
+    fn output_async(&mut self) -> CommandFuture<IoResult<Output>> {
+        match capture::spawn_for_output(self, capture::Configured::default()) {
+            Ok(child) => spawn_blocking(move || capture::output(child)),
+            Err(e) => CommandFuture::ready(Err(e)),
+        }
+    }
+
+    fn status_async(&mut self) -> CommandFuture<IoResult<ExitStatus>> {
+        match self.spawn() {
+            Ok(child) => wait(child),
+            Err(e) => CommandFuture::ready(Err(e)),
+        }
+    }
+
+    #[cfg(feature = "check")]
+    fn check_async(&mut self) -> CommandFuture<Result<Output, CommandExtError>> {
+        if let Err(e) = preflight(self) {
+            return CommandFuture::ready(Err(e));
+        }
+
+        match capture::spawn_for_output(self, capture::Configured::default()) {
+            Ok(child) => spawn_blocking(move || check_output(capture::output(child))),
+            Err(e) => CommandFuture::ready(Err(e.into())),
+        }
+    }
+
+    fn stdout_lines_stream(&mut self) -> IoResult<LineStream> {
+        let child = self.stdout(Stdio::piped()).spawn()?;
+        Ok(LineStream {
+            events: EventStream::new(child),
+            status: None,
+        })
+    }
+
+    fn events_stream(&mut self) -> IoResult<EventStream> {
+        let child = self.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+        Ok(EventStream::new(child))
+    }
+}
+
+/// Execute and check `commands`, running at most `limit` at once, and call `f` with the
+/// index and result of each as it finishes until `f` returns false. After a command fails
+/// no more are started, but the running ones are still reported.
+#[cfg(feature = "check")]
+fn run_checked<F>(commands: Vec<Command>, limit: usize, mut f: F)
+where
+    F: FnMut(usize, Result<Output, CommandExtError>) -> bool,
+{
+    let (sender, results) = std::sync::mpsc::channel();
+    let mut commands = commands.into_iter().enumerate();
+    let mut running = 0;
+    let mut failed = false;
+
+    loop {
+        while !failed && running < limit.max(1) {
+            let Some((index, mut command)) = commands.next() else {
+                break;
+            };
+
+            match preflight(&command) {
+                Ok(()) => {
+                    let sender = sender.clone();
+                    pool::spawn(move || {
+                        sender.send((index, check_output(command.output()))).ok();
+                    });
+                    running += 1;
+                }
+                Err(e) => {
+                    failed = true;
+                    if !f(index, Err(e)) {
+                        return;
+                    }
+                }
+            }
+        }
+
+        if running == 0 {
+            return;
+        }
+
+        let Ok((index, result)) = results.recv() else {
+            return;
+        };
+        running -= 1;
+        failed |= result.is_err();
+        if !f(index, result) {
+            return;
+        }
+    }
+}
+
+/// Execute and check every command, running at most `limit` at once, and resolve to their
+/// outputs in the order the commands were given. Once a command fails no more are
+/// started, and the future resolves to the first error in that order after the running
+/// commands finish. A `limit` of zero is treated as one.
+#[cfg(feature = "check")]
+pub fn join_all_checked<I>(
+    commands: I,
+    limit: usize,
+) -> CommandFuture<Result<Vec<Output>, CommandExtError>>
+where
+    I: IntoIterator<Item = Command>,
+{
+    let commands = commands.into_iter().collect::<Vec<_>>();
+    spawn_blocking(move || {
+        let mut results = Vec::new();
+        results.resize_with(commands.len(), || None);
+        run_checked(commands, limit, |index, result| {
+            results[index] = Some(result);
+            true
+        });
+
+        // Commands which were never started because an earlier one failed have no result,
+        // and come after the failure in the order they were given
+        results
+            .into_iter()
+            .map_while(|r| r)
+            .collect::<Result<Vec<_>, _>>()
+    })
+}
+
+#[cfg(feature = "check")]
+/// A stream of the results of commands run by [`try_stream`], in the order they finish.
+/// Dropping the stream stops starting new commands.
+pub struct ResultStream {
+    results: Receiver<(usize, Result<Output, CommandExtError>)>,
+}
+
+#[cfg(feature = "check")]
+impl ResultStream {
+    /// Poll for the next result, with the same signature as `futures::Stream::poll_next`.
+    /// Each item is the index of the command in the order given and its result.
+    pub fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<(usize, Result<Output, CommandExtError>)>> {
+        self.results.poll_recv(cx)
+    }
+
+    /// The next result, or `None` after every started command has finished
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Next<'_, Self> {
+        Next(self)
+    }
+}
+
+#[cfg(feature = "check")]
+impl Future for Next<'_, ResultStream> {
+    type Output = Option<(usize, Result<Output, CommandExtError>)>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.get_mut().0).poll_next(cx)
+    }
+}
+
+/// Execute and check every command, running at most `limit` at once, and stream their
+/// results as they finish. Once a command fails no more are started. A `limit` of zero is
+/// treated as one.
+#[cfg(feature = "check")]
+pub fn try_stream<I>(commands: I, limit: usize) -> ResultStream
+where
+    I: IntoIterator<Item = Command>,
+{
+    let commands = commands.into_iter().collect::<Vec<_>>();
+    let (sender, results) = bounded();
+    pool::spawn(move || {
+        run_checked(commands, limit, |index, result| {
+            sender.send((index, result))
+        })
+    });
+    ResultStream { results }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::ErrorKind,
+        process::{Command, Stdio},
+    };
+
+    use super::{block_on, spawn_blocking, Event, EventStream, LineStream, STREAM_CAPACITY};
+    use crate::CommandExtAsync;
+
+    #[test]
+    /// Test that the streams can be polled without pinning them, as the module docs promise
+    fn test_streams_unpin() {
+        fn assert_unpin<T: Unpin>() {}
+
+        assert_unpin::<EventStream>();
+        assert_unpin::<LineStream>();
+        #[cfg(feature = "check")]
+        assert_unpin::<super::ResultStream>();
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that commands executed asynchronously behave like their blocking versions
+    fn test_async() -> anyhow::Result<()> {
+        let output = block_on(Command::new("echo").arg("x").output_async())?;
+        assert_eq!(output.stdout, b"x\n");
+
+        let status = block_on(Command::new("false").stdout(Stdio::null()).status_async())?;
+        assert!(!status.success());
+
+        let missing = block_on(Command::new("asdfasdfasdfasdfjkljkljkl").output_async());
+        assert_eq!(
+            missing.map_err(|e| e.kind()).err(),
+            Some(ErrorKind::NotFound)
+        );
+
+        #[cfg(feature = "check")]
+        assert!(block_on(Command::new("false").check_async()).is_err());
+
+        // Several children run concurrently within one task
+        let both = block_on(async {
+            let a = Command::new("sleep").arg("0.2").status_async();
+            let b = Command::new("sleep").arg("0.2").status_async();
+            (a.await, b.await)
+        });
+        assert!(both.0?.success() && both.1?.success());
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that streams deliver every line in order followed by the exit
+    fn test_streams() -> anyhow::Result<()> {
+        let mut lines = Command::new("sh")
+            .args(["-c", "echo a; echo b >&2; printf 'c\\r\\nd'; exit 3"])
+            .stderr(Stdio::null())
+            .stdout_lines_stream()?;
+        let received = block_on(async {
+            let mut received = Vec::new();
+            while let Some(line) = lines.next().await {
+                received.push(line?);
+            }
+            std::io::Result::Ok(received)
+        })?;
+        assert_eq!(received, ["a", "c", "d"]);
+        assert_eq!(lines.status().and_then(|s| s.code()), Some(3));
+
+        let mut events = Command::new("sh")
+            .args(["-c", "echo a >&2"])
+            .events_stream()?;
+        assert_eq!(
+            block_on(events.next()).transpose()?,
+            Some(Event::Stderr("a".into()))
+        );
+        assert!(matches!(block_on(events.next()), Some(Ok(Event::Exit(s))) if s.success()));
+        assert!(block_on(events.next()).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a slow consumer pauses the child, and dropping the stream kills it
+    fn test_backpressure() -> anyhow::Result<()> {
+        let mut lines = Command::new("yes").stdout_lines_stream()?;
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert_eq!(block_on(lines.next()).transpose()?.as_deref(), Some("y"));
+
+        let queued = lines.events.events.0.lock().items.len();
+        assert!(queued <= STREAM_CAPACITY, "{queued} lines queued");
+
+        let stat = format!("/proc/{}/stat", lines.id());
+        drop(lines);
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        #[cfg(target_os = "linux")]
+        assert!(std::fs::read_to_string(stat).map_or(true, |stat| stat.contains(") Z ")));
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    #[cfg(feature = "check")]
+    /// Test that batches run concurrently, keep their order and stop after a failure
+    fn test_batches() -> anyhow::Result<()> {
+        use super::{join_all_checked, try_stream};
+        use std::time::{Duration, Instant};
+
+        let sleeps = |n| {
+            (0..n).map(|i| {
+                let mut command = Command::new("sh");
+                command.args(["-c", &format!("sleep 0.2; echo {i}")]);
+                command
+            })
+        };
+
+        let start = Instant::now();
+        let outputs = block_on(join_all_checked(sleeps(4), 4))?;
+        assert!(start.elapsed() < Duration::from_millis(700));
+        assert_eq!(
+            outputs.iter().map(|o| o.stdout.clone()).collect::<Vec<_>>(),
+            [b"0\n", b"1\n", b"2\n", b"3\n"]
+        );
+
+        let failing = [
+            Command::new("false"),
+            Command::new("true"),
+            Command::new("true"),
+        ];
+        assert!(block_on(join_all_checked(failing, 1)).is_err());
+
+        let failing = [
+            Command::new("true"),
+            Command::new("false"),
+            Command::new("true"),
+        ];
+        let mut results = try_stream(failing, 1);
+        let indexes = block_on(async {
+            let mut indexes = Vec::new();
+            while let Some((index, result)) = results.next().await {
+                indexes.push((index, result.is_ok()));
+            }
+            indexes
+        });
+        assert_eq!(indexes, [(0, true), (1, false)]);
+
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "boom")]
+    /// Test that a panic in a blocking task is resumed by whoever awaits it
+    fn test_panic() {
+        block_on(spawn_blocking(|| panic!("boom")));
+    }
+}
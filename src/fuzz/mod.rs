@@ -0,0 +1,337 @@
+//! Extension trait to run a command once per generated input and collect the inputs which
+//! make it fail
+//!
+//! Each input is fed to the command on stdin, or written to a temporary file whose path is
+//! passed as the last argument. Failing inputs are minimized by rerunning the command with
+//! parts of the input removed for as long as it keeps failing.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::CommandExtFuzz;
+//! // A "parser" which crashes on any input containing a `!`
+//! let report = Command::new("bash")
+//!     .args(["-c", "! grep -q '!'"])
+//!     .fuzz()
+//!     .run(["hello", "oh no!!", "fine"]);
+//!
+//! assert_eq!(report.cases, 3);
+//! assert_eq!(report.failures.len(), 1);
+//! assert_eq!(report.failures[0].input, b"oh no!!");
+//! assert_eq!(report.failures[0].minimized, b"!");
+//! ```
+
+use std::{
+    io::{Result as IoResult, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Output, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{scratch, spawn::copy_config};
+
+/// The most times the command is rerun while minimizing a single failing input
+pub const DEFAULT_MINIMIZE_RUNS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// How each input is given to the command
+pub enum InputMode {
+    #[default]
+    /// Write the input to the command's stdin
+    Stdin,
+    /// Write the input to a temporary file and pass its path as the last argument. The file
+    /// is created in the [scratch root](crate::scratch), or [`CommandFuzz::workspace_root`].
+    File,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// An input which made the command fail
+pub struct Failure {
+    /// The generated input
+    pub input: Vec<u8>,
+    /// The smallest input found which still makes the command fail
+    pub minimized: Vec<u8>,
+    /// The status the command exited with on the generated input, if it ran
+    pub status: Option<ExitStatus>,
+    /// The error which prevented the command from running, if any
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// The result of running a command on every generated input
+pub struct FuzzReport {
+    /// The number of inputs the command was run with, not counting minimization
+    pub cases: usize,
+    /// The inputs which made the command fail, in the order they were generated
+    pub failures: Vec<Failure>,
+}
+
+type FailurePredicate = Box<dyn Fn(&Output) -> bool>;
+
+/// A command which is run once per generated input
+pub struct CommandFuzz<'a> {
+    command: &'a mut Command,
+    mode: InputMode,
+    minimize_runs: usize,
+    root: Option<PathBuf>,
+    fails: FailurePredicate,
+}
+
+impl std::fmt::Debug for CommandFuzz<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommandFuzz")
+            .field("command", &self.command)
+            .field("mode", &self.mode)
+            .field("minimize_runs", &self.minimize_runs)
+            .field("root", &self.root)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A fresh copy of the program, arguments, environment and working directory of `command`
+fn rebuild(command: &Command) -> Command {
+    let mut copy = Command::new(command.get_program());
+    copy_config(command, &mut copy);
+    copy
+}
+
+/// A temporary input file which is removed when dropped
+struct InputFile(PathBuf);
+
+impl InputFile {
+    fn new(root: Option<&Path>, input: &[u8]) -> IoResult<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let name = format!(
+            "command-ext-fuzz-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        );
+        scratch::create(root, &name, input).map(Self)
+    }
+}
+
+impl Drop for InputFile {
+    fn drop(&mut self) {
+        scratch::remove(&self.0);
+    }
+}
+
+impl<'a> CommandFuzz<'a> {
+    /// Write each input to the command's stdin. This is the default.
+    pub fn stdin(&mut self) -> &mut Self {
+        self.mode = InputMode::Stdin;
+        self
+    }
+
+    /// Write each input to a temporary file and pass its path as the last argument
+    pub fn file(&mut self) -> &mut Self {
+        self.mode = InputMode::File;
+        self
+    }
+
+    /// Decide which outputs count as failures. By default, any unsuccessful exit status does.
+    pub fn fail_when<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&Output) -> bool + 'static,
+    {
+        self.fails = Box::new(f);
+        self
+    }
+
+    /// Only count termination by a signal as a failure, ignoring ordinary error exits
+    pub fn crashes_only(&mut self) -> &mut Self {
+        self.fail_when(|output| output.status.code().is_none())
+    }
+
+    /// Rerun the command at most `runs` times while minimizing each failing input. Zero
+    /// disables minimization.
+    pub fn minimize_runs(&mut self, runs: usize) -> &mut Self {
+        self.minimize_runs = runs;
+        self
+    }
+
+    /// Write input files to `root` instead of the [scratch root](crate::scratch)
+    pub fn workspace_root<P: Into<PathBuf>>(&mut self, root: P) -> &mut Self {
+        self.root = Some(root.into());
+        self
+    }
+
+    fn execute(&self, input: &[u8]) -> IoResult<Output> {
+        let mut command = rebuild(self.command);
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+
+        match self.mode {
+            InputMode::Stdin => {
+                let mut child = command.stdin(Stdio::piped()).spawn()?;
+                if let Some(mut stdin) = child.stdin.take() {
+                    // The command may exit without reading all of its input
+                    stdin.write_all(input).ok();
+                }
+                crate::capture::output(child)
+            }
+            InputMode::File => {
+                let file = InputFile::new(self.root.as_deref(), input)?;
+                command.arg(&file.0).stdin(Stdio::null()).output()
+            }
+        }
+    }
+
+    fn fails(&self, input: &[u8]) -> bool {
+        self.execute(input)
+            .map_or(true, |output| (self.fails)(&output))
+    }
+
+    /// Remove ever smaller chunks of a failing input for as long as the command keeps failing
+    fn minimize(&self, input: &[u8]) -> Vec<u8> {
+        let mut input = input.to_vec();
+        let mut runs = 0;
+        let mut chunk = (input.len() / 2).max(1);
+
+        while !input.is_empty() && runs < self.minimize_runs {
+            let mut start = 0;
+            let mut removed = false;
+
+            while start < input.len() && runs < self.minimize_runs {
+                let end = (start + chunk).min(input.len());
+                let candidate = [&input[..start], &input[end..]].concat();
+                runs += 1;
+
+                if self.fails(&candidate) {
+                    input = candidate;
+                    removed = true;
+                } else {
+                    start = end;
+                }
+            }
+
+            if !removed {
+                if chunk == 1 {
+                    break;
+                }
+                chunk /= 2;
+            }
+        }
+
+        input
+    }
+
+    /// How each input is given to the command
+    pub fn get_input_mode(&self) -> InputMode {
+        self.mode
+    }
+
+    /// The most executions spent minimizing each failing input
+    pub fn get_minimize_runs(&self) -> usize {
+        self.minimize_runs
+    }
+
+    /// The directory input files are written to, if it is set instead of the scratch root
+    pub fn get_workspace_root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    /// Run the command once per input, minimizing the inputs which make it fail
+    pub fn run<I, T>(&mut self, inputs: I) -> FuzzReport
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<Vec<u8>>,
+    {
+        let mut report = FuzzReport::default();
+
+        inputs.into_iter().for_each(|input| {
+            let input = input.into();
+            report.cases += 1;
+
+            let (status, error) = match self.execute(&input) {
+                Ok(output) if !(self.fails)(&output) => return,
+                Ok(output) => (Some(output.status), None),
+                Err(e) => (None, Some(e.to_string())),
+            };
+
+            report.failures.push(Failure {
+                minimized: self.minimize(&input),
+                input,
+                status,
+                error,
+            });
+        });
+
+        report
+    }
+}
+
+pub trait CommandExtFuzz {
+    fn fuzz(&mut self) -> CommandFuzz<'_>;
+}
+
+impl CommandExtFuzz for Command {
+    fn fuzz(&mut self) -> CommandFuzz<'_> {
+        CommandFuzz {
+            command: self,
+            mode: InputMode::default(),
+            minimize_runs: DEFAULT_MINIMIZE_RUNS,
+            root: None,
+            fails: Box::new(|output| !output.status.success()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use crate::CommandExtFuzz;
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_file() {
+        let report = Command::new("bash")
+            .args(["-c", "! grep -q x \"$0\""])
+            .fuzz()
+            .file()
+            .run(["abc", "aaxaa"]);
+
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].minimized, b"x");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_no_minimize() {
+        let report = Command::new("false")
+            .fuzz()
+            .minimize_runs(0)
+            .run([vec![1, 2, 3]]);
+
+        assert_eq!(report.failures[0].minimized, [1, 2, 3]);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that input files are written to the workspace root and removed afterwards
+    fn test_workspace_root() -> anyhow::Result<()> {
+        let root =
+            std::env::temp_dir().join(format!("command-ext-fuzz-root-{}", std::process::id()));
+        std::fs::create_dir_all(&root)?;
+        let expected = format!("{}\n", root.display()).into_bytes();
+
+        let report = Command::new("bash")
+            .args(["-c", "dirname \"$0\""])
+            .fuzz()
+            .file()
+            .workspace_root(&root)
+            .fail_when(move |output| output.stdout == expected)
+            .minimize_runs(0)
+            .run(["a"]);
+
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(std::fs::read_dir(&root)?.count(), 0);
+        assert!(!crate::scratch::created()
+            .iter()
+            .any(|p| p.starts_with(&root)));
+        std::fs::remove_dir(&root)?;
+        Ok(())
+    }
+}
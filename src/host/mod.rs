@@ -0,0 +1,701 @@
+//! Host capability probing and platform-gated commands
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{host, CommandExtHost, Os, Outcome};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! println!("running on {} ({})", host().os(), host().arch());
+//!
+//! match Command::new("echo").arg("x").only_on(Os::Linux).run()? {
+//!     Outcome::Completed(output) => println!("{}", String::from_utf8_lossy(&output.stdout)),
+//!     Outcome::Skipped { reason } => println!("skipped: {reason}"),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    env::{consts, split_paths, var, var_os},
+    ffi::OsStr,
+    fmt::{Display, Formatter, Result as FmtResult},
+    fs::read_to_string,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Output},
+    sync::OnceLock,
+};
+
+use crate::{
+    wrap::{ExecutionInfo, HasCommand},
+    CommandWrap,
+};
+#[cfg(feature = "check")]
+use crate::{CommandExtCheck, CommandExtError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// An operating system family a command can be gated on
+pub enum Os {
+    Linux,
+    MacOs,
+    Windows,
+    FreeBsd,
+    Other,
+}
+
+impl Os {
+    /// The operating system this crate was compiled for
+    pub fn current() -> Self {
+        match consts::OS {
+            "linux" => Self::Linux,
+            "macos" => Self::MacOs,
+            "windows" => Self::Windows,
+            "freebsd" => Self::FreeBsd,
+            _ => Self::Other,
+        }
+    }
+}
+
+impl Display for Os {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Linux => write!(f, "linux"),
+            Self::MacOs => write!(f, "macos"),
+            Self::Windows => write!(f, "windows"),
+            Self::FreeBsd => write!(f, "freebsd"),
+            Self::Other => write!(f, "{}", consts::OS),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// A CPU architecture a command can be gated on
+pub enum Arch {
+    X86,
+    X86_64,
+    Arm,
+    Aarch64,
+    Other,
+}
+
+impl Arch {
+    /// The architecture this crate was compiled for
+    pub fn current() -> Self {
+        match consts::ARCH {
+            "x86" => Self::X86,
+            "x86_64" => Self::X86_64,
+            "arm" => Self::Arm,
+            "aarch64" => Self::Aarch64,
+            _ => Self::Other,
+        }
+    }
+}
+
+impl Display for Arch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::X86 => write!(f, "x86"),
+            Self::X86_64 => write!(f, "x86_64"),
+            Self::Arm => write!(f, "arm"),
+            Self::Aarch64 => write!(f, "aarch64"),
+            Self::Other => write!(f, "{}", consts::ARCH),
+        }
+    }
+}
+
+/// Shells which are probed for on the `PATH`
+const SHELLS: &[&str] = &["sh", "bash", "zsh", "fish", "pwsh", "powershell", "cmd"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Information about the host the current process is running on
+pub struct HostInfo {
+    os: Os,
+    arch: Arch,
+    container: bool,
+    wsl: bool,
+    shells: Vec<String>,
+}
+
+impl HostInfo {
+    /// Probe the current host. Prefer [`host`], which caches the result.
+    pub fn probe() -> Self {
+        Self {
+            os: Os::current(),
+            arch: Arch::current(),
+            container: detect_container(),
+            wsl: detect_wsl(),
+            shells: SHELLS
+                .iter()
+                .filter(|s| which(s).is_some())
+                .map(|s| s.to_string())
+                .collect(),
+        }
+    }
+
+    /// The operating system of the host
+    pub fn os(&self) -> Os {
+        self.os
+    }
+
+    /// The CPU architecture of the host
+    pub fn arch(&self) -> Arch {
+        self.arch
+    }
+
+    /// Whether the host appears to be a container (docker, podman, kubernetes, lxc)
+    pub fn in_container(&self) -> bool {
+        self.container
+    }
+
+    /// Whether the host is the Windows Subsystem for Linux
+    pub fn is_wsl(&self) -> bool {
+        self.wsl
+    }
+
+    /// The shells available on the `PATH`, by name
+    pub fn shells(&self) -> &[String] {
+        &self.shells
+    }
+
+    /// Whether a shell with the given name is available
+    pub fn has_shell(&self, shell: &str) -> bool {
+        self.shells.iter().any(|s| s == shell)
+    }
+
+    /// Whether a tool with the given name is available on the `PATH`. Unlike the rest of
+    /// the host information, this is not cached.
+    pub fn has_tool<S: AsRef<OsStr>>(&self, tool: S) -> bool {
+        which(tool).is_some()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+/// Who the current process runs as and where, for attributing the commands it executes on
+/// shared machines. Each part is `None` if it cannot be determined on this platform.
+pub struct Identity {
+    /// The name of the effective user
+    pub user: Option<String>,
+    /// The effective user ID, on Unix
+    pub uid: Option<u32>,
+    /// The name of the host
+    pub hostname: Option<String>,
+    /// The terminal attached to the standard streams, if any
+    pub tty: Option<String>,
+}
+
+impl Identity {
+    /// Probe the identity of the current process. Prefer [`identity`], which caches the
+    /// result.
+    pub fn probe() -> Self {
+        let uid = sys::euid();
+        Self {
+            // The environment names the user who logged in, which is not the effective user
+            // under setuid programs, so the user database is preferred
+            user: uid.and_then(user_name).or_else(|| {
+                ["USER", "LOGNAME", "USERNAME"]
+                    .iter()
+                    .find_map(|v| var(v).ok())
+            }),
+            uid,
+            hostname: sys::hostname().or_else(|| var("COMPUTERNAME").ok()),
+            tty: sys::tty(),
+        }
+    }
+}
+
+impl Display for Identity {
+    /// Displays the identity like `user@host (uid 1000) on /dev/pts/0`
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self.user.as_deref().unwrap_or("unknown"))?;
+        write!(f, "@{}", self.hostname.as_deref().unwrap_or("unknown"))?;
+        if let Some(uid) = self.uid {
+            write!(f, " (uid {uid})")?;
+        }
+        if let Some(tty) = &self.tty {
+            write!(f, " on {tty}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The name of the user with ID `uid` in the user database
+fn user_name(uid: u32) -> Option<String> {
+    read_to_string("/etc/passwd")
+        .ok()?
+        .lines()
+        .find_map(|line| {
+            let mut fields = line.split(':');
+            let name = fields.next()?;
+            (fields.nth(1)?.parse() == Ok(uid)).then(|| name.to_string())
+        })
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::{
+        ffi::{c_char, c_int, CStr},
+        os::raw::c_uint,
+    };
+
+    extern "C" {
+        fn geteuid() -> c_uint;
+        fn gethostname(name: *mut c_char, len: usize) -> c_int;
+        fn isatty(fd: c_int) -> c_int;
+        fn ttyname_r(fd: c_int, buf: *mut c_char, len: usize) -> c_int;
+    }
+
+    /// Convert a buffer filled with a nul-terminated string by a C function
+    fn string(buf: &[u8]) -> Option<String> {
+        CStr::from_bytes_until_nul(buf)
+            .ok()
+            .map(|s| s.to_string_lossy().into_owned())
+            .filter(|s| !s.is_empty())
+    }
+
+    pub(super) fn euid() -> Option<u32> {
+        // SAFETY: geteuid always succeeds and has no memory safety requirements
+        Some(unsafe { geteuid() })
+    }
+
+    pub(super) fn hostname() -> Option<String> {
+        let mut buf = [0u8; 256];
+        // SAFETY: gethostname writes at most len bytes into buf. The last byte is left
+        // zero, so the name is terminated even if it was truncated.
+        match unsafe { gethostname(buf.as_mut_ptr().cast(), buf.len() - 1) } {
+            0 => string(&buf),
+            _ => None,
+        }
+    }
+
+    pub(super) fn tty() -> Option<String> {
+        (0..3).find_map(|fd| {
+            let mut buf = [0u8; 256];
+            // SAFETY: isatty has no memory safety requirements, and ttyname_r writes at
+            // most len bytes including the terminating nul into buf
+            let found = unsafe {
+                isatty(fd) == 1 && ttyname_r(fd, buf.as_mut_ptr().cast(), buf.len()) == 0
+            };
+            found.then(|| string(&buf)).flatten()
+        })
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    pub(super) fn euid() -> Option<u32> {
+        None
+    }
+
+    pub(super) fn hostname() -> Option<String> {
+        None
+    }
+
+    pub(super) fn tty() -> Option<String> {
+        None
+    }
+}
+
+static HOST: OnceLock<HostInfo> = OnceLock::new();
+static IDENTITY: OnceLock<Identity> = OnceLock::new();
+
+/// Information about the current host, probed once on first use
+pub fn host() -> &'static HostInfo {
+    HOST.get_or_init(HostInfo::probe)
+}
+
+/// The identity of the current process, probed once on first use
+pub fn identity() -> &'static Identity {
+    IDENTITY.get_or_init(Identity::probe)
+}
+
+/// Find the full path of an executable on the `PATH`, if it exists. Paths containing a
+/// directory separator are checked directly rather than searched for.
+pub fn which<S: AsRef<OsStr>>(program: S) -> Option<PathBuf> {
+    which_in(program, var_os("PATH")?)
+}
+
+/// Find the full path of an executable on the given search path, which has the same
+/// format as the `PATH` environment variable
+pub fn which_in<S, P>(program: S, path: P) -> Option<PathBuf>
+where
+    S: AsRef<OsStr>,
+    P: AsRef<OsStr>,
+{
+    let program = Path::new(program.as_ref());
+
+    if program.components().count() > 1 {
+        return is_executable(program).then(|| program.to_path_buf());
+    }
+
+    let extensions = if cfg!(windows) {
+        var_os("PATHEXT")
+            .map(|e| {
+                e.to_string_lossy()
+                    .split(';')
+                    .filter(|e| !e.is_empty())
+                    .map(|e| e.to_string())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_else(|| vec![".EXE".to_string(), ".BAT".to_string(), ".CMD".to_string()])
+    } else {
+        Vec::new()
+    };
+
+    split_paths(&path).find_map(|dir| {
+        let candidate = dir.join(program);
+
+        if is_executable(&candidate) {
+            return Some(candidate);
+        }
+
+        extensions.iter().find_map(|ext| {
+            let mut name = candidate.clone().into_os_string();
+            name.push(ext);
+            let candidate = PathBuf::from(name);
+            is_executable(&candidate).then_some(candidate)
+        })
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+fn detect_container() -> bool {
+    Path::new("/.dockerenv").exists()
+        || Path::new("/run/.containerenv").exists()
+        || var_os("KUBERNETES_SERVICE_HOST").is_some()
+        || read_to_string("/proc/1/cgroup")
+            .map(|c| {
+                ["docker", "kubepods", "containerd", "lxc", "podman"]
+                    .iter()
+                    .any(|n| c.contains(n))
+            })
+            .unwrap_or(false)
+}
+
+fn detect_wsl() -> bool {
+    var_os("WSL_DISTRO_NAME").is_some()
+        || read_to_string("/proc/sys/kernel/osrelease")
+            .map(|r| r.to_lowercase().contains("microsoft"))
+            .unwrap_or(false)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The outcome of running a command which may be skipped on the current host
+pub enum Outcome {
+    /// The command was executed successfully
+    Completed(Output),
+    /// The command was not executed, because a guard did not match the current host
+    Skipped { reason: String },
+}
+
+impl Outcome {
+    /// Whether the command was skipped
+    pub fn is_skipped(&self) -> bool {
+        matches!(self, Self::Skipped { .. })
+    }
+
+    /// The output of the command, if it was executed
+    pub fn output(&self) -> Option<&Output> {
+        match self {
+            Self::Completed(output) => Some(output),
+            Self::Skipped { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Guard {
+    OnlyOn(Os),
+    SkipOn(Os),
+    OnlyOnArch(Arch),
+    OnlyIfTool(PathBuf),
+}
+
+impl Display for Guard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::OnlyOn(os) => write!(f, "only on {os}"),
+            Self::SkipOn(os) => write!(f, "not on {os}"),
+            Self::OnlyOnArch(arch) => write!(f, "only on {arch}"),
+            Self::OnlyIfTool(tool) => write!(f, "only if {} is available", tool.display()),
+        }
+    }
+}
+
+impl Guard {
+    /// The reason the command should be skipped on `host`, if any
+    fn skip_reason(&self, host: &HostInfo) -> Option<String> {
+        match self {
+            Self::OnlyOn(os) => {
+                (host.os() != *os).then(|| format!("only runs on {os}, host is {}", host.os()))
+            }
+            Self::SkipOn(os) => (host.os() == *os).then(|| format!("does not run on {os}")),
+            Self::OnlyOnArch(arch) => (host.arch() != *arch)
+                .then(|| format!("only runs on {arch}, host is {}", host.arch())),
+            Self::OnlyIfTool(tool) => (!host.has_tool(tool))
+                .then(|| format!("requires {}, which was not found", tool.display())),
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A command which only executes when all of its host guards match
+pub struct CommandHost<'a> {
+    command: &'a mut Command,
+    guards: Vec<Guard>,
+    last_execution: Option<ExecutionInfo>,
+}
+
+impl<'a> CommandHost<'a> {
+    /// Only run the command on the given operating system
+    pub fn only_on(&mut self, os: Os) -> &mut Self {
+        self.guards.push(Guard::OnlyOn(os));
+        self
+    }
+
+    /// Never run the command on the given operating system
+    pub fn skip_on(&mut self, os: Os) -> &mut Self {
+        self.guards.push(Guard::SkipOn(os));
+        self
+    }
+
+    /// Only run the command on the given architecture
+    pub fn only_on_arch(&mut self, arch: Arch) -> &mut Self {
+        self.guards.push(Guard::OnlyOnArch(arch));
+        self
+    }
+
+    /// Only run the command if the given tool is available on the `PATH`
+    pub fn only_if_tool<S: AsRef<OsStr>>(&mut self, tool: S) -> &mut Self {
+        self.guards
+            .push(Guard::OnlyIfTool(PathBuf::from(tool.as_ref())));
+        self
+    }
+
+    /// Descriptions of the guards the command runs under, such as `only on linux`
+    pub fn get_guards(&self) -> Vec<String> {
+        self.guards.iter().map(|g| g.to_string()).collect()
+    }
+
+    /// The reason the command will be skipped on this host, or `None` if it will run
+    pub fn skip_reason(&self) -> Option<String> {
+        self.guards.iter().find_map(|g| g.skip_reason(host()))
+    }
+
+    fn skipped(&self) -> Option<Error> {
+        self.skip_reason()
+            .map(|reason| Error::new(ErrorKind::Unsupported, format!("skipped: {reason}")))
+    }
+
+    #[cfg(feature = "check")]
+    /// Check the command if all guards match, otherwise skip it without executing
+    pub fn run(&mut self) -> Result<Outcome, CommandExtError> {
+        match self.skip_reason() {
+            Some(reason) => Ok(Outcome::Skipped { reason }),
+            None => {
+                let (output, info) = ExecutionInfo::measure(|| self.command.check());
+                self.last_execution = Some(info.with_output(&output));
+                output.map(Outcome::Completed)
+            }
+        }
+    }
+}
+
+impl<'a> HasCommand for CommandHost<'a> {
+    fn command(&self) -> &Command {
+        self.command
+    }
+
+    fn command_mut(&mut self) -> &mut Command {
+        self.command
+    }
+}
+
+impl<'a> CommandWrap for CommandHost<'a> {
+    fn spawn(&mut self) -> std::io::Result<Child> {
+        match self.skipped() {
+            Some(e) => Err(e),
+            None => self.command_mut().spawn(),
+        }
+    }
+
+    fn last_execution(&self) -> Option<ExecutionInfo> {
+        self.last_execution
+    }
+
+    fn describe(&self) -> Option<String> {
+        let guards = self
+            .guards
+            .iter()
+            .map(|g| g.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        Some(match self.skip_reason() {
+            Some(reason) => format!("host guards ({guards}): skipped, {reason}"),
+            None => format!("host guards ({guards}): runs on this host"),
+        })
+    }
+
+    fn output(&mut self) -> std::io::Result<Output> {
+        if let Some(e) = self.skipped() {
+            return Err(e);
+        }
+
+        let (output, info) = ExecutionInfo::measure(|| self.command.output());
+        self.last_execution = Some(info.with_output(&output));
+        output
+    }
+
+    fn status(&mut self) -> std::io::Result<ExitStatus> {
+        if let Some(e) = self.skipped() {
+            return Err(e);
+        }
+
+        let (status, info) = ExecutionInfo::measure(|| self.command.status());
+        self.last_execution = Some(info.with_status(&status));
+        status
+    }
+}
+
+impl<'a> From<&'a mut Command> for CommandHost<'a> {
+    fn from(value: &'a mut Command) -> Self {
+        Self {
+            command: value,
+            guards: Vec::new(),
+            last_execution: None,
+        }
+    }
+}
+
+pub trait CommandExtHost {
+    fn only_on(&mut self, os: Os) -> CommandHost<'_>;
+    fn skip_on(&mut self, os: Os) -> CommandHost<'_>;
+    fn only_on_arch(&mut self, arch: Arch) -> CommandHost<'_>;
+    fn only_if_tool<S: AsRef<OsStr>>(&mut self, tool: S) -> CommandHost<'_>;
+}
+
+impl CommandExtHost for Command {
+    fn only_on(&mut self, os: Os) -> CommandHost<'_> {
+        let mut host = CommandHost::from(self);
+        host.only_on(os);
+        host
+    }
+
+    fn skip_on(&mut self, os: Os) -> CommandHost<'_> {
+        let mut host = CommandHost::from(self);
+        host.skip_on(os);
+        host
+    }
+
+    fn only_on_arch(&mut self, arch: Arch) -> CommandHost<'_> {
+        let mut host = CommandHost::from(self);
+        host.only_on_arch(arch);
+        host
+    }
+
+    fn only_if_tool<S: AsRef<OsStr>>(&mut self, tool: S) -> CommandHost<'_> {
+        let mut host = CommandHost::from(self);
+        host.only_if_tool(tool);
+        host
+    }
+}
+
+#[cfg(feature = "check")]
+impl<'a> CommandExtCheck for CommandHost<'a> {
+    type Error = CommandExtError;
+
+    fn check(&mut self) -> Result<Output, Self::Error> {
+        match self.run()? {
+            Outcome::Completed(output) => Ok(output),
+            Outcome::Skipped { reason } => Err(CommandExtError::Skipped { reason }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use super::{host, identity, which, Arch, Os};
+    #[cfg(feature = "check")]
+    use crate::{CommandExtHost, CommandWrap};
+
+    #[test]
+    fn test_host() {
+        assert_eq!(host().os(), Os::current());
+        assert_eq!(host().arch(), Arch::current());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    #[cfg_attr(miri, ignore)]
+    /// Test that the identity matches what the standard tools report
+    fn test_identity() -> anyhow::Result<()> {
+        let identity = identity();
+        let uid = Command::new("id").arg("-u").output()?.stdout;
+        assert_eq!(
+            identity.uid.map(|u| u.to_string()),
+            Some(String::from_utf8_lossy(&uid).trim().to_string())
+        );
+        assert!(identity.hostname.is_some());
+        assert!(identity
+            .to_string()
+            .contains(&format!("(uid {})", identity.uid.unwrap_or(0))));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_which() {
+        assert!(which("sh").is_some());
+        assert!(which("asdfasdfasdfasdfjkljkljkl").is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "check")]
+    #[cfg_attr(miri, ignore)]
+    fn test_only_on_current() -> anyhow::Result<()> {
+        let outcome = Command::new("echo").arg("x").only_on(Os::current()).run()?;
+        assert_eq!(
+            outcome.output().map(|o| o.stdout.as_slice()),
+            Some(b"x\n".as_slice())
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "check")]
+    #[cfg_attr(miri, ignore)]
+    fn test_skip_on_current() -> anyhow::Result<()> {
+        let mut command = Command::new("echo");
+        let mut host = command.skip_on(Os::current());
+        assert!(host.run()?.is_skipped());
+        assert!(host.output().is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "check")]
+    #[cfg_attr(miri, ignore)]
+    fn test_only_if_tool() -> anyhow::Result<()> {
+        assert!(Command::new("echo")
+            .only_if_tool("asdfasdfasdfasdfjkljkljkl")
+            .run()?
+            .is_skipped());
+        Ok(())
+    }
+}
@@ -0,0 +1,531 @@
+//! Client and server for the GNU make jobserver protocol
+//!
+//! Nested build systems each pick their own parallelism, so a script running `make -j8`
+//! in parallel with `cargo build` can start far more jobs than the machine has CPUs. The
+//! jobserver protocol shares one pool of job tokens between every cooperating process: a
+//! process holds one implicit token for itself, and must take another from the jobserver
+//! for each additional job it runs at once, returning it when the job finishes.
+//!
+//! [`Jobserver::from_env`] joins the jobserver of a parent `make` or `cargo`, and
+//! [`Jobserver::new`] creates a new one. [`Jobserver::configure`] lets a child `make` or
+//! `cargo` use it, and [`crate::Runner::jobserver`] makes a runner take a token for each
+//! command it runs. The jobserver is only available on Unix, where it is a pipe (or, for
+//! parents running GNU make 4.4 or later, a named pipe) of one byte per token.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::jobserver::Jobserver;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let jobserver = match Jobserver::from_env() {
+//!     Some(jobserver) => jobserver,
+//!     None => Jobserver::new(4)?,
+//! };
+//!
+//! let token = jobserver.acquire()?;
+//! let mut make = Command::new("echo");
+//! jobserver.configure(&mut make);
+//! make.arg("make -C subproject").status()?;
+//! drop(token);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    env::var,
+    ffi::OsStr,
+    fs::{File, OpenOptions},
+    io::{Error, ErrorKind, Read, Result as IoResult, Write},
+    mem::ManuallyDrop,
+    os::unix::{
+        fs::FileTypeExt,
+        io::{AsRawFd, FromRawFd, RawFd},
+        process::CommandExt,
+    },
+    path::PathBuf,
+    process::Command,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::spawn::prefers_posix_spawn;
+
+mod sys {
+    use std::{
+        ffi::c_int,
+        fs::File,
+        io::{Error, Result as IoResult},
+        os::unix::io::FromRawFd,
+    };
+
+    extern "C" {
+        #[cfg(not(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "openbsd",
+            target_os = "dragonfly"
+        )))]
+        fn pipe(fds: *mut c_int) -> c_int;
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "openbsd",
+            target_os = "dragonfly"
+        ))]
+        fn pipe2(fds: *mut c_int, flags: c_int) -> c_int;
+        fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+    }
+
+    const F_GETFD: c_int = 1;
+    const F_SETFD: c_int = 2;
+    const FD_CLOEXEC: c_int = 1;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const O_CLOEXEC: c_int = 0o2000000;
+    #[cfg(target_os = "freebsd")]
+    const O_CLOEXEC: c_int = 0x0010_0000;
+    #[cfg(target_os = "netbsd")]
+    const O_CLOEXEC: c_int = 0x0040_0000;
+    #[cfg(target_os = "openbsd")]
+    const O_CLOEXEC: c_int = 0x0001_0000;
+    #[cfg(target_os = "dragonfly")]
+    const O_CLOEXEC: c_int = 0x0002_0000;
+
+    /// Set or clear the close-on-exec flag of `fd`
+    pub(super) fn set_cloexec(fd: c_int, cloexec: bool) -> IoResult<()> {
+        let flags = if cloexec { FD_CLOEXEC } else { 0 };
+        // SAFETY: F_SETFD only writes the descriptor flags. fcntl is async-signal-safe, so
+        // this may run between fork and exec.
+        match unsafe { fcntl(fd, F_SETFD, flags) } {
+            -1 => Err(Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Create a pipe whose ends are closed in child processes, unless a child clears their
+    /// close-on-exec flag. The flag is set atomically where `pipe2` is available, so a
+    /// child spawned by another thread meanwhile cannot inherit the pipe.
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly"
+    ))]
+    pub(super) fn pipe_cloexec() -> IoResult<(File, File)> {
+        let mut fds = [0; 2];
+        // SAFETY: fds has room for the two descriptors pipe2 writes
+        if unsafe { pipe2(fds.as_mut_ptr(), O_CLOEXEC) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        // SAFETY: pipe2 succeeded, so both descriptors are open and owned by no one else
+        Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+    }
+
+    /// Create a pipe whose ends are closed in child processes, unless a child clears their
+    /// close-on-exec flag. Without `pipe2`, a child spawned by another thread between
+    /// creating the pipe and setting the flag inherits the pipe.
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly"
+    )))]
+    pub(super) fn pipe_cloexec() -> IoResult<(File, File)> {
+        let mut fds = [0; 2];
+        // SAFETY: fds has room for the two descriptors pipe writes
+        if unsafe { pipe(fds.as_mut_ptr()) } == -1 {
+            return Err(Error::last_os_error());
+        }
+        // SAFETY: pipe succeeded, so both descriptors are open and owned by no one else
+        let files = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+        set_cloexec(fds[0], true)?;
+        set_cloexec(fds[1], true)?;
+        Ok(files)
+    }
+
+    /// Whether `fd` is an open file descriptor
+    pub(super) fn is_open(fd: c_int) -> bool {
+        // SAFETY: F_GETFD only reads the descriptor flags
+        unsafe { fcntl(fd, F_GETFD) != -1 }
+    }
+}
+
+/// Whether `file` is a pipe or named pipe
+fn is_fifo(file: &File) -> bool {
+    file.metadata().is_ok_and(|m| m.file_type().is_fifo())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// How a jobserver is reached, as given in `MAKEFLAGS`
+enum Auth {
+    Fds(RawFd, RawFd),
+    Fifo(PathBuf),
+}
+
+/// The jobserver named by the last `--jobserver-auth` (or older `--jobserver-fds`) option
+/// in `makeflags`
+fn parse(makeflags: &str) -> Option<Auth> {
+    let value = makeflags.split_whitespace().rev().find_map(|flag| {
+        flag.strip_prefix("--jobserver-auth=")
+            .or_else(|| flag.strip_prefix("--jobserver-fds="))
+    })?;
+
+    match value.strip_prefix("fifo:") {
+        Some(path) => Some(Auth::Fifo(PathBuf::from(path))),
+        None => {
+            let (read, write) = value.split_once(',')?;
+            Some(Auth::Fds(read.parse().ok()?, write.parse().ok()?))
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// The state of the implicit token of this process
+struct Implicit {
+    /// Whether the token is taken
+    taken: bool,
+    /// The number of threads waiting to read a token from the pipe
+    waiting: usize,
+    /// The number of times the token was written to the pipe for a waiting thread, and
+    /// not yet taken back
+    lent: usize,
+}
+
+#[derive(Debug)]
+struct Inner {
+    read: ManuallyDrop<File>,
+    write: ManuallyDrop<File>,
+    /// Whether the descriptors belong to this jobserver and are closed with it
+    owned: bool,
+    /// The descriptors children must inherit to use the jobserver, which a named pipe
+    /// does not need
+    fds: Option<(RawFd, RawFd)>,
+    implicit: Mutex<Implicit>,
+    /// The value of `MAKEFLAGS` for children which should use this jobserver
+    makeflags: String,
+}
+
+impl Inner {
+    fn lock(&self) -> MutexGuard<'_, Implicit> {
+        self.implicit.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        if self.owned {
+            // SAFETY: the descriptors are owned and not used after this
+            unsafe {
+                ManuallyDrop::drop(&mut self.read);
+                ManuallyDrop::drop(&mut self.write);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A handle to a jobserver. Clones share the same tokens.
+pub struct Jobserver(Arc<Inner>);
+
+impl Jobserver {
+    /// Join the jobserver of a parent `make` or `cargo`, named in `CARGO_MAKEFLAGS`,
+    /// `MAKEFLAGS` or `MFLAGS`. Returns `None` if there is none, or if its pipe was not
+    /// passed to this process, which `make` only does for recipes marked with `+` or
+    /// calling `$(MAKE)`.
+    pub fn from_env() -> Option<Self> {
+        ["CARGO_MAKEFLAGS", "MAKEFLAGS", "MFLAGS"]
+            .iter()
+            .find_map(|name| {
+                let makeflags = var(name).ok()?;
+                parse(&makeflags).map(|auth| (auth, makeflags))
+            })
+            .and_then(|(auth, makeflags)| Self::connect(auth, makeflags).ok())
+    }
+
+    fn connect(auth: Auth, makeflags: String) -> IoResult<Self> {
+        let (read, write, owned, fds) = match auth {
+            Auth::Fds(read, write) => {
+                if !sys::is_open(read) || !sys::is_open(write) {
+                    return Err(Error::new(
+                        ErrorKind::NotFound,
+                        "the jobserver pipe was not passed to this process",
+                    ));
+                }
+                // SAFETY: the descriptors are open, and are never closed because they
+                // belong to the parent's jobserver
+                let (read, write) = unsafe {
+                    (
+                        ManuallyDrop::new(File::from_raw_fd(read)),
+                        ManuallyDrop::new(File::from_raw_fd(write)),
+                    )
+                };
+                // A stale MAKEFLAGS can name descriptors which were reused for unrelated
+                // files, which tokens must never be read from or written to
+                if !is_fifo(&read) || !is_fifo(&write) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "the jobserver descriptors are not pipes",
+                    ));
+                }
+                let fds = (read.as_raw_fd(), write.as_raw_fd());
+                (read, write, false, Some(fds))
+            }
+            Auth::Fifo(path) => {
+                let fifo = OpenOptions::new().read(true).write(true).open(path)?;
+                if !is_fifo(&fifo) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "the jobserver path is not a named pipe",
+                    ));
+                }
+                (
+                    ManuallyDrop::new(fifo.try_clone()?),
+                    ManuallyDrop::new(fifo),
+                    true,
+                    None,
+                )
+            }
+        };
+
+        Ok(Self(Arc::new(Inner {
+            read,
+            write,
+            owned,
+            fds,
+            implicit: Mutex::default(),
+            makeflags,
+        })))
+    }
+
+    /// Create a jobserver allowing `jobs` jobs at once, including the one this process
+    /// runs itself. Its pipe is only inherited by the children it is
+    /// [configured](Jobserver::configure) for. A `jobs` of zero is treated as one.
+    pub fn new(jobs: u32) -> IoResult<Self> {
+        let jobs = jobs.max(1);
+        let (read, mut write) = sys::pipe_cloexec()?;
+        write.write_all(&vec![b'+'; jobs as usize - 1])?;
+
+        let fds = (read.as_raw_fd(), write.as_raw_fd());
+        let makeflags = format!(
+            "-j{jobs} --jobserver-fds={0},{1} --jobserver-auth={0},{1}",
+            fds.0, fds.1
+        );
+
+        Ok(Self(Arc::new(Inner {
+            read: ManuallyDrop::new(read),
+            write: ManuallyDrop::new(write),
+            owned: true,
+            fds: Some(fds),
+            implicit: Mutex::default(),
+            makeflags,
+        })))
+    }
+
+    /// Take a token, waiting until one is free. The first token is the implicit token of
+    /// this process, which is always available unless it is already taken. The token is
+    /// returned when it is dropped.
+    pub fn acquire(&self) -> IoResult<Token> {
+        {
+            let mut implicit = self.0.lock();
+            if !implicit.taken {
+                implicit.taken = true;
+                return Ok(Token {
+                    jobserver: self.clone(),
+                    byte: None,
+                });
+            }
+            implicit.waiting += 1;
+        }
+
+        let mut byte = [0];
+        let read = loop {
+            match (&*self.0.read).read(&mut byte) {
+                Ok(1) => break Ok(byte[0]),
+                Ok(_) => {
+                    break Err(Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "the jobserver pipe was closed",
+                    ))
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.0.lock().waiting -= 1;
+
+        read.map(|byte| Token {
+            jobserver: self.clone(),
+            byte: Some(byte),
+        })
+    }
+
+    /// Let `command`, if it is a `make`, `cargo` or another jobserver client, take its
+    /// tokens from this jobserver. The jobserver's pipe is inherited by `command`, and by
+    /// no child which is not configured. Configuring a command again has no effect.
+    ///
+    /// Letting `command` inherit the pipe of a [new](Jobserver::new) jobserver requires a
+    /// `pre_exec` closure, so when [`crate::spawn::prefers_posix_spawn`] is set, `command`
+    /// is left unconfigured and runs its own jobs. The jobserver of a parent is still
+    /// passed on, since its pipe is already inherited.
+    pub fn configure(&self, command: &mut Command) {
+        let configured = command
+            .get_envs()
+            .any(|(key, value)| key == "MAKEFLAGS" && value == Some(OsStr::new(&self.0.makeflags)));
+        let inherited = self.0.fds.is_none() || !self.0.owned;
+        if configured || (!inherited && prefers_posix_spawn()) {
+            return;
+        }
+
+        command
+            .env("MAKEFLAGS", &self.0.makeflags)
+            .env("CARGO_MAKEFLAGS", &self.0.makeflags)
+            .env_remove("MFLAGS");
+
+        if let Some((read, write)) = self.0.fds.filter(|_| !prefers_posix_spawn()) {
+            // SAFETY: the closure only calls fcntl, which is async-signal-safe
+            unsafe {
+                command.pre_exec(move || {
+                    sys::set_cloexec(read, false)?;
+                    sys::set_cloexec(write, false)
+                });
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A job token taken from a [`Jobserver`], which is returned when it is dropped
+pub struct Token {
+    jobserver: Jobserver,
+    /// The byte read from the pipe, or `None` for the implicit token
+    byte: Option<u8>,
+}
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        let inner = &self.jobserver.0;
+        let mut implicit = inner.lock();
+        match self.byte {
+            // A token taken from the pipe replaces a lent implicit token instead of being
+            // written back, which would keep the implicit token in the pipe for good
+            Some(_) if implicit.lent > 0 && implicit.waiting == 0 => {
+                implicit.lent -= 1;
+                implicit.taken = false;
+            }
+            // A token which cannot be returned is lost, which only reduces parallelism
+            Some(byte) => {
+                (&*inner.write).write_all(&[byte]).ok();
+            }
+            // A thread blocked reading the pipe cannot see the implicit token become free,
+            // so it is lent to the pipe while any thread is waiting
+            None if implicit.waiting > 0 && (&*inner.write).write_all(b"+").is_ok() => {
+                implicit.lent += 1;
+            }
+            None => implicit.taken = false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{path::PathBuf, process::Command};
+
+    use super::{parse, Auth, Jobserver};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("-j8 --jobserver-fds=3,4 --jobserver-auth=3,4"),
+            Some(Auth::Fds(3, 4))
+        );
+        assert_eq!(
+            parse(" -j --jobserver-auth=fifo:/tmp/GMfifo1"),
+            Some(Auth::Fifo(PathBuf::from("/tmp/GMfifo1")))
+        );
+        assert_eq!(parse("-j8"), None);
+        assert_eq!(parse("--jobserver-auth=x"), None);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that tokens are shared between handles and returned when dropped
+    fn test_tokens() -> anyhow::Result<()> {
+        let jobserver = Jobserver::new(3)?;
+        let tokens = (0..3)
+            .map(|_| jobserver.clone().acquire())
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(tokens.iter().filter(|t| t.byte.is_none()).count(), 1);
+        drop(tokens);
+
+        let again = (0..3)
+            .map(|_| jobserver.acquire())
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(again.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that descriptors which are not pipes are never used as a jobserver
+    fn test_not_a_pipe() -> anyhow::Result<()> {
+        use std::os::unix::io::AsRawFd;
+
+        let file = std::fs::File::open("Cargo.toml")?;
+        let fd = file.as_raw_fd();
+        assert!(Jobserver::connect(Auth::Fds(fd, fd), String::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    #[cfg_attr(miri, ignore)]
+    /// Test that the pipe of a new jobserver is only inherited by configured children
+    fn test_inherited_only_when_configured() -> anyhow::Result<()> {
+        let jobserver = Jobserver::new(2)?;
+        let (read, _) = jobserver.0.fds.expect("a new jobserver is a pipe");
+        let probe = format!("test -e /proc/self/fd/{read}");
+
+        let mut plain = Command::new("sh");
+        assert!(!plain.args(["-c", &probe]).status()?.success());
+
+        let mut configured = Command::new("sh");
+        jobserver.configure(&mut configured);
+        assert!(configured.args(["-c", &probe]).status()?.success());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a child make uses the jobserver it is configured with
+    fn test_configure() -> anyhow::Result<()> {
+        let jobserver = Jobserver::new(2)?;
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo $MAKEFLAGS"]);
+        jobserver.configure(&mut command);
+        let output = command.output()?;
+        assert!(String::from_utf8_lossy(&output.stdout).contains("--jobserver-auth="));
+
+        let mut make = Command::new("make");
+        jobserver.configure(&mut make);
+        if let Ok(output) = make
+            .args(["-f", "-", "--no-print-directory"])
+            .env_remove("MAKELEVEL")
+            .stdin(std::process::Stdio::null())
+            .output()
+        {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(!stderr.contains("jobserver unavailable"), "{stderr}");
+        }
+
+        Ok(())
+    }
+}
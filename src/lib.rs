@@ -4,6 +4,11 @@
 //! make it easier to use, particularly in cargo scripts where many commands may be executed
 //! with different requirements for error checking, logging, and so forth.
 //!
+//! The [`CommandExt`] trait provides the most common operations, such as `check`, `run`
+//! and `checked_stdout`, on commands and every wrapper, so it is the only import most
+//! scripts need. The traits below configure more specific behavior, and
+//! `use command_ext::prelude::*;` imports all of them at once.
+//!
 //! ## CommandExtCheck
 //!
 //! `CommandExtCheck` allows you to check the result of a command and get a nicely packaged
@@ -101,18 +106,59 @@
 //!
 //! For other cases where you might want to hook into what `Command` is doing, you can use
 //! `CommandWrap` to implement your own wrappers. See the examples for more details.
+//!
+//! ## Minimal builds
+//!
+//! Most modules are behind a feature of the same name. The building blocks the others share
+//! are always compiled, and need nothing outside `std`: `error`, `wrap`, `clock`, `random`,
+//! `metrics`, `pool`, `capture`, `spawn`, `spec`, `batch`, `features` and `prelude`.
+//! Building with `default-features = false` keeps only those; adding `features = ["core"]`
+//! also enables checking, with `check`, `context` and `ext`, still with no dependencies
+//! outside `std`. The logging, tracing and printing wrappers bring in their dependencies
+//! only when their features are enabled, and derive their builders with `typed-builder`
+//! only when the default `typed-builder` feature is enabled. Without it, equivalent
+//! hand-written builders are used, which build on older compilers.
 
 pub mod error;
-pub use error::CommandExtError;
+pub use error::{CommandExtError, ErrorCategory, Exit, TerminationReason};
 
 pub mod wrap;
-pub use wrap::{CommandWrap, HasCommand};
+pub use wrap::{CommandWrap, Execute, ExecutionInfo, HasCommand};
+
+pub mod clock;
+pub use clock::Clock;
+
+pub mod random;
+
+pub mod metrics;
+
+#[cfg(any(feature = "fuzz", feature = "script"))]
+pub mod scratch;
+
+pub mod pool;
+
+pub mod capture;
+
+pub mod spawn;
+
+pub mod features;
+pub use features::{features, Features};
+
+pub mod spec;
+pub use spec::{CommandEdit, CommandExtFingerprint, CommandSpec, Fingerprint};
+
+pub mod batch;
 
 #[cfg(feature = "check")]
 pub mod check;
 #[cfg(feature = "check")]
 pub use check::CommandExtCheck;
 
+#[cfg(feature = "check")]
+pub mod context;
+#[cfg(feature = "check")]
+pub use context::{CommandExtContext, CommandResultExt};
+
 #[cfg(feature = "log")]
 pub mod log;
 #[cfg(feature = "log")]
@@ -121,37 +167,188 @@ pub use log::CommandExtLog;
 #[cfg(feature = "print")]
 pub mod print;
 #[cfg(feature = "print")]
-pub use print::CommandExtPrint;
+pub use print::{CommandExtPrint, PrintBuffer, PrintTarget};
 
 #[cfg(feature = "tracing")]
 pub mod trace;
 #[cfg(feature = "tracing")]
 pub use trace::CommandExtTrace;
 
-#[cfg(all(feature = "check", feature = "log", feature = "print", feature = "tracing"))]
-pub trait CommandExt: CommandExtCheck + CommandExtLog + CommandExtPrint + CommandExtTrace {}
+mod display;
+pub use display::{CommandExtLossy, Lossy};
+
+#[cfg(all(
+    any(feature = "log", feature = "print", feature = "tracing"),
+    not(feature = "typed-builder")
+))]
+mod builder;
+
+#[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+pub mod verbosity;
+#[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+pub use verbosity::{CommandExtVerbosity, Verbosity};
+
+#[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+pub mod defaults;
+#[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+pub use defaults::Defaults;
+
+#[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+pub mod profile;
+#[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+pub use profile::{CommandExtProfile, Profile};
+
+#[cfg(feature = "ci")]
+pub mod ci;
+
+#[cfg(feature = "section")]
+pub mod section;
+#[cfg(feature = "section")]
+pub use section::{CommandExtSection, Reporter};
+
+#[cfg(feature = "host")]
+pub mod host;
+#[cfg(feature = "host")]
+pub use host::{host, identity, Arch, CommandExtHost, HostInfo, Identity, Os, Outcome};
+
+#[cfg(feature = "env")]
+pub mod env;
+#[cfg(feature = "env")]
+pub use env::CommandExtEnv;
 
-#[cfg(all(feature = "check", feature = "log", feature = "print", not(feature = "tracing")))]
-pub trait CommandExt: CommandExtCheck + CommandExtLog + CommandExtPrint {}
+#[cfg(feature = "explain")]
+pub mod explain;
+#[cfg(feature = "explain")]
+pub use explain::{CommandExtExplain, Explanation};
 
-#[cfg(all(feature = "check", not(feature = "log"), feature = "print", feature = "tracing"))]
-pub trait CommandExt: CommandExtCheck + CommandExtPrint + CommandExtTrace {}
+#[cfg(feature = "validate")]
+pub mod validate;
+#[cfg(feature = "validate")]
+pub use validate::{CommandExtStrict, CommandExtValidate, ValidationIssue};
 
-#[cfg(all(feature = "check", feature = "log", not(feature = "print"), feature = "tracing"))]
-pub trait CommandExt: CommandExtCheck + CommandExtLog + CommandExtTrace {}
+#[cfg(feature = "registry")]
+pub mod registry;
+#[cfg(feature = "registry")]
+pub use registry::{CommandListing, CommandRegistry, Listing};
 
-#[cfg(all(not(feature = "check"), feature = "log", feature = "print", feature = "tracing"))]
-pub trait CommandExt: CommandExtLog + CommandExtPrint + CommandExtTrace {}
+#[cfg(feature = "locale")]
+pub mod locale;
+#[cfg(feature = "locale")]
+pub use locale::CommandExtLocale;
 
-#[cfg(all(feature = "check", not(feature = "log"), not(feature = "print"), not(feature = "tracing")))]
-pub trait CommandExt: CommandExtCheck {}
+#[cfg(feature = "script")]
+pub mod script;
 
-#[cfg(all(not(feature = "check"), feature = "log", not(feature = "print"), not(feature = "tracing")))]
-pub trait CommandExt: CommandExtLog {}
+#[cfg(feature = "stdin")]
+pub mod stdin;
+#[cfg(feature = "stdin")]
+pub use stdin::CommandExtStdin;
 
-#[cfg(all(not(feature = "check"), not(feature = "log"), feature = "print", not(feature = "tracing")))]
-pub trait CommandExt: CommandExtPrint {}
+#[cfg(feature = "shebang")]
+pub mod shebang;
+#[cfg(feature = "shebang")]
+pub use shebang::CommandExtShebang;
 
-#[cfg(all(not(feature = "check"), not(feature = "log"), not(feature = "print"), feature = "tracing"))]
-pub trait CommandExt: CommandExtTrace {}
+#[cfg(feature = "snapshot")]
+pub mod snapshot;
+#[cfg(feature = "snapshot")]
+pub use snapshot::CommandExtSnapshot;
+
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+#[cfg(feature = "fuzz")]
+pub use fuzz::CommandExtFuzz;
+
+#[cfg(feature = "watch")]
+pub mod watch;
+#[cfg(feature = "watch")]
+pub use watch::CommandExtWatch;
+
+#[cfg(feature = "daemon")]
+pub mod daemon;
+#[cfg(feature = "daemon")]
+pub use daemon::{CommandExtPidfile, Daemon};
+
+#[cfg(feature = "async")]
+pub mod future;
+#[cfg(feature = "async")]
+pub use future::CommandExtAsync;
+
+#[cfg(feature = "priority")]
+pub mod priority;
+#[cfg(feature = "priority")]
+pub use priority::{CommandExtIoPriority, IoPriority};
+
+#[cfg(feature = "space")]
+pub mod space;
+#[cfg(feature = "space")]
+pub use space::CommandExtFreeSpace;
+
+#[cfg(all(feature = "jobserver", unix))]
+pub mod jobserver;
+
+#[cfg(feature = "quote")]
+pub mod quote;
+
+#[cfg(feature = "tools")]
+pub mod tools;
+#[cfg(feature = "tools")]
+pub use tools::ToolManifest;
+
+#[cfg(feature = "scope")]
+pub mod scope;
+
+#[cfg(feature = "oneshot")]
+pub mod oneshot;
+#[cfg(feature = "oneshot")]
+pub use oneshot::CommandExtOneShot;
+
+#[cfg(feature = "retry")]
+pub mod retry;
+#[cfg(feature = "retry")]
+pub use retry::CommandExtRetry;
+
+#[cfg(feature = "timeout")]
+pub mod timeout;
+#[cfg(feature = "timeout")]
+pub use timeout::CommandExtTimeout;
+
+#[cfg(feature = "expr")]
+pub mod expr;
+#[cfg(feature = "expr")]
+pub use expr::Expression;
+
+#[cfg(feature = "parse")]
+pub mod parse;
+#[cfg(feature = "parse")]
+pub use parse::ParseOutput;
+
+#[cfg(feature = "chain")]
+pub mod chain;
+#[cfg(feature = "chain")]
+pub use chain::CommandExtChain;
+
+#[cfg(feature = "transaction")]
+pub mod transaction;
+#[cfg(feature = "transaction")]
+pub use transaction::{Transaction, TransactionPlan};
+
+#[cfg(feature = "bench")]
+pub mod bench;
+
+#[cfg(feature = "reap")]
+pub mod reap;
+#[cfg(feature = "reap")]
+pub use reap::CommandExtOnExit;
+
+#[cfg(feature = "runner")]
+pub mod runner;
+#[cfg(feature = "runner")]
+pub use runner::{ExecutionReport, History, Runner, StateFile};
+
+#[cfg(feature = "check")]
+pub mod ext;
+#[cfg(feature = "check")]
+pub use ext::CommandExt;
 
+pub mod prelude;
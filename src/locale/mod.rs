@@ -0,0 +1,344 @@
+//! Extension trait to run a command with a fixed locale and time zone
+//!
+//! The output of tools like `date`, `ls` and `sort` depends on the locale and time zone of
+//! the environment they run in, which makes parsing it fragile. [`CommandLocale::locale`]
+//! sets `LC_ALL` and `LANG` and removes `LANGUAGE`, and [`CommandLocale::timezone`] sets
+//! `TZ`.
+//!
+//! When a locale is not installed, the C library silently falls back to the `C` locale, and
+//! an unknown time zone silently becomes UTC. In strict mode the command refuses to run with
+//! a locale missing from `locale -a` or a time zone missing from the zoneinfo database, and
+//! fails if its error output contains a warning that the locale could not be set.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{CommandExtCheck, CommandExtLocale};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let output = Command::new("date")
+//!     .arg("+%Z")
+//!     .locale("C")
+//!     .timezone("UTC")
+//!     .strict()
+//!     .check()?;
+//! assert_eq!(output.stdout, b"UTC\n");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    env::var_os,
+    io::{Error, Result as IoResult},
+    path::PathBuf,
+    process::{Child, Command, ExitStatus, Output},
+};
+
+#[cfg(feature = "check")]
+use crate::{
+    check::{check_with, preflight},
+    CommandExtCheck,
+};
+use crate::{
+    wrap::{ExecutionInfo, HasCommand},
+    CommandExtError, CommandWrap,
+};
+
+/// Warnings printed by common tools and language runtimes when the locale cannot be set
+const LOCALE_MARKERS: &[&str] = &[
+    "cannot change locale",
+    "Setting locale failed",
+    "Failed to set locale",
+    "setlocale: ",
+];
+
+/// Directories the zoneinfo database is installed in, checked after `TZDIR`
+const ZONEINFO_DIRS: &[&str] = &[
+    "/usr/share/zoneinfo",
+    "/usr/lib/zoneinfo",
+    "/usr/share/lib/zoneinfo",
+];
+
+/// Normalize a locale name the way the C library does when matching codesets, so that
+/// `C.UTF-8` matches the `C.utf8` listed by `locale -a`
+fn normalize(locale: &str) -> String {
+    match locale.split_once('.') {
+        Some((name, codeset)) => format!(
+            "{name}.{}",
+            codeset
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric())
+                .collect::<String>()
+                .to_ascii_lowercase()
+        ),
+        None => locale.to_string(),
+    }
+}
+
+/// Whether `locale` is installed, or `None` if the installed locales cannot be listed
+fn locale_installed(locale: &str) -> Option<bool> {
+    if matches!(locale, "C" | "POSIX") {
+        return Some(true);
+    }
+
+    let output = Command::new("locale").arg("-a").output().ok()?;
+    let wanted = normalize(locale);
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|l| normalize(l.trim()) == wanted),
+    )
+}
+
+/// Whether `tz` names a known time zone, or `None` if there is no zoneinfo database
+fn timezone_known(tz: &str) -> Option<bool> {
+    let tz = tz.strip_prefix(':').unwrap_or(tz);
+
+    // POSIX TZ strings such as `EST5EDT` carry their own offset
+    if matches!(tz, "UTC" | "GMT") || tz.contains(|c: char| c.is_ascii_digit()) {
+        return Some(true);
+    }
+
+    var_os("TZDIR")
+        .map(PathBuf::from)
+        .into_iter()
+        .chain(ZONEINFO_DIRS.iter().map(PathBuf::from))
+        .find(|d| d.is_dir())
+        .map(|dir| dir.join(tz).is_file())
+}
+
+#[derive(Debug)]
+/// A command which runs with a fixed locale and time zone
+pub struct CommandLocale<'a> {
+    command: &'a mut Command,
+    locale: Option<String>,
+    timezone: Option<String>,
+    strict: bool,
+    last_execution: Option<ExecutionInfo>,
+}
+
+impl<'a> CommandLocale<'a> {
+    /// Run the command with the locale `locale`, for example `C.UTF-8`
+    pub fn locale<S: Into<String>>(&mut self, locale: S) -> &mut Self {
+        let locale = locale.into();
+        self.command
+            .env("LC_ALL", &locale)
+            .env("LANG", &locale)
+            .env_remove("LANGUAGE");
+        self.locale = Some(locale);
+        self
+    }
+
+    /// Run the command in the time zone `timezone`, for example `UTC` or `Europe/Paris`
+    pub fn timezone<S: Into<String>>(&mut self, timezone: S) -> &mut Self {
+        let timezone = timezone.into();
+        self.command.env("TZ", &timezone);
+        self.timezone = Some(timezone);
+        self
+    }
+
+    /// Verify that the locale and time zone are honored by the command
+    pub fn strict(&mut self) -> &mut Self {
+        self.strict = true;
+        self
+    }
+
+    /// The locale the command runs with, if it is set
+    pub fn get_locale(&self) -> Option<&str> {
+        self.locale.as_deref()
+    }
+
+    /// The time zone the command runs in, if it is set
+    pub fn get_timezone(&self) -> Option<&str> {
+        self.timezone.as_deref()
+    }
+
+    /// Whether the locale and time zone are verified
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    fn not_honored(variable: &str, value: &str, reason: String) -> CommandExtError {
+        CommandExtError::NotHonored {
+            variable: variable.to_string(),
+            value: value.to_string(),
+            reason,
+        }
+    }
+
+    /// Fail if the locale or time zone is not installed on this host
+    fn verify_before(&self) -> Result<(), CommandExtError> {
+        if !self.strict {
+            return Ok(());
+        }
+
+        if let Some(locale) = &self.locale {
+            if locale_installed(locale) == Some(false) {
+                let reason = "the locale is not installed".to_string();
+                return Err(Self::not_honored("LC_ALL", locale, reason));
+            }
+        }
+
+        if let Some(timezone) = &self.timezone {
+            if timezone_known(timezone) == Some(false) {
+                let reason = "the time zone is not in the zoneinfo database".to_string();
+                return Err(Self::not_honored("TZ", timezone, reason));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fail if the error output of the command warns that the locale could not be set
+    fn verify_after(&self, output: &Output) -> Result<(), CommandExtError> {
+        let (Some(locale), true) = (&self.locale, self.strict) else {
+            return Ok(());
+        };
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        match stderr
+            .lines()
+            .find(|l| LOCALE_MARKERS.iter().any(|m| l.contains(m)))
+        {
+            Some(line) => Err(Self::not_honored("LC_ALL", locale, line.trim().to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a> HasCommand for CommandLocale<'a> {
+    fn command(&self) -> &Command {
+        self.command
+    }
+
+    fn command_mut(&mut self) -> &mut Command {
+        self.command
+    }
+}
+
+impl<'a> CommandWrap for CommandLocale<'a> {
+    fn last_execution(&self) -> Option<ExecutionInfo> {
+        self.last_execution
+    }
+
+    fn spawn(&mut self) -> IoResult<Child> {
+        self.verify_before()?;
+        self.command.spawn()
+    }
+
+    fn output(&mut self) -> IoResult<Output> {
+        self.verify_before()?;
+        let (output, info) = ExecutionInfo::measure(|| self.command.output());
+        self.last_execution = Some(info.with_output(&output));
+        let output = output?;
+        self.verify_after(&output).map_err(Error::from)?;
+        Ok(output)
+    }
+
+    fn status(&mut self) -> IoResult<ExitStatus> {
+        self.verify_before()?;
+        let (status, info) = ExecutionInfo::measure(|| self.command.status());
+        self.last_execution = Some(info.with_status(&status));
+        status
+    }
+}
+
+impl<'a> From<&'a mut Command> for CommandLocale<'a> {
+    fn from(value: &'a mut Command) -> Self {
+        Self {
+            command: value,
+            locale: None,
+            timezone: None,
+            strict: false,
+            last_execution: None,
+        }
+    }
+}
+
+#[cfg(feature = "check")]
+impl<'a> CommandExtCheck for CommandLocale<'a> {
+    type Error = CommandExtError;
+
+    fn check(&mut self) -> Result<Output, Self::Error> {
+        preflight(self.command)?;
+        self.verify_before()?;
+        let (output, info) = ExecutionInfo::measure(|| self.command.output());
+        self.last_execution = Some(info.with_output(&output));
+        if let Ok(output) = &output {
+            self.verify_after(output)?;
+        }
+        check_with(self, output)
+    }
+}
+
+pub trait CommandExtLocale {
+    fn locale<S: Into<String>>(&mut self, locale: S) -> CommandLocale<'_>;
+    fn timezone<S: Into<String>>(&mut self, timezone: S) -> CommandLocale<'_>;
+}
+
+impl CommandExtLocale for Command {
+    fn locale<S: Into<String>>(&mut self, locale: S) -> CommandLocale<'_> {
+        let mut wrapper = CommandLocale::from(self);
+        wrapper.locale(locale);
+        wrapper
+    }
+
+    fn timezone<S: Into<String>>(&mut self, timezone: S) -> CommandLocale<'_> {
+        let mut wrapper = CommandLocale::from(self);
+        wrapper.timezone(timezone);
+        wrapper
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use super::normalize;
+    use crate::{CommandExtLocale, CommandWrap};
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("C.UTF-8"), "C.utf8");
+        assert_eq!(normalize("en_US.utf8"), "en_US.utf8");
+        assert_eq!(normalize("POSIX"), "POSIX");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_locale() -> anyhow::Result<()> {
+        let output = Command::new("sh")
+            .args(["-c", "echo $LC_ALL $TZ"])
+            .locale("C")
+            .timezone("UTC")
+            .strict()
+            .output()?;
+        assert_eq!(output.stdout, b"C UTC\n");
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(all(unix, feature = "check"))]
+    #[cfg_attr(miri, ignore)]
+    fn test_strict() {
+        use crate::{CommandExtCheck, CommandExtError};
+
+        let result = Command::new("true")
+            .timezone("Nowhere/Atlantis")
+            .strict()
+            .check();
+        if std::path::Path::new("/usr/share/zoneinfo").is_dir() {
+            assert!(matches!(result, Err(CommandExtError::NotHonored { .. })));
+        }
+
+        let result = Command::new("sh")
+            .args([
+                "-c",
+                "echo 'sh: warning: setlocale: LC_ALL: cannot change locale' >&2",
+            ])
+            .locale("C")
+            .strict()
+            .check();
+        assert!(matches!(result, Err(CommandExtError::NotHonored { .. })));
+    }
+}
@@ -0,0 +1,270 @@
+//! Opt-in instrumentation of the overhead this crate adds to the commands it executes
+//!
+//! When recording is [enabled](enable), the time spent in wrapper hooks, in the threads which
+//! capture output, and in emitting log records is added up per program in a process-wide
+//! registry, which [`snapshot`] and [`get`] read. This lets performance-sensitive users check
+//! that the wrappers cost next to nothing compared to the commands they run, and attach
+//! numbers to a report when they do not. Recording is off by default, and while it is off
+//! instrumented code only checks a flag.
+//!
+//! Overhead is measured with the system's monotonic clock even when another
+//! [`Clock`](crate::Clock) is installed, since it is the time this crate spends and not the
+//! time the commands it runs take. Time spent outside an execution of a command by a wrapper
+//! or layer of this crate, such as calling [`crate::capture::read_both`] directly, is not
+//! recorded.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{metrics, CommandExtCheck, CommandExtLog};
+//! metrics::enable();
+//! Command::new("echo")
+//!     .arg("x")
+//!     .log_stdout(log::Level::Debug)
+//!     .check()
+//!     .unwrap();
+//!
+//! let overhead = metrics::get("echo").unwrap();
+//! assert!(overhead.executions >= 1);
+//! println!("{overhead}");
+//! # metrics::disable();
+//! ```
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt::{Display, Formatter, Result as FmtResult},
+    ops::AddAssign,
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// A kind of overhead which is recorded
+pub(crate) enum Category {
+    /// Running the hooks of wrappers
+    Hooks,
+    /// Copying captured output in the threads which read it
+    Capture,
+    /// Formatting and emitting log, trace and print records
+    #[cfg_attr(
+        not(any(feature = "log", feature = "print", feature = "tracing")),
+        allow(unused)
+    )]
+    Logging,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// The overhead recorded for one program, or for all of them
+pub struct Overhead {
+    /// The number of executions the overhead was recorded over
+    pub executions: u64,
+    /// Time spent running the hooks of wrappers. This includes the time spent logging from
+    /// the hooks of the reporting wrappers, which is also counted in `logging`.
+    pub hooks: Duration,
+    /// Time spent copying output in the threads which capture it, not counting the time
+    /// spent waiting for the child to write it
+    pub capture: Duration,
+    /// Time spent formatting and emitting log, trace and print records
+    pub logging: Duration,
+}
+
+impl Overhead {
+    /// The total overhead, counting the time spent logging from hooks once
+    pub fn total(&self) -> Duration {
+        self.hooks + self.capture
+    }
+
+    /// The average overhead of one execution
+    pub fn per_execution(&self) -> Duration {
+        match u32::try_from(self.executions) {
+            Ok(0) => Duration::ZERO,
+            Ok(executions) => self.total() / executions,
+            Err(_) => Duration::from_secs_f64(self.total().as_secs_f64() / self.executions as f64),
+        }
+    }
+
+    fn add(&mut self, category: Category, duration: Duration) {
+        match category {
+            Category::Hooks => self.hooks += duration,
+            Category::Capture => self.capture += duration,
+            Category::Logging => self.logging += duration,
+        }
+    }
+}
+
+impl AddAssign for Overhead {
+    fn add_assign(&mut self, other: Self) {
+        self.executions += other.executions;
+        self.hooks += other.hooks;
+        self.capture += other.capture;
+        self.logging += other.logging;
+    }
+}
+
+impl Display for Overhead {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "{} executions, {:?} per execution (hooks {:?}, capture {:?}, logging {:?})",
+            self.executions,
+            self.per_execution(),
+            self.hooks,
+            self.capture,
+            self.logging
+        )
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static REGISTRY: Mutex<BTreeMap<String, Overhead>> = Mutex::new(BTreeMap::new());
+
+thread_local! {
+    /// The program whose execution is in progress on this thread
+    static CURRENT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+fn registry() -> MutexGuard<'static, BTreeMap<String, Overhead>> {
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Start recording overhead
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Stop recording overhead. What was already recorded is kept until [`reset`].
+pub fn disable() {
+    ENABLED.store(false, Ordering::Relaxed);
+}
+
+/// Whether overhead is being recorded
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Discard all recorded overhead
+pub fn reset() {
+    registry().clear();
+}
+
+/// The overhead recorded so far for each program, by program name
+pub fn snapshot() -> BTreeMap<String, Overhead> {
+    registry().clone()
+}
+
+/// The overhead recorded so far for `program`, if it was executed while recording
+pub fn get(program: &str) -> Option<Overhead> {
+    registry().get(program).copied()
+}
+
+/// The overhead recorded so far for all programs
+pub fn total() -> Overhead {
+    registry()
+        .values()
+        .fold(Overhead::default(), |mut total, o| {
+            total += *o;
+            total
+        })
+}
+
+/// An execution of a command in progress on the current thread, which overhead recorded on
+/// the thread is attributed to until it is dropped
+pub(crate) struct Execution(Option<Option<String>>);
+
+impl Drop for Execution {
+    fn drop(&mut self) {
+        if let Some(previous) = self.0.take() {
+            CURRENT.with(|current| *current.borrow_mut() = previous);
+        }
+    }
+}
+
+/// Attribute the overhead recorded on the current thread to `command` until the returned
+/// guard is dropped. Executions nested in another one, such as a wrapper executing through
+/// the wrapper it wraps, are attributed to and counted as the outermost one.
+pub(crate) fn execution(command: &Command) -> Execution {
+    if !enabled() || CURRENT.with(|current| current.borrow().is_some()) {
+        return Execution(None);
+    }
+
+    let program = command.get_program().to_string_lossy().into_owned();
+    registry().entry(program.clone()).or_default().executions += 1;
+    Execution(Some(
+        CURRENT.with(|current| current.borrow_mut().replace(program)),
+    ))
+}
+
+/// The program overhead recorded on the current thread is attributed to, for handing to
+/// threads which work on its behalf
+pub(crate) fn current() -> Option<String> {
+    if !enabled() {
+        return None;
+    }
+
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Record `duration` of overhead in `category` for `program`
+pub(crate) fn record(program: &str, category: Category, duration: Duration) {
+    if let Some(overhead) = registry().get_mut(program) {
+        overhead.add(category, duration);
+    }
+}
+
+/// Run `f`, recording the time it takes as overhead in `category` for the execution in
+/// progress on the current thread
+pub(crate) fn measure<T, F>(category: Category, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let Some(program) = current() else {
+        return f();
+    };
+
+    let start = Instant::now();
+    let result = f();
+    record(&program, category, start.elapsed());
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::{process::Command, time::Duration};
+
+    use super::{execution, get, measure, Category};
+
+    #[test]
+    /// Test that overhead is attributed to the outermost execution, and only while enabled
+    fn test_attribution() {
+        let outer = Command::new("metrics-test-outer");
+        let inner = Command::new("metrics-test-inner");
+
+        super::enable();
+        {
+            let _outer = execution(&outer);
+            let _inner = execution(&inner);
+            measure(Category::Hooks, || {
+                std::thread::sleep(Duration::from_millis(5))
+            });
+        }
+        super::disable();
+
+        let _ignored = execution(&outer);
+        measure(Category::Logging, || {
+            std::thread::sleep(Duration::from_millis(5))
+        });
+
+        let overhead = get("metrics-test-outer").unwrap();
+        assert_eq!(overhead.executions, 1);
+        assert!(overhead.hooks >= Duration::from_millis(5));
+        assert_eq!(overhead.logging, Duration::ZERO);
+        assert!(get("metrics-test-inner").is_none());
+    }
+}
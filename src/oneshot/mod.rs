@@ -0,0 +1,185 @@
+//! Extension trait to catch a command being executed more than once
+//!
+//! Arguments accumulate on a [`Command`], so executing the same `&mut Command` again
+//! after adding more arguments runs it with every argument added so far. Marking a
+//! command as one-shot turns its second execution into an error, or into a warning with
+//! [`CommandOneShot::warn_only`].
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{CommandExtCheck, CommandExtError, CommandExtOneShot, CommandWrap};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut command = Command::new("echo");
+//! let mut once = command.one_shot();
+//! once.arg("first").check()?;
+//!
+//! let reused = once.arg("second").check();
+//! assert!(matches!(reused, Err(CommandExtError::AlreadyExecuted { .. })));
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::{Error, Result as IoResult},
+    process::{Child, Command, ExitStatus, Output},
+};
+
+#[cfg(feature = "check")]
+use crate::{
+    check::{check_with, preflight},
+    CommandExtCheck,
+};
+use crate::{wrap::HasCommand, CommandExtError, CommandWrap};
+
+fn warn(message: String) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!("{message}");
+    #[cfg(all(feature = "log", not(feature = "tracing")))]
+    log::warn!("{message}");
+    #[cfg(not(any(feature = "log", feature = "tracing")))]
+    eprintln!("warning: {message}");
+}
+
+#[derive(Debug)]
+pub struct CommandOneShot<'a> {
+    command: &'a mut Command,
+    warn_only: bool,
+    executions: usize,
+}
+
+impl<'a> CommandOneShot<'a> {
+    /// Log a warning when the command is executed again instead of failing
+    pub fn warn_only(&mut self, warn_only: bool) -> &mut Self {
+        self.warn_only = warn_only;
+        self
+    }
+
+    /// Whether executing the command again only logs a warning
+    pub fn get_warn_only(&self) -> bool {
+        self.warn_only
+    }
+
+    /// The number of times the command has been executed, including rejected attempts
+    pub fn executions(&self) -> usize {
+        self.executions
+    }
+
+    /// Count an execution, returning an error if the command already ran and executing it
+    /// again is not allowed
+    fn execute(&mut self) -> Result<(), CommandExtError> {
+        self.executions += 1;
+        if self.executions == 1 {
+            return Ok(());
+        }
+
+        let error = CommandExtError::AlreadyExecuted {
+            program: self.command.get_program().to_string_lossy().into_owned(),
+            executions: self.executions - 1,
+        };
+
+        if self.warn_only {
+            warn(error.to_string());
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+}
+
+impl<'a> HasCommand for CommandOneShot<'a> {
+    fn command(&self) -> &Command {
+        self.command
+    }
+
+    fn command_mut(&mut self) -> &mut Command {
+        self.command
+    }
+}
+
+impl<'a> CommandWrap for CommandOneShot<'a> {
+    fn describe(&self) -> Option<String> {
+        Some(format!(
+            "{} when executed more than once",
+            if self.warn_only { "warns" } else { "fails" }
+        ))
+    }
+
+    fn spawn(&mut self) -> IoResult<Child> {
+        self.execute().map_err(Error::from)?;
+        self.command.spawn()
+    }
+
+    fn output(&mut self) -> IoResult<Output> {
+        self.execute().map_err(Error::from)?;
+        self.command.output()
+    }
+
+    fn status(&mut self) -> IoResult<ExitStatus> {
+        self.execute().map_err(Error::from)?;
+        self.command.status()
+    }
+}
+
+impl<'a> From<&'a mut Command> for CommandOneShot<'a> {
+    fn from(value: &'a mut Command) -> Self {
+        Self {
+            command: value,
+            warn_only: false,
+            executions: 0,
+        }
+    }
+}
+
+#[cfg(feature = "check")]
+impl<'a> CommandExtCheck for CommandOneShot<'a> {
+    type Error = CommandExtError;
+
+    fn check(&mut self) -> Result<Output, Self::Error> {
+        preflight(self.command)?;
+        self.execute()?;
+        let output = self.command.output();
+        check_with(self, output)
+    }
+}
+
+pub trait CommandExtOneShot {
+    /// Mark the command as executable only once
+    fn one_shot(&mut self) -> CommandOneShot<'_>;
+}
+
+impl CommandExtOneShot for Command {
+    fn one_shot(&mut self) -> CommandOneShot<'_> {
+        CommandOneShot::from(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::ErrorKind, process::Command};
+
+    use crate::{CommandExtOneShot, CommandWrap};
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a second execution fails unless only warnings are requested
+    fn test_one_shot() -> anyhow::Result<()> {
+        let mut command = Command::new("true");
+        let mut once = command.one_shot();
+        assert!(once.status()?.success());
+        assert_eq!(
+            once.output().map_err(|e| e.kind()).err(),
+            Some(ErrorKind::InvalidInput)
+        );
+        assert_eq!(once.executions(), 2);
+
+        let mut command = Command::new("true");
+        let mut once = command.one_shot();
+        once.warn_only(true);
+        assert!(once.status()?.success());
+        assert!(once.status()?.success());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,712 @@
+//! Typed parsers for the machine-readable output of common tools
+//!
+//! Each parser implements [`ParseOutput`], so a command's output can be checked and parsed
+//! in one step with [`crate::CommandExt::check_parsed`], instead of picking values out of it
+//! with ad-hoc string handling. Parsers are provided for:
+//!
+//! - `cargo --message-format=json`, as a [`Vec<CargoMessage>`]
+//! - `git status --porcelain=v2 --branch`, as a [`GitStatus`]
+//! - `docker inspect`, as a [`Vec<DockerInspect>`]
+//! - any JSON document, as a [`Json`] value
+//!
+//! JSON is parsed by a small parser in this module, so no dependencies are needed.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! # use std::process::Command;
+//! # use command_ext::{parse::GitStatus, CommandExt, CommandExtError};
+//! # fn main() -> Result<(), CommandExtError> {
+//! let status = Command::new("git")
+//!     .args(["status", "--porcelain=v2", "--branch"])
+//!     .check_parsed::<GitStatus>()?;
+//!
+//! if !status.is_clean() {
+//!     println!("{} files changed on {:?}", status.entries.len(), status.head);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{iter::Peekable, str::CharIndices};
+
+use crate::CommandExtError;
+
+/// A type which can be parsed from the standard output of a command
+pub trait ParseOutput: Sized {
+    /// The name of the format, used in errors
+    const FORMAT: &'static str;
+
+    /// Parse the standard output of a command, returning why it could not be parsed on
+    /// failure
+    fn parse_output(stdout: &str) -> Result<Self, String>;
+}
+
+/// Parse the standard output of a command, reporting failure as
+/// [`CommandExtError::ParseFailed`]
+pub fn parse<T>(stdout: &[u8]) -> Result<T, CommandExtError>
+where
+    T: ParseOutput,
+{
+    T::parse_output(&String::from_utf8_lossy(stdout)).map_err(|reason| {
+        CommandExtError::ParseFailed {
+            format: T::FORMAT.to_string(),
+            reason,
+        }
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A JSON value. Objects keep their members in document order.
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Parse a JSON document
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parser = JsonParser {
+            text,
+            chars: text.char_indices().peekable(),
+        };
+        let value = parser.value()?;
+        parser.whitespace();
+        match parser.chars.next() {
+            Some((at, c)) => Err(format!("unexpected {c:?} after the document at byte {at}")),
+            None => Ok(value),
+        }
+    }
+
+    /// The member `key` of an object
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Self::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// The value at a path of object keys, such as `["State", "Status"]`
+    pub fn pointer(&self, path: &[&str]) -> Option<&Json> {
+        path.iter().try_fold(self, |value, key| value.get(key))
+    }
+
+    /// The value of a string
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// The value of a number
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// The value of a number which is an integer
+    pub fn as_i64(&self) -> Option<i64> {
+        self.as_f64()
+            .filter(|n| n.fract() == 0.0 && n.abs() < 2f64.powi(63))
+            .map(|n| n as i64)
+    }
+
+    /// The value of a boolean
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// The elements of an array
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Self::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    /// The member `key` of an object as an owned string
+    fn string(&self, key: &str) -> Option<String> {
+        self.get(key).and_then(Json::as_str).map(str::to_string)
+    }
+
+    /// The member `key` of an object as a list of owned strings
+    fn strings(&self, key: &str) -> Vec<String> {
+        self.get(key)
+            .and_then(Json::as_array)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|v| v.as_str().map(str::to_string))
+            .collect()
+    }
+}
+
+impl ParseOutput for Json {
+    const FORMAT: &'static str = "JSON";
+
+    fn parse_output(stdout: &str) -> Result<Self, String> {
+        Self::parse(stdout)
+    }
+}
+
+/// A recursive descent parser for [RFC 8259](https://www.rfc-editor.org/rfc/rfc8259) JSON
+struct JsonParser<'a> {
+    text: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl JsonParser<'_> {
+    fn whitespace(&mut self) {
+        while self
+            .chars
+            .next_if(|(_, c)| matches!(c, ' ' | '\t' | '\n' | '\r'))
+            .is_some()
+        {}
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.chars.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            Some((at, c)) => Err(format!(
+                "expected {expected:?} but found {c:?} at byte {at}"
+            )),
+            None => Err(format!("expected {expected:?} but the document ended")),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        word.chars().try_for_each(|c| self.expect(c))?;
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.whitespace();
+        match self.chars.peek().copied() {
+            Some((_, '{')) => self.object(),
+            Some((_, '[')) => self.array(),
+            Some((_, '"')) => self.string().map(Json::String),
+            Some((_, 't')) => self.literal("true", Json::Bool(true)),
+            Some((_, 'f')) => self.literal("false", Json::Bool(false)),
+            Some((_, 'n')) => self.literal("null", Json::Null),
+            Some((_, '-' | '0'..='9')) => self.number(),
+            Some((at, c)) => Err(format!("unexpected {c:?} at byte {at}")),
+            None => Err("expected a value but the document ended".to_string()),
+        }
+    }
+
+    /// Parse the elements of an array or the members of an object between `open` and
+    /// `close`, each with `element`
+    fn sequence<T>(
+        &mut self,
+        open: char,
+        close: char,
+        mut element: impl FnMut(&mut Self) -> Result<T, String>,
+    ) -> Result<Vec<T>, String> {
+        self.expect(open)?;
+        self.whitespace();
+        let mut elements = Vec::new();
+        if self.chars.next_if(|(_, c)| *c == close).is_some() {
+            return Ok(elements);
+        }
+
+        loop {
+            elements.push(element(self)?);
+            self.whitespace();
+            match self.chars.next() {
+                Some((_, ',')) => self.whitespace(),
+                Some((_, c)) if c == close => return Ok(elements),
+                Some((at, c)) => return Err(format!("unexpected {c:?} at byte {at}")),
+                None => return Err(format!("expected {close:?} but the document ended")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.sequence('{', '}', |p| {
+            let key = p.string()?;
+            p.whitespace();
+            p.expect(':')?;
+            Ok((key, p.value()?))
+        })
+        .map(Json::Object)
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.sequence('[', ']', Self::value).map(Json::Array)
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.chars.peek().map_or(self.text.len(), |(at, _)| *at);
+        while self
+            .chars
+            .next_if(|(_, c)| matches!(c, '-' | '+' | '.' | 'e' | 'E' | '0'..='9'))
+            .is_some()
+        {}
+        let end = self.chars.peek().map_or(self.text.len(), |(at, _)| *at);
+        let number = &self.text[start..end];
+        number
+            .parse()
+            .map(Json::Number)
+            .map_err(|_| format!("invalid number {number:?} at byte {start}"))
+    }
+
+    fn hex(&mut self) -> Result<u32, String> {
+        (0..4).try_fold(0, |value, _| match self.chars.next() {
+            Some((_, c)) if c.is_ascii_hexdigit() => Ok(value * 16 + c.to_digit(16).unwrap_or(0)),
+            _ => Err("invalid unicode escape".to_string()),
+        })
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(string),
+                Some((_, '\\')) => {
+                    let escaped = match self.chars.next() {
+                        Some((_, '"')) => '"',
+                        Some((_, '\\')) => '\\',
+                        Some((_, '/')) => '/',
+                        Some((_, 'b')) => '\u{8}',
+                        Some((_, 'f')) => '\u{c}',
+                        Some((_, 'n')) => '\n',
+                        Some((_, 'r')) => '\r',
+                        Some((_, 't')) => '\t',
+                        Some((_, 'u')) => {
+                            let high = self.hex()?;
+                            let code = if (0xd800..0xdc00).contains(&high) {
+                                self.expect('\\')?;
+                                self.expect('u')?;
+                                let low = self.hex()?;
+                                0x10000
+                                    + ((high - 0xd800) << 10)
+                                    + (low.wrapping_sub(0xdc00) & 0x3ff)
+                            } else {
+                                high
+                            };
+                            char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        Some((at, c)) => return Err(format!("invalid escape {c:?} at byte {at}")),
+                        None => return Err("unterminated string".to_string()),
+                    };
+                    string.push(escaped);
+                }
+                Some((_, c)) => string.push(c),
+                None => return Err("unterminated string".to_string()),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The crate target a [`CargoMessage`] is about
+pub struct CargoTarget {
+    /// The name of the target
+    pub name: String,
+    /// The kinds of the target, such as `lib`, `bin` or `test`
+    pub kind: Vec<String>,
+    /// The path of the target's root source file
+    pub src_path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A diagnostic emitted by the compiler
+pub struct CompilerMessage {
+    /// The severity, such as `error` or `warning`
+    pub level: String,
+    /// The primary message
+    pub message: String,
+    /// The diagnostic code, such as `E0308`, if it has one
+    pub code: Option<String>,
+    /// The diagnostic as the compiler would print it, if rendered
+    pub rendered: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A message printed by `cargo --message-format=json`. The fields common to the message
+/// kinds are parsed, and the whole message is available as [`CargoMessage::json`].
+pub struct CargoMessage {
+    /// The kind of message, such as `compiler-artifact`, `compiler-message`,
+    /// `build-script-executed` or `build-finished`
+    pub reason: String,
+    /// The package the message is about, if any
+    pub package_id: Option<String>,
+    /// The target the message is about, if any
+    pub target: Option<CargoTarget>,
+    /// The diagnostic of a `compiler-message`
+    pub message: Option<CompilerMessage>,
+    /// The files produced by a `compiler-artifact`
+    pub filenames: Vec<String>,
+    /// The executable produced by a `compiler-artifact`, if it is one
+    pub executable: Option<String>,
+    /// Whether the build succeeded, for `build-finished`
+    pub success: Option<bool>,
+    /// The complete message
+    pub json: Json,
+}
+
+impl CargoMessage {
+    fn from_json(json: Json) -> Result<Self, String> {
+        let reason = json
+            .string("reason")
+            .ok_or_else(|| "message has no reason".to_string())?;
+        let target = json.get("target").map(|t| CargoTarget {
+            name: t.string("name").unwrap_or_default(),
+            kind: t.strings("kind"),
+            src_path: t.string("src_path").unwrap_or_default(),
+        });
+        let message = json.get("message").map(|m| CompilerMessage {
+            level: m.string("level").unwrap_or_default(),
+            message: m.string("message").unwrap_or_default(),
+            code: m.get("code").and_then(|c| c.string("code")),
+            rendered: m.string("rendered"),
+        });
+
+        Ok(Self {
+            reason,
+            package_id: json.string("package_id"),
+            target,
+            message,
+            filenames: json.strings("filenames"),
+            executable: json.string("executable"),
+            success: json.get("success").and_then(Json::as_bool),
+            json,
+        })
+    }
+}
+
+impl ParseOutput for Vec<CargoMessage> {
+    const FORMAT: &'static str = "cargo JSON messages";
+
+    /// Parse one message per line. Lines which are not JSON objects, such as output printed
+    /// by build scripts, are skipped.
+    fn parse_output(stdout: &str) -> Result<Self, String> {
+        stdout
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| line.starts_with('{'))
+            .map(|(i, line)| {
+                Json::parse(line)
+                    .and_then(CargoMessage::from_json)
+                    .map_err(|e| format!("line {}: {e}", i + 1))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// The kind of a [`GitStatusEntry`]
+pub enum GitEntryKind {
+    /// A tracked file which was changed
+    Changed,
+    /// A tracked file which was renamed or copied
+    Renamed,
+    /// A file with merge conflicts
+    Unmerged,
+    /// A file which is not tracked
+    Untracked,
+    /// A file which is ignored
+    Ignored,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A file reported by `git status --porcelain=v2`
+pub struct GitStatusEntry {
+    /// The kind of entry
+    pub kind: GitEntryKind,
+    /// The status of the file in the index, `.` if it is unchanged
+    pub index: char,
+    /// The status of the file in the working tree, `.` if it is unchanged
+    pub worktree: char,
+    /// The path of the file
+    pub path: String,
+    /// The path the file was renamed or copied from
+    pub original_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// The output of `git status --porcelain=v2`, with the branch headers if `--branch` was
+/// passed
+pub struct GitStatus {
+    /// The current commit, unless the repository has no commits
+    pub oid: Option<String>,
+    /// The current branch, unless `HEAD` is detached
+    pub head: Option<String>,
+    /// The upstream branch, if one is set
+    pub upstream: Option<String>,
+    /// The number of commits ahead of the upstream branch
+    pub ahead: Option<u32>,
+    /// The number of commits behind the upstream branch
+    pub behind: Option<u32>,
+    /// The changed, untracked and ignored files
+    pub entries: Vec<GitStatusEntry>,
+}
+
+impl GitStatus {
+    /// Whether the working tree and index have no changes and no untracked files
+    pub fn is_clean(&self) -> bool {
+        self.entries.iter().all(|e| e.kind == GitEntryKind::Ignored)
+    }
+
+    fn header(&mut self, header: &str) -> Result<(), String> {
+        let (key, value) = header.split_once(' ').unwrap_or((header, ""));
+        let known = |value: &str, unknown: &str| (value != unknown).then(|| value.to_string());
+        match key {
+            "branch.oid" => self.oid = known(value, "(initial)"),
+            "branch.head" => self.head = known(value, "(detached)"),
+            "branch.upstream" => self.upstream = Some(value.to_string()),
+            "branch.ab" => {
+                let count = |prefix: char, field: Option<&str>| {
+                    field
+                        .and_then(|f| f.strip_prefix(prefix))
+                        .and_then(|f| f.parse().ok())
+                        .ok_or_else(|| format!("invalid branch.ab header {value:?}"))
+                };
+                let mut fields = value.split(' ');
+                self.ahead = Some(count('+', fields.next())?);
+                self.behind = Some(count('-', fields.next())?);
+            }
+            // Headers added by newer versions of git, such as stash counts
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl ParseOutput for GitStatus {
+    const FORMAT: &'static str = "git status --porcelain=v2";
+
+    fn parse_output(stdout: &str) -> Result<Self, String> {
+        let mut status = Self::default();
+
+        for line in stdout.lines().filter(|l| !l.is_empty()) {
+            let invalid = || format!("invalid status line {line:?}");
+            let (kind, rest) = line.split_once(' ').ok_or_else(invalid)?;
+            // The number of fields before the path for each kind of entry
+            let (kind, fields) = match kind {
+                "#" => {
+                    status.header(rest)?;
+                    continue;
+                }
+                "1" => (GitEntryKind::Changed, 7),
+                "2" => (GitEntryKind::Renamed, 8),
+                "u" => (GitEntryKind::Unmerged, 9),
+                "?" => (GitEntryKind::Untracked, 0),
+                "!" => (GitEntryKind::Ignored, 0),
+                _ => return Err(invalid()),
+            };
+
+            let mut parts = rest.splitn(fields + 1, ' ');
+            let xy = match fields {
+                0 => [kind_char(kind); 2],
+                _ => {
+                    let mut xy = parts.next().ok_or_else(invalid)?.chars();
+                    [xy.next(), xy.next()].map(|c| c.unwrap_or('.'))
+                }
+            };
+            let path = parts.nth(fields.saturating_sub(1)).ok_or_else(invalid)?;
+            let (path, original_path) = match kind {
+                GitEntryKind::Renamed => {
+                    let (path, original) = path.split_once('\t').ok_or_else(invalid)?;
+                    (path, Some(original.to_string()))
+                }
+                _ => (path, None),
+            };
+
+            status.entries.push(GitStatusEntry {
+                kind,
+                index: xy[0],
+                worktree: xy[1],
+                path: path.to_string(),
+                original_path,
+            });
+        }
+
+        Ok(status)
+    }
+}
+
+/// The status character of untracked and ignored entries, which have no status field
+fn kind_char(kind: GitEntryKind) -> char {
+    match kind {
+        GitEntryKind::Ignored => '!',
+        _ => '?',
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The state of a container reported by `docker inspect`
+pub struct DockerState {
+    /// The status, such as `running` or `exited`
+    pub status: String,
+    /// Whether the container is running
+    pub running: bool,
+    /// The exit code of the container's process, if it has exited
+    pub exit_code: i64,
+    /// The process ID of the container's process, or 0 if it is not running
+    pub pid: i64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// An object described by `docker inspect`. The fields common to containers and images are
+/// parsed, and the whole description is available as [`DockerInspect::json`].
+pub struct DockerInspect {
+    /// The full ID of the object
+    pub id: String,
+    /// The name of a container, without the leading `/`
+    pub name: Option<String>,
+    /// The image a container was created from
+    pub image: Option<String>,
+    /// When the object was created
+    pub created: Option<String>,
+    /// The state of a container
+    pub state: Option<DockerState>,
+    /// The complete description
+    pub json: Json,
+}
+
+impl DockerInspect {
+    fn from_json(json: Json) -> Result<Self, String> {
+        let id = json
+            .string("Id")
+            .ok_or_else(|| "object has no Id".to_string())?;
+        let state = json.get("State").map(|s| DockerState {
+            status: s.string("Status").unwrap_or_default(),
+            running: s.get("Running").and_then(Json::as_bool).unwrap_or_default(),
+            exit_code: s.get("ExitCode").and_then(Json::as_i64).unwrap_or_default(),
+            pid: s.get("Pid").and_then(Json::as_i64).unwrap_or_default(),
+        });
+
+        Ok(Self {
+            id,
+            name: json
+                .string("Name")
+                .map(|n| n.trim_start_matches('/').to_string()),
+            image: json
+                .pointer(&["Config", "Image"])
+                .and_then(Json::as_str)
+                .map(str::to_string),
+            created: json.string("Created"),
+            state,
+            json,
+        })
+    }
+}
+
+impl ParseOutput for Vec<DockerInspect> {
+    const FORMAT: &'static str = "docker inspect";
+
+    fn parse_output(stdout: &str) -> Result<Self, String> {
+        match Json::parse(stdout)? {
+            Json::Array(objects) => objects.into_iter().map(DockerInspect::from_json).collect(),
+            _ => Err("expected an array of objects".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, CargoMessage, DockerInspect, GitEntryKind, GitStatus, Json, ParseOutput};
+    use crate::CommandExtError;
+
+    #[test]
+    fn test_json() {
+        let json =
+            Json::parse(r#" {"a": [1, -2.5e1, true, null], "b": {"c": "x\"é😀\n"}} "#).unwrap();
+        assert_eq!(
+            json.pointer(&["b", "c"]).and_then(Json::as_str),
+            Some("x\"é😀\n")
+        );
+        let a = json.get("a").and_then(Json::as_array).unwrap();
+        assert_eq!(a[0].as_i64(), Some(1));
+        assert_eq!(a[1].as_f64(), Some(-25.0));
+        assert_eq!(a[2].as_bool(), Some(true));
+        assert_eq!(a[3], Json::Null);
+        assert_eq!(Json::parse("[]").unwrap(), Json::Array(Vec::new()));
+        assert!(Json::parse("{\"a\": 1,}").is_err());
+        assert!(Json::parse("[1] 2").is_err());
+        assert!(Json::parse("\"abc").is_err());
+    }
+
+    #[test]
+    fn test_cargo() {
+        let stdout = r#"{"reason":"compiler-message","package_id":"x 0.1.0","target":{"kind":["lib"],"name":"x","src_path":"/x/src/lib.rs"},"message":{"level":"warning","message":"unused variable: `y`","code":{"code":"unused_variables"},"rendered":"warning: unused variable"}}
+build script output
+{"reason":"compiler-artifact","package_id":"x 0.1.0","target":{"kind":["bin"],"name":"x","src_path":"/x/src/main.rs"},"filenames":["/x/target/debug/x"],"executable":"/x/target/debug/x"}
+{"reason":"build-finished","success":true}
+"#;
+        let messages = Vec::<CargoMessage>::parse_output(stdout).unwrap();
+        assert_eq!(messages.len(), 3);
+        let message = messages[0].message.as_ref().unwrap();
+        assert_eq!(message.level, "warning");
+        assert_eq!(message.code.as_deref(), Some("unused_variables"));
+        assert_eq!(messages[0].target.as_ref().unwrap().kind, ["lib"]);
+        assert_eq!(messages[1].executable.as_deref(), Some("/x/target/debug/x"));
+        assert_eq!(messages[2].success, Some(true));
+    }
+
+    #[test]
+    fn test_git_status() {
+        let stdout = "# branch.oid 0123abcd\n\
+                      # branch.head main\n\
+                      # branch.upstream origin/main\n\
+                      # branch.ab +2 -1\n\
+                      1 .M N... 100644 100644 100644 aaaa bbbb src/a file.rs\n\
+                      2 R. N... 100644 100644 100644 aaaa bbbb R100 new.rs\told.rs\n\
+                      u UU N... 100644 100644 100644 100644 aaaa bbbb cccc conflict.rs\n\
+                      ? untracked.txt\n\
+                      ! target/\n";
+        let status = GitStatus::parse_output(stdout).unwrap();
+        assert_eq!(status.head.as_deref(), Some("main"));
+        assert_eq!(status.upstream.as_deref(), Some("origin/main"));
+        assert_eq!((status.ahead, status.behind), (Some(2), Some(1)));
+        assert_eq!(status.entries.len(), 5);
+        assert_eq!(status.entries[0].path, "src/a file.rs");
+        assert_eq!(status.entries[0].worktree, 'M');
+        assert_eq!(status.entries[1].kind, GitEntryKind::Renamed);
+        assert_eq!(status.entries[1].original_path.as_deref(), Some("old.rs"));
+        assert_eq!(status.entries[2].kind, GitEntryKind::Unmerged);
+        assert_eq!(status.entries[2].path, "conflict.rs");
+        assert_eq!(status.entries[3].kind, GitEntryKind::Untracked);
+        assert!(!status.is_clean());
+
+        let clean = GitStatus::parse_output(
+            "# branch.oid (initial)\n# branch.head (detached)\n! target/\n",
+        )
+        .unwrap();
+        assert!(clean.is_clean());
+        assert_eq!((clean.oid, clean.head), (None, None));
+        assert!(GitStatus::parse_output("x y\n").is_err());
+    }
+
+    #[test]
+    fn test_docker_inspect() {
+        let stdout = r#"[{"Id":"abc123","Created":"2024-01-01T00:00:00Z","Name":"/web","State":{"Status":"exited","Running":false,"Pid":0,"ExitCode":137},"Config":{"Image":"nginx:latest"}}]"#;
+        let objects = Vec::<DockerInspect>::parse_output(stdout).unwrap();
+        assert_eq!(objects[0].name.as_deref(), Some("web"));
+        assert_eq!(objects[0].image.as_deref(), Some("nginx:latest"));
+        let state = objects[0].state.as_ref().unwrap();
+        assert_eq!((state.status.as_str(), state.exit_code), ("exited", 137));
+    }
+
+    #[test]
+    fn test_parse_error() {
+        match parse::<Vec<DockerInspect>>(b"{}") {
+            Err(CommandExtError::ParseFailed { format, reason }) => {
+                assert_eq!(format, "docker inspect");
+                assert_eq!(reason, "expected an array of objects");
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! A small shared pool of threads for blocking work done on behalf of running commands,
+//! such as draining their output pipes
+//!
+//! Worker threads are started on demand and kept alive for a short time after they become
+//! idle, so scripts which launch many short commands reuse a handful of threads instead of
+//! creating new ones for every command. A job never waits for a busy worker: when every
+//! worker is occupied a new one is started, so jobs which block until a child exits cannot
+//! starve each other.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::{io::Read, process::{Command, Stdio}};
+//! # use command_ext::pool;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut child = Command::new("echo").arg("x").stdout(Stdio::piped()).spawn()?;
+//! let mut stdout = child.stdout.take().ok_or("no stdout")?;
+//! let task = pool::spawn(move || {
+//!     let mut buf = Vec::new();
+//!     stdout.read_to_end(&mut buf).map(|_| buf)
+//! });
+//! child.wait()?;
+//! assert_eq!(task.join().expect("reader panicked")?, b"x\n");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::VecDeque,
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        mpsc::{channel, Receiver},
+        Condvar, Mutex, MutexGuard,
+    },
+    thread::{Builder, Result as ThreadResult},
+    time::Duration,
+};
+
+/// How long an idle worker waits for a new job before exiting
+const KEEP_ALIVE: Duration = Duration::from_secs(5);
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+struct State {
+    jobs: VecDeque<Job>,
+    idle: usize,
+    wakeups: usize,
+    workers: usize,
+}
+
+struct Pool {
+    state: Mutex<State>,
+    available: Condvar,
+}
+
+static POOL: Pool = Pool {
+    state: Mutex::new(State {
+        jobs: VecDeque::new(),
+        idle: 0,
+        wakeups: 0,
+        workers: 0,
+    }),
+    available: Condvar::new(),
+};
+
+impl Pool {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn execute(&'static self, job: Job) {
+        let mut state = self.lock();
+        state.jobs.push_back(job);
+
+        // Hand the job to an idle worker, which stops counting as idle as soon as it is
+        // claimed so that concurrent jobs cannot claim the same worker
+        if state.idle > 0 {
+            state.idle -= 1;
+            state.wakeups += 1;
+            self.available.notify_one();
+            return;
+        }
+
+        state.workers += 1;
+        drop(state);
+
+        if let Err(e) = Builder::new()
+            .name("command-ext-pool".to_string())
+            .spawn(move || self.work())
+        {
+            // Run the job on the calling thread rather than losing it
+            let mut state = self.lock();
+            state.workers -= 1;
+            let job = state.jobs.pop_back();
+            drop(state);
+            match job {
+                Some(job) => job(),
+                None => panic!("failed to start a pool worker: {e}"),
+            }
+        }
+    }
+
+    fn work(&self) {
+        let mut state = self.lock();
+
+        loop {
+            if let Some(job) = state.jobs.pop_front() {
+                drop(state);
+                job();
+                state = self.lock();
+                continue;
+            }
+
+            state.idle += 1;
+            while state.wakeups == 0 {
+                let (next, timeout) = self
+                    .available
+                    .wait_timeout(state, KEEP_ALIVE)
+                    .unwrap_or_else(|e| e.into_inner());
+                state = next;
+
+                if timeout.timed_out() && state.wakeups == 0 {
+                    state.idle -= 1;
+                    state.workers -= 1;
+                    return;
+                }
+            }
+            state.wakeups -= 1;
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A handle to a job running on the shared pool
+pub struct Task<T> {
+    result: Receiver<ThreadResult<T>>,
+}
+
+impl<T> Task<T> {
+    /// Wait for the job to finish. Like [`std::thread::JoinHandle::join`], this returns an
+    /// error containing the panic payload if the job panicked.
+    pub fn join(self) -> ThreadResult<T> {
+        self.result
+            .recv()
+            .unwrap_or_else(|_| Err(Box::new("pool job was lost")))
+    }
+}
+
+/// Run `f` on the shared pool
+pub fn spawn<F, T>(f: F) -> Task<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (sender, result) = channel();
+    POOL.execute(Box::new(move || {
+        sender.send(catch_unwind(AssertUnwindSafe(f))).ok();
+    }));
+    Task { result }
+}
+
+/// The number of worker threads currently alive, busy or idle
+pub fn workers() -> usize {
+    POOL.lock().workers
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Barrier};
+
+    use super::{spawn, workers};
+
+    #[test]
+    fn test_spawn() {
+        let tasks = (0..64).map(|i| spawn(move || i * 2)).collect::<Vec<_>>();
+        let results = tasks
+            .into_iter()
+            .map(|t| t.join().expect("task panicked"))
+            .collect::<Vec<_>>();
+        assert_eq!(results, (0..64).map(|i| i * 2).collect::<Vec<_>>());
+        assert!(workers() >= 1);
+    }
+
+    #[test]
+    fn test_blocking_jobs_do_not_starve() {
+        // Every job waits for all of the others, so they must all run at once
+        let barrier = Arc::new(Barrier::new(8));
+        let tasks = (0..8)
+            .map(|_| {
+                let barrier = barrier.clone();
+                spawn(move || {
+                    barrier.wait();
+                })
+            })
+            .collect::<Vec<_>>();
+        tasks
+            .into_iter()
+            .for_each(|t| t.join().expect("task panicked"));
+    }
+
+    #[test]
+    fn test_panic() {
+        assert!(spawn(|| panic!("boom")).join().is_err());
+        assert_eq!(spawn(|| 1).join().ok(), Some(1));
+    }
+}
@@ -0,0 +1,106 @@
+//! Everything a script usually needs, imported with `use command_ext::prelude::*;`
+//!
+//! The prelude exports every extension trait enabled by the crate's features, the wrapper
+//! traits, the error type, and the log levels the logging and tracing wrappers take, as
+//! [`LogLevel`] and [`TraceLevel`] since both crates name theirs `Level`.
+//!
+//! # Example
+//!
+//! ```rust
+//! use std::process::Command;
+//! use command_ext::prelude::*;
+//! # fn main() -> Result<(), CommandExtError> {
+//! Command::new("echo")
+//!     .arg("x")
+//!     .log_status(LogLevel::Info)
+//!     .check()?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub use crate::{
+    CommandExtError, CommandExtFingerprint, CommandExtLossy, CommandWrap, ErrorCategory, Exit,
+    HasCommand,
+};
+
+#[cfg(feature = "check")]
+pub use crate::{CommandExt, CommandExtCheck, CommandExtContext, CommandResultExt};
+
+#[cfg(feature = "log")]
+pub use crate::CommandExtLog;
+#[cfg(feature = "log")]
+pub use log::Level as LogLevel;
+
+#[cfg(feature = "print")]
+pub use crate::CommandExtPrint;
+
+#[cfg(feature = "tracing")]
+pub use crate::CommandExtTrace;
+#[cfg(feature = "tracing")]
+pub use tracing::Level as TraceLevel;
+
+#[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+pub use crate::{CommandExtProfile, CommandExtVerbosity, Verbosity};
+
+#[cfg(feature = "section")]
+pub use crate::CommandExtSection;
+
+#[cfg(feature = "host")]
+pub use crate::CommandExtHost;
+
+#[cfg(feature = "env")]
+pub use crate::CommandExtEnv;
+
+#[cfg(feature = "explain")]
+pub use crate::CommandExtExplain;
+
+#[cfg(feature = "validate")]
+pub use crate::{CommandExtStrict, CommandExtValidate};
+
+#[cfg(feature = "locale")]
+pub use crate::CommandExtLocale;
+
+#[cfg(feature = "stdin")]
+pub use crate::CommandExtStdin;
+
+#[cfg(feature = "shebang")]
+pub use crate::CommandExtShebang;
+
+#[cfg(feature = "snapshot")]
+pub use crate::CommandExtSnapshot;
+
+#[cfg(feature = "fuzz")]
+pub use crate::CommandExtFuzz;
+
+#[cfg(feature = "watch")]
+pub use crate::CommandExtWatch;
+
+#[cfg(feature = "daemon")]
+pub use crate::CommandExtPidfile;
+
+#[cfg(feature = "async")]
+pub use crate::CommandExtAsync;
+
+#[cfg(feature = "priority")]
+pub use crate::CommandExtIoPriority;
+
+#[cfg(feature = "space")]
+pub use crate::CommandExtFreeSpace;
+
+#[cfg(feature = "oneshot")]
+pub use crate::CommandExtOneShot;
+
+#[cfg(feature = "retry")]
+pub use crate::CommandExtRetry;
+
+#[cfg(feature = "timeout")]
+pub use crate::CommandExtTimeout;
+
+#[cfg(feature = "expr")]
+pub use crate::{cmd, Expression};
+
+#[cfg(feature = "chain")]
+pub use crate::CommandExtChain;
+
+#[cfg(feature = "reap")]
+pub use crate::CommandExtOnExit;
@@ -1,4 +1,4 @@
-//! Extension trait to log properties of a command
+//! Extension trait to print properties of a command
 //!
 //! # Example
 //!
@@ -12,73 +12,271 @@
 //!     .print_status()
 //!     .print_stdout()
 //!     .print_stderr()
+//!     .print_to_stderr()
+//!     .print_timestamps()
 //!     .output()?;
 //! # Ok(())
 //! # }
 //! ```
-
-use std::{ffi::OsStr, process::Command};
+//!
+//! Output can also be sent to any writer, for example to assert on it in tests:
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{CommandExtPrint, CommandWrap, PrintBuffer};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let buffer = PrintBuffer::new();
+//! Command::new("echo")
+//!     .arg("x")
+//!     .print_stdout()
+//!     .print_to(buffer.clone())
+//!     .output()?;
+//! assert_eq!(buffer.contents(), "stdout: x\n");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! In tests, use `print_captured()` so printed lines are captured by the test harness along
+//! with the test's own output, and only shown when the test fails.
+//!
+//! Printing wraps a plain [`Command`]; put [layers](crate::Execute) such as retries on the
+//! [`CommandPrint`] to print each attempt.
+
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    io::{stderr, stdout, Result as IoResult, Write},
+    process::Command,
+    sync::{Arc, Mutex},
+    time::UNIX_EPOCH,
+};
+#[cfg(feature = "typed-builder")]
 use typed_builder::TypedBuilder;
 
-use crate::{wrap::HasCommand, CommandWrap};
 #[cfg(feature = "check")]
-use crate::{CommandExtCheck, CommandExtError};
+use crate::{check::check_wrapper, CommandExtCheck, CommandExtError};
+use crate::{
+    clock,
+    defaults::Defaults,
+    display::CommandLine,
+    metrics::{self, Category},
+    wrap::{ExecutionInfo, HasCommand},
+    CommandWrap, Verbosity,
+};
+
+#[derive(Default)]
+/// Where [`CommandPrint`] writes its output
+pub enum PrintTarget {
+    #[default]
+    /// Print to the standard output of the current process
+    Stdout,
+    /// Print to the standard error of the current process
+    Stderr,
+    /// Print to standard output with [`println!`], which the test harness captures like
+    /// the test's own output, including from threads the test spawns. Writing to
+    /// [`stdout`] directly is not captured, so lines printed by commands run in a test
+    /// otherwise show up interleaved with the harness output even when the test passes.
+    Captured,
+    /// Print to an arbitrary writer, such as a file or a [`PrintBuffer`]
+    Writer(Box<dyn Write + Send>),
+}
+
+impl Debug for PrintTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Stdout => write!(f, "Stdout"),
+            Self::Stderr => write!(f, "Stderr"),
+            Self::Captured => write!(f, "Captured"),
+            Self::Writer(_) => write!(f, "Writer(..)"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// A cloneable in-memory writer, useful as a [`PrintTarget`] in tests
+pub struct PrintBuffer(Arc<Mutex<Vec<u8>>>);
 
-#[derive(TypedBuilder, Debug)]
+impl PrintBuffer {
+    /// Create an empty buffer
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything written to the buffer so far
+    pub fn contents(&self) -> String {
+        self.0
+            .lock()
+            .map(|b| String::from_utf8_lossy(&b).to_string())
+            .unwrap_or_default()
+    }
+}
+
+impl Write for PrintBuffer {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.0
+            .lock()
+            .map_err(|_| std::io::Error::other("print buffer poisoned"))?
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg_attr(feature = "typed-builder", derive(TypedBuilder))]
+#[derive(Debug)]
 pub struct CommandPrint<'a> {
     command: &'a mut Command,
-    #[builder(default, setter(into))]
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default = crate::defaults::get(), setter(skip))
+    )]
+    /// The ambient defaults when the wrapper was built, read once for every setting left
+    /// unset
+    _defaults: Defaults,
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default = _defaults.print_args, setter(into))
+    )]
     /// The log level for args before execution
     args: bool,
-    #[builder(default, setter(into))]
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default = _defaults.print_envs, setter(into))
+    )]
     /// Whether to log the environment on execution
     envs: bool,
-    #[builder(default, setter(into))]
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default = _defaults.print_current_dir, setter(into))
+    )]
     /// Whether to log the current directory on execution
     current_dir: bool,
-    #[builder(default, setter(into))]
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default = _defaults.print_status, setter(into))
+    )]
     /// Whether to log the status after execution
     status: bool,
-    #[builder(default, setter(into))]
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default = _defaults.print_stdout, setter(into))
+    )]
     /// Whether to log stdout after execution
     stdout: bool,
-    #[builder(default, setter(into))]
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default = _defaults.print_stderr, setter(into))
+    )]
     /// Whether to log stderr after execution
     stderr: bool,
+    #[cfg_attr(feature = "typed-builder", builder(default, setter(into)))]
+    /// Where printed lines are written
+    target: PrintTarget,
+    #[cfg_attr(feature = "typed-builder", builder(default, setter(into)))]
+    /// Whether to prefix printed lines with a UTC timestamp
+    timestamps: bool,
+    #[cfg_attr(feature = "typed-builder", builder(default, setter(skip)))]
+    /// The last execution of the command through this wrapper
+    last_execution: Option<ExecutionInfo>,
+}
+
+#[cfg(not(feature = "typed-builder"))]
+crate::builder::builder! {
+    CommandPrint, CommandPrintBuilder, _defaults {
+        args: bool = _defaults.print_args, |value: bool| value,
+        envs: bool = _defaults.print_envs, |value: bool| value,
+        current_dir: bool = _defaults.print_current_dir, |value: bool| value,
+        status: bool = _defaults.print_status, |value: bool| value,
+        stdout: bool = _defaults.print_stdout, |value: bool| value,
+        stderr: bool = _defaults.print_stderr, |value: bool| value,
+        target: PrintTarget = Default::default(), |value: PrintTarget| value,
+        timestamps: bool = Default::default(), |value: bool| value,
+    }
+}
+
+/// Format the current time as an RFC 3339 UTC timestamp with millisecond precision
+fn timestamp() -> String {
+    let now = clock::current()
+        .system_now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let (days, secs) = (now.as_secs() / 86400, now.as_secs() % 86400);
+
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        now.subsec_millis()
+    )
 }
 
 impl<'a> CommandPrint<'a> {
+    fn emit(&mut self, line: String) {
+        metrics::measure(Category::Logging, || self.write(line))
+    }
+
+    fn write(&mut self, line: String) {
+        let line = if self.timestamps {
+            format!("{} {line}", timestamp())
+        } else {
+            line
+        };
+
+        match &mut self.target {
+            PrintTarget::Stdout => writeln!(stdout(), "{line}"),
+            PrintTarget::Stderr => writeln!(stderr(), "{line}"),
+            PrintTarget::Captured => {
+                println!("{line}");
+                Ok(())
+            }
+            PrintTarget::Writer(w) => writeln!(w, "{line}"),
+        }
+        .ok();
+    }
+
     fn print_before(&mut self) {
         if self.args {
-            println!(
-                "args: {} {}",
-                self.command().get_program().to_string_lossy(),
-                self.command()
-                    .get_args()
-                    .collect::<Vec<_>>()
-                    .join(OsStr::new(" "))
-                    .to_string_lossy()
-            );
+            let line = format!("args: {}", CommandLine(self.command()));
+            self.emit(line);
         }
 
         if self.envs {
-            self.command().get_envs().for_each(|(k, v)| {
-                println!(
-                    "envs: {}={}",
-                    k.to_string_lossy(),
-                    v.unwrap_or_default().to_string_lossy()
-                );
-            });
+            let lines = self
+                .command()
+                .get_envs()
+                .map(|(k, v)| {
+                    format!(
+                        "envs: {}={}",
+                        k.to_string_lossy(),
+                        v.unwrap_or_default().to_string_lossy()
+                    )
+                })
+                .collect::<Vec<_>>();
+            lines.into_iter().for_each(|line| self.emit(line));
         }
 
         if self.current_dir {
-            println!(
+            let line = format!(
                 "current_dir: {}",
                 self.command()
                     .get_current_dir()
                     .map(|d| d.to_string_lossy())
                     .unwrap_or_default()
             );
+            self.emit(line);
         }
     }
 }
@@ -94,6 +292,10 @@ impl<'a> HasCommand for CommandPrint<'a> {
 }
 
 impl<'a> CommandWrap for CommandPrint<'a> {
+    fn last_execution(&self) -> Option<ExecutionInfo> {
+        self.last_execution
+    }
+
     fn on_spawn(&mut self) {
         self.print_before();
     }
@@ -106,30 +308,42 @@ impl<'a> CommandWrap for CommandPrint<'a> {
         self.print_before();
     }
 
-    fn after_output(&mut self, output: &std::io::Result<std::process::Output>) {
+    fn after_output(
+        &mut self,
+        output: &std::io::Result<std::process::Output>,
+        info: &ExecutionInfo,
+    ) {
+        self.last_execution = Some(*info);
+
         if let Ok(output) = output {
             if self.status {
-                println!("status: {}", output.status);
+                self.emit(format!("status: {}", output.status));
             }
             if self.stdout {
                 let out = String::from_utf8_lossy(&output.stdout).trim().to_string();
                 if !out.is_empty() {
-                    println!("stdout: {out}",);
+                    self.emit(format!("stdout: {out}"));
                 }
             }
             if self.stderr {
                 let err = String::from_utf8_lossy(&output.stderr).trim().to_string();
                 if !err.is_empty() {
-                    println!("stderr: {err}",);
+                    self.emit(format!("stderr: {err}"));
                 }
             }
         }
     }
 
-    fn after_status(&mut self, status: &std::io::Result<std::process::ExitStatus>) {
+    fn after_status(
+        &mut self,
+        status: &std::io::Result<std::process::ExitStatus>,
+        info: &ExecutionInfo,
+    ) {
+        self.last_execution = Some(*info);
+
         if let Ok(status) = status {
             if self.status {
-                println!("status: {}", status);
+                self.emit(format!("status: {}", status));
             }
         }
     }
@@ -142,85 +356,172 @@ impl<'a> From<&'a mut Command> for CommandPrint<'a> {
 }
 
 pub trait CommandExtPrint {
-    fn print_args(&mut self) -> CommandPrint;
-    fn print_envs(&mut self) -> CommandPrint;
-    fn print_current_dir(&mut self) -> CommandPrint;
-    fn print_status(&mut self) -> CommandPrint;
-    fn print_stdout(&mut self) -> CommandPrint;
-    fn print_stderr(&mut self) -> CommandPrint;
+    fn print_args(&mut self) -> CommandPrint<'_>;
+    fn print_envs(&mut self) -> CommandPrint<'_>;
+    fn print_current_dir(&mut self) -> CommandPrint<'_>;
+    fn print_status(&mut self) -> CommandPrint<'_>;
+    fn print_stdout(&mut self) -> CommandPrint<'_>;
+    fn print_stderr(&mut self) -> CommandPrint<'_>;
 }
 
 impl CommandExtPrint for Command {
-    fn print_args(&mut self) -> CommandPrint
-    {
+    fn print_args(&mut self) -> CommandPrint<'_> {
         CommandPrint::builder().command(self).args(true).build()
     }
 
-    fn print_envs(&mut self) -> CommandPrint
-    {
+    fn print_envs(&mut self) -> CommandPrint<'_> {
         CommandPrint::builder().command(self).envs(true).build()
     }
 
-    fn print_current_dir(&mut self) -> CommandPrint
-    {
+    fn print_current_dir(&mut self) -> CommandPrint<'_> {
         CommandPrint::builder()
             .command(self)
             .current_dir(true)
             .build()
     }
 
-    fn print_status(&mut self) -> CommandPrint
-    {
+    fn print_status(&mut self) -> CommandPrint<'_> {
         CommandPrint::builder().command(self).status(true).build()
     }
 
-    fn print_stdout(&mut self) -> CommandPrint
-    {
+    fn print_stdout(&mut self) -> CommandPrint<'_> {
         CommandPrint::builder().command(self).stdout(true).build()
     }
 
-    fn print_stderr(&mut self) -> CommandPrint
-    {
+    fn print_stderr(&mut self) -> CommandPrint<'_> {
         CommandPrint::builder().command(self).stderr(true).build()
     }
 }
 
 impl<'a> CommandPrint<'a> {
-    pub fn print_args(&'a mut self) -> &'a mut CommandPrint
-    {
+    pub fn print_args(&mut self) -> &mut Self {
         self.args = true;
         self
     }
 
-    pub fn print_envs(&'a mut self) -> &'a mut CommandPrint
-    {
+    pub fn print_envs(&mut self) -> &mut Self {
         self.envs = true;
         self
     }
 
-    pub fn print_current_dir(&'a mut self) -> &'a mut CommandPrint
-    {
+    pub fn print_current_dir(&mut self) -> &mut Self {
         self.current_dir = true;
         self
     }
 
-    pub fn print_status(&'a mut self) -> &'a mut CommandPrint
-    {
+    pub fn print_status(&mut self) -> &mut Self {
         self.status = true;
         self
     }
 
-    pub fn print_stdout(&'a mut self) -> &'a mut CommandPrint
-    {
+    pub fn print_stdout(&mut self) -> &mut Self {
         self.stdout = true;
         self
     }
 
-    pub fn print_stderr(&'a mut self) -> &'a mut CommandPrint
-    {
+    pub fn print_stderr(&mut self) -> &mut Self {
         self.stderr = true;
         self
     }
+
+    /// Replace every setting with the given defaults
+    pub fn with_defaults(&mut self, defaults: &Defaults) -> &mut Self {
+        self.args = defaults.print_args;
+        self.envs = defaults.print_envs;
+        self.current_dir = defaults.print_current_dir;
+        self.status = defaults.print_status;
+        self.stdout = defaults.print_stdout;
+        self.stderr = defaults.print_stderr;
+        self
+    }
+
+    /// Print everything reported at the given verbosity, replacing any selection made so far
+    pub fn verbosity(&mut self, verbosity: Verbosity) -> &mut Self {
+        let selection = verbosity.selection();
+        self.args = selection.args;
+        self.envs = selection.envs;
+        self.current_dir = selection.current_dir;
+        self.status = selection.status;
+        self.stdout = selection.stdout;
+        self.stderr = selection.stderr;
+        self
+    }
+
+    /// Print to the standard output of the current process (the default)
+    pub fn print_to_stdout(&mut self) -> &mut Self {
+        self.target = PrintTarget::Stdout;
+        self
+    }
+
+    /// Print to the standard error of the current process
+    pub fn print_to_stderr(&mut self) -> &mut Self {
+        self.target = PrintTarget::Stderr;
+        self
+    }
+
+    /// Print through [`println!`] so the output is captured by the test harness, see
+    /// [`PrintTarget::Captured`]
+    pub fn print_captured(&mut self) -> &mut Self {
+        self.target = PrintTarget::Captured;
+        self
+    }
+
+    /// Print to the given writer
+    pub fn print_to<W>(&mut self, writer: W) -> &mut Self
+    where
+        W: Write + Send + 'static,
+    {
+        self.target = PrintTarget::Writer(Box::new(writer));
+        self
+    }
+
+    /// Prefix each printed line with a UTC timestamp
+    pub fn print_timestamps(&mut self) -> &mut Self {
+        self.timestamps = true;
+        self
+    }
+}
+
+impl<'a> CommandPrint<'a> {
+    /// Whether the arguments are printed
+    pub fn get_print_args(&self) -> bool {
+        self.args
+    }
+
+    /// Whether the environment is printed
+    pub fn get_print_envs(&self) -> bool {
+        self.envs
+    }
+
+    /// Whether the current directory is printed
+    pub fn get_print_current_dir(&self) -> bool {
+        self.current_dir
+    }
+
+    /// Whether the status is printed
+    pub fn get_print_status(&self) -> bool {
+        self.status
+    }
+
+    /// Whether stdout is printed
+    pub fn get_print_stdout(&self) -> bool {
+        self.stdout
+    }
+
+    /// Whether stderr is printed
+    pub fn get_print_stderr(&self) -> bool {
+        self.stderr
+    }
+
+    /// Whether printed lines are prefixed with a timestamp
+    pub fn get_print_timestamps(&self) -> bool {
+        self.timestamps
+    }
+
+    /// Where printed lines are written
+    pub fn get_print_target(&self) -> &PrintTarget {
+        &self.target
+    }
 }
 
 #[cfg(feature = "check")]
@@ -228,16 +529,7 @@ impl<'a> CommandExtCheck for CommandPrint<'a> {
     type Error = CommandExtError;
 
     fn check(&mut self) -> Result<std::process::Output, Self::Error> {
-        self.output().map_err(CommandExtError::from).and_then(|r| {
-            r.status
-                .success()
-                .then_some(r.clone())
-                .ok_or_else(|| CommandExtError::Check {
-                    status: r.status,
-                    stdout: String::from_utf8_lossy(&r.stdout).to_string(),
-                    stderr: String::from_utf8_lossy(&r.stderr).to_string(),
-                })
-        })
+        check_wrapper(self)
     }
 }
 
@@ -246,25 +538,20 @@ mod test {
     use std::process::Command;
     use test_log::test;
 
+    use super::{CommandPrint, PrintBuffer, PrintTarget};
     use crate::{CommandExtPrint, CommandWrap};
 
     #[test]
     #[cfg_attr(miri, ignore)]
     fn test_args() -> anyhow::Result<()> {
-        Command::new("echo")
-            .arg("x")
-            .print_args()
-            .output()?;
+        Command::new("echo").arg("x").print_args().output()?;
         Ok(())
     }
 
     #[test]
     #[cfg_attr(miri, ignore)]
     fn test_envs() -> anyhow::Result<()> {
-        Command::new("echo")
-            .env("x", "y")
-            .print_envs()
-            .output()?;
+        Command::new("echo").env("x", "y").print_envs().output()?;
         Ok(())
     }
 
@@ -281,10 +568,7 @@ mod test {
     #[test]
     #[cfg_attr(miri, ignore)]
     fn test_status() -> anyhow::Result<()> {
-        Command::new("echo")
-            .arg("x")
-            .print_status()
-            .output()?;
+        Command::new("echo").arg("x").print_status().output()?;
 
         Ok(())
     }
@@ -292,9 +576,31 @@ mod test {
     #[test]
     #[cfg_attr(miri, ignore)]
     fn test_stdout() -> anyhow::Result<()> {
+        Command::new("echo").arg("x").print_stdout().output()?;
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_stderr() -> anyhow::Result<()> {
+        Command::new("bash")
+            .args(["-c", "echo y 1>&2"])
+            .print_stderr()
+            .output()?;
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_stderr_timestamps() -> anyhow::Result<()> {
         Command::new("echo")
             .arg("x")
+            .print_args()
             .print_stdout()
+            .print_to_stderr()
+            .print_timestamps()
             .output()?;
 
         Ok(())
@@ -302,12 +608,71 @@ mod test {
 
     #[test]
     #[cfg_attr(miri, ignore)]
-    fn test_stderr() -> anyhow::Result<()> {
+    fn test_print_to() -> anyhow::Result<()> {
+        let buffer = PrintBuffer::new();
         Command::new("bash")
-            .args(["-c", "echo y 1>&2"])
+            .args(["-c", "echo y 1>&2; echo x"])
+            .print_args()
+            .print_status()
+            .print_stdout()
             .print_stderr()
+            .print_to(buffer.clone())
             .output()?;
 
+        assert_eq!(
+            buffer.contents(),
+            "args: bash -c echo y 1>&2; echo x\nstatus: exit status: 0\nstdout: x\nstderr: y\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that captured printing works from the test thread and from threads it spawns
+    fn test_print_captured() -> anyhow::Result<()> {
+        let mut command = Command::new("echo");
+        command.arg("x");
+        let mut print = command.print_stdout();
+        print.print_captured();
+        assert!(matches!(print.target, PrintTarget::Captured));
+        print.output()?;
+
+        std::thread::spawn(|| {
+            Command::new("echo")
+                .arg("y")
+                .print_stdout()
+                .print_captured()
+                .output()
+        })
+        .join()
+        .expect("thread panicked")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let timestamp = super::timestamp();
+        assert_eq!(timestamp.len(), 24, "Unexpected timestamp {timestamp}");
+        assert!(timestamp.starts_with("20"));
+        assert!(timestamp.ends_with('Z'));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that the builder sets the given fields and leaves the rest at their defaults
+    fn test_builder() -> anyhow::Result<()> {
+        let buffer = PrintBuffer::new();
+        let mut command = Command::new("echo");
+        command.arg("x");
+        CommandPrint::builder()
+            .command(&mut command)
+            .stdout(true)
+            .target(PrintTarget::Writer(Box::new(buffer.clone())))
+            .build()
+            .output()?;
+        assert_eq!(buffer.contents(), "stdout: x\n");
         Ok(())
     }
 }
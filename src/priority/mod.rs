@@ -0,0 +1,317 @@
+//! Extension trait to run a command with a lower I/O priority
+//!
+//! Heavy archival and copy commands launched from scripts can saturate a disk and make the
+//! rest of the machine sluggish. Running them with an idle I/O priority lets interactive
+//! programs go first.
+//!
+//! On Linux, the priority is set with the `ioprio_set` system call. It is set in the child
+//! before the program starts, which requires a `pre_exec` closure. When
+//! [`crate::spawn::prefers_posix_spawn`] is set, it is instead set on the child right after
+//! it is spawned, so I/O the program does immediately, and processes it starts before the
+//! priority is applied, run at the normal priority. Collecting output then sets up streams
+//! which are not configured through the wrapper as [`Command::output`] does, since the
+//! standard library does not expose how the streams of a command are configured. On Windows, the child is created with
+//! a low priority class, which also lowers its I/O priority; this replaces any creation
+//! flags set on the command before. On other platforms, the priority is not changed.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{CommandExtCheck, CommandExtIoPriority, IoPriority};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! Command::new("tar")
+//!     .args(["-cf", "/dev/null", "src"])
+//!     .io_priority(IoPriority::Idle)
+//!     .check()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::Result as IoResult,
+    process::{Child, Command, ExitStatus, Output, Stdio},
+};
+
+#[cfg(target_os = "linux")]
+use crate::spawn::prefers_posix_spawn;
+use crate::{capture, wrap::HasCommand, CommandWrap};
+#[cfg(feature = "check")]
+use crate::{check::check_wrapper, CommandExtCheck, CommandExtError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// The I/O scheduling priority of a command
+pub enum IoPriority {
+    /// Only perform I/O when no other program needs the disk
+    Idle,
+    /// Share the disk with other programs, at a level from 0 (highest) to 7 (lowest). New
+    /// processes normally run at level 4.
+    BestEffort(u8),
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io::{Error, Result as IoResult};
+
+    use super::IoPriority;
+
+    extern "C" {
+        fn syscall(number: std::ffi::c_long, ...) -> std::ffi::c_long;
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    const SYS_IOPRIO_SET: Option<std::ffi::c_long> = Some(251);
+    #[cfg(target_arch = "x86")]
+    const SYS_IOPRIO_SET: Option<std::ffi::c_long> = Some(289);
+    #[cfg(target_arch = "arm")]
+    const SYS_IOPRIO_SET: Option<std::ffi::c_long> = Some(314);
+    #[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+    // The generic system call table
+    const SYS_IOPRIO_SET: Option<std::ffi::c_long> = Some(30);
+    #[cfg(not(any(
+        target_arch = "x86_64",
+        target_arch = "x86",
+        target_arch = "arm",
+        target_arch = "aarch64",
+        target_arch = "riscv64"
+    )))]
+    // Other architectures number their system calls differently, so the priority is left
+    // alone rather than making the wrong call
+    const SYS_IOPRIO_SET: Option<std::ffi::c_long> = None;
+
+    const IOPRIO_WHO_PROCESS: std::ffi::c_int = 1;
+    const IOPRIO_CLASS_SHIFT: std::ffi::c_int = 13;
+    const IOPRIO_CLASS_BE: std::ffi::c_int = 2;
+    const IOPRIO_CLASS_IDLE: std::ffi::c_int = 3;
+
+    /// Set the I/O priority of the process `pid`, where 0 is the calling process. This
+    /// only makes a system call, so it is safe to call between `fork` and `exec`. Does
+    /// nothing on architectures whose system call number is not known.
+    pub(super) fn set(pid: u32, priority: IoPriority) -> IoResult<()> {
+        let Some(number) = SYS_IOPRIO_SET else {
+            return Err(Error::from(std::io::ErrorKind::Unsupported));
+        };
+        let value = match priority {
+            IoPriority::Idle => IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+            IoPriority::BestEffort(level) => {
+                IOPRIO_CLASS_BE << IOPRIO_CLASS_SHIFT | std::ffi::c_int::from(level.min(7))
+            }
+        };
+        let pid = std::ffi::c_int::try_from(pid).map_err(Error::other)?;
+
+        // SAFETY: ioprio_set takes three integers and has no memory safety requirements
+        match unsafe { syscall(number, IOPRIO_WHO_PROCESS, pid, value) } {
+            -1 => Err(Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CommandIoPriority<'a> {
+    command: &'a mut Command,
+    priority: IoPriority,
+    /// Whether the priority is set in the child before it runs the program, rather than
+    /// after it is spawned
+    in_child: bool,
+    configured: capture::Configured,
+}
+
+impl<'a> CommandIoPriority<'a> {
+    /// The I/O priority the command runs with
+    pub fn get_io_priority(&self) -> IoPriority {
+        self.priority
+    }
+
+    /// Apply the priority to a child which was just spawned, if it was not set in the
+    /// child. Failing to lower the priority does not stop the command.
+    fn after_spawned(&self, child: &Child) {
+        #[cfg(target_os = "linux")]
+        if !self.in_child {
+            sys::set(child.id(), self.priority).ok();
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = child;
+    }
+
+    fn wrap(command: &'a mut Command, priority: IoPriority) -> Self {
+        #[cfg(target_os = "linux")]
+        let in_child = !prefers_posix_spawn();
+        #[cfg(target_os = "linux")]
+        if in_child {
+            use std::os::unix::process::CommandExt;
+
+            // SAFETY: the closure only makes a system call, which is async-signal-safe.
+            // Failing to lower the priority does not stop the command.
+            unsafe {
+                command.pre_exec(move || {
+                    sys::set(0, priority).ok();
+                    Ok(())
+                });
+            }
+        }
+
+        #[cfg(windows)]
+        let in_child = {
+            use std::os::windows::process::CommandExt;
+
+            const IDLE_PRIORITY_CLASS: u32 = 0x0000_0040;
+            const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x0000_4000;
+
+            match priority {
+                IoPriority::Idle => {
+                    command.creation_flags(IDLE_PRIORITY_CLASS);
+                }
+                IoPriority::BestEffort(level) if level > 4 => {
+                    command.creation_flags(BELOW_NORMAL_PRIORITY_CLASS);
+                }
+                IoPriority::BestEffort(_) => {}
+            }
+            true
+        };
+
+        #[cfg(not(any(target_os = "linux", windows)))]
+        let in_child = true;
+
+        Self {
+            command,
+            priority,
+            in_child,
+            configured: capture::Configured::default(),
+        }
+    }
+}
+
+impl<'a> HasCommand for CommandIoPriority<'a> {
+    fn command(&self) -> &Command {
+        self.command
+    }
+
+    fn command_mut(&mut self) -> &mut Command {
+        self.command
+    }
+}
+
+impl<'a> CommandWrap for CommandIoPriority<'a> {
+    fn describe(&self) -> Option<String> {
+        Some(format!("runs with I/O priority {:?}", self.priority))
+    }
+
+    fn on_stdin(&mut self, _cfg: &Stdio) {
+        self.configured.stdin = true;
+    }
+
+    fn on_stdout(&mut self, _cfg: &Stdio) {
+        self.configured.stdout = true;
+    }
+
+    fn on_stderr(&mut self, _cfg: &Stdio) {
+        self.configured.stderr = true;
+    }
+
+    fn spawn(&mut self) -> IoResult<Child> {
+        let child = self.command.spawn()?;
+        self.after_spawned(&child);
+        Ok(child)
+    }
+
+    fn output(&mut self) -> IoResult<Output> {
+        if self.in_child {
+            return self.command.output();
+        }
+
+        let child = capture::spawn_for_output(self.command, self.configured)?;
+        self.after_spawned(&child);
+        capture::output(child)
+    }
+
+    fn status(&mut self) -> IoResult<ExitStatus> {
+        if self.in_child {
+            return self.command.status();
+        }
+
+        let mut child = self.command.spawn()?;
+        self.after_spawned(&child);
+        child.wait()
+    }
+}
+
+#[cfg(feature = "check")]
+impl<'a> CommandExtCheck for CommandIoPriority<'a> {
+    type Error = CommandExtError;
+
+    fn check(&mut self) -> Result<Output, Self::Error> {
+        check_wrapper(self)
+    }
+}
+
+pub trait CommandExtIoPriority {
+    /// Run the command with the I/O priority `priority`
+    fn io_priority(&mut self, priority: IoPriority) -> CommandIoPriority<'_>;
+}
+
+impl CommandExtIoPriority for Command {
+    fn io_priority(&mut self, priority: IoPriority) -> CommandIoPriority<'_> {
+        CommandIoPriority::wrap(self, priority)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::{Command, Stdio};
+
+    use super::{CommandIoPriority, IoPriority};
+    use crate::{CommandExtIoPriority, CommandWrap};
+
+    /// The I/O priority reported by `ionice` for `pid`, if it is installed
+    fn ionice(pid: &str) -> Option<String> {
+        let output = Command::new("ionice").args(["-p", pid]).output().ok()?;
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    #[cfg(target_os = "linux")]
+    /// Test that the priority is applied both in the child and after spawning it
+    fn test_io_priority() -> anyhow::Result<()> {
+        if ionice("0").is_none() {
+            return Ok(());
+        }
+
+        let output = Command::new("sh")
+            .args(["-c", "ionice -p $$"])
+            .io_priority(IoPriority::Idle)
+            .output()?;
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "idle");
+
+        let mut command = Command::new("sleep");
+        command.arg("0.2");
+        let mut wrapped = CommandIoPriority {
+            command: &mut command,
+            priority: IoPriority::BestEffort(7),
+            in_child: false,
+            configured: Default::default(),
+        };
+        let mut child = wrapped.spawn()?;
+        assert_eq!(
+            ionice(&child.id().to_string()).as_deref(),
+            Some("best-effort: prio 7")
+        );
+        assert!(child.wait()?.success());
+
+        // Streams configured through the wrapper are kept when it collects output itself
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo out; echo err >&2"]);
+        let mut wrapped = CommandIoPriority {
+            command: &mut command,
+            priority: IoPriority::Idle,
+            in_child: false,
+            configured: Default::default(),
+        };
+        let output = wrapped.stdout(Stdio::null()).output()?;
+        assert!(output.stdout.is_empty());
+        assert_eq!(output.stderr, b"err\n");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,471 @@
+//! Named presets of reporting, environment, timeout, and retry settings
+//!
+//! Profiles are defined once and then selected per command with
+//! [`CommandExtProfile::profile`], or for every command by setting the
+//! `COMMAND_EXT_PROFILE` environment variable to the name of a profile.
+//!
+//! A command executed with a profile which allows more than one attempt is executed again
+//! after the profile's retry delay when it exits unsuccessfully or times out, until it
+//! succeeds or the attempts run out. Commands which fail to spawn are not retried. Spawning
+//! a command only applies the environment settings of its profile, since the child is not
+//! waited for.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::{process::Command, time::Duration};
+//! # use command_ext::{profile, CommandExtCheck, CommandExtProfile, Verbosity};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! profile::define("ci", |p| {
+//!     p.reporting(|d| d.verbosity(Verbosity::Verbose))
+//!         .env("CARGO_TERM_COLOR", "never")
+//!         .attempts(3)
+//!         .retry_delay(Duration::from_secs(1))
+//! });
+//! profile::define("local", |p| p.reporting(|d| d.verbosity(Verbosity::Quiet)));
+//!
+//! Command::new("echo").arg("x").profile("ci").check()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    env::var_os,
+    ffi::{OsStr, OsString},
+    io::{Error, ErrorKind, Result as IoResult},
+    process::{Child, Command, ExitStatus, Output},
+    sync::RwLock,
+    time::Duration,
+};
+
+#[cfg(feature = "timeout")]
+use crate::timeout::CommandTimeout;
+#[cfg(feature = "check")]
+use crate::{
+    check::{check_with, preflight},
+    CommandExtCheck, CommandExtError,
+};
+use crate::{
+    clock,
+    defaults::Defaults,
+    wrap::{ExecutionInfo, HasCommand},
+    CommandWrap,
+};
+
+/// The environment variable naming the profile applied to every command
+pub const PROFILE_ENV: &str = "COMMAND_EXT_PROFILE";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A named preset of reporting, environment, timeout, and retry settings
+pub struct Profile {
+    name: String,
+    reporting: Defaults,
+    envs: Vec<(OsString, Option<OsString>)>,
+    timeout: Option<Duration>,
+    attempts: usize,
+    retry_delay: Duration,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            reporting: Defaults::default(),
+            envs: Vec::new(),
+            timeout: None,
+            attempts: 1,
+            retry_delay: Duration::ZERO,
+        }
+    }
+}
+
+impl Profile {
+    /// Create an empty profile, which executes commands once without a timeout
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// The name of the profile
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The reporting settings of the profile
+    pub fn reporting_defaults(&self) -> &Defaults {
+        &self.reporting
+    }
+
+    /// The environment variables set (`Some`) or removed (`None`) by the profile
+    pub fn envs(&self) -> &[(OsString, Option<OsString>)] {
+        &self.envs
+    }
+
+    /// The time each attempt of a command using this profile may run for, if it is limited
+    pub fn get_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// The number of times a command using this profile is attempted
+    pub fn get_attempts(&self) -> usize {
+        self.attempts
+    }
+
+    /// The time to wait between attempts of a command using this profile
+    pub fn get_retry_delay(&self) -> Duration {
+        self.retry_delay
+    }
+
+    /// Configure how commands using this profile are reported
+    pub fn reporting<F>(&mut self, f: F) -> &mut Self
+    where
+        F: FnOnce(&mut Defaults) -> &mut Defaults,
+    {
+        f(&mut self.reporting);
+        self
+    }
+
+    /// Set an environment variable for commands using this profile
+    pub fn env<K, V>(&mut self, key: K, val: V) -> &mut Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.envs
+            .push((key.as_ref().to_owned(), Some(val.as_ref().to_owned())));
+        self
+    }
+
+    /// Remove an environment variable for commands using this profile
+    pub fn env_remove<K: AsRef<OsStr>>(&mut self, key: K) -> &mut Self {
+        self.envs.push((key.as_ref().to_owned(), None));
+        self
+    }
+
+    #[cfg(feature = "timeout")]
+    /// Kill each attempt of a command using this profile once it runs for longer than
+    /// `timeout`
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set the number of times a command using this profile is attempted. At least one
+    /// attempt is always made.
+    pub fn attempts(&mut self, attempts: usize) -> &mut Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    /// Set the time to wait between attempts of a command using this profile
+    pub fn retry_delay(&mut self, delay: Duration) -> &mut Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    /// Apply the environment settings of this profile to `command`
+    pub fn apply(&self, command: &mut Command) {
+        self.envs.iter().for_each(|(k, v)| match v {
+            Some(v) => {
+                command.env(k, v);
+            }
+            None => {
+                command.env_remove(k);
+            }
+        });
+    }
+
+    /// Execute `command` for its output with this profile applied
+    pub fn output(&self, command: &mut Command) -> IoResult<Output> {
+        self.apply(command);
+        self.retry(
+            || {
+                crate::defaults::output_reported(command, &self.reporting, |command| {
+                    self.output_once(command)
+                })
+            },
+            |output| output.status.success(),
+        )
+    }
+
+    /// Execute `command` for its status with this profile applied
+    pub fn status(&self, command: &mut Command) -> IoResult<ExitStatus> {
+        self.apply(command);
+        self.retry(|| self.status_once(command), ExitStatus::success)
+    }
+
+    /// Execute one attempt of `command` for its output
+    fn output_once(&self, command: &mut Command) -> IoResult<Output> {
+        #[cfg(feature = "timeout")]
+        if let Some(timeout) = self.timeout {
+            return CommandTimeout::new(command, timeout).output();
+        }
+
+        command.output()
+    }
+
+    /// Execute one attempt of `command` for its status
+    fn status_once(&self, command: &mut Command) -> IoResult<ExitStatus> {
+        #[cfg(feature = "timeout")]
+        if let Some(timeout) = self.timeout {
+            return CommandTimeout::new(command, timeout).status();
+        }
+
+        command.status()
+    }
+
+    /// Run `execute` until it succeeds, fails with anything but a timeout, or the attempts
+    /// run out
+    fn retry<T, E, S>(&self, mut execute: E, succeeded: S) -> IoResult<T>
+    where
+        E: FnMut() -> IoResult<T>,
+        S: Fn(&T) -> bool,
+    {
+        let mut attempt = 1;
+        loop {
+            let result = execute();
+            let retryable = match &result {
+                Ok(value) => !succeeded(value),
+                Err(e) => e.kind() == ErrorKind::TimedOut,
+            };
+
+            if !retryable || attempt >= self.attempts {
+                return result;
+            }
+
+            clock::current().sleep(self.retry_delay);
+            attempt += 1;
+        }
+    }
+}
+
+static PROFILES: RwLock<BTreeMap<String, Profile>> = RwLock::new(BTreeMap::new());
+
+/// Define the profile `name`, replacing any existing profile with the same name
+pub fn define<S, F>(name: S, f: F)
+where
+    S: Into<String>,
+    F: FnOnce(&mut Profile) -> &mut Profile,
+{
+    let mut profile = Profile::new(name);
+    f(&mut profile);
+    PROFILES
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(profile.name.clone(), profile);
+}
+
+/// The profile named `name`, if it is defined
+pub fn get(name: &str) -> Option<Profile> {
+    PROFILES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(name)
+        .cloned()
+}
+
+/// The profile selected by the `COMMAND_EXT_PROFILE` environment variable, if it is set
+/// to the name of a defined profile. Otherwise, when running under CI, the
+/// [`crate::ci::PROFILE`] profile if it is defined.
+pub fn active() -> Option<Profile> {
+    match var_os(PROFILE_ENV) {
+        Some(name) => get(&name.to_string_lossy()),
+        #[cfg(feature = "ci")]
+        None => crate::ci::detect().and_then(|_| get(crate::ci::PROFILE)),
+        #[cfg(not(feature = "ci"))]
+        None => None,
+    }
+}
+
+#[derive(Debug)]
+/// A command which executes with a named profile applied
+pub struct CommandProfile<'a> {
+    command: &'a mut Command,
+    name: String,
+    last_execution: Option<ExecutionInfo>,
+}
+
+impl<'a> CommandProfile<'a> {
+    /// The name of the profile the command executes with
+    pub fn get_profile(&self) -> &str {
+        &self.name
+    }
+
+    fn profile(&self) -> IoResult<Profile> {
+        get(&self.name).ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("no profile named {} is defined", self.name),
+            )
+        })
+    }
+}
+
+impl<'a> HasCommand for CommandProfile<'a> {
+    fn command(&self) -> &Command {
+        self.command
+    }
+
+    fn command_mut(&mut self) -> &mut Command {
+        self.command
+    }
+}
+
+impl<'a> CommandWrap for CommandProfile<'a> {
+    fn spawn(&mut self) -> IoResult<Child> {
+        self.profile()?.apply(self.command);
+        self.command.spawn()
+    }
+
+    fn last_execution(&self) -> Option<ExecutionInfo> {
+        self.last_execution
+    }
+
+    fn describe(&self) -> Option<String> {
+        Some(match get(&self.name) {
+            Some(profile) => {
+                let mut settings = vec![format!("{} environment changes", profile.envs().len())];
+                if let Some(timeout) = profile.get_timeout() {
+                    settings.push(format!("killed after {timeout:?}"));
+                }
+                if profile.get_attempts() > 1 {
+                    settings.push(format!("{} attempts", profile.get_attempts()));
+                }
+                format!("profile {} ({})", self.name, settings.join(", "))
+            }
+            None => format!("profile {} (not defined)", self.name),
+        })
+    }
+
+    fn output(&mut self) -> IoResult<Output> {
+        let profile = self.profile()?;
+        let (output, info) = ExecutionInfo::measure(|| profile.output(self.command));
+        self.last_execution = Some(info.with_output(&output));
+        output
+    }
+
+    fn status(&mut self) -> IoResult<ExitStatus> {
+        let profile = self.profile()?;
+        let (status, info) = ExecutionInfo::measure(|| profile.status(self.command));
+        self.last_execution = Some(info.with_status(&status));
+        status
+    }
+}
+
+pub trait CommandExtProfile {
+    fn profile<S: Into<String>>(&mut self, name: S) -> CommandProfile<'_>;
+}
+
+impl CommandExtProfile for Command {
+    fn profile<S: Into<String>>(&mut self, name: S) -> CommandProfile<'_> {
+        CommandProfile {
+            command: self,
+            name: name.into(),
+            last_execution: None,
+        }
+    }
+}
+
+#[cfg(feature = "check")]
+impl<'a> CommandExtCheck for CommandProfile<'a> {
+    type Error = CommandExtError;
+
+    fn check(&mut self) -> Result<Output, Self::Error> {
+        let profile = get(&self.name).ok_or_else(|| CommandExtError::UnknownProfile {
+            name: self.name.clone(),
+        })?;
+        preflight(self.command)?;
+
+        let (output, info) = ExecutionInfo::measure(|| profile.output(self.command));
+        self.last_execution = Some(info.with_output(&output));
+        check_with(self, output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use super::{define, get};
+    use crate::{CommandExtProfile, CommandWrap, Verbosity};
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_profile() -> anyhow::Result<()> {
+        define("test_profile", |p| {
+            p.reporting(|d| d.verbosity(Verbosity::Verbose))
+                .env("PROFILE_VAR", "x")
+        });
+        assert_eq!(get("test_profile").map(|p| p.envs().len()), Some(1));
+
+        let output = Command::new("bash")
+            .args(["-c", "echo $PROFILE_VAR"])
+            .profile("test_profile")
+            .output()?;
+        assert_eq!(output.stdout, b"x\n");
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that commands are attempted again until they succeed or the attempts run out
+    fn test_profile_attempts() -> anyhow::Result<()> {
+        define("test_profile_attempts", |p| p.attempts(3));
+        let counter =
+            std::env::temp_dir().join(format!("command-ext-attempts-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&counter);
+        let script = format!(
+            "echo x >> {0}; test $(wc -l < {0}) -ge 2",
+            counter.display()
+        );
+
+        let status = Command::new("sh")
+            .args(["-c", &script])
+            .profile("test_profile_attempts")
+            .status()?;
+        assert!(status.success());
+        assert_eq!(std::fs::read_to_string(&counter)?.lines().count(), 2);
+        std::fs::remove_file(&counter)?;
+
+        let output = Command::new("false")
+            .profile("test_profile_attempts")
+            .output()?;
+        assert!(!output.status.success());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "timeout")]
+    #[cfg_attr(miri, ignore)]
+    /// Test that each attempt is killed once it runs for longer than the profile's timeout
+    fn test_profile_timeout() {
+        use std::{io::ErrorKind, time::Duration};
+
+        define("test_profile_timeout", |p| {
+            p.timeout(Duration::from_millis(100)).attempts(2)
+        });
+        let profile = get("test_profile_timeout").unwrap();
+        assert_eq!(profile.get_timeout(), Some(Duration::from_millis(100)));
+
+        let error = Command::new("sleep")
+            .arg("5")
+            .profile("test_profile_timeout")
+            .output()
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    #[cfg(feature = "check")]
+    fn test_unknown_profile() {
+        use crate::{CommandExtCheck, CommandExtError};
+
+        assert!(matches!(
+            Command::new("echo").profile("missing").check(),
+            Err(CommandExtError::UnknownProfile { name }) if name == "missing"
+        ));
+    }
+}
@@ -0,0 +1,548 @@
+//! Quoting and splitting arguments for shells and Windows command lines
+//!
+//! [`std::process::Command`] passes arguments to a program directly, so they never need
+//! quoting. Command lines which are interpreted again, such as the remote command of `ssh`,
+//! `sh -c` scripts, `docker exec` invocations or `cmd /c` lines, need each argument quoted
+//! for the shell or runtime which will split them. The functions in this module quote a
+//! single argument so it is read back unchanged:
+//!
+//! * [`quote_posix`] for POSIX shells (`sh`, `bash`, `zsh`, and `ssh` remote commands)
+//! * [`quote_windows`] for programs which split their command line like the Microsoft C
+//!   runtime (`CommandLineToArgvW`)
+//! * [`quote_windows_cmd`] for lines passed through `cmd.exe`
+//! * [`quote_powershell`] for PowerShell
+//!
+//! The inverse, [`split_posix`] and [`split_windows`], split a command line written as a
+//! string into arguments by the same rules, and [`shell`] builds a [`Command`] from one,
+//! for configuration files where users write command lines as strings. Arguments
+//! containing a NUL byte cannot be passed to a program at all, and are quoted
+//! as if the NUL were any other character.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::quote::quote_posix;
+//! let file = "it's here.txt";
+//! let mut ssh = Command::new("ssh");
+//! ssh.arg("host").arg(
+//!     ["cat", file]
+//!         .map(|a| quote_posix(a.as_ref()).into_string().unwrap())
+//!         .join(" "),
+//! );
+//!
+//! assert_eq!(ssh.get_args().nth(1).unwrap(), r#"cat 'it'\''s here.txt'"#);
+//! ```
+//!
+//! ```rust
+//! # use command_ext::quote::{shell, split_posix};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! assert_eq!(
+//!     split_posix(r#"cc -o "my program" 'main.c' # build"#)?,
+//!     ["cc", "-o", "my program", "main.c"]
+//! );
+//!
+//! let output = shell("echo 'hello world'")?.output()?;
+//! # #[cfg(unix)]
+//! assert_eq!(output.stdout, b"hello world\n");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    ffi::{OsStr, OsString},
+    iter::repeat_n,
+    mem::take,
+    process::Command,
+};
+
+use crate::CommandExtError;
+
+/// Build an `OsString` from the encoded bytes of an `OsStr` with ASCII bytes inserted
+/// before or in place of ASCII bytes
+fn from_bytes(bytes: Vec<u8>) -> OsString {
+    // SAFETY: every caller copies the bytes of a valid `OsStr`, only inserting or removing
+    // ASCII bytes next to ASCII bytes, which keeps the encoding valid
+    unsafe { OsString::from_encoded_bytes_unchecked(bytes) }
+}
+
+/// Quote `arg` for a POSIX shell. Arguments made only of characters which are never
+/// special are returned unchanged, and others are wrapped in single quotes, which keep
+/// everything but a single quote literal.
+pub fn quote_posix(arg: &OsStr) -> OsString {
+    let bytes = arg.as_encoded_bytes();
+    let plain = |b: &u8| b.is_ascii_alphanumeric() || b"_-./=:,+@%".contains(b);
+
+    if !bytes.is_empty() && bytes.iter().all(plain) {
+        return arg.to_os_string();
+    }
+
+    let mut quoted = vec![b'\''];
+    for &b in bytes {
+        match b {
+            b'\'' => quoted.extend_from_slice(b"'\\''"),
+            b => quoted.push(b),
+        }
+    }
+    quoted.push(b'\'');
+    from_bytes(quoted)
+}
+
+/// Quote `arg` for a Windows program which splits its command line like the Microsoft C
+/// runtime. Arguments without spaces, tabs, newlines or double quotes are returned
+/// unchanged, and others are wrapped in double quotes, with backslashes doubled where
+/// they precede a double quote.
+pub fn quote_windows(arg: &OsStr) -> OsString {
+    let bytes = arg.as_encoded_bytes();
+
+    if !bytes.is_empty() && !bytes.iter().any(|b| b" \t\n\x0b\"".contains(b)) {
+        return arg.to_os_string();
+    }
+
+    let mut quoted = vec![b'"'];
+    let mut backslashes = 0;
+    for &b in bytes {
+        match b {
+            b'\\' => backslashes += 1,
+            b'"' => {
+                // Backslashes before a quote are escapes, so each must be doubled, and the
+                // quote itself escaped
+                quoted.extend(repeat_n(b'\\', backslashes * 2 + 1));
+                quoted.push(b'"');
+                backslashes = 0;
+                continue;
+            }
+            _ => {}
+        }
+        if b != b'\\' {
+            quoted.extend(repeat_n(b'\\', backslashes));
+            quoted.push(b);
+            backslashes = 0;
+        }
+    }
+    // Backslashes before the closing quote must be doubled too
+    quoted.extend(repeat_n(b'\\', backslashes * 2));
+    quoted.push(b'"');
+    from_bytes(quoted)
+}
+
+/// Quote `arg` for a command line run by `cmd.exe`, such as the argument of `cmd /c`. The
+/// argument is quoted with [`quote_windows`] for the program, and then every character
+/// `cmd.exe` treats specially is escaped with `^`. Environment variable references
+/// (`%NAME%`) cannot be escaped on the command line, so a `%` is left as is, and is only
+/// literal if no variable has the name which follows it.
+pub fn quote_windows_cmd(arg: &OsStr) -> OsString {
+    let quoted = quote_windows(arg);
+    let mut escaped = Vec::new();
+
+    for &b in quoted.as_encoded_bytes() {
+        if b"()!^\"<>&|".contains(&b) {
+            escaped.push(b'^');
+        }
+        escaped.push(b);
+    }
+    from_bytes(escaped)
+}
+
+/// The UTF-8 encodings of the typographic single quotes, which PowerShell treats like `'`
+const SMART_QUOTES: [&[u8]; 4] = [
+    "\u{2018}".as_bytes(),
+    "\u{2019}".as_bytes(),
+    "\u{201a}".as_bytes(),
+    "\u{201b}".as_bytes(),
+];
+
+/// Quote `arg` for PowerShell. Arguments are wrapped in single quotes, which keep
+/// everything literal, with single quotes (including typographic ones) doubled.
+pub fn quote_powershell(arg: &OsStr) -> OsString {
+    let bytes = arg.as_encoded_bytes();
+    let mut quoted = vec![b'\''];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'\'' {
+            quoted.extend_from_slice(b"''");
+            i += 1;
+        } else if let Some(q) = SMART_QUOTES.iter().find(|q| bytes[i..].starts_with(q)) {
+            quoted.extend_from_slice(q);
+            quoted.extend_from_slice(q);
+            i += q.len();
+        } else {
+            quoted.push(bytes[i]);
+            i += 1;
+        }
+    }
+    quoted.push(b'\'');
+
+    // SAFETY: the typographic quotes are duplicated whole, so the encoding stays valid
+    unsafe { OsString::from_encoded_bytes_unchecked(quoted) }
+}
+
+/// An error for the command line `line` which cannot be split because of `reason`
+fn invalid(line: &str, reason: &str) -> CommandExtError {
+    CommandExtError::InvalidCommandLine {
+        line: line.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+/// Split `line` into arguments like a POSIX shell, without expanding anything. Words are
+/// separated by unquoted whitespace. Single quotes keep everything literal, double quotes
+/// keep everything literal except for backslashes escaping `$`, `` ` ``, `"`, `\\` and
+/// newlines, and an unquoted backslash escapes any character. A backslash before a newline
+/// joins the lines, and a `#` at the start of a word begins a comment. Unterminated quotes
+/// and a trailing backslash are errors.
+pub fn split_posix(line: &str) -> Result<Vec<String>, CommandExtError> {
+    let mut args = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                args.extend(word.take());
+            }
+            '#' if word.is_none() => {
+                if !chars.any(|c| c == '\n') {
+                    break;
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') => {}
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err(invalid(line, "trailing backslash")),
+            },
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(invalid(line, "unterminated single quote")),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('\n') => {}
+                            Some(c @ ('$' | '`' | '"' | '\\')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(invalid(line, "unterminated double quote")),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(invalid(line, "unterminated double quote")),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(word);
+
+    Ok(args)
+}
+
+/// Split `line` into arguments like `CommandLineToArgvW` and the Microsoft C runtime. The
+/// first argument is the program, which ends at the first whitespace, or at the closing
+/// quote if it starts with one, and is taken literally. The remaining arguments are
+/// separated by whitespace outside double quotes. Backslashes are literal unless they
+/// precede a double quote, where each pair becomes one backslash and an odd one makes the
+/// quote literal. Inside quotes, `""` is a literal quote. An unterminated quote extends to
+/// the end of the line.
+pub fn split_windows(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut chars = line
+        .trim_start_matches([' ', '\t', '\n', '\r'])
+        .chars()
+        .peekable();
+
+    match chars.peek() {
+        None => return args,
+        Some('"') => {
+            chars.next();
+            args.push(chars.by_ref().take_while(|c| *c != '"').collect());
+        }
+        Some(_) => {
+            let mut program = String::new();
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                program.push(c);
+            }
+            args.push(program);
+        }
+    }
+
+    let mut word = String::new();
+    // Whether an argument was started, which an empty pair of quotes does
+    let mut started = false;
+    let mut quoted = false;
+    let mut backslashes = 0;
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            backslashes += 1;
+            continue;
+        }
+
+        if c == '"' {
+            word.extend(repeat_n('\\', backslashes / 2));
+            // An escaped quote, or a doubled quote inside quotes, is literal
+            if backslashes % 2 == 1 || (quoted && chars.next_if_eq(&'"').is_some()) {
+                word.push('"');
+            } else {
+                quoted = !quoted;
+            }
+            started = true;
+            backslashes = 0;
+            continue;
+        }
+
+        if backslashes > 0 {
+            word.extend(repeat_n('\\', backslashes));
+            started = true;
+            backslashes = 0;
+        }
+
+        if (c == ' ' || c == '\t') && !quoted {
+            if started {
+                args.push(take(&mut word));
+                started = false;
+            }
+        } else {
+            word.push(c);
+            started = true;
+        }
+    }
+
+    word.extend(repeat_n('\\', backslashes));
+    if started || backslashes > 0 {
+        args.push(word);
+    }
+
+    args
+}
+
+/// A command for the command line `line`, split with [`split_windows`] on Windows hosts
+/// and [`split_posix`] elsewhere. The line is not run by a shell, so it cannot use
+/// redirections, pipes or variables. Empty lines are an error.
+pub fn shell(line: &str) -> Result<Command, CommandExtError> {
+    let args = if cfg!(windows) {
+        split_windows(line)
+    } else {
+        split_posix(line)?
+    };
+
+    let mut args = args.into_iter();
+    let program = args.next().ok_or_else(|| invalid(line, "no program"))?;
+    let mut command = Command::new(program);
+    command.args(args);
+    Ok(command)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{ffi::OsStr, process::Command};
+
+    use super::{
+        quote_posix, quote_powershell, quote_windows, quote_windows_cmd, shell, split_posix,
+        split_windows,
+    };
+
+    /// Arguments which are awkward to quote
+    const AWKWARD: &[&str] = &[
+        "",
+        " ",
+        "plain",
+        "with space",
+        "it's",
+        "''",
+        "\"double\"",
+        "$HOME",
+        "`uname`",
+        "$(uname)",
+        "a\\b",
+        "trailing\\",
+        "back\\\"slash",
+        "tab\tand\nnewline",
+        "*?[a]",
+        "~user",
+        "a;b&c|d>e<f",
+        "#comment",
+        "!bang",
+        "%PATH%",
+        "ünïcödé ✓",
+        "-n",
+    ];
+
+    #[test]
+    fn test_quote_posix() {
+        let q = |s: &str| quote_posix(OsStr::new(s)).into_string().unwrap();
+        assert_eq!(q("plain"), "plain");
+        assert_eq!(q("a/b-c_d.e=f:g,h+i@j%k"), "a/b-c_d.e=f:g,h+i@j%k");
+        assert_eq!(q(""), "''");
+        assert_eq!(q("with space"), "'with space'");
+        assert_eq!(q("it's"), r"'it'\''s'");
+        assert_eq!(q("$HOME"), "'$HOME'");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    #[cfg_attr(miri, ignore)]
+    /// Test that every awkward argument survives a round trip through `sh`
+    fn test_quote_posix_sh() -> anyhow::Result<()> {
+        for arg in AWKWARD {
+            let script = format!(
+                "printf %s {}",
+                quote_posix(OsStr::new(arg)).into_string().unwrap()
+            );
+            let output = Command::new("sh").args(["-c", &script]).output()?;
+            assert_eq!(String::from_utf8_lossy(&output.stdout), *arg, "{script}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_quote_posix_non_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let arg = OsStr::from_bytes(b"a'\xff");
+        assert_eq!(quote_posix(arg).as_bytes(), b"'a'\\''\xff'");
+    }
+
+    #[test]
+    fn test_quote_windows() {
+        let q = |s: &str| quote_windows(OsStr::new(s)).into_string().unwrap();
+        assert_eq!(q("plain"), "plain");
+        assert_eq!(q(r"C:\Program Files\x"), r#""C:\Program Files\x""#);
+        assert_eq!(q(r"C:\path\"), r"C:\path\");
+        assert_eq!(q(r"with space\"), r#""with space\\""#);
+        assert_eq!(q(""), r#""""#);
+        assert_eq!(q(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(q(r#"a\"b"#), r#""a\\\"b""#);
+        assert_eq!(q(r"a\\b c"), r#""a\\b c""#);
+    }
+
+    #[test]
+    fn test_quote_windows_cmd() {
+        let q = |s: &str| quote_windows_cmd(OsStr::new(s)).into_string().unwrap();
+        assert_eq!(q("plain"), "plain");
+        assert_eq!(q("a&b"), "a^&b");
+        assert_eq!(q("a b"), r#"^"a b^""#);
+        assert_eq!(q(r#"x "y" | z"#), r#"^"x \^"y\^" ^| z^""#);
+        assert_eq!(q("(a)<b>!c^"), "^(a^)^<b^>^!c^^");
+        assert_eq!(q("%PATH%"), "%PATH%");
+    }
+
+    #[test]
+    fn test_quote_powershell() {
+        let q = |s: &str| quote_powershell(OsStr::new(s)).into_string().unwrap();
+        assert_eq!(q("plain"), "'plain'");
+        assert_eq!(q(""), "''");
+        assert_eq!(q("it's"), "'it''s'");
+        assert_eq!(q("$env:PATH"), "'$env:PATH'");
+        assert_eq!(q("it\u{2019}s"), "'it\u{2019}\u{2019}s'");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that every awkward argument survives a round trip through PowerShell, when it
+    /// is installed
+    fn test_quote_powershell_pwsh() -> anyhow::Result<()> {
+        for arg in AWKWARD {
+            let script = format!(
+                "[Console]::Out.Write({})",
+                quote_powershell(OsStr::new(arg)).into_string().unwrap()
+            );
+            let output = match Command::new("pwsh")
+                .args(["-NoProfile", "-Command", &script])
+                .output()
+            {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+                output => output?,
+            };
+            assert_eq!(String::from_utf8_lossy(&output.stdout), *arg, "{script}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_posix() -> anyhow::Result<()> {
+        assert_eq!(split_posix("  a  b\tc\n")?, ["a", "b", "c"]);
+        assert_eq!(
+            split_posix(r#"a "b c" 'd e' f\ g"#)?,
+            ["a", "b c", "d e", "f g"]
+        );
+        assert_eq!(split_posix(r#"'' "" x"#)?, ["", "", "x"]);
+        assert_eq!(split_posix(r#""a\"b\$c\d""#)?, [r#"a"b$c\d"#]);
+        assert_eq!(split_posix(r"'a\b'")?, [r"a\b"]);
+        assert_eq!(split_posix("a\\\nb")?, ["ab"]);
+        assert_eq!(split_posix("a#b # comment\nc")?, ["a#b", "c"]);
+        assert_eq!(split_posix("# only a comment")?, Vec::<String>::new());
+        assert!(split_posix("'open").is_err());
+        assert!(split_posix("\"open").is_err());
+        assert!(split_posix("trailing\\").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_windows() {
+        assert_eq!(
+            split_windows(r#""C:\Program Files\x.exe" a "b c""#),
+            [r"C:\Program Files\x.exe", "a", "b c"]
+        );
+        assert_eq!(split_windows(r"C:\x\ a\b"), [r"C:\x\", r"a\b"]);
+        assert_eq!(split_windows(r#"x a\\"b c" d"#), ["x", r"a\b c", "d"]);
+        assert_eq!(split_windows(r#"x a\"b"#), ["x", r#"a"b"#]);
+        assert_eq!(split_windows(r#"x "" """#), ["x", "", ""]);
+        assert_eq!(split_windows(r#"x "a""b""#), ["x", r#"a"b"#]);
+        assert_eq!(
+            split_windows(r#"x "unterminated y"#),
+            ["x", "unterminated y"]
+        );
+        assert_eq!(split_windows("  "), Vec::<String>::new());
+    }
+
+    #[test]
+    /// Test that splitting quoted arguments gives back the arguments
+    fn test_split_quoted() -> anyhow::Result<()> {
+        let posix = AWKWARD
+            .iter()
+            .map(|a| quote_posix(OsStr::new(a)).into_string().unwrap())
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert_eq!(split_posix(&posix)?, AWKWARD);
+
+        let windows = std::iter::once("program".to_string())
+            .chain(
+                AWKWARD
+                    .iter()
+                    .map(|a| quote_windows(OsStr::new(a)).into_string().unwrap()),
+            )
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert_eq!(split_windows(&windows)[1..], *AWKWARD);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shell() -> anyhow::Result<()> {
+        let command = shell("cc -o 'my program' main.c")?;
+        assert_eq!(command.get_program(), "cc");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            ["-o", "my program", "main.c"]
+        );
+        assert!(shell("   ").is_err());
+        Ok(())
+    }
+}
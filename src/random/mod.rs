@@ -0,0 +1,118 @@
+//! Seeding for randomized behavior, such as the jitter between retries
+//!
+//! Randomized parts of this crate draw their seeds from here. Seeds are random unless a
+//! seed is installed for the whole process with [`set_seed`], or for a closure on the
+//! current thread with [`with_seed`]. Under an installed seed, each randomized part draws
+//! the next seed of a fixed sequence, so a run which creates them in the same order makes
+//! the same choices, and recorded runs and tests are reproducible.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::{process::Command, sync::Arc, time::Duration};
+//! # use command_ext::{clock::{self, MockClock}, random, CommandExtCheck, CommandExtRetry};
+//! let delays = |seed| {
+//!     let mock = MockClock::new();
+//!     clock::with(Arc::new(mock.clone()), || {
+//!         random::with_seed(seed, || {
+//!             Command::new("false")
+//!                 .retry(4)
+//!                 .delay(Duration::from_secs(1))
+//!                 .jitter(true)
+//!                 .check()
+//!         })
+//!     })
+//!     .ok();
+//!     mock.sleeps()
+//! };
+//!
+//! assert_eq!(delays(7), delays(7));
+//! ```
+
+use std::{
+    cell::Cell,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::Mutex,
+};
+
+static GLOBAL: Mutex<Option<u64>> = Mutex::new(None);
+
+thread_local! {
+    static SCOPED: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Advance a splitmix64 state, returning the next value of its sequence
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Install `seed` for the whole process
+pub fn set_seed(seed: u64) {
+    *GLOBAL.lock().unwrap_or_else(|e| e.into_inner()) = Some(seed);
+}
+
+/// Restore random seeds for the whole process
+pub fn reset() {
+    *GLOBAL.lock().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Run `f` with `seed` installed on the current thread, taking precedence over the seed
+/// installed with [`set_seed`]
+pub fn with_seed<T, F>(seed: u64, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    /// Restores the previous seed of the thread, even if `f` panics
+    struct Restore(Option<u64>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            SCOPED.with(|scoped| scoped.set(self.0));
+        }
+    }
+
+    let _restore = Restore(SCOPED.with(|scoped| scoped.replace(Some(seed))));
+    f()
+}
+
+/// The next seed for a randomized part of this crate: the next of the sequence installed
+/// with [`with_seed`] or [`set_seed`], or a random seed
+#[cfg_attr(not(feature = "retry"), allow(unused))]
+pub(crate) fn seed() -> u64 {
+    let scoped = SCOPED.with(|scoped| {
+        scoped.get().map(|mut state| {
+            let seed = splitmix64(&mut state);
+            scoped.set(Some(state));
+            seed
+        })
+    });
+
+    scoped
+        .or_else(|| {
+            GLOBAL
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .as_mut()
+                .map(splitmix64)
+        })
+        .unwrap_or_else(|| RandomState::new().build_hasher().finish())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{seed, with_seed};
+
+    #[test]
+    /// Test that seeds follow the same sequence under the same installed seed
+    fn test_seed() {
+        let first = with_seed(1, || [seed(), seed()]);
+        assert_eq!(first, with_seed(1, || [seed(), seed()]));
+        assert_ne!(first[0], first[1]);
+        assert_ne!(first, with_seed(2, || [seed(), seed()]));
+    }
+}
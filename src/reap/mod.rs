@@ -0,0 +1,115 @@
+//! Extension trait to receive the exit status of a background command through a callback
+//!
+//! Commands started with [`CommandOnExit::spawn`] are waited on by a reaper thread from the
+//! [`pool`](crate::pool), which invokes the callback with the exit status once the child
+//! exits. The caller never blocks, and the child never lingers as a zombie.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::{process::Command, sync::mpsc::channel};
+//! # use command_ext::CommandExtOnExit;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let (sender, receiver) = channel();
+//! Command::new("bash")
+//!     .args(["-c", "exit 3"])
+//!     .on_exit(move |status| {
+//!         sender.send(status.ok().and_then(|s| s.code())).ok();
+//!     })
+//!     .spawn()?;
+//!
+//! assert_eq!(receiver.recv()?, Some(3));
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::Result as IoResult,
+    process::{Command, ExitStatus},
+};
+
+use crate::{pool, wrap::HasCommand};
+
+type Callback = Box<dyn FnOnce(IoResult<ExitStatus>) + Send + 'static>;
+
+/// A command whose exit status is delivered to a callback from a reaper thread
+pub struct CommandOnExit<'a> {
+    command: &'a mut Command,
+    callback: Option<Callback>,
+}
+
+impl<'a> std::fmt::Debug for CommandOnExit<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommandOnExit")
+            .field("command", &self.command)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> CommandOnExit<'a> {
+    /// Spawn the command and return its PID. The callback is invoked from a reaper thread
+    /// when the child exits. If spawning fails, the callback is kept for the next attempt.
+    /// Spawning again after a success reaps the new child without invoking any callback.
+    pub fn spawn(&mut self) -> IoResult<u32> {
+        let mut child = self.command.spawn()?;
+        let pid = child.id();
+
+        match self.callback.take() {
+            Some(callback) => drop(pool::spawn(move || callback(child.wait()))),
+            None => drop(pool::spawn(move || child.wait())),
+        }
+
+        Ok(pid)
+    }
+}
+
+impl<'a> HasCommand for CommandOnExit<'a> {
+    fn command(&self) -> &Command {
+        self.command
+    }
+
+    fn command_mut(&mut self) -> &mut Command {
+        self.command
+    }
+}
+
+pub trait CommandExtOnExit {
+    fn on_exit<F>(&mut self, callback: F) -> CommandOnExit<'_>
+    where
+        F: FnOnce(IoResult<ExitStatus>) + Send + 'static;
+}
+
+impl CommandExtOnExit for Command {
+    fn on_exit<F>(&mut self, callback: F) -> CommandOnExit<'_>
+    where
+        F: FnOnce(IoResult<ExitStatus>) + Send + 'static,
+    {
+        CommandOnExit {
+            command: self,
+            callback: Some(Box::new(callback)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{process::Command, sync::mpsc::channel, time::Duration};
+
+    use crate::CommandExtOnExit;
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_on_exit() -> anyhow::Result<()> {
+        let (sender, receiver) = channel();
+        let mut command = Command::new("sleep");
+        command.arg("0.1");
+        let mut on_exit = command.on_exit(move |status| {
+            sender.send(status.map(|s| s.success()).ok()).ok();
+        });
+
+        let pid = on_exit.spawn()?;
+        assert_ne!(pid, 0);
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5))?, Some(true));
+        Ok(())
+    }
+}
@@ -0,0 +1,341 @@
+//! A registry of named, reusable command invocations
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::CommandRegistry;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut registry = CommandRegistry::new();
+//! registry
+//!     .with_default(|c| {
+//!         c.env("CARGO_TERM_COLOR", "never");
+//!     })
+//!     .define("hello", || {
+//!         let mut c = Command::new("echo");
+//!         c.arg("hello");
+//!         c
+//!     });
+//!
+//! let output = registry.run("hello")?;
+//! assert_eq!(output.stdout, b"hello\n");
+//!
+//! registry.describe("hello", "Say hello");
+//! // NAME   DESCRIPTION  COMMAND
+//! // hello  Say hello    echo hello
+//! println!("{}", registry.listing());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    process::{Command, Output},
+};
+
+use crate::{host, CommandExtCheck, CommandExtError, Os};
+
+type Factory = Box<dyn Fn() -> Command + Send + Sync>;
+type DefaultFn = Box<dyn Fn(&mut Command) + Send + Sync>;
+
+struct Entry {
+    factory: Factory,
+    description: Option<String>,
+    platforms: Vec<Os>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A description of a registered command, as shown in [`CommandRegistry::listing`]
+pub struct CommandListing {
+    /// The name the command is registered under
+    pub name: String,
+    /// The description of the command, if one was given
+    pub description: Option<String>,
+    /// The command line the command runs, with defaults applied
+    pub command_line: String,
+    /// The platforms the command is restricted to, or empty if it runs everywhere
+    pub platforms: Vec<Os>,
+    /// Whether the command can run on the current host
+    pub available: bool,
+}
+
+#[derive(Default)]
+/// A set of named command factories sharing common defaults
+pub struct CommandRegistry {
+    commands: BTreeMap<String, Entry>,
+    defaults: Vec<DefaultFn>,
+}
+
+impl CommandRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a factory for the command `name`, replacing any existing command with the
+    /// same name
+    pub fn define<S, F>(&mut self, name: S, factory: F) -> &mut Self
+    where
+        S: Into<String>,
+        F: Fn() -> Command + Send + Sync + 'static,
+    {
+        self.commands.insert(
+            name.into(),
+            Entry {
+                factory: Box::new(factory),
+                description: None,
+                platforms: Vec::new(),
+            },
+        );
+        self
+    }
+
+    /// Set the description of the command `name`, shown in listings. Does nothing if no
+    /// command named `name` is registered.
+    pub fn describe<S, D>(&mut self, name: S, description: D) -> &mut Self
+    where
+        S: AsRef<str>,
+        D: Into<String>,
+    {
+        if let Some(entry) = self.commands.get_mut(name.as_ref()) {
+            entry.description = Some(description.into());
+        }
+        self
+    }
+
+    /// Restrict the command `name` to the given platform. May be called more than once to
+    /// allow several platforms. Running the command on any other platform is skipped.
+    pub fn only_on<S>(&mut self, name: S, os: Os) -> &mut Self
+    where
+        S: AsRef<str>,
+    {
+        if let Some(entry) = self.commands.get_mut(name.as_ref()) {
+            entry.platforms.push(os);
+        }
+        self
+    }
+
+    /// Add a default which is applied, in registration order, to every command created by
+    /// this registry
+    pub fn with_default<F>(&mut self, default: F) -> &mut Self
+    where
+        F: Fn(&mut Command) + Send + Sync + 'static,
+    {
+        self.defaults.push(Box::new(default));
+        self
+    }
+
+    /// Whether a command named `name` is registered
+    pub fn contains(&self, name: &str) -> bool {
+        self.commands.contains_key(name)
+    }
+
+    /// The names of all registered commands, in sorted order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.commands.keys().map(|k| k.as_str())
+    }
+
+    fn entry(&self, name: &str) -> Result<&Entry, CommandExtError> {
+        self.commands
+            .get(name)
+            .ok_or_else(|| CommandExtError::UnknownCommand {
+                name: name.to_string(),
+            })
+    }
+
+    fn build(&self, entry: &Entry) -> Command {
+        let mut command = (entry.factory)();
+        self.defaults.iter().for_each(|d| d(&mut command));
+        command
+    }
+
+    /// Create the command named `name` with all defaults applied
+    pub fn command(&self, name: &str) -> Result<Command, CommandExtError> {
+        self.entry(name).map(|e| self.build(e))
+    }
+
+    /// Create and check the command named `name`. If the command is restricted to other
+    /// platforms, it is not executed and a [`CommandExtError::Skipped`] error is returned.
+    pub fn run(&self, name: &str) -> Result<Output, CommandExtError> {
+        let entry = self.entry(name)?;
+
+        if !entry.platforms.is_empty() && !entry.platforms.contains(&host().os()) {
+            return Err(CommandExtError::Skipped {
+                reason: format!("{name} does not run on {}", host().os()),
+            });
+        }
+
+        self.build(entry).check()
+    }
+
+    /// Describe every registered command, in sorted order
+    pub fn list(&self) -> Vec<CommandListing> {
+        self.commands
+            .iter()
+            .map(|(name, entry)| {
+                let command = self.build(entry);
+                CommandListing {
+                    name: name.clone(),
+                    description: entry.description.clone(),
+                    command_line: std::iter::once(command.get_program())
+                        .chain(command.get_args())
+                        .collect::<Vec<_>>()
+                        .join(OsStr::new(" "))
+                        .to_string_lossy()
+                        .to_string(),
+                    platforms: entry.platforms.clone(),
+                    available: entry.platforms.is_empty() || entry.platforms.contains(&host().os()),
+                }
+            })
+            .collect()
+    }
+
+    /// A human-readable table of every registered command, suitable for `--list` output
+    pub fn listing(&self) -> Listing {
+        Listing(self.list())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A printable table of registered commands
+pub struct Listing(pub Vec<CommandListing>);
+
+impl Display for Listing {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let description = |l: &CommandListing| l.description.clone().unwrap_or_default();
+        let name_width = self.0.iter().map(|l| l.name.len()).fold(4, usize::max);
+        let description_width = self
+            .0
+            .iter()
+            .map(|l| description(l).len())
+            .fold(11, usize::max);
+
+        write!(
+            f,
+            "{:name_width$}  {:description_width$}  COMMAND",
+            "NAME", "DESCRIPTION"
+        )?;
+
+        for listing in &self.0 {
+            write!(
+                f,
+                "\n{:name_width$}  {:description_width$}  {}",
+                listing.name,
+                description(listing),
+                listing.command_line
+            )?;
+
+            if !listing.platforms.is_empty() {
+                write!(
+                    f,
+                    " [{} only{}]",
+                    listing
+                        .platforms
+                        .iter()
+                        .map(|p| p.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    if listing.available {
+                        ""
+                    } else {
+                        ", unavailable"
+                    }
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Debug for CommandRegistry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("CommandRegistry")
+            .field("commands", &self.commands.keys().collect::<Vec<_>>())
+            .field("defaults", &self.defaults.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use super::CommandRegistry;
+    use crate::{CommandExtError, Os};
+
+    fn registry() -> CommandRegistry {
+        let mut registry = CommandRegistry::new();
+        registry
+            .with_default(|c| {
+                c.env("REGISTRY_DEFAULT", "x");
+            })
+            .define("env", || {
+                let mut c = Command::new("bash");
+                c.args(["-c", "echo $REGISTRY_DEFAULT"]);
+                c
+            })
+            .define("false", || Command::new("false"))
+            .describe("false", "Always fails");
+        registry
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_run() -> anyhow::Result<()> {
+        let output = registry().run("env")?;
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "x\n");
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_run_failure() {
+        assert!(matches!(
+            registry().run("false"),
+            Err(CommandExtError::Check { .. })
+        ));
+    }
+
+    #[test]
+    fn test_unknown() {
+        let registry = registry();
+        assert_eq!(registry.names().collect::<Vec<_>>(), ["env", "false"]);
+        assert!(matches!(
+            registry.command("missing"),
+            Err(CommandExtError::UnknownCommand { name }) if name == "missing"
+        ));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_platform_gated() {
+        let mut registry = registry();
+        let other = if Os::current() == Os::Windows {
+            Os::Linux
+        } else {
+            Os::Windows
+        };
+        registry.only_on("env", other);
+        assert!(matches!(
+            registry.run("env"),
+            Err(CommandExtError::Skipped { .. })
+        ));
+    }
+
+    #[test]
+    fn test_listing() {
+        let mut registry = registry();
+        registry.only_on("false", Os::current());
+        let listing = registry.listing().to_string();
+        let lines = listing.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("NAME   DESCRIPTION"));
+        assert!(lines[1].starts_with("env    "));
+        assert!(lines[1].ends_with("bash -c echo $REGISTRY_DEFAULT"));
+        assert!(lines[2].contains("Always fails"));
+        assert!(lines[2].ends_with(&format!("false [{} only]", Os::current())));
+    }
+}
@@ -0,0 +1,642 @@
+//! Extension trait to retry a command which fails
+//!
+//! A command which changes shared state before it fails, such as one which pushes to a
+//! remote or appends to a file, can make things worse each time it is retried. Commands can
+//! be marked with [`CommandRetry::idempotent`], and the retry layer refuses to retry a
+//! command marked as not idempotent, failing with [`CommandExtError::RetryRefused`] instead.
+//! [`CommandRetry::retry_only_if_idempotent`] extends this to every command which is not
+//! explicitly marked idempotent, and [`CommandRetry::force`] retries regardless.
+//!
+//! The delay between attempts starts at [`CommandRetry::delay`] and is multiplied by
+//! [`CommandRetry::multiplier`] after each attempt, up to [`CommandRetry::max_interval`].
+//! With [`CommandRetry::jitter`], each delay is instead chosen at random between zero and
+//! that interval, so many clients retrying at once spread out, and [`CommandRetry::seed`] or
+//! a seed installed with [`crate::random`] makes those delays reproducible.
+//! [`CommandRetry::max_elapsed`] gives up once the next attempt would start too late, and
+//! [`CommandRetry::on_retry`] is called with each failure which is retried, so scripts can
+//! log why they are retrying.
+//!
+//! Which failures are retried is decided by [`CommandRetry::retry_if_error`], which is
+//! given the structured error of each failed attempt. By default every failure is retried
+//! except a failure to spawn the program, which will not succeed on a later attempt. The
+//! predicates in this module cover common cases, such as [`exit_code`] for the `EX_TEMPFAIL`
+//! code some tools exit with when they should be retried, and [`stderr_contains`] for
+//! transient network errors.
+//!
+//! Retrying is a layer over any [`Execute`], so it applies to a plain [`Command`], to every
+//! wrapper, and to other layers such as [timeouts](crate::timeout). Retrying a wrapper runs
+//! its hooks for each attempt, so a logged command logs every attempt.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use std::time::Duration;
+//! # use command_ext::{retry, CommandExtCheck, CommandExtError, CommandExtRetry};
+//! let mut command = Command::new("false");
+//! let result = command
+//!     .retry(3)
+//!     .idempotent(false)
+//!     .check();
+//! assert!(matches!(result, Err(CommandExtError::RetryRefused { .. })));
+//!
+//! let mut command = Command::new("true");
+//! command
+//!     .retry(5)
+//!     .delay(Duration::from_millis(100))
+//!     .max_interval(Duration::from_secs(2))
+//!     .max_elapsed(Duration::from_secs(10))
+//!     .jitter(true)
+//!     .on_retry(|attempt, e| eprintln!("attempt {attempt} failed, retrying: {e}"))
+//!     .check()
+//!     .unwrap();
+//!
+//! let tempfail = retry::exit_code(retry::EX_TEMPFAIL);
+//! let reset = retry::stderr_contains("connection reset");
+//! let mut command = Command::new("false");
+//! let result = command
+//!     .retry(3)
+//!     .retry_if_error(move |e| tempfail(e) || reset(e))
+//!     .check();
+//! assert!(matches!(result, Err(CommandExtError::Check { .. })));
+//! ```
+
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    io::{Error, ErrorKind, Result as IoResult},
+    process::{Child, Command, ExitStatus, Output},
+    time::Duration,
+};
+
+use crate::{
+    check::{check_with, preflight},
+    clock, random,
+    wrap::{Execute, ExecutionInfo, HasCommand},
+    CommandExtCheck, CommandExtError, CommandWrap,
+};
+
+/// The number of attempts made by default, including the first
+pub const DEFAULT_ATTEMPTS: usize = 3;
+
+/// The factor the delay between attempts is multiplied by after each attempt by default
+pub const DEFAULT_MULTIPLIER: f64 = 2.0;
+
+/// The exit code from `sysexits.h` for a temporary failure which should be retried
+pub const EX_TEMPFAIL: i32 = 75;
+
+type RetryCallback<'a> = Box<dyn FnMut(usize, &CommandExtError) + 'a>;
+type RetryPredicate<'a> = Box<dyn Fn(&CommandExtError) -> bool + 'a>;
+
+/// Whether `error` is a failure to spawn the program because it does not exist or cannot be
+/// executed
+pub fn spawn_failed(error: &CommandExtError) -> bool {
+    matches!(
+        error,
+        CommandExtError::StdIoError(e)
+            if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::PermissionDenied)
+    )
+}
+
+/// A predicate matching commands which exited with `code`
+pub fn exit_code(code: i32) -> impl Fn(&CommandExtError) -> bool {
+    move |error| {
+        matches!(
+            error,
+            CommandExtError::Check { status, .. } if status.code() == Some(code)
+        )
+    }
+}
+
+/// A predicate matching commands which failed with `pattern` in their error stream, ignoring
+/// case. The error stream is only available to the predicate when it is captured, so this
+/// never matches a failure from [`CommandWrap::status`].
+pub fn stderr_contains(pattern: &str) -> impl Fn(&CommandExtError) -> bool {
+    let pattern = pattern.to_lowercase();
+    move |error| {
+        matches!(
+            error,
+            CommandExtError::Check { stderr, .. } if stderr.to_lowercase().contains(&pattern)
+        )
+    }
+}
+
+/// A layer which retries a command, or any wrapper around one, when it fails
+pub struct CommandRetry<'a, W = Command>
+where
+    W: Execute,
+{
+    inner: &'a mut W,
+    attempts: usize,
+    delay: Duration,
+    multiplier: f64,
+    max_interval: Option<Duration>,
+    max_elapsed: Option<Duration>,
+    jitter: bool,
+    on_retry: Option<RetryCallback<'a>>,
+    retry_if: Option<RetryPredicate<'a>>,
+    idempotent: Option<bool>,
+    only_if_idempotent: bool,
+    force: bool,
+    attempts_made: usize,
+    rng: u64,
+}
+
+impl<W> Debug for CommandRetry<'_, W>
+where
+    W: Execute,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("CommandRetry")
+            .field("command", self.inner.command())
+            .field("attempts", &self.attempts)
+            .field("delay", &self.delay)
+            .field("multiplier", &self.multiplier)
+            .field("max_interval", &self.max_interval)
+            .field("max_elapsed", &self.max_elapsed)
+            .field("jitter", &self.jitter)
+            .field("idempotent", &self.idempotent)
+            .field("only_if_idempotent", &self.only_if_idempotent)
+            .field("force", &self.force)
+            .field("attempts_made", &self.attempts_made)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a, W> CommandRetry<'a, W>
+where
+    W: Execute,
+{
+    /// Retry `inner` when it fails
+    pub fn new(inner: &'a mut W) -> Self {
+        Self {
+            inner,
+            attempts: DEFAULT_ATTEMPTS,
+            delay: Duration::ZERO,
+            multiplier: DEFAULT_MULTIPLIER,
+            max_interval: None,
+            max_elapsed: None,
+            jitter: false,
+            on_retry: None,
+            retry_if: None,
+            idempotent: None,
+            only_if_idempotent: false,
+            force: false,
+            attempts_made: 0,
+            rng: random::seed() | 1,
+        }
+    }
+
+    /// The command or wrapper this layer retries
+    pub fn inner(&mut self) -> &mut W {
+        self.inner
+    }
+
+    /// Make at most `attempts` attempts to run the command, including the first
+    pub fn attempts(&mut self, attempts: usize) -> &mut Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    /// Wait for `delay` after the first attempt, and a multiple of it after later attempts
+    pub fn delay(&mut self, delay: Duration) -> &mut Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Multiply the delay between attempts by `multiplier` after each attempt. A multiplier
+    /// of 1 waits for the same delay between every attempt.
+    pub fn multiplier(&mut self, multiplier: f64) -> &mut Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Never wait for longer than `max_interval` between attempts
+    pub fn max_interval(&mut self, max_interval: Duration) -> &mut Self {
+        self.max_interval = Some(max_interval);
+        self
+    }
+
+    /// Stop retrying once the next attempt would start more than `max_elapsed` after the
+    /// first
+    pub fn max_elapsed(&mut self, max_elapsed: Duration) -> &mut Self {
+        self.max_elapsed = Some(max_elapsed);
+        self
+    }
+
+    /// Wait for a random time between zero and the delay between attempts instead of the
+    /// full delay
+    pub fn jitter(&mut self, jitter: bool) -> &mut Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Choose the jittered delays from a sequence seeded with `seed`, instead of one seeded
+    /// by [`crate::random`], so they are the same on every run
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.rng = seed | 1;
+        self
+    }
+
+    /// Only retry failures for which `predicate` returns true, instead of every failure
+    /// except [`spawn_failed`]
+    pub fn retry_if_error<F>(&mut self, predicate: F) -> &mut Self
+    where
+        F: Fn(&CommandExtError) -> bool + 'a,
+    {
+        self.retry_if = Some(Box::new(predicate));
+        self
+    }
+
+    /// Call `callback` with the number of the failed attempt, starting from 1, and its error
+    /// before each retry
+    pub fn on_retry<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(usize, &CommandExtError) + 'a,
+    {
+        self.on_retry = Some(Box::new(callback));
+        self
+    }
+
+    /// Mark whether running the command more than once is safe. A command marked as not
+    /// idempotent is never retried unless [`CommandRetry::force`] is set.
+    pub fn idempotent(&mut self, idempotent: bool) -> &mut Self {
+        self.idempotent = Some(idempotent);
+        self
+    }
+
+    /// Only retry the command if it is explicitly marked with [`CommandRetry::idempotent`]
+    pub fn retry_only_if_idempotent(&mut self) -> &mut Self {
+        self.only_if_idempotent = true;
+        self
+    }
+
+    /// Retry the command even if it is not idempotent
+    pub fn force(&mut self, force: bool) -> &mut Self {
+        self.force = force;
+        self
+    }
+
+    /// The maximum number of attempts, including the first
+    pub fn get_attempts(&self) -> usize {
+        self.attempts
+    }
+
+    /// Whether the command is marked idempotent, if it is marked at all
+    pub fn get_idempotent(&self) -> Option<bool> {
+        self.idempotent
+    }
+
+    /// The number of attempts made the last time the command was executed
+    pub fn attempts_made(&self) -> usize {
+        self.attempts_made
+    }
+
+    /// The interval after the failed attempt `attempt`, before any jitter
+    pub fn interval(&self, attempt: usize) -> Duration {
+        // Zero times an overflowing power is NaN, which would otherwise become the maximum
+        if self.delay.is_zero() {
+            return Duration::ZERO;
+        }
+
+        let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+        let interval = self.delay.as_secs_f64() * self.multiplier.powi(exponent);
+        let max = self.max_interval.unwrap_or(Duration::MAX).as_secs_f64();
+        Duration::try_from_secs_f64(interval.min(max)).unwrap_or(Duration::MAX)
+    }
+
+    /// The time to wait after the failed attempt `attempt`
+    fn backoff(&mut self, attempt: usize) -> Duration {
+        let interval = self.interval(attempt);
+        if !self.jitter {
+            return interval;
+        }
+
+        // xorshift64, which is plenty for spreading out retries
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        interval.mul_f64((self.rng >> 11) as f64 / (1u64 << 53) as f64)
+    }
+
+    /// Why the command must not be retried, if it must not
+    fn refusal(&self) -> Option<&'static str> {
+        if self.force {
+            return None;
+        }
+
+        match self.idempotent {
+            Some(false) => Some("it is marked as not idempotent"),
+            None if self.only_if_idempotent => Some("it is not marked idempotent"),
+            _ => None,
+        }
+    }
+
+    /// Run `execute` until it succeeds, the attempts run out, or retrying is refused
+    fn run<T>(
+        &mut self,
+        mut execute: impl FnMut(&mut W) -> Result<T, CommandExtError>,
+    ) -> Result<T, CommandExtError> {
+        self.attempts_made = 0;
+        let clock = clock::current();
+        let start = clock.now();
+
+        loop {
+            self.attempts_made += 1;
+
+            let error = match execute(self.inner) {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+
+            let retryable = match &self.retry_if {
+                Some(predicate) => predicate(&error),
+                None => !spawn_failed(&error),
+            };
+
+            if self.attempts_made >= self.attempts || !retryable {
+                return Err(error);
+            }
+
+            if let Some(reason) = self.refusal() {
+                return Err(CommandExtError::RetryRefused {
+                    program: self.command().get_program().to_string_lossy().into_owned(),
+                    reason: reason.to_string(),
+                    source: Box::new(error),
+                });
+            }
+
+            let delay = self.backoff(self.attempts_made);
+            if let Some(max_elapsed) = self.max_elapsed {
+                if clock.now().saturating_duration_since(start) + delay > max_elapsed {
+                    return Err(error);
+                }
+            }
+
+            if let Some(on_retry) = self.on_retry.as_mut() {
+                on_retry(self.attempts_made, &error);
+            }
+
+            clock.sleep(delay);
+        }
+    }
+}
+
+impl<'a, W> HasCommand for CommandRetry<'a, W>
+where
+    W: Execute,
+{
+    fn command(&self) -> &Command {
+        self.inner.command()
+    }
+
+    fn command_mut(&mut self) -> &mut Command {
+        self.inner.command_mut()
+    }
+}
+
+impl<'a, W> CommandWrap for CommandRetry<'a, W>
+where
+    W: Execute,
+{
+    fn describe(&self) -> Option<String> {
+        Some(match self.refusal() {
+            Some(reason) => format!("not retried, {reason}"),
+            None => format!("retried up to {} times", self.attempts - 1),
+        })
+    }
+
+    /// Pass the result of an execution which an outer layer waited for itself on to the
+    /// wrapped command's hooks
+    fn map_output(&mut self, output: IoResult<Output>, info: &ExecutionInfo) -> IoResult<Output> {
+        self.inner.finish_output(output, info)
+    }
+
+    /// Pass the result of an execution which an outer layer waited for itself on to the
+    /// wrapped command's hooks
+    fn map_status(
+        &mut self,
+        status: IoResult<ExitStatus>,
+        info: &ExecutionInfo,
+    ) -> IoResult<ExitStatus> {
+        self.inner.finish_status(status, info)
+    }
+
+    /// Pass the output being checked to the wrapped command's hooks
+    fn on_check(&mut self, output: &Output) -> Option<CommandExtError> {
+        self.inner.finish_check(output)
+    }
+
+    /// Spawning is not retried, since whether the child succeeds is not known until it is
+    /// waited for
+    fn spawn(&mut self) -> IoResult<Child> {
+        self.inner.execute_spawn()
+    }
+
+    fn output(&mut self) -> IoResult<Output> {
+        // The output streams are only decoded into the error of a failed attempt when a
+        // predicate or callback will look at it
+        let observed = self.retry_if.is_some() || self.on_retry.is_some();
+        let lossy = |bytes: &[u8]| {
+            if observed {
+                String::from_utf8_lossy(bytes).into_owned()
+            } else {
+                String::new()
+            }
+        };
+
+        let mut failed = None;
+        let result = self.run(|inner| {
+            let output = inner.execute_output()?;
+            if output.status.success() {
+                return Ok(output);
+            }
+
+            let error = CommandExtError::Check {
+                status: output.status,
+                stdout: lossy(&output.stdout),
+                stderr: lossy(&output.stderr),
+            };
+            failed = Some(output);
+            Err(error)
+        });
+
+        match (result, failed) {
+            (Ok(output), _) | (Err(CommandExtError::Check { .. }), Some(output)) => Ok(output),
+            (Err(e), _) => Err(Error::from(e)),
+        }
+    }
+
+    fn status(&mut self) -> IoResult<ExitStatus> {
+        let result = self.run(|inner| {
+            let status = inner.execute_status()?;
+            if status.success() {
+                return Ok(status);
+            }
+
+            Err(CommandExtError::Check {
+                status,
+                stdout: String::new(),
+                stderr: String::new(),
+            })
+        });
+
+        match result {
+            Ok(status) | Err(CommandExtError::Check { status, .. }) => Ok(status),
+            Err(e) => Err(Error::from(e)),
+        }
+    }
+}
+
+impl<'a> From<&'a mut Command> for CommandRetry<'a> {
+    fn from(value: &'a mut Command) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<'a, W> CommandExtCheck for CommandRetry<'a, W>
+where
+    W: Execute,
+{
+    type Error = CommandExtError;
+
+    fn check(&mut self) -> Result<Output, Self::Error> {
+        preflight(self.command())?;
+        self.run(|inner| {
+            let output = inner.execute_checked();
+            check_with(inner, output)
+        })
+    }
+}
+
+pub trait CommandExtRetry: Execute + Sized {
+    /// Retry the command when it fails, making at most `attempts` attempts including the
+    /// first
+    fn retry(&mut self, attempts: usize) -> CommandRetry<'_, Self>;
+}
+
+impl<T> CommandExtRetry for T
+where
+    T: Execute,
+{
+    fn retry(&mut self, attempts: usize) -> CommandRetry<'_, Self> {
+        let mut retry = CommandRetry::new(self);
+        retry.attempts(attempts);
+        retry
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{process::Command, time::Duration};
+
+    use super::{exit_code, stderr_contains, EX_TEMPFAIL};
+    use crate::{CommandExtCheck, CommandExtError, CommandExtRetry, CommandWrap};
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that failing commands are retried only when retrying them is allowed
+    fn test_retry_idempotent() {
+        let mut command = Command::new("false");
+        let mut retry = command.retry(3);
+        assert!(matches!(retry.check(), Err(CommandExtError::Check { .. })));
+        assert_eq!(retry.attempts_made(), 3);
+
+        retry.idempotent(false);
+        match retry.check() {
+            Err(CommandExtError::RetryRefused { reason, source, .. }) => {
+                assert_eq!(reason, "it is marked as not idempotent");
+                assert!(matches!(*source, CommandExtError::Check { .. }));
+            }
+            other => panic!("Unexpected result from command: {:?}", other),
+        }
+        assert_eq!(retry.attempts_made(), 1);
+
+        retry.force(true);
+        assert!(retry.check().is_err());
+        assert_eq!(retry.attempts_made(), 3);
+
+        let mut command = Command::new("false");
+        let mut retry = command.retry(2);
+        retry.retry_only_if_idempotent();
+        assert!(retry.output().is_err());
+        retry.idempotent(true);
+        assert!(!retry.status().expect("status").success());
+        assert_eq!(retry.attempts_made(), 2);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that the delay between attempts backs off and that the retry callback is called
+    fn test_retry_backoff() {
+        use std::sync::Arc;
+
+        use crate::clock::{self, MockClock};
+
+        let mut command = Command::new("false");
+        let mut retry = command.retry(10);
+        retry
+            .delay(Duration::from_millis(100))
+            .max_interval(Duration::from_millis(300));
+        assert_eq!(retry.interval(1), Duration::from_millis(100));
+        assert_eq!(retry.interval(2), Duration::from_millis(200));
+        assert_eq!(retry.interval(3), Duration::from_millis(300));
+        assert_eq!(retry.interval(30), Duration::from_millis(300));
+
+        retry.delay(Duration::ZERO);
+        assert_eq!(retry.interval(2000), Duration::ZERO);
+        retry.delay(Duration::from_millis(100));
+
+        retry.jitter(true);
+        assert!((1..20).all(|a| retry.backoff(a) <= retry.interval(a)));
+
+        retry.seed(42);
+        let seeded = (1..20).map(|a| retry.backoff(a)).collect::<Vec<_>>();
+        retry.seed(42);
+        assert!((1..20).map(|a| retry.backoff(a)).eq(seeded));
+
+        let mock = MockClock::new();
+        let mut retried = Vec::new();
+        let mut command = Command::new("false");
+        let result = clock::with(Arc::new(mock.clone()), || {
+            command
+                .retry(10)
+                .delay(Duration::from_secs(20))
+                .max_elapsed(Duration::from_secs(100))
+                .on_retry(|attempt, _| retried.push(attempt))
+                .check()
+        });
+        assert!(matches!(result, Err(CommandExtError::Check { .. })));
+        assert_eq!(retried, [1, 2]);
+        assert_eq!(
+            mock.sleeps(),
+            [Duration::from_secs(20), Duration::from_secs(40)]
+        );
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that only the failures matched by the retry predicate are retried
+    fn test_retry_if_error() {
+        let mut command = Command::new("asdfasdfasdfasdfjkljkljkl");
+        let mut retry = command.retry(3);
+        assert!(retry.check().is_err());
+        assert_eq!(retry.attempts_made(), 1);
+
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo 'Connection reset by peer' >&2; exit 75"]);
+        let mut retry = command.retry(3);
+        retry.retry_if_error(exit_code(1));
+        assert!(retry.check().is_err());
+        assert_eq!(retry.attempts_made(), 1);
+
+        retry.retry_if_error(exit_code(EX_TEMPFAIL));
+        assert!(retry.check().is_err());
+        assert_eq!(retry.attempts_made(), 3);
+
+        retry.retry_if_error(stderr_contains("connection reset"));
+        let output = retry.output().expect("output");
+        assert!(!output.status.success());
+        assert_eq!(output.stderr, b"Connection reset by peer\n");
+        assert_eq!(retry.attempts_made(), 3);
+    }
+}
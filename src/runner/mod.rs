@@ -0,0 +1,1354 @@
+//! A runner which executes checked commands and remembers what it executed
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::Runner;
+//! let runner = Runner::new();
+//! runner.run_labeled("greet", Command::new("echo").arg("hello")).ok();
+//! runner.run_labeled("fail", &mut Command::new("false")).ok();
+//!
+//! let history = runner.history();
+//! assert_eq!(history.len(), 2);
+//! assert_eq!(history.failed().count(), 1);
+//! assert_eq!(history.labeled("greet").count(), 1);
+//! ```
+//!
+//! Running a command whose [fingerprint](crate::Fingerprint) matches one already in the
+//! history logs a warning, since repeating an identical command is usually a mistake.
+//!
+//! ## Coalescing identical executions
+//!
+//! When several threads share a runner created with [`Runner::coalesce_identical`], a
+//! command started while an identical one is already running waits for it and shares its
+//! result instead of running again.
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::Runner;
+//! let runner = Runner::new().coalesce_identical(true);
+//! std::thread::scope(|s| {
+//!     for _ in 0..4 {
+//!         s.spawn(|| runner.run(Command::new("sleep").arg("0.5")));
+//!     }
+//! });
+//! assert!(runner.history().iter().any(|r| r.coalesced));
+//! ```
+//!
+//! ## Budgeting concurrency
+//!
+//! A runner shared between threads can limit how much runs at once with a budget of cost
+//! units, like the job count of `make -j`. Commands cost one unit each unless their label
+//! is given a heavier weight.
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::Runner;
+//! let runner = Runner::new().budget(4).weight("link", 4);
+//! std::thread::scope(|s| {
+//!     for i in 0..8 {
+//!         let runner = &runner;
+//!         s.spawn(move || runner.run(Command::new("echo").arg(i.to_string())));
+//!     }
+//!     // Uses the whole budget, so it never overlaps another step
+//!     s.spawn(|| runner.run_labeled("link", Command::new("echo").arg("link")));
+//! });
+//! ```
+//!
+//! ## Waiting for resources
+//!
+//! Scripts which start many heavy commands at once can overload the machine. A runner
+//! created with [`Runner::max_load`] or [`Runner::min_free_memory`] waits to start each
+//! command until the system load or available memory recovers.
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::Runner;
+//! let runner = Runner::new().max_load(64.0).min_free_memory(64 << 20);
+//! runner.run(Command::new("echo").arg("generated")).ok();
+//! ```
+//!
+//! ## Sharing a jobserver
+//!
+//! With the `jobserver` feature, a runner given a [`crate::jobserver::Jobserver`] takes a
+//! job token for each command, so commands started by a runner inside `make` or `cargo`
+//! count against their job limit, and child builds share the runner's tokens.
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{jobserver::Jobserver, Runner};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! # #[cfg(unix)]
+//! # {
+//! let jobserver = Jobserver::from_env().map_or_else(|| Jobserver::new(4), Ok)?;
+//! let runner = Runner::new().jobserver(jobserver);
+//! runner.run(Command::new("echo").arg("make -C subproject")).ok();
+//! # }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! ## Skipping recently successful commands
+//!
+//! A runner given a [`StateFile`] records when each labeled command last succeeded, across
+//! runs of the script. Commands given an interval with [`Runner::at_most_every`] are
+//! skipped while their last success is more recent than the interval.
+//!
+//! ```rust,no_run
+//! # use std::{process::Command, time::Duration};
+//! # use command_ext::{Runner, StateFile};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let runner = Runner::new()
+//!     .state_file(StateFile::open("target/script-state.txt")?)
+//!     .at_most_every("fetch", Duration::from_secs(24 * 60 * 60));
+//!
+//! runner.run_labeled("fetch", Command::new("cargo").arg("fetch"))?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! ## Breaking the circuit to failing tools
+//!
+//! A runner created with [`Runner::circuit_breaker`] counts consecutive failures of each
+//! label, or of each program for commands without one. Once a tool fails too many times in
+//! a row, later invocations fail immediately with [`CommandExtError::CircuitOpen`] until a
+//! cooldown passes, instead of waiting on a dead service again. After the cooldown, the
+//! circuit is half-open: one invocation runs as a trial, while concurrent invocations keep
+//! failing immediately. The circuit closes again if the trial succeeds, and opens for
+//! another cooldown if it fails.
+//!
+//! ```rust
+//! # use std::{process::Command, time::Duration};
+//! # use command_ext::{CommandExtError, Runner};
+//! let runner = Runner::new().circuit_breaker(2, Duration::from_secs(60));
+//! runner.run_labeled("upload", &mut Command::new("false")).ok();
+//! runner.run_labeled("upload", &mut Command::new("false")).ok();
+//!
+//! let result = runner.run_labeled("upload", &mut Command::new("false"));
+//! assert!(matches!(result, Err(CommandExtError::CircuitOpen { .. })));
+//! ```
+//!
+//! ## Rerunning failed commands
+//!
+//! A runner can save its history to a report file at the end of a script. When the script
+//! is run again with [`Runner::rerun_failed`] pointing at that report, commands which
+//! succeeded last time are not executed again, so only the failures (and commands the
+//! report does not know about) are rerun. Commands are matched by their label, or by their
+//! program, arguments and working directory when they have none.
+//!
+//! ```rust,no_run
+//! # use std::process::Command;
+//! # use command_ext::Runner;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let runner = Runner::new();
+//! if std::env::args().any(|a| a == "--failed") {
+//!     runner.rerun_failed("target/last-run.txt")?;
+//! }
+//!
+//! let result = runner.run_labeled("test", Command::new("cargo").arg("test"));
+//! runner.save_history("target/last-run.txt")?;
+//! result?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fmt::{Display, Formatter, Result as FmtResult},
+    fs::{read_to_string, rename, write},
+    io::{Error, ErrorKind, Result as IoResult},
+    ops::Deref,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Output},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::panicking,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    check::{check_output, default_output, preflight},
+    clock,
+    host::{identity, Identity},
+    wrap::ExecutionInfo,
+    CommandExtError, CommandExtFingerprint, Fingerprint,
+};
+
+/// The number of executions a runner remembers by default
+pub const DEFAULT_HISTORY_CAPACITY: usize = 256;
+
+fn warn(message: String) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!("{message}");
+    #[cfg(all(feature = "log", not(feature = "tracing")))]
+    log::warn!("{message}");
+    #[cfg(not(any(feature = "log", feature = "tracing")))]
+    drop(message);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A record of a single command executed by a [`Runner`]
+pub struct ExecutionReport {
+    /// The label the command was run with, if any
+    pub label: Option<String>,
+    /// The program, converted lossily to UTF-8
+    pub program: String,
+    /// The arguments, converted lossily to UTF-8
+    pub args: Vec<String>,
+    /// The working directory of the command, if it was set
+    pub current_dir: Option<PathBuf>,
+    /// The user, host and terminal the command was run from
+    pub identity: Identity,
+    /// The fingerprint of the command's configuration
+    pub fingerprint: Fingerprint,
+    /// Timing, status and capture sizes of the execution
+    pub info: ExecutionInfo,
+    /// The error which prevented the command from running, if any
+    pub error: Option<String>,
+    /// Whether the command was not executed because it succeeded in the run being
+    /// rerun with [`Runner::rerun_failed`], or more recently than its
+    /// [interval](Runner::at_most_every)
+    pub skipped: bool,
+    /// Whether the command was skipped because it succeeded more recently than its
+    /// [interval](Runner::at_most_every)
+    pub fresh: bool,
+    /// Whether the command was not executed because an identical command was already
+    /// running, and the result of that execution was shared
+    pub coalesced: bool,
+}
+
+impl ExecutionReport {
+    fn new(label: Option<String>, command: &Command, info: ExecutionInfo) -> Self {
+        Self {
+            label,
+            program: command.get_program().to_string_lossy().into_owned(),
+            args: command
+                .get_args()
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect(),
+            current_dir: command.get_current_dir().map(PathBuf::from),
+            identity: identity().clone(),
+            fingerprint: command.fingerprint(),
+            info,
+            error: None,
+            skipped: false,
+            fresh: false,
+            coalesced: false,
+        }
+    }
+
+    /// Whether the command ran and exited successfully, or was skipped because it did so in
+    /// the run being rerun
+    pub fn success(&self) -> bool {
+        self.skipped || (self.error.is_none() && self.info.status.is_some_and(|s| s.success()))
+    }
+
+    /// The key used to match this execution against a saved report: its label, or its
+    /// working directory, program and arguments when it has none
+    pub fn key(&self) -> String {
+        match &self.label {
+            Some(label) => format!("label:{label}"),
+            None => format!(
+                "command:{}:{}",
+                self.current_dir
+                    .as_deref()
+                    .map(|d| d.to_string_lossy())
+                    .unwrap_or_default(),
+                self.args
+                    .iter()
+                    .fold(self.program.clone(), |line, arg| line + " " + arg)
+            ),
+        }
+    }
+}
+
+impl Display for ExecutionReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        if let Some(label) = &self.label {
+            write!(f, "[{label}] ")?;
+        }
+
+        write!(f, "{}", self.program)?;
+        self.args.iter().try_for_each(|a| write!(f, " {a}"))?;
+
+        if self.fresh {
+            return write!(f, " (skipped, passed recently)");
+        }
+        if self.skipped {
+            return write!(f, " (skipped, passed last run)");
+        }
+
+        if self.coalesced {
+            write!(f, " (coalesced)")?;
+        }
+
+        match (&self.error, self.info.status) {
+            (Some(error), _) => write!(f, " ({error})"),
+            (None, Some(status)) => write!(f, " ({status}, {:?})", self.info.duration),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// A snapshot of the executions remembered by a [`Runner`], oldest first
+pub struct History(Vec<ExecutionReport>);
+
+impl History {
+    /// The executions run with the given label
+    pub fn labeled<'a>(&'a self, label: &'a str) -> impl Iterator<Item = &'a ExecutionReport> {
+        self.0
+            .iter()
+            .filter(move |r| r.label.as_deref() == Some(label))
+    }
+
+    /// The executions which failed to run or exited unsuccessfully
+    pub fn failed(&self) -> impl Iterator<Item = &ExecutionReport> {
+        self.0.iter().filter(|r| !r.success())
+    }
+
+    /// The executions which exited successfully
+    pub fn succeeded(&self) -> impl Iterator<Item = &ExecutionReport> {
+        self.0.iter().filter(|r| r.success())
+    }
+}
+
+impl Deref for History {
+    type Target = [ExecutionReport];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl IntoIterator for History {
+    type Item = ExecutionReport;
+    type IntoIter = std::vec::IntoIter<ExecutionReport>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+#[derive(Debug)]
+/// Executes checked commands and keeps a bounded history of what it executed. A runner can
+/// be shared between threads.
+pub struct Runner {
+    history: Mutex<VecDeque<ExecutionReport>>,
+    capacity: usize,
+    passed: Mutex<HashSet<String>>,
+    coalesce: bool,
+    inflight: Mutex<HashMap<Fingerprint, Arc<Flight>>>,
+    max_load: Option<f64>,
+    min_free_memory: Option<u64>,
+    budget: Option<Budget>,
+    weights: HashMap<String, u32>,
+    #[cfg(all(feature = "jobserver", unix))]
+    jobserver: Option<crate::jobserver::Jobserver>,
+    state: Option<StateFile>,
+    intervals: HashMap<String, Duration>,
+    breaker: Option<(u32, Duration)>,
+    circuits: Mutex<HashMap<String, Circuit>>,
+}
+
+#[derive(Debug, Default)]
+/// The consecutive failures of one tool run by a [`Runner`] with a circuit breaker
+struct Circuit {
+    failures: u32,
+    open_until: Option<Instant>,
+    /// Whether the trial invocation after a cooldown is running
+    trial: bool,
+}
+
+#[derive(Debug)]
+/// A file recording when each labeled command last succeeded, kept between runs of a
+/// script. The file is rewritten each time a success is recorded.
+pub struct StateFile {
+    path: PathBuf,
+    successes: Mutex<BTreeMap<String, SystemTime>>,
+}
+
+impl StateFile {
+    /// Open the state file at `path`. If it does not exist, the state starts empty and the
+    /// file is created when the first success is recorded.
+    pub fn open<P: AsRef<Path>>(path: P) -> IoResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let successes = match read_to_string(&path) {
+            Ok(state) => {
+                let mut lines = state.lines();
+                if lines.next() != Some(STATE_HEADER) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "not a command-ext state file",
+                    ));
+                }
+
+                lines
+                    .filter_map(|line| {
+                        let (secs, label) = line.split_once('\t')?;
+                        let at = UNIX_EPOCH.checked_add(Duration::from_secs(secs.parse().ok()?))?;
+                        Some((unescape(label), at))
+                    })
+                    .collect()
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            path,
+            successes: Mutex::new(successes),
+        })
+    }
+
+    /// The path of the state file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// When the command labeled `label` last succeeded, to the second
+    pub fn last_success(&self, label: &str) -> Option<SystemTime> {
+        self.lock().get(label).copied()
+    }
+
+    /// Record that the command labeled `label` succeeded at `at`, and save the state
+    pub fn record_success(&self, label: &str, at: SystemTime) -> IoResult<()> {
+        let mut successes = self.lock();
+        successes.insert(label.to_string(), at);
+
+        let state = successes
+            .iter()
+            .fold(STATE_HEADER.to_string(), |mut state, (label, at)| {
+                let secs = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                state.push_str(&format!("\n{secs}\t{}", escape(label)));
+                state
+            });
+
+        // Replace the file in one step, so an interrupted write does not lose the state
+        let mut partial = self.path.clone().into_os_string();
+        partial.push(".partial");
+        write(&partial, state + "\n")?;
+        rename(partial, &self.path)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, SystemTime>> {
+        self.successes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[derive(Debug)]
+/// A number of cost units shared by the commands a runner executes at once
+struct Budget {
+    capacity: u32,
+    used: Mutex<u32>,
+    freed: Condvar,
+}
+
+impl Budget {
+    fn new(capacity: u32) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            used: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Wait until `cost` units are free and take them. A cost larger than the whole budget
+    /// waits for the budget to be entirely free instead.
+    fn acquire(&self, cost: u32) -> BudgetGuard<'_> {
+        let cost = cost.min(self.capacity);
+        let used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+        let mut used = self
+            .freed
+            .wait_while(used, |used| *used + cost > self.capacity)
+            .unwrap_or_else(|e| e.into_inner());
+        *used += cost;
+        BudgetGuard { budget: self, cost }
+    }
+}
+
+/// Returns the units taken from a [`Budget`] when the command using them finishes
+struct BudgetGuard<'a> {
+    budget: &'a Budget,
+    cost: u32,
+}
+
+impl Drop for BudgetGuard<'_> {
+    fn drop(&mut self) {
+        *self.budget.used.lock().unwrap_or_else(|e| e.into_inner()) -= self.cost;
+        self.budget.freed.notify_all();
+    }
+}
+
+/// How often a runner which is waiting for resources checks them again
+const RESOURCE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The one minute load average of the system, if it can be read on this platform
+pub fn load_average() -> Option<f64> {
+    #[cfg(unix)]
+    {
+        extern "C" {
+            fn getloadavg(loadavg: *mut f64, nelem: std::ffi::c_int) -> std::ffi::c_int;
+        }
+
+        let mut load = 0.0;
+        // SAFETY: getloadavg writes at most one sample into load
+        (unsafe { getloadavg(&mut load, 1) } == 1).then_some(load)
+    }
+    #[cfg(not(unix))]
+    None
+}
+
+/// The memory available for starting new programs without swapping, in bytes, if it can be
+/// read on this platform
+pub fn available_memory() -> Option<u64> {
+    read_to_string("/proc/meminfo")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(|kib| kib.saturating_mul(1024))
+}
+
+/// The result of an execution shared between coalesced callers. The error of a failed
+/// execution is not `Clone`, so it is shared as its kind and message.
+type SharedOutput = (Result<Output, (ErrorKind, String)>, ExecutionInfo);
+
+/// Ends the trial invocation of a circuit if the execution running it panics, so that the
+/// circuit does not stay half-open
+struct Trial<'a> {
+    runner: &'a Runner,
+    key: &'a str,
+}
+
+impl Drop for Trial<'_> {
+    fn drop(&mut self) {
+        if panicking() {
+            if let Some(circuit) = self.runner.circuits().get_mut(self.key) {
+                circuit.trial = false;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// An execution which identical concurrent executions wait on instead of running
+struct Flight {
+    result: Mutex<Option<SharedOutput>>,
+    done: Condvar,
+}
+
+/// Ends a [`Flight`] when the execution finishes, even by panicking, so that waiting
+/// callers are always released
+struct Leader<'a> {
+    runner: &'a Runner,
+    fingerprint: Fingerprint,
+    flight: Arc<Flight>,
+}
+
+impl Leader<'_> {
+    fn finish(&self, result: SharedOutput) {
+        *self.flight.result.lock().unwrap_or_else(|e| e.into_inner()) = Some(result);
+    }
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        self.runner
+            .inflight
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.fingerprint);
+
+        let mut result = self.flight.result.lock().unwrap_or_else(|e| e.into_inner());
+        if result.is_none() {
+            let (_, info) = ExecutionInfo::measure(|| ());
+            *result = Some((
+                Err((ErrorKind::Other, "coalesced execution panicked".to_string())),
+                info,
+            ));
+        }
+        self.flight.done.notify_all();
+    }
+}
+
+impl Default for Runner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Runner {
+    /// A runner which remembers the last [`DEFAULT_HISTORY_CAPACITY`] executions
+    pub fn new() -> Self {
+        Self::with_history_capacity(DEFAULT_HISTORY_CAPACITY)
+    }
+
+    /// A runner which remembers the last `capacity` executions
+    pub fn with_history_capacity(capacity: usize) -> Self {
+        Self {
+            history: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            passed: Mutex::new(HashSet::new()),
+            coalesce: false,
+            inflight: Mutex::new(HashMap::new()),
+            max_load: None,
+            min_free_memory: None,
+            budget: None,
+            weights: HashMap::new(),
+            #[cfg(all(feature = "jobserver", unix))]
+            jobserver: None,
+            state: None,
+            intervals: HashMap::new(),
+            breaker: None,
+            circuits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Limit the total cost of the commands running at once, like the job count of `make
+    /// -j`. Each command costs one unit unless its label was given a different
+    /// [weight](Runner::weight), and a command waits to start until enough units are free.
+    /// A budget of zero is treated as one.
+    pub fn budget(mut self, units: u32) -> Self {
+        self.budget = Some(Budget::new(units));
+        self
+    }
+
+    /// Make commands run with `label` cost `weight` units of the [budget](Runner::budget),
+    /// for example to let one link step use the budget of several compile steps. A weight
+    /// larger than the budget makes the command run alone.
+    pub fn weight<S: Into<String>>(mut self, label: S, weight: u32) -> Self {
+        self.weights.insert(label.into(), weight);
+        self
+    }
+
+    #[cfg(all(feature = "jobserver", unix))]
+    /// Take a token from `jobserver` for each command before starting it, and let commands
+    /// which are jobserver clients themselves, like `make` and `cargo`, share its tokens
+    /// instead of starting their own jobs on top of the runner's
+    pub fn jobserver(mut self, jobserver: crate::jobserver::Jobserver) -> Self {
+        self.jobserver = Some(jobserver);
+        self
+    }
+
+    /// Record when each labeled command succeeds in `state`, so that later runs of the
+    /// script can skip commands run [at most every](Runner::at_most_every) interval
+    pub fn state_file(mut self, state: StateFile) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    /// Skip commands run with `label` if they succeeded less than `interval` ago according
+    /// to the [state file](Runner::state_file), for example for expensive setup steps which
+    /// only need to run once a day. Without a state file, commands always run. Skipped
+    /// commands are recorded in the history, and checking them succeeds with empty output.
+    pub fn at_most_every<S: Into<String>>(mut self, label: S, interval: Duration) -> Self {
+        self.intervals.insert(label.into(), interval);
+        self
+    }
+
+    /// Whether the command labeled `label` succeeded less than its interval ago
+    fn fresh(&self, label: &str) -> bool {
+        let (Some(state), Some(interval)) = (&self.state, self.intervals.get(label)) else {
+            return false;
+        };
+
+        state
+            .last_success(label)
+            .and_then(|at| clock::current().system_now().duration_since(at).ok())
+            .is_some_and(|age| age < *interval)
+    }
+
+    /// After `failures` consecutive failures of the commands run with a label, or of a
+    /// program run without one, fail further invocations immediately with
+    /// [`CommandExtError::CircuitOpen`] until `cooldown` has passed. Then one invocation runs
+    /// as a trial while the others keep failing: a failure of the trial opens the circuit
+    /// again, and a success closes it.
+    pub fn circuit_breaker(mut self, failures: u32, cooldown: Duration) -> Self {
+        self.breaker = Some((failures.max(1), cooldown));
+        self
+    }
+
+    fn circuits(&self) -> MutexGuard<'_, HashMap<String, Circuit>> {
+        self.circuits.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Fail instead of running the command keyed by `key` if its circuit is open. The first
+    /// call after the cooldown starts the trial invocation and returns `true`, and later
+    /// calls fail until the trial is recorded.
+    fn circuit_open(&self, key: &str) -> Result<bool, CommandExtError> {
+        if self.breaker.is_none() {
+            return Ok(false);
+        }
+
+        let mut circuits = self.circuits();
+        let Some(circuit) = circuits.get_mut(key) else {
+            return Ok(false);
+        };
+        let Some(open_until) = circuit.open_until else {
+            return Ok(false);
+        };
+
+        let remaining = open_until.checked_duration_since(clock::current().now());
+        if remaining.is_none() && !circuit.trial {
+            circuit.trial = true;
+            return Ok(true);
+        }
+
+        Err(CommandExtError::CircuitOpen {
+            key: key.to_string(),
+            failures: circuit.failures,
+            remaining: remaining.unwrap_or_default(),
+            trial: remaining.is_none(),
+        })
+    }
+
+    /// Count a success or failure of the command keyed by `key`, opening its circuit after
+    /// too many failures in a row
+    fn record_circuit(&self, key: &str, success: bool) {
+        let Some((threshold, cooldown)) = self.breaker else {
+            return;
+        };
+
+        let mut circuits = self.circuits();
+        if success {
+            circuits.remove(key);
+            return;
+        }
+
+        let circuit = circuits.entry(key.to_string()).or_default();
+        circuit.trial = false;
+        circuit.failures += 1;
+        if circuit.failures >= threshold {
+            circuit.open_until = Some(clock::current().now() + cooldown);
+        }
+    }
+
+    /// Wait to start each command while the system's one minute [load
+    /// average](load_average) is above `load`. On platforms where the load cannot be read,
+    /// commands are not delayed.
+    pub fn max_load(mut self, load: f64) -> Self {
+        self.max_load = Some(load);
+        self
+    }
+
+    /// Wait to start each command while less than `bytes` of [memory is
+    /// available](available_memory). On platforms where the available memory cannot be
+    /// read, commands are not delayed.
+    pub fn min_free_memory(mut self, bytes: u64) -> Self {
+        self.min_free_memory = Some(bytes);
+        self
+    }
+
+    /// Whether the system has the resources this runner requires to start a command
+    fn resources_available(&self) -> bool {
+        let loaded = self
+            .max_load
+            .is_some_and(|max| load_average().is_some_and(|load| load > max));
+        let low_memory = self
+            .min_free_memory
+            .is_some_and(|min| available_memory().is_some_and(|free| free < min));
+
+        !loaded && !low_memory
+    }
+
+    /// Block until the system has the resources this runner requires to start a command
+    fn wait_for_resources(&self) {
+        let clock = clock::current();
+        while !self.resources_available() {
+            clock.sleep(RESOURCE_POLL_INTERVAL);
+        }
+    }
+
+    /// Coalesce concurrent executions of commands with identical
+    /// [fingerprints](crate::Fingerprint): while one is running, identical commands wait
+    /// for it instead of starting another child, and every caller receives its result.
+    /// Commands which differ only in their stdio configuration are considered identical.
+    pub fn coalesce_identical(mut self, coalesce: bool) -> Self {
+        self.coalesce = coalesce;
+        self
+    }
+
+    /// Execute `command` once its cost fits in the budget and the system has the resources
+    /// this runner requires
+    fn launch(
+        &self,
+        label: Option<&str>,
+        command: &mut Command,
+    ) -> (IoResult<Output>, ExecutionInfo) {
+        let cost = label
+            .and_then(|l| self.weights.get(l))
+            .copied()
+            .unwrap_or(1);
+        let _units = self.budget.as_ref().map(|b| b.acquire(cost));
+        #[cfg(all(feature = "jobserver", unix))]
+        let token = self
+            .jobserver
+            .as_ref()
+            .map(|jobserver| {
+                jobserver.configure(command);
+                jobserver.acquire()
+            })
+            .transpose();
+        #[cfg(not(all(feature = "jobserver", unix)))]
+        let token = Ok(());
+        self.wait_for_resources();
+        let (output, info) = ExecutionInfo::measure(|| {
+            // The token is held until the command exits
+            token.and_then(|_token| default_output(command))
+        });
+        let info = info.with_output(&output);
+        (output, info)
+    }
+
+    /// Execute `command`, or wait for an identical execution which is already running and
+    /// share its result. Returns whether the result was shared.
+    fn execute_coalesced(
+        &self,
+        fingerprint: Fingerprint,
+        label: Option<&str>,
+        command: &mut Command,
+    ) -> (IoResult<Output>, ExecutionInfo, bool) {
+        let running = {
+            let mut inflight = self.inflight.lock().unwrap_or_else(|e| e.into_inner());
+            match inflight.get(&fingerprint) {
+                Some(flight) => Ok(flight.clone()),
+                None => {
+                    let flight = Arc::new(Flight::default());
+                    inflight.insert(fingerprint, flight.clone());
+                    Err(flight)
+                }
+            }
+        };
+
+        let flight = match running {
+            Ok(flight) => flight,
+            Err(flight) => {
+                let leader = Leader {
+                    runner: self,
+                    fingerprint,
+                    flight,
+                };
+
+                let (output, info) = self.launch(label, command);
+                leader.finish((
+                    output
+                        .as_ref()
+                        .map(Clone::clone)
+                        .map_err(|e| (e.kind(), e.to_string())),
+                    info,
+                ));
+
+                return (output, info, false);
+            }
+        };
+
+        let result = flight.result.lock().unwrap_or_else(|e| e.into_inner());
+        let result = flight
+            .done
+            .wait_while(result, |r| r.is_none())
+            .unwrap_or_else(|e| e.into_inner());
+        let (output, info) = result.clone().expect("flight finished without a result");
+
+        (
+            output.map_err(|(kind, message)| Error::new(kind, message)),
+            info,
+            true,
+        )
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VecDeque<ExecutionReport>> {
+        self.history.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record(&self, report: ExecutionReport) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut history = self.lock();
+        if history.len() == self.capacity {
+            history.pop_front();
+        }
+        history.push_back(report);
+    }
+
+    fn execute(
+        &self,
+        label: Option<String>,
+        command: &mut Command,
+    ) -> Result<Output, CommandExtError> {
+        let (_, info) = ExecutionInfo::measure(|| ());
+        let mut report = ExecutionReport::new(label, command, info);
+
+        if self
+            .lock()
+            .iter()
+            .any(|r| r.fingerprint == report.fingerprint && !r.skipped)
+        {
+            warn(format!(
+                "command already ran in this session with the same configuration: {report}"
+            ));
+        }
+
+        report.fresh = report.label.as_deref().is_some_and(|l| self.fresh(l));
+        if report.fresh
+            || self
+                .passed
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .contains(&report.key())
+        {
+            report.skipped = true;
+            self.record(report);
+            return Ok(Output {
+                status: ExitStatus::default(),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            });
+        }
+
+        if let Err(error) = preflight(command) {
+            report.error = Some(error.to_string());
+            self.record(report);
+            return Err(error);
+        }
+
+        let circuit = report
+            .label
+            .clone()
+            .unwrap_or_else(|| report.program.clone());
+        let _trial = match self.circuit_open(&circuit) {
+            Ok(trial) => trial.then(|| Trial {
+                runner: self,
+                key: &circuit,
+            }),
+            Err(error) => {
+                report.error = Some(error.to_string());
+                self.record(report);
+                return Err(error);
+            }
+        };
+
+        let (output, info) = if self.coalesce {
+            let (output, info, coalesced) =
+                self.execute_coalesced(report.fingerprint, report.label.as_deref(), command);
+            report.coalesced = coalesced;
+            (output, info)
+        } else {
+            self.launch(report.label.as_deref(), command)
+        };
+        report.info = info;
+        report.error = output.as_ref().err().map(ToString::to_string);
+        self.record_circuit(&circuit, report.success());
+        if let (Some(state), Some(label), true) = (&self.state, &report.label, report.success()) {
+            if let Err(e) = state.record_success(label, report.info.started_at) {
+                warn(format!("failed to save {}: {e}", state.path.display()));
+            }
+        }
+        self.record(report);
+
+        check_output(output)
+    }
+
+    /// Execute and check `command`, recording it in the history
+    pub fn run(&self, command: &mut Command) -> Result<Output, CommandExtError> {
+        self.execute(None, command)
+    }
+
+    /// Execute and check `command`, recording it in the history under `label`
+    pub fn run_labeled<S: Into<String>>(
+        &self,
+        label: S,
+        command: &mut Command,
+    ) -> Result<Output, CommandExtError> {
+        self.execute(Some(label.into()), command)
+    }
+
+    /// The executions this runner remembers, oldest first
+    pub fn history(&self) -> History {
+        History(self.lock().iter().cloned().collect())
+    }
+
+    /// Forget every remembered execution
+    pub fn clear_history(&self) {
+        self.lock().clear();
+    }
+
+    /// Save the outcome of every remembered execution to a report file which can be passed
+    /// to [`Runner::rerun_failed`]. When a command ran more than once, its last outcome is
+    /// saved.
+    pub fn save_history<P: AsRef<Path>>(&self, path: P) -> IoResult<()> {
+        let outcomes = self
+            .lock()
+            .iter()
+            .map(|r| (r.key(), r.success()))
+            .collect::<BTreeMap<_, _>>();
+
+        let report =
+            outcomes
+                .into_iter()
+                .fold(REPORT_HEADER.to_string(), |mut report, (key, success)| {
+                    report.push('\n');
+                    report.push_str(if success { "passed\t" } else { "failed\t" });
+                    report.push_str(&escape(&key));
+                    report
+                });
+
+        write(path, report + "\n")
+    }
+
+    /// Skip commands which succeeded in the run saved at `path` by
+    /// [`Runner::save_history`]. Skipped commands are recorded in the history, and checking
+    /// them succeeds with empty output. If the report does not exist, every command runs.
+    pub fn rerun_failed<P: AsRef<Path>>(&self, path: P) -> IoResult<()> {
+        let report = match read_to_string(path) {
+            Ok(report) => report,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let mut lines = report.lines();
+        if lines.next() != Some(REPORT_HEADER) {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "not a command-ext history report",
+            ));
+        }
+
+        *self.passed.lock().unwrap_or_else(|e| e.into_inner()) = lines
+            .filter_map(|line| line.strip_prefix("passed\t"))
+            .map(unescape)
+            .collect();
+
+        Ok(())
+    }
+}
+
+/// The first line of a report written by [`Runner::save_history`]
+const REPORT_HEADER: &str = "# command-ext history v1";
+
+/// The first line of a [`StateFile`]
+const STATE_HEADER: &str = "# command-ext state v1";
+
+/// Escape a key so it fits on one line of a report
+fn escape(key: &str) -> String {
+    key.chars().fold(String::new(), |mut escaped, c| {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+        escaped
+    })
+}
+
+/// Reverse [`escape`]
+fn unescape(key: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = key.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+
+    unescaped
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        env::temp_dir,
+        fs::{read_to_string, remove_file},
+        process::Command,
+        thread::scope,
+        time::{Duration, Instant},
+    };
+
+    use super::{available_memory, escape, load_average, unescape, Runner, StateFile, Trial};
+    use crate::CommandExtError;
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a runner only remembers its most recent executions
+    fn test_history_capacity() {
+        let runner = Runner::with_history_capacity(2);
+        (0..3).for_each(|i| {
+            runner
+                .run_labeled(i.to_string(), Command::new("echo").arg(i.to_string()))
+                .ok();
+        });
+
+        let history = runner.history();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].label.as_deref(), Some("1"));
+        assert_eq!(history[1].args, ["2"]);
+        assert_eq!(history.succeeded().count(), 2);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that failures to run or check a command are recorded in the history
+    fn test_failures() {
+        let runner = Runner::new();
+        assert!(runner.run(&mut Command::new("false")).is_err());
+        assert!(runner
+            .run(&mut Command::new("asdfasdfasdfasdfjkljkljkl"))
+            .is_err());
+        assert!(matches!(
+            runner.run(Command::new("true").current_dir("/nonexistent")),
+            Err(CommandExtError::CwdNotFound { .. })
+        ));
+
+        let history = runner.history();
+        assert_eq!(history.failed().count(), 3);
+        assert!(history[0].info.status.is_some());
+        assert!(history[1].error.is_some());
+        assert_eq!(&history[1].identity, crate::identity());
+
+        runner.clear_history();
+        assert!(runner.history().is_empty());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that identical concurrent commands run once and share the result
+    fn test_coalesce_identical() -> anyhow::Result<()> {
+        let path = temp_dir().join(format!("command-ext-coalesce-{}.txt", std::process::id()));
+        let script = format!("echo run >> {}; sleep 0.5; echo done", path.display());
+
+        let runner = Runner::new().coalesce_identical(true);
+        scope(|s| {
+            (0..4)
+                .map(|_| s.spawn(|| runner.run(Command::new("sh").args(["-c", &script]))))
+                .collect::<Vec<_>>()
+                .into_iter()
+                .try_for_each(|h| {
+                    assert_eq!(h.join().expect("thread panicked")?.stdout, b"done\n");
+                    anyhow::Ok(())
+                })
+        })?;
+
+        assert_eq!(read_to_string(&path)?, "run\n");
+        assert_eq!(runner.history().iter().filter(|r| r.coalesced).count(), 3);
+        remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    /// Test that commands only start while the system has the resources required
+    fn test_resources() {
+        assert!(load_average().is_some_and(|l| l >= 0.0));
+        assert!(available_memory().is_some_and(|m| m > 0));
+
+        assert!(Runner::new().max_load(f64::MAX).resources_available());
+        assert!(!Runner::new().max_load(-1.0).resources_available());
+        assert!(!Runner::new()
+            .min_free_memory(u64::MAX)
+            .resources_available());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that weighted commands share the budget of concurrent units
+    fn test_budget() {
+        let runner = Runner::new().budget(2).weight("heavy", 2);
+        let start = Instant::now();
+        scope(|s| {
+            s.spawn(|| runner.run_labeled("heavy", Command::new("sleep").arg("0.3")));
+            (0..2).for_each(|_| {
+                s.spawn(|| runner.run(Command::new("sleep").arg("0.3")));
+            });
+        });
+
+        // The heavy command uses the whole budget, so it cannot overlap the light ones
+        assert!(start.elapsed() >= Duration::from_millis(600));
+        assert_eq!(runner.history().succeeded().count(), 3);
+    }
+
+    #[test]
+    #[cfg(all(feature = "jobserver", unix))]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a runner holds a jobserver token while each command runs and gives
+    /// commands the jobserver
+    fn test_jobserver() {
+        let jobserver = crate::jobserver::Jobserver::new(1).unwrap();
+        let runner = Runner::new().jobserver(jobserver);
+        let start = Instant::now();
+        scope(|s| {
+            (0..2).for_each(|_| {
+                s.spawn(|| runner.run(Command::new("sleep").arg("0.3")));
+            });
+        });
+
+        // A single token lets only one command run at once
+        assert!(start.elapsed() >= Duration::from_millis(600));
+        let output = runner
+            .run(Command::new("sh").args(["-c", "echo $MAKEFLAGS"]))
+            .unwrap();
+        assert!(String::from_utf8_lossy(&output.stdout).starts_with("-j1 "));
+    }
+
+    #[test]
+    /// Test that keys are escaped into single fields of the report file
+    fn test_escape() {
+        let key = "label:a\tb\\n\nc";
+        assert_eq!(unescape(&escape(key)), key);
+        assert!(!escape(key).contains(['\t', '\n']));
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that rerunning a report skips the commands which passed
+    fn test_rerun_failed() -> anyhow::Result<()> {
+        let path = temp_dir().join(format!("command-ext-rerun-{}.txt", std::process::id()));
+
+        let runner = Runner::new();
+        runner.run_labeled("pass", &mut Command::new("true")).ok();
+        runner.run_labeled("fail", &mut Command::new("false")).ok();
+        runner.run(Command::new("echo").arg("x")).ok();
+        runner.save_history(&path)?;
+
+        let rerun = Runner::new();
+        rerun.rerun_failed(&path)?;
+        rerun.run_labeled("pass", &mut Command::new("false"))?;
+        assert!(rerun
+            .run_labeled("fail", &mut Command::new("false"))
+            .is_err());
+        assert!(rerun.run(Command::new("echo").arg("x"))?.stdout.is_empty());
+        assert!(!rerun.run(Command::new("echo").arg("y"))?.stdout.is_empty());
+
+        let history = rerun.history();
+        assert_eq!(history.iter().filter(|r| r.skipped).count(), 2);
+        assert_eq!(history.failed().count(), 1);
+
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a labeled command is skipped while its last success is recent
+    fn test_at_most_every() -> anyhow::Result<()> {
+        let path = temp_dir().join(format!("command-ext-state-{}.txt", std::process::id()));
+        let day = Duration::from_secs(24 * 60 * 60);
+
+        let runner = Runner::new()
+            .state_file(StateFile::open(&path)?)
+            .at_most_every("setup", day);
+        runner.run_labeled("setup", Command::new("echo").arg("x"))?;
+        runner.run_labeled("fail", &mut Command::new("false")).ok();
+
+        let state = StateFile::open(&path)?;
+        assert!(state.last_success("setup").is_some());
+        assert!(state.last_success("fail").is_none());
+
+        let rerun = Runner::new()
+            .state_file(state)
+            .at_most_every("setup", day)
+            .at_most_every("other", day);
+        assert!(rerun
+            .run_labeled("setup", Command::new("echo").arg("x"))?
+            .stdout
+            .is_empty());
+        assert!(!rerun
+            .run_labeled("other", Command::new("echo").arg("x"))?
+            .stdout
+            .is_empty());
+        assert_eq!(rerun.history().iter().filter(|r| r.fresh).count(), 1);
+
+        remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a circuit opens after consecutive failures and recovers through a single
+    /// trial
+    fn test_circuit_breaker() {
+        use std::{
+            panic::{catch_unwind, AssertUnwindSafe},
+            sync::Arc,
+        };
+
+        use crate::clock::{self, MockClock};
+
+        let mock = MockClock::new();
+        clock::with(Arc::new(mock.clone()), || {
+            let runner = Runner::new().circuit_breaker(2, Duration::from_secs(60));
+            let fail = || runner.run_labeled("service", &mut Command::new("false"));
+
+            assert!(matches!(fail(), Err(CommandExtError::Check { .. })));
+            assert!(matches!(fail(), Err(CommandExtError::Check { .. })));
+            match fail() {
+                Err(CommandExtError::CircuitOpen { key, failures, .. }) => {
+                    assert_eq!(key, "service");
+                    assert_eq!(failures, 2);
+                }
+                other => panic!("Unexpected result from command: {:?}", other),
+            }
+
+            // Other tools are not affected
+            assert!(runner.run(&mut Command::new("false")).is_err());
+            assert!(runner.run(&mut Command::new("true")).is_ok());
+
+            mock.advance(Duration::from_secs(61));
+            assert!(runner
+                .run_labeled("service", &mut Command::new("true"))
+                .is_ok());
+            assert!(matches!(
+                runner.run_labeled("service", &mut Command::new("false")),
+                Err(CommandExtError::Check { .. })
+            ));
+            assert_eq!(runner.history().failed().count(), 5);
+
+            // Only one trial runs after the cooldown, and its failure opens the circuit again
+            assert!(matches!(fail(), Err(CommandExtError::Check { .. })));
+            mock.advance(Duration::from_secs(61));
+            assert!(matches!(runner.circuit_open("service"), Ok(true)));
+            assert!(matches!(
+                runner.circuit_open("service"),
+                Err(CommandExtError::CircuitOpen { trial: true, .. })
+            ));
+            runner.record_circuit("service", false);
+            assert!(matches!(
+                runner.circuit_open("service"),
+                Err(CommandExtError::CircuitOpen { trial: false, remaining, .. })
+                    if !remaining.is_zero()
+            ));
+
+            // A trial which panics ends, so another trial runs instead of the circuit
+            // staying half-open
+            mock.advance(Duration::from_secs(61));
+            let panicked = catch_unwind(AssertUnwindSafe(|| {
+                assert!(matches!(runner.circuit_open("service"), Ok(true)));
+                let _trial = Trial {
+                    runner: &runner,
+                    key: "service",
+                };
+                panic!("trial panicked");
+            }));
+            assert!(panicked.is_err());
+            assert!(matches!(runner.circuit_open("service"), Ok(true)));
+        });
+    }
+}
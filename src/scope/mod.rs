@@ -0,0 +1,288 @@
+//! Structured concurrency for child processes
+//!
+//! Children spawned in a scope never outlive it. When the scope's closure returns, the
+//! scope waits for every child which is still running. When it panics, or when
+//! [`try_scope`] returns an error, the children which are still running are killed first,
+//! so an early exit cannot leave orphaned processes behind.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::scope::scope;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! scope(|s| -> std::io::Result<()> {
+//!     let server = s.spawn(Command::new("sleep").arg("0.2"))?;
+//!     let client = s.spawn(Command::new("echo").arg("request"))?;
+//!     assert!(client.wait()?.success());
+//!     server.kill()
+//! })?;
+//! // Both children have exited and been reaped here
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! With the `async` feature, [`scope_async`] does the same for asynchronous code, and also
+//! kills the children when the scope's future is dropped before it finishes.
+
+use std::{
+    io::Result as IoResult,
+    process::{Child, Command, ExitStatus},
+    sync::{Arc, Mutex, MutexGuard},
+    thread::panicking,
+    time::Duration,
+};
+
+use crate::clock;
+
+/// The shortest pause between polls while waiting for a child
+const MIN_BACKOFF: Duration = Duration::from_micros(50);
+/// The longest pause between polls while waiting for a child
+const MAX_BACKOFF: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone)]
+/// A handle to a child spawned in a [`Scope`]. Handles can be cloned and sent to other
+/// threads, and the child can be killed through one handle while another waits for it.
+pub struct ScopedChild {
+    child: Arc<Mutex<Child>>,
+    pid: u32,
+}
+
+impl ScopedChild {
+    fn lock(&self) -> MutexGuard<'_, Child> {
+        self.child.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The process ID of the child
+    pub fn id(&self) -> u32 {
+        self.pid
+    }
+
+    /// Kill the child if it is still running
+    pub fn kill(&self) -> IoResult<()> {
+        let mut child = self.lock();
+        match child.try_wait()? {
+            Some(_) => Ok(()),
+            None => child.kill(),
+        }
+    }
+
+    /// The exit status of the child, if it has exited
+    pub fn try_wait(&self) -> IoResult<Option<ExitStatus>> {
+        self.lock().try_wait()
+    }
+
+    /// Wait for the child to exit. The child is polled rather than waited for while
+    /// holding it, so other handles can still kill it.
+    pub fn wait(&self) -> IoResult<ExitStatus> {
+        let clock = clock::current();
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            if let Some(status) = self.try_wait()? {
+                return Ok(status);
+            }
+            clock.sleep(backoff);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+/// A scope which owns the children spawned in it. See [`scope`].
+pub struct Scope {
+    children: Mutex<Vec<ScopedChild>>,
+}
+
+impl Scope {
+    fn lock(&self) -> MutexGuard<'_, Vec<ScopedChild>> {
+        self.children.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Spawn `command` as a child owned by this scope
+    pub fn spawn(&self, command: &mut Command) -> IoResult<ScopedChild> {
+        let child = command.spawn()?;
+        let child = ScopedChild {
+            pid: child.id(),
+            child: Arc::new(Mutex::new(child)),
+        };
+        self.lock().push(child.clone());
+        Ok(child)
+    }
+
+    /// The children spawned in this scope, in the order they were spawned
+    pub fn children(&self) -> Vec<ScopedChild> {
+        self.lock().clone()
+    }
+
+    /// Kill every child which is still running
+    pub fn kill_all(&self) {
+        self.children().iter().for_each(|c| {
+            c.kill().ok();
+        });
+    }
+
+    /// Wait for every child to exit, returning their statuses in the order they were
+    /// spawned
+    pub fn wait_all(&self) -> Vec<IoResult<ExitStatus>> {
+        self.children().iter().map(ScopedChild::wait).collect()
+    }
+}
+
+/// Kills the children of a scope if it is left by panicking, and waits for them whenever
+/// it is left
+struct Guard<'a>(&'a Scope);
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        if panicking() {
+            self.0.kill_all();
+        }
+        self.0.wait_all();
+    }
+}
+
+/// Run `f` with a scope for spawning children, returning once every child has exited.
+/// If `f` panics, the children which are still running are killed before the panic
+/// continues.
+pub fn scope<F, T>(f: F) -> T
+where
+    F: FnOnce(&Scope) -> T,
+{
+    let scope = Scope::default();
+    let _guard = Guard(&scope);
+    f(&scope)
+}
+
+/// Like [`scope`], but if `f` returns an error, the children which are still running are
+/// killed instead of waited for
+pub fn try_scope<F, T, E>(f: F) -> Result<T, E>
+where
+    F: FnOnce(&Scope) -> Result<T, E>,
+{
+    scope(|s| {
+        let result = f(s);
+        if result.is_err() {
+            s.kill_all();
+        }
+        result
+    })
+}
+
+/// Kills and reaps the children of an asynchronous scope unless it finished normally
+#[cfg(feature = "async")]
+struct AsyncGuard(Option<Arc<Scope>>);
+
+#[cfg(feature = "async")]
+impl Drop for AsyncGuard {
+    fn drop(&mut self) {
+        if let Some(scope) = self.0.take() {
+            scope.kill_all();
+            scope.wait_all();
+        }
+    }
+}
+
+/// Run the future returned by `f` with a scope for spawning children, resolving once it
+/// has and every child has exited. Children are waited for without blocking the calling
+/// task. If the future panics, or is dropped before it finishes because it was cancelled
+/// or timed out, the children which are still running are killed.
+#[cfg(feature = "async")]
+pub async fn scope_async<F, Fut, T>(f: F) -> T
+where
+    F: FnOnce(Arc<Scope>) -> Fut,
+    Fut: std::future::Future<Output = T>,
+{
+    let scope = Arc::new(Scope::default());
+    let mut guard = AsyncGuard(Some(scope.clone()));
+    let value = f(scope.clone()).await;
+
+    let waiting = scope.clone();
+    crate::future::spawn_blocking(move || waiting.wait_all()).await;
+    guard.0 = None;
+
+    value
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        panic::{catch_unwind, AssertUnwindSafe},
+        process::Command,
+        time::{Duration, Instant},
+    };
+
+    use super::{scope, try_scope, ScopedChild};
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a scope waits for its children when it returns normally
+    fn test_waits() -> anyhow::Result<()> {
+        let start = Instant::now();
+        let child = scope(|s| s.spawn(Command::new("sleep").arg("0.2")))?;
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert!(child.try_wait()?.is_some_and(|s| s.success()));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that children are killed when the scope exits early
+    fn test_kills() -> anyhow::Result<()> {
+        let start = Instant::now();
+        let mut spawned: Option<ScopedChild> = None;
+        let result: std::io::Result<()> = try_scope(|s| {
+            spawned = Some(s.spawn(Command::new("sleep").arg("10"))?);
+            Err(std::io::Error::other("early exit"))
+        });
+        assert!(result.is_err());
+        assert!(spawned
+            .and_then(|c| c.try_wait().ok().flatten())
+            .is_some_and(|s| !s.success()));
+
+        let panicked = catch_unwind(AssertUnwindSafe(|| {
+            scope(|s| {
+                s.spawn(Command::new("sleep").arg("10")).ok();
+                panic!("boom");
+            })
+        }));
+        assert!(panicked.is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    #[cfg(feature = "async")]
+    /// Test that an asynchronous scope waits for its children, and kills them when dropped
+    fn test_async() -> anyhow::Result<()> {
+        use std::future::Future;
+
+        use super::scope_async;
+        use crate::future::block_on;
+
+        let child = block_on(scope_async(|s| async move {
+            s.spawn(Command::new("sleep").arg("0.2"))
+        }))?;
+        assert!(child.try_wait()?.is_some_and(|s| s.success()));
+
+        let start = Instant::now();
+        let mut spawned = None;
+        let scoped = scope_async(|s| {
+            spawned = s.spawn(Command::new("sleep").arg("10")).ok();
+            std::future::pending::<()>()
+        });
+        let mut scoped = Box::pin(scoped);
+        let waker = std::task::Waker::noop();
+        let _ = scoped
+            .as_mut()
+            .poll(&mut std::task::Context::from_waker(waker));
+        drop(scoped);
+        assert!(spawned
+            .and_then(|c| c.try_wait().ok().flatten())
+            .is_some_and(|s| !s.success()));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        Ok(())
+    }
+}
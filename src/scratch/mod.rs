@@ -0,0 +1,119 @@
+//! The directory temporary files are created in, and the files this crate created there
+//!
+//! [Scripts](crate::script) and [fuzzed inputs](crate::fuzz) are written to temporary files.
+//! They are created in the system's temporary directory unless another root is set for the
+//! whole process with [`set_root`], or for a closure on the current thread with
+//! [`with_root`]. This lets tests run against a sandboxed directory and CI place scratch
+//! files on a fast disk. [`created`] lists the files this crate created which it has not
+//! yet removed, so tests can check that nothing is left behind.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use command_ext::{scratch, script::{Language, Script}};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let root = std::env::temp_dir().join("command-ext-scratch-example");
+//! std::fs::create_dir_all(&root)?;
+//!
+//! let script = scratch::with_root(&root, || Script::new(Language::Sh, "echo x"))?;
+//! assert!(script.path().starts_with(&root));
+//! assert!(scratch::created().contains(&script.path().to_path_buf()));
+//!
+//! drop(script);
+//! assert!(!scratch::created().iter().any(|p| p.starts_with(&root)));
+//! # std::fs::remove_dir(&root)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    cell::RefCell,
+    env::temp_dir,
+    fs::{remove_file, OpenOptions},
+    io::{Result as IoResult, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, RwLock},
+};
+
+static GLOBAL: RwLock<Option<PathBuf>> = RwLock::new(None);
+static CREATED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+thread_local! {
+    static SCOPED: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+fn created_files() -> MutexGuard<'static, Vec<PathBuf>> {
+    CREATED.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Create temporary files in `root` for the whole process
+pub fn set_root<P: Into<PathBuf>>(root: P) {
+    *GLOBAL.write().unwrap_or_else(|e| e.into_inner()) = Some(root.into());
+}
+
+/// Create temporary files in the system's temporary directory for the whole process
+pub fn reset() {
+    *GLOBAL.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Run `f` creating temporary files in `root` on the current thread, taking precedence over
+/// the root set with [`set_root`]
+pub fn with_root<P, T, F>(root: P, f: F) -> T
+where
+    P: Into<PathBuf>,
+    F: FnOnce() -> T,
+{
+    /// Restores the previous root of the thread, even if `f` panics
+    struct Restore(Option<PathBuf>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            SCOPED.with(|scoped| *scoped.borrow_mut() = self.0.take());
+        }
+    }
+
+    let _restore = Restore(SCOPED.with(|scoped| scoped.borrow_mut().replace(root.into())));
+    f()
+}
+
+/// The directory temporary files are created in on the current thread: the one set with
+/// [`with_root`], then the one set with [`set_root`], then the system's temporary directory
+pub fn root() -> PathBuf {
+    SCOPED
+        .with(|scoped| scoped.borrow().clone())
+        .or_else(|| GLOBAL.read().unwrap_or_else(|e| e.into_inner()).clone())
+        .unwrap_or_else(temp_dir)
+}
+
+/// The temporary files this crate created which have not been removed, oldest first
+pub fn created() -> Vec<PathBuf> {
+    created_files().clone()
+}
+
+/// Create the new file `name` in `dir`, or the current root if it is `None`, containing
+/// `contents` and readable and writable only by the current user. Fails rather than
+/// following an existing file or link at the same path.
+pub(crate) fn create(dir: Option<&Path>, name: &str, contents: &[u8]) -> IoResult<PathBuf> {
+    let path = dir.map_or_else(root, Path::to_path_buf).join(name);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options.open(&path)?;
+    file.write_all(contents)
+        .and_then(|()| file.sync_all())
+        .inspect_err(|_| {
+            remove_file(&path).ok();
+        })?;
+
+    created_files().push(path.clone());
+    Ok(path)
+}
+
+/// Remove a file made with [`create`]
+pub(crate) fn remove(path: &Path) {
+    remove_file(path).ok();
+    created_files().retain(|p| p != path);
+}
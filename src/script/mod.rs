@@ -0,0 +1,222 @@
+//! Run inline scripts from temporary files instead of long `-c` strings
+//!
+//! A [`Script`] writes its source to a new temporary file which only the current user can
+//! read, with the extension its interpreter expects, and removes the file when dropped. The
+//! file is created in the [scratch root](crate::scratch), the system's temporary directory
+//! by default. A script wraps the command which runs the interpreter on that file, so
+//! arguments, environment variables and the rest of the crate's wrappers apply to it like
+//! to any other command.
+//! When a script fails, its file can be kept with [`Script::keep`] to debug it.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use command_ext::{script::{run_script, Language, Script}, CommandExtCheck, CommandWrap};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let output = run_script(Language::Sh, "echo one\necho two")?;
+//! assert_eq!(output.stdout, b"one\ntwo\n");
+//!
+//! let output = Script::new(Language::Sh, "echo \"$1\"")?.arg("x").check()?;
+//! assert_eq!(output.stdout, b"x\n");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    io::Result as IoResult,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::UNIX_EPOCH,
+};
+
+#[cfg(feature = "check")]
+use std::process::Output;
+
+#[cfg(feature = "check")]
+use crate::{
+    check::{check_with, default_output, preflight},
+    CommandExtCheck, CommandExtError,
+};
+use crate::{clock, scratch, wrap::HasCommand, CommandWrap};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+/// The language of an inline script
+pub enum Language {
+    /// Run with `bash`
+    Bash,
+    /// Run with `sh`
+    Sh,
+    /// Run with `pwsh`, or `powershell` on Windows hosts without PowerShell 7
+    PowerShell,
+    /// Run with `python3`, or `python` on Windows
+    Python,
+}
+
+impl Language {
+    /// The extension of script files in this language
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Bash | Self::Sh => "sh",
+            Self::PowerShell => "ps1",
+            Self::Python => "py",
+        }
+    }
+
+    /// The command which runs a script file in this language, without the file
+    pub fn interpreter(&self) -> Command {
+        match self {
+            Self::Bash => Command::new("bash"),
+            Self::Sh => Command::new("sh"),
+            Self::PowerShell => {
+                let program = match cfg!(windows) && !has_pwsh() {
+                    true => "powershell",
+                    false => "pwsh",
+                };
+                let mut command = Command::new(program);
+                command.args(["-NoProfile", "-NonInteractive"]);
+                if cfg!(windows) {
+                    command.args(["-ExecutionPolicy", "Bypass"]);
+                }
+                command.arg("-File");
+                command
+            }
+            Self::Python if cfg!(windows) => Command::new("python"),
+            Self::Python => Command::new("python3"),
+        }
+    }
+}
+
+impl Display for Language {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Bash => write!(f, "bash"),
+            Self::Sh => write!(f, "sh"),
+            Self::PowerShell => write!(f, "powershell"),
+            Self::Python => write!(f, "python"),
+        }
+    }
+}
+
+fn has_pwsh() -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join("pwsh.exe").is_file()))
+}
+
+/// Create a new script file in the [scratch root](crate::scratch)
+fn create_script(source: &str, extension: &str) -> IoResult<PathBuf> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let nanos = clock::current()
+        .system_now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let name = format!(
+        "command-ext-script-{}-{}-{nanos:08x}.{extension}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed),
+    );
+
+    scratch::create(None, &name, source.as_bytes())
+}
+
+#[derive(Debug)]
+/// An inline script written to a temporary file, and the command which runs it
+pub struct Script {
+    command: Command,
+    path: PathBuf,
+    keep: bool,
+}
+
+impl Script {
+    /// Write `source` to a new temporary file and prepare the interpreter for `language` to
+    /// run it
+    pub fn new<S: AsRef<str>>(language: Language, source: S) -> IoResult<Self> {
+        let path = create_script(source.as_ref(), language.extension())?;
+        let mut command = language.interpreter();
+        command.arg(&path);
+
+        Ok(Self {
+            command,
+            path,
+            keep: false,
+        })
+    }
+
+    /// The path of the script file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Keep the script file when the script is dropped, for example to debug a failure
+    pub fn keep(&mut self) -> &mut Self {
+        self.keep = true;
+        self
+    }
+}
+
+impl Drop for Script {
+    fn drop(&mut self) {
+        if !self.keep {
+            scratch::remove(&self.path);
+        }
+    }
+}
+
+impl HasCommand for Script {
+    fn command(&self) -> &Command {
+        &self.command
+    }
+
+    fn command_mut(&mut self) -> &mut Command {
+        &mut self.command
+    }
+}
+
+impl CommandWrap for Script {}
+
+#[cfg(feature = "check")]
+impl CommandExtCheck for Script {
+    type Error = CommandExtError;
+
+    fn check(&mut self) -> Result<Output, Self::Error> {
+        preflight(&self.command)?;
+        let output = default_output(&mut self.command);
+        check_with(self, output)
+    }
+}
+
+#[cfg(feature = "check")]
+/// Run `source` as a script in `language`, checking that it succeeds
+pub fn run_script<S: AsRef<str>>(language: Language, source: S) -> Result<Output, CommandExtError> {
+    Script::new(language, source)?.check()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Language, Script};
+    use crate::CommandWrap;
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_script() -> anyhow::Result<()> {
+        let mut script = Script::new(Language::Bash, "echo \"${BASH_SOURCE[0]##*.} $X\"")?;
+        let path = script.path().to_path_buf();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(path.metadata()?.permissions().mode() & 0o777, 0o600);
+        }
+
+        let output = script.env("X", "y").output()?;
+        assert_eq!(output.stdout, b"sh y\n");
+
+        drop(script);
+        assert!(!path.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,383 @@
+//! Extension trait to wrap the output of a command in a collapsible section of a CI log
+//!
+//! GitHub Actions, GitLab CI, and TeamCity each recognize their own markers for grouping
+//! log lines. [`Reporter::detect`] picks the markers for the CI system detected by
+//! [`crate::ci::detect`], falling back to a plain heading elsewhere.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{CommandExtCheck, CommandExtSection, PrintBuffer, Reporter};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let buffer = PrintBuffer::new();
+//! Command::new("echo")
+//!     .arg("x")
+//!     .section("Say x")
+//!     .reporter(Reporter::GitHub)
+//!     .write_to(buffer.clone())
+//!     .check()?;
+//! assert_eq!(buffer.contents(), "::group::Say x\nx\n::endgroup::\n");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::{stderr, stdout, Result as IoResult, Write},
+    process::{Command, ExitStatus, Output},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::UNIX_EPOCH,
+};
+
+#[cfg(feature = "check")]
+use crate::{check::check_wrapper, CommandExtCheck, CommandExtError};
+use crate::{
+    ci::{detect, Ci},
+    clock,
+    display::CommandLine,
+    wrap::{ExecutionInfo, HasCommand},
+    CommandWrap, PrintTarget,
+};
+
+static SECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+/// The markers used to delimit a section of log output
+pub enum Reporter {
+    /// `::group::` and `::endgroup::` workflow commands
+    GitHub,
+    /// `section_start` and `section_end` markers, collapsed by default
+    GitLab,
+    /// `blockOpened` and `blockClosed` service messages
+    TeamCity,
+    #[default]
+    /// A `--- title` heading, for logs which do not support sections
+    Plain,
+}
+
+/// Escape the data of a GitHub Actions workflow command
+fn github_escape(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escape a value in a TeamCity service message
+fn teamcity_escape(value: &str) -> String {
+    value
+        .chars()
+        .fold(String::with_capacity(value.len()), |mut escaped, c| {
+            match c {
+                '|' => escaped.push_str("||"),
+                '\'' => escaped.push_str("|'"),
+                '\n' => escaped.push_str("|n"),
+                '\r' => escaped.push_str("|r"),
+                '[' => escaped.push_str("|["),
+                ']' => escaped.push_str("|]"),
+                c => escaped.push(c),
+            }
+            escaped
+        })
+}
+
+fn unix_time() -> u64 {
+    clock::current()
+        .system_now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl Reporter {
+    /// The reporter for the CI system the process is running under
+    pub fn detect() -> Self {
+        Self::for_ci(detect())
+    }
+
+    /// The reporter for `ci`, or [`Reporter::Plain`] if it has no section markers
+    pub fn for_ci(ci: Option<Ci>) -> Self {
+        match ci {
+            Some(Ci::GitHubActions) => Self::GitHub,
+            Some(Ci::GitLab) => Self::GitLab,
+            Some(Ci::TeamCity) => Self::TeamCity,
+            _ => Self::Plain,
+        }
+    }
+
+    /// The line opening the section `id` titled `title`
+    pub fn start(&self, id: &str, title: &str) -> String {
+        match self {
+            Self::GitHub => format!("::group::{}", github_escape(title)),
+            Self::GitLab => format!(
+                "\x1b[0Ksection_start:{}:{id}[collapsed=true]\r\x1b[0K{title}",
+                unix_time()
+            ),
+            Self::TeamCity => format!("##teamcity[blockOpened name='{}']", teamcity_escape(title)),
+            Self::Plain => format!("--- {title}"),
+        }
+    }
+
+    /// The line closing the section `id` titled `title`, if the reporter closes sections
+    pub fn end(&self, id: &str, title: &str) -> Option<String> {
+        match self {
+            Self::GitHub => Some("::endgroup::".to_string()),
+            Self::GitLab => Some(format!("\x1b[0Ksection_end:{}:{id}\r\x1b[0K", unix_time())),
+            Self::TeamCity => Some(format!(
+                "##teamcity[blockClosed name='{}']",
+                teamcity_escape(title)
+            )),
+            Self::Plain => None,
+        }
+    }
+
+    /// The line reporting a failure of the command in the section titled `title`, if the
+    /// reporter surfaces failures outside the log
+    pub fn failure(&self, title: &str, message: &str) -> Option<String> {
+        match self {
+            Self::GitHub => Some(format!(
+                "::error title={}::{}",
+                github_escape(&title.replace(['\r', '\n'], " "))
+                    .replace(',', "%2C")
+                    .replace(':', "%3A"),
+                github_escape(message)
+            )),
+            Self::TeamCity => Some(format!(
+                "##teamcity[buildProblem description='{}: {}']",
+                teamcity_escape(title),
+                teamcity_escape(message)
+            )),
+            Self::GitLab | Self::Plain => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A command whose output is wrapped in a collapsible section of the log
+pub struct CommandSection<'a> {
+    command: &'a mut Command,
+    title: String,
+    id: String,
+    reporter: Reporter,
+    target: PrintTarget,
+    last_execution: Option<ExecutionInfo>,
+}
+
+impl<'a> CommandSection<'a> {
+    /// Use `reporter` instead of the one detected from the environment
+    pub fn reporter(&mut self, reporter: Reporter) -> &mut Self {
+        self.reporter = reporter;
+        self
+    }
+
+    /// Write the section and captured output to the given writer instead of standard output
+    pub fn write_to<W>(&mut self, writer: W) -> &mut Self
+    where
+        W: Write + Send + 'static,
+    {
+        self.target = PrintTarget::Writer(Box::new(writer));
+        self
+    }
+
+    /// The title of the section
+    pub fn get_title(&self) -> &str {
+        &self.title
+    }
+
+    /// The reporter which writes the section markers
+    pub fn get_reporter(&self) -> Reporter {
+        self.reporter
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        match &mut self.target {
+            PrintTarget::Stdout => stdout().write_all(bytes),
+            PrintTarget::Stderr => stderr().write_all(bytes),
+            PrintTarget::Captured => {
+                print!("{}", String::from_utf8_lossy(bytes));
+                Ok(())
+            }
+            PrintTarget::Writer(w) => w.write_all(bytes),
+        }
+        .ok();
+    }
+
+    fn emit_line(&mut self, line: String) {
+        self.emit(format!("{line}\n").as_bytes());
+    }
+
+    fn open(&mut self) {
+        let line = self.reporter.start(&self.id, &self.title);
+        self.emit_line(line);
+    }
+
+    fn close(&mut self, status: Option<ExitStatus>) {
+        if let Some(line) = self.reporter.end(&self.id, &self.title) {
+            self.emit_line(line);
+        }
+
+        if let Some(status) = status.filter(|s| !s.success()) {
+            let message = format!("{} failed with {status}", CommandLine(self.command));
+            if let Some(line) = self.reporter.failure(&self.title, &message) {
+                self.emit_line(line);
+            }
+        }
+    }
+
+    fn emit_output(&mut self, bytes: &[u8]) {
+        if !bytes.is_empty() {
+            self.emit(bytes);
+            if !bytes.ends_with(b"\n") {
+                self.emit(b"\n");
+            }
+        }
+    }
+}
+
+impl<'a> HasCommand for CommandSection<'a> {
+    fn command(&self) -> &Command {
+        self.command
+    }
+
+    fn command_mut(&mut self) -> &mut Command {
+        self.command
+    }
+}
+
+impl<'a> CommandWrap for CommandSection<'a> {
+    fn last_execution(&self) -> Option<ExecutionInfo> {
+        self.last_execution
+    }
+
+    fn reads_output(&self) -> bool {
+        true
+    }
+
+    fn describe(&self) -> Option<String> {
+        Some(format!("section {:?} ({:?})", self.title, self.reporter))
+    }
+
+    fn on_output(&mut self) {
+        self.open();
+    }
+
+    fn on_status(&mut self) {
+        self.open();
+    }
+
+    fn after_output(&mut self, output: &IoResult<Output>, info: &ExecutionInfo) {
+        self.last_execution = Some(*info);
+
+        match output {
+            Ok(output) => {
+                self.emit_output(&output.stdout);
+                self.emit_output(&output.stderr);
+            }
+            Err(e) => self.emit_line(format!("error: {e}")),
+        }
+
+        self.close(info.status);
+    }
+
+    fn after_status(&mut self, status: &IoResult<ExitStatus>, info: &ExecutionInfo) {
+        self.last_execution = Some(*info);
+
+        if let Err(e) = status {
+            self.emit_line(format!("error: {e}"));
+        }
+
+        self.close(info.status);
+    }
+}
+
+impl<'a> From<&'a mut Command> for CommandSection<'a> {
+    fn from(value: &'a mut Command) -> Self {
+        let title = CommandLine(value).to_string();
+        Self {
+            command: value,
+            title,
+            id: format!("command_ext_{}", SECTIONS.fetch_add(1, Ordering::Relaxed)),
+            reporter: Reporter::detect(),
+            target: PrintTarget::default(),
+            last_execution: None,
+        }
+    }
+}
+
+pub trait CommandExtSection {
+    /// Wrap the output of the command in a section titled `title`
+    fn section<S: Into<String>>(&mut self, title: S) -> CommandSection<'_>;
+    /// Wrap the output of the command in a section titled with its command line
+    fn sectioned(&mut self) -> CommandSection<'_>;
+}
+
+impl CommandExtSection for Command {
+    fn section<S: Into<String>>(&mut self, title: S) -> CommandSection<'_> {
+        let mut section = CommandSection::from(self);
+        section.title = title.into();
+        section
+    }
+
+    fn sectioned(&mut self) -> CommandSection<'_> {
+        CommandSection::from(self)
+    }
+}
+
+#[cfg(feature = "check")]
+impl<'a> CommandExtCheck for CommandSection<'a> {
+    type Error = CommandExtError;
+
+    fn check(&mut self) -> Result<Output, Self::Error> {
+        check_wrapper(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use super::Reporter;
+    use crate::{ci::Ci, CommandExtSection, CommandWrap, PrintBuffer};
+
+    #[test]
+    fn test_reporters() {
+        assert_eq!(Reporter::for_ci(Some(Ci::GitLab)), Reporter::GitLab);
+        assert_eq!(Reporter::for_ci(Some(Ci::Jenkins)), Reporter::Plain);
+        assert_eq!(
+            Reporter::GitHub.start("id", "100% done\r\nnext"),
+            "::group::100%25 done%0D%0Anext"
+        );
+        assert_eq!(
+            Reporter::GitHub.failure("a: 5%, b", "x"),
+            Some("::error title=a%3A 5%25%2C b::x".to_string())
+        );
+        assert_eq!(
+            Reporter::TeamCity.start("id", "it's [x]"),
+            "##teamcity[blockOpened name='it|'s |[x|]']"
+        );
+
+        let start = Reporter::GitLab.start("build", "Build");
+        assert!(start.starts_with("\x1b[0Ksection_start:"));
+        assert!(start.ends_with(":build[collapsed=true]\r\x1b[0KBuild"));
+        assert!(Reporter::Plain.end("build", "Build").is_none());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_section_failure() -> anyhow::Result<()> {
+        let buffer = PrintBuffer::new();
+        Command::new("bash")
+            .args(["-c", "echo out; exit 2"])
+            .section("Fail")
+            .reporter(Reporter::TeamCity)
+            .write_to(buffer.clone())
+            .status()?;
+
+        let contents = buffer.contents();
+        let lines = contents.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "##teamcity[blockOpened name='Fail']");
+        assert_eq!(lines[1], "##teamcity[blockClosed name='Fail']");
+        assert!(lines[2].starts_with("##teamcity[buildProblem description='Fail: bash -c"));
+        Ok(())
+    }
+}
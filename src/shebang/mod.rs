@@ -0,0 +1,264 @@
+//! Extension trait to run scripts through the interpreter named by their `#!` line on
+//! platforms which do not do so themselves
+//!
+//! Unix kernels read the `#!` line of an executable script and run its interpreter. Windows
+//! does not, so a cross-platform build script which runs `tools/gen.py` directly works on
+//! Linux and fails on Windows. With [`CommandShebang::respect_shebang`] enabled, a command
+//! whose program is a path to a script starting with `#!` is rewritten on Windows to run
+//! the interpreter instead, for example `python3 tools/gen.py`. Elsewhere, the command runs
+//! unchanged.
+//!
+//! The rewritten invocation keeps the arguments, environment changes and working directory
+//! of the command, but not its stdio configuration, which cannot be read back from a
+//! [`Command`].
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! # use std::process::Command;
+//! # use command_ext::{CommandExtShebang, CommandWrap};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! Command::new("tools/gen.py")
+//!     .arg("--out")
+//!     .arg("generated")
+//!     .respect_shebang(true)
+//!     .status()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::{BufRead, BufReader, Read, Result as IoResult},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Output},
+};
+
+#[cfg(feature = "check")]
+use crate::{check::check_wrapper, CommandExtCheck, CommandExtError};
+use crate::{
+    spawn::copy_config,
+    wrap::{ExecutionInfo, HasCommand},
+    CommandWrap,
+};
+
+/// How much of the first line of a script is read looking for a `#!` line
+const MAX_SHEBANG_LEN: u64 = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The interpreter named by the `#!` line of a script
+pub struct Shebang {
+    /// The interpreter to run. Paths are reduced to their file name when they do not exist,
+    /// so `/usr/bin/python3` becomes `python3` and is searched for on the `PATH`.
+    pub interpreter: OsString,
+    /// The arguments passed to the interpreter before the script
+    pub args: Vec<String>,
+}
+
+impl Shebang {
+    /// Parse a `#!` line. `#!/usr/bin/env program` names `program` as the interpreter.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut words = line.strip_prefix("#!")?.split_whitespace();
+        let mut interpreter = words.next()?;
+
+        if Path::new(interpreter)
+            .file_name()
+            .is_some_and(|n| n == "env")
+        {
+            interpreter = words.find(|w| !w.starts_with('-'))?;
+        }
+
+        let interpreter = match Path::new(interpreter) {
+            path if path.exists() => path.as_os_str().to_owned(),
+            path => path.file_name()?.to_owned(),
+        };
+
+        Some(Self {
+            interpreter,
+            args: words.map(str::to_string).collect(),
+        })
+    }
+
+    /// Read the `#!` line of the script at `path`, if it has one
+    pub fn read<P: AsRef<Path>>(path: P) -> IoResult<Option<Self>> {
+        let mut line = Vec::new();
+        BufReader::new(File::open(path)?.take(MAX_SHEBANG_LEN)).read_until(b'\n', &mut line)?;
+
+        Ok(String::from_utf8(line)
+            .ok()
+            .and_then(|line| Self::parse(line.trim_end())))
+    }
+}
+
+#[derive(Debug)]
+/// A command which runs scripts through the interpreter named by their `#!` line
+pub struct CommandShebang<'a> {
+    command: &'a mut Command,
+    respect: bool,
+    last_execution: Option<ExecutionInfo>,
+}
+
+impl<'a> CommandShebang<'a> {
+    /// Whether to rewrite invocations of scripts on platforms which ignore `#!` lines
+    pub fn respect_shebang(&mut self, respect: bool) -> &mut Self {
+        self.respect = respect;
+        self
+    }
+
+    /// Whether invocations of scripts are rewritten on platforms which ignore `#!` lines
+    pub fn get_respect_shebang(&self) -> bool {
+        self.respect
+    }
+
+    /// The path of the script the command runs, if its program is a path to a file
+    fn script(&self) -> Option<PathBuf> {
+        let program = Path::new(self.command.get_program());
+        let path = match self.command.get_current_dir() {
+            Some(dir) => dir.join(program),
+            None => program.to_path_buf(),
+        };
+
+        (program.components().count() > 1 && path.is_file()).then_some(path)
+    }
+
+    /// The command running the script through its interpreter, if the program is a script
+    /// with a `#!` line
+    fn rewrite(&self) -> IoResult<Option<Command>> {
+        let Some(script) = self.script() else {
+            return Ok(None);
+        };
+
+        Ok(Shebang::read(&script)?.map(|shebang| {
+            let mut command = Command::new(shebang.interpreter);
+            command.args(shebang.args).arg(self.command.get_program());
+            copy_config(self.command, &mut command);
+            command
+        }))
+    }
+
+    /// The command to execute: the rewritten invocation when it applies on this platform
+    fn rewritten(&self) -> IoResult<Option<Command>> {
+        match self.respect && cfg!(windows) {
+            true => self.rewrite(),
+            false => Ok(None),
+        }
+    }
+}
+
+impl<'a> HasCommand for CommandShebang<'a> {
+    fn command(&self) -> &Command {
+        self.command
+    }
+
+    fn command_mut(&mut self) -> &mut Command {
+        self.command
+    }
+}
+
+impl<'a> CommandWrap for CommandShebang<'a> {
+    fn last_execution(&self) -> Option<ExecutionInfo> {
+        self.last_execution
+    }
+
+    fn describe(&self) -> Option<String> {
+        self.respect.then(|| match self.rewrite() {
+            Ok(Some(command)) => format!("shebang: runs as {command:?}"),
+            _ => "shebang: program is not a script with a #! line".to_string(),
+        })
+    }
+
+    fn spawn(&mut self) -> IoResult<Child> {
+        match self.rewritten()? {
+            Some(mut command) => command.spawn(),
+            None => self.command.spawn(),
+        }
+    }
+
+    fn output(&mut self) -> IoResult<Output> {
+        let mut rewritten = self.rewritten()?;
+        let command = rewritten.as_mut().unwrap_or(self.command);
+        let (output, info) = ExecutionInfo::measure(|| command.output());
+        self.last_execution = Some(info.with_output(&output));
+        output
+    }
+
+    fn status(&mut self) -> IoResult<ExitStatus> {
+        let mut rewritten = self.rewritten()?;
+        let command = rewritten.as_mut().unwrap_or(self.command);
+        let (status, info) = ExecutionInfo::measure(|| command.status());
+        self.last_execution = Some(info.with_status(&status));
+        status
+    }
+}
+
+impl<'a> From<&'a mut Command> for CommandShebang<'a> {
+    fn from(value: &'a mut Command) -> Self {
+        Self {
+            command: value,
+            respect: false,
+            last_execution: None,
+        }
+    }
+}
+
+#[cfg(feature = "check")]
+impl<'a> CommandExtCheck for CommandShebang<'a> {
+    type Error = CommandExtError;
+
+    fn check(&mut self) -> Result<Output, Self::Error> {
+        check_wrapper(self)
+    }
+}
+
+pub trait CommandExtShebang {
+    fn respect_shebang(&mut self, respect: bool) -> CommandShebang<'_>;
+}
+
+impl CommandExtShebang for Command {
+    fn respect_shebang(&mut self, respect: bool) -> CommandShebang<'_> {
+        let mut shebang = CommandShebang::from(self);
+        shebang.respect_shebang(respect);
+        shebang
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env::temp_dir, fs::write, process::Command};
+
+    use super::Shebang;
+    use crate::CommandExtShebang;
+
+    #[test]
+    fn test_parse() {
+        let shebang = Shebang::parse("#!/usr/bin/env -S python3 -u").unwrap();
+        assert_eq!(shebang.interpreter, "python3");
+        assert_eq!(shebang.args, ["-u"]);
+
+        let shebang = Shebang::parse("#!/nonexistent/bin/perl -w").unwrap();
+        assert_eq!(shebang.interpreter, "perl");
+        assert_eq!(shebang.args, ["-w"]);
+
+        assert_eq!(Shebang::parse("print('x')"), None);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_rewrite() -> anyhow::Result<()> {
+        let script = temp_dir().join(format!("command-ext-shebang-{}", std::process::id()));
+        write(&script, "#!/usr/bin/env sh\necho \"$0 $1\"\n")?;
+
+        let mut command = Command::new(&script);
+        command.arg("x");
+        let mut rewritten = command.respect_shebang(true).rewrite()?.unwrap();
+        let output = rewritten.output()?;
+        std::fs::remove_file(&script)?;
+
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            format!("{} x\n", script.display())
+        );
+        Ok(())
+    }
+}
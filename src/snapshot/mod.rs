@@ -0,0 +1,162 @@
+//! Extension trait to compare the output of a command against a stored snapshot
+//!
+//! Snapshots are plain text files holding the expected stdout of a command. Leading and
+//! trailing whitespace is ignored, as are differences in line endings. When the
+//! `COMMAND_EXT_UPDATE_SNAPSHOTS` environment variable is set to anything other than `0`,
+//! snapshots are rewritten with the actual output instead of being compared.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! # use std::process::Command;
+//! # use command_ext::CommandExtSnapshot;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! Command::new("cargo")
+//!     .args(["build", "--help"])
+//!     .check_matches_snapshot("tests/snapshots/build_help.txt")?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    env::var_os,
+    fs::{create_dir_all, read_to_string, write},
+    io::ErrorKind,
+    path::Path,
+    process::Output,
+};
+
+use crate::{CommandExtCheck, CommandExtError};
+
+/// The environment variable which enables rewriting snapshots with the actual output
+pub const UPDATE_SNAPSHOTS_ENV: &str = "COMMAND_EXT_UPDATE_SNAPSHOTS";
+
+fn update_mode() -> bool {
+    var_os(UPDATE_SNAPSHOTS_ENV).is_some_and(|v| !v.is_empty() && v != "0")
+}
+
+fn normalize(text: &str) -> String {
+    text.replace("\r\n", "\n").trim().to_string()
+}
+
+/// A line diff from `expected` to `actual`, with removed lines prefixed by `-`, added lines
+/// by `+` and unchanged lines by a space
+fn diff(expected: &str, actual: &str) -> String {
+    let expected = expected.lines().collect::<Vec<_>>();
+    let actual = actual.lines().collect::<Vec<_>>();
+
+    // lcs[i][j] is the length of the longest common subsequence of expected[i..] and
+    // actual[j..]
+    let mut lcs = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    (0..expected.len()).rev().for_each(|i| {
+        (0..actual.len()).rev().for_each(|j| {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        });
+    });
+
+    let (mut i, mut j) = (0, 0);
+    let mut lines = Vec::new();
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            lines.push(format!(" {}", expected[i]));
+            i += 1;
+            j += 1;
+        } else if j < actual.len() && (i == expected.len() || lcs[i][j + 1] > lcs[i + 1][j]) {
+            lines.push(format!("+{}", actual[j]));
+            j += 1;
+        } else {
+            lines.push(format!("-{}", expected[i]));
+            i += 1;
+        }
+    }
+
+    lines.join("\n")
+}
+
+/// Compare the stdout of a successful command with the snapshot at `path`, or rewrite the
+/// snapshot in update mode
+fn matches_snapshot(output: Output, path: &Path) -> Result<Output, CommandExtError> {
+    let actual = normalize(&String::from_utf8_lossy(&output.stdout));
+
+    if update_mode() {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        write(path, actual + "\n")?;
+        return Ok(output);
+    }
+
+    let expected = match read_to_string(path) {
+        Ok(expected) => Some(normalize(&expected)),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    match expected {
+        Some(expected) if expected == actual => Ok(output),
+        expected => Err(CommandExtError::SnapshotMismatch {
+            path: path.to_path_buf(),
+            diff: diff(expected.as_deref().unwrap_or_default(), &actual),
+        }),
+    }
+}
+
+pub trait CommandExtSnapshot {
+    /// Check the command, then compare its trimmed stdout with the snapshot at `path`
+    fn check_matches_snapshot<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<Output, CommandExtError>;
+}
+
+impl<T> CommandExtSnapshot for T
+where
+    T: CommandExtCheck<Error = CommandExtError>,
+{
+    fn check_matches_snapshot<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<Output, CommandExtError> {
+        matches_snapshot(self.check()?, path.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env::temp_dir, fs::write, process::Command};
+
+    use super::diff;
+    use crate::{CommandExtError, CommandExtSnapshot};
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff("a\nb\nc", "a\nx\nc"), " a\n-b\n+x\n c");
+        assert_eq!(diff("", "a"), "+a");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_snapshot() -> anyhow::Result<()> {
+        let path = temp_dir().join(format!("command-ext-snapshot-{}.txt", std::process::id()));
+        write(&path, "x\r\ny\n\n")?;
+
+        Command::new("printf")
+            .arg("x\\ny")
+            .check_matches_snapshot(&path)?;
+
+        let error = Command::new("echo")
+            .arg("x")
+            .check_matches_snapshot(&path)
+            .unwrap_err();
+        assert!(
+            matches!(&error, CommandExtError::SnapshotMismatch { diff, .. } if diff == " x\n-y")
+        );
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+//! Extension trait to check for free disk space before running a command
+//!
+//! Builds and archive extractions which run out of space part way through leave
+//! half-written outputs behind and fail with confusing errors. Requiring the space up front
+//! fails early with an error saying which volume is full.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{CommandExtCheck, CommandExtFreeSpace};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! Command::new("echo")
+//!     .arg("building")
+//!     .require_free_space("target", 1 << 20)
+//!     .check()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::{Error, ErrorKind, Result as IoResult},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Output},
+};
+
+#[cfg(feature = "check")]
+use crate::{
+    check::{check_with, preflight},
+    CommandExtCheck,
+};
+use crate::{wrap::HasCommand, CommandExtError, CommandWrap};
+
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+mod sys {
+    use std::{
+        ffi::{c_char, c_int, c_ulong, CString},
+        io::{Error, Result as IoResult},
+        mem::MaybeUninit,
+        os::unix::ffi::OsStrExt,
+        path::Path,
+    };
+
+    /// The leading fields of `struct statvfs`, padded to more than its full size
+    #[repr(C)]
+    struct StatVfs {
+        f_bsize: c_ulong,
+        f_frsize: c_ulong,
+        f_blocks: c_ulong,
+        f_bfree: c_ulong,
+        f_bavail: c_ulong,
+        rest: [c_ulong; 16],
+    }
+
+    extern "C" {
+        fn statvfs(path: *const c_char, buf: *mut StatVfs) -> c_int;
+    }
+
+    pub(super) fn available(path: &Path) -> IoResult<u64> {
+        let path = CString::new(path.as_os_str().as_bytes()).map_err(Error::other)?;
+        let mut buf = MaybeUninit::<StatVfs>::zeroed();
+
+        // SAFETY: path is NUL terminated and buf is larger than a struct statvfs
+        match unsafe { statvfs(path.as_ptr(), buf.as_mut_ptr()) } {
+            0 => {
+                // SAFETY: statvfs succeeded, so it initialized buf
+                let buf = unsafe { buf.assume_init() };
+                Ok(buf.f_bavail.saturating_mul(buf.f_frsize))
+            }
+            _ => Err(Error::last_os_error()),
+        }
+    }
+}
+
+#[cfg(all(unix, not(all(target_os = "linux", target_pointer_width = "64"))))]
+mod sys {
+    use std::{
+        io::{Error, Result as IoResult},
+        path::Path,
+        process::Command,
+    };
+
+    /// The available space reported by POSIX `df`, whose field layout does not vary
+    /// between platforms like `struct statvfs` does
+    pub(super) fn available(path: &Path) -> IoResult<u64> {
+        let output = Command::new("df").arg("-Pk").arg(path).output()?;
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .nth(1)
+            .and_then(|line| line.split_whitespace().nth(3))
+            .and_then(|kib| kib.parse::<u64>().ok())
+            .map(|kib| kib.saturating_mul(1024))
+            .ok_or_else(|| {
+                Error::other(format!(
+                    "could not read the free space of {}",
+                    path.display()
+                ))
+            })
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::{
+        io::{Error, Result as IoResult},
+        os::windows::ffi::OsStrExt,
+        path::Path,
+    };
+
+    extern "system" {
+        fn GetDiskFreeSpaceExW(
+            directory: *const u16,
+            available: *mut u64,
+            total: *mut u64,
+            free: *mut u64,
+        ) -> i32;
+    }
+
+    pub(super) fn available(path: &Path) -> IoResult<u64> {
+        let path = path
+            .as_os_str()
+            .encode_wide()
+            .chain(Some(0))
+            .collect::<Vec<_>>();
+        let mut available = 0;
+
+        // SAFETY: path is NUL terminated, and the totals which are not needed may be null
+        match unsafe {
+            GetDiskFreeSpaceExW(
+                path.as_ptr(),
+                &mut available,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            )
+        } {
+            0 => Err(Error::last_os_error()),
+            _ => Ok(available),
+        }
+    }
+}
+
+/// The number of bytes available to unprivileged users on the volume containing `path`.
+/// If `path` does not exist yet, the volume of its closest existing ancestor is used.
+pub fn available_space<P: AsRef<Path>>(path: P) -> IoResult<u64> {
+    let path = path.as_ref();
+    let existing = path
+        .ancestors()
+        .find(|p| p.exists())
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+
+    sys::available(existing)
+}
+
+#[derive(Debug)]
+pub struct CommandFreeSpace<'a> {
+    command: &'a mut Command,
+    requirements: Vec<(PathBuf, u64)>,
+}
+
+impl<'a> CommandFreeSpace<'a> {
+    /// Also require `bytes` of free space on the volume containing `path`
+    pub fn require_free_space<P: AsRef<Path>>(&mut self, path: P, bytes: u64) -> &mut Self {
+        self.requirements.push((path.as_ref().to_path_buf(), bytes));
+        self
+    }
+
+    /// The paths and the number of bytes of free space required on their volumes
+    pub fn get_requirements(&self) -> &[(PathBuf, u64)] {
+        &self.requirements
+    }
+
+    /// Check every requirement, failing with the first which is not met. Paths relative to
+    /// the command's working directory are resolved against it.
+    fn ensure(&self) -> Result<(), CommandExtError> {
+        self.requirements.iter().try_for_each(|(path, required)| {
+            let path = match self.command.get_current_dir() {
+                Some(dir) => dir.join(path),
+                None => path.clone(),
+            };
+
+            let available = available_space(&path)?;
+            if available < *required {
+                return Err(CommandExtError::InsufficientSpace {
+                    path,
+                    available,
+                    required: *required,
+                });
+            }
+
+            Ok(())
+        })
+    }
+
+    fn ensure_io(&self) -> IoResult<()> {
+        self.ensure().map_err(|e| match e {
+            CommandExtError::StdIoError(e) => e,
+            e => Error::new(ErrorKind::StorageFull, e),
+        })
+    }
+}
+
+impl<'a> HasCommand for CommandFreeSpace<'a> {
+    fn command(&self) -> &Command {
+        self.command
+    }
+
+    fn command_mut(&mut self) -> &mut Command {
+        self.command
+    }
+}
+
+impl<'a> CommandWrap for CommandFreeSpace<'a> {
+    fn describe(&self) -> Option<String> {
+        Some(
+            self.requirements
+                .iter()
+                .map(|(path, bytes)| format!("requires {bytes} bytes free at {}", path.display()))
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+    }
+
+    fn spawn(&mut self) -> IoResult<Child> {
+        self.ensure_io()?;
+        self.command.spawn()
+    }
+
+    fn output(&mut self) -> IoResult<Output> {
+        self.ensure_io()?;
+        self.command.output()
+    }
+
+    fn status(&mut self) -> IoResult<ExitStatus> {
+        self.ensure_io()?;
+        self.command.status()
+    }
+}
+
+impl<'a> From<&'a mut Command> for CommandFreeSpace<'a> {
+    fn from(value: &'a mut Command) -> Self {
+        Self {
+            command: value,
+            requirements: Vec::new(),
+        }
+    }
+}
+
+#[cfg(feature = "check")]
+impl<'a> CommandExtCheck for CommandFreeSpace<'a> {
+    type Error = CommandExtError;
+
+    fn check(&mut self) -> Result<Output, Self::Error> {
+        preflight(self.command)?;
+        self.ensure()?;
+        let output = self.command.output();
+        check_with(self, output)
+    }
+}
+
+pub trait CommandExtFreeSpace {
+    /// Fail before executing the command if there are fewer than `bytes` of free space on
+    /// the volume containing `path`
+    fn require_free_space<P: AsRef<Path>>(&mut self, path: P, bytes: u64) -> CommandFreeSpace<'_>;
+}
+
+impl CommandExtFreeSpace for Command {
+    fn require_free_space<P: AsRef<Path>>(&mut self, path: P, bytes: u64) -> CommandFreeSpace<'_> {
+        let mut wrapper = CommandFreeSpace::from(self);
+        wrapper.require_free_space(path, bytes);
+        wrapper
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::ErrorKind, process::Command};
+
+    use super::available_space;
+    use crate::{CommandExtFreeSpace, CommandWrap};
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that requirements are checked against the volume before executing
+    fn test_require_free_space() -> anyhow::Result<()> {
+        assert!(available_space("target/does/not/exist")? > 0);
+
+        let mut command = Command::new("true");
+        let mut wrapper = command.require_free_space(".", 1);
+        assert!(wrapper.status()?.success());
+
+        wrapper.require_free_space("target", u64::MAX);
+        let error = wrapper.status().err().map(|e| e.kind());
+        assert_eq!(error, Some(ErrorKind::StorageFull));
+
+        #[cfg(feature = "check")]
+        {
+            use crate::{CommandExtCheck, CommandExtError};
+
+            match command.require_free_space(".", u64::MAX).check() {
+                Err(CommandExtError::InsufficientSpace { required, .. }) => {
+                    assert_eq!(required, u64::MAX)
+                }
+                other => panic!("Unexpected result from command: {:?}", other),
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+//! Control over how children are started
+//!
+//! On most unix platforms the standard library starts children with `posix_spawn`, which
+//! uses `vfork` or `clone(CLONE_VM)` under the hood and is much cheaper than `fork` followed
+//! by `exec` for processes with a large address space. The standard library falls back to
+//! `fork`/`exec` whenever a command is configured with something `posix_spawn` cannot
+//! express:
+//!
+//! * a `pre_exec` closure
+//! * `uid`, `gid` or `groups`
+//! * `current_dir`, when the C library lacks `posix_spawn_file_actions_addchdir_np`
+//! * `process_group`, when the C library lacks `POSIX_SPAWN_SETPGROUP`
+//!
+//! The `watch` and `timeout` wrappers set `process_group` on unix, so that they can kill a
+//! command along with its descendants. The `priority` wrapper and the jobserver's
+//! `configure` install a `pre_exec` closure. Those, and any other setting which would need
+//! a `pre_exec` closure (for example to set up a pseudo-terminal or a parent death signal),
+//! check [`prefers_posix_spawn`] and, when it is set, use an alternative which does not
+//! require one or skip the setting entirely. Their documentation says so explicitly.
+//!
+//! Scripts which start thousands of tiny processes should call
+//! [`set_prefer_posix_spawn`] once at startup. The `spawn` benchmark measures the
+//! throughput of the different ways of starting short commands.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use command_ext::spawn;
+//! spawn::set_prefer_posix_spawn(true);
+//! assert!(spawn::prefers_posix_spawn());
+//! # spawn::set_prefer_posix_spawn(false);
+//! ```
+
+#[cfg(any(feature = "watch", feature = "timeout"))]
+use std::process::Child;
+#[cfg(any(
+    feature = "fuzz",
+    feature = "shebang",
+    feature = "watch",
+    feature = "timeout"
+))]
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static PREFER_POSIX_SPAWN: AtomicBool = AtomicBool::new(false);
+
+/// Ask wrappers to avoid configuring commands in ways that force `fork`/`exec`
+pub fn set_prefer_posix_spawn(prefer: bool) {
+    PREFER_POSIX_SPAWN.store(prefer, Ordering::Relaxed);
+}
+
+/// Whether wrappers should avoid configuring commands in ways that force `fork`/`exec`
+pub fn prefers_posix_spawn() -> bool {
+    PREFER_POSIX_SPAWN.load(Ordering::Relaxed)
+}
+
+/// Append the arguments of `from` to `to`, and copy its environment changes and working
+/// directory. Stdio configuration and environment clearing cannot be read back from a
+/// command, so they are not copied.
+#[cfg(any(feature = "fuzz", feature = "shebang"))]
+pub(crate) fn copy_config(from: &Command, to: &mut Command) {
+    to.args(from.get_args());
+    from.get_envs().for_each(|(k, v)| match v {
+        Some(v) => {
+            to.env(k, v);
+        }
+        None => {
+            to.env_remove(k);
+        }
+    });
+    if let Some(dir) = from.get_current_dir() {
+        to.current_dir(dir);
+    }
+}
+
+#[cfg(all(unix, any(feature = "watch", feature = "timeout")))]
+mod sys {
+    use std::{os::unix::process::CommandExt, process::Command};
+
+    extern "C" {
+        fn kill(pid: i32, sig: i32) -> i32;
+    }
+
+    const SIGKILL: i32 = 9;
+
+    pub(super) fn isolate(command: &mut Command) {
+        command.process_group(0);
+    }
+
+    /// Kill the process group led by `pid`, returning whether it could be signalled
+    pub(super) fn kill_group(pid: u32) -> bool {
+        // SAFETY: kill has no memory safety requirements
+        i32::try_from(pid).is_ok_and(|pid| unsafe { kill(-pid, SIGKILL) } == 0)
+    }
+}
+
+#[cfg(all(not(unix), any(feature = "watch", feature = "timeout")))]
+mod sys {
+    use std::process::Command;
+
+    pub(super) fn isolate(_command: &mut Command) {}
+
+    pub(super) fn kill_group(_pid: u32) -> bool {
+        false
+    }
+}
+
+/// Start `command` in a process group of its own on unix, so that [`kill_group`] kills its
+/// descendants along with it
+#[cfg(any(feature = "watch", feature = "timeout"))]
+pub(crate) fn isolate(command: &mut Command) {
+    sys::isolate(command);
+}
+
+/// Kill `child` and, where process groups are supported, every process in the group it was
+/// started in by [`isolate`]
+#[cfg(any(feature = "watch", feature = "timeout"))]
+pub(crate) fn kill_group(child: &mut Child) {
+    if !sys::kill_group(child.id()) {
+        child.kill().ok();
+    }
+}
@@ -0,0 +1,751 @@
+//! An owned copy of the configuration of a command, and a stable fingerprint of it
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{CommandExtFingerprint, CommandSpec};
+//! let mut first = Command::new("cargo");
+//! first.arg("build").env("RUSTFLAGS", "-Dwarnings");
+//! let mut second = Command::new("cargo");
+//! second.arg("build").env("RUSTFLAGS", "-Dwarnings");
+//!
+//! assert_eq!(first.fingerprint(), second.fingerprint());
+//! assert_eq!(CommandSpec::from(&first), CommandSpec::from(&second));
+//!
+//! second.arg("--release");
+//! assert_ne!(first.fingerprint(), second.fingerprint());
+//! ```
+
+use std::{
+    ffi::{OsStr, OsString},
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    hash::{Hash, Hasher},
+    io::{Error, ErrorKind, Result as IoResult},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+};
+
+#[cfg(feature = "check")]
+use crate::{check::check_wrapper, CommandExtCheck, CommandExtError};
+use crate::{wrap::HasCommand, CommandWrap};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// How one of the standard streams of a command is configured
+pub enum StdioConfig {
+    /// Not configured, so the default of the executing method applies: inherited for
+    /// `spawn` and `status`, piped for `output`
+    Default,
+    /// Inherited from the current process
+    Inherit,
+    /// Connected to a pipe
+    Piped,
+    /// Connected to the null device
+    Null,
+    /// Connected to a file or another handle
+    Other(String),
+    /// Left to a [`Command`], which does not expose how its streams are configured
+    Unknown,
+}
+
+impl StdioConfig {
+    /// The standard library configuration for this stream, if it can be recreated
+    pub(crate) fn stdio(&self) -> Option<Stdio> {
+        match self {
+            Self::Inherit => Some(Stdio::inherit()),
+            Self::Piped => Some(Stdio::piped()),
+            Self::Null => Some(Stdio::null()),
+            Self::Default | Self::Other(_) | Self::Unknown => None,
+        }
+    }
+}
+
+impl Display for StdioConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Default => write!(f, "default"),
+            Self::Inherit => write!(f, "inherit"),
+            Self::Piped => write!(f, "piped"),
+            Self::Null => write!(f, "null"),
+            Self::Other(other) => write!(f, "{other}"),
+            Self::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// A stable hash of the program, arguments, environment and working directory of a command.
+/// Fingerprints are computed only from what the standard library exposes through stable
+/// getters, and from what a [`CommandSpec`] tracks itself, so they do not depend on the
+/// process or on the version of Rust and can be saved and compared across runs on the same
+/// platform.
+///
+/// Whether the environment is cleared is part of the fingerprint of a spec, but a
+/// [`Command`] does not expose it, so a command which clears its environment has the same
+/// fingerprint as one which does not.
+pub struct Fingerprint(pub u64);
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// A 64-bit FNV-1a hasher. Unlike the standard library's default hasher, its output is
+/// fixed, which is what makes a [`Fingerprint`] stable.
+struct Fnv(u64);
+
+impl Fnv {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0 = bytes
+            .iter()
+            .fold(self.0, |h, b| (h ^ u64::from(*b)).wrapping_mul(Self::PRIME));
+    }
+
+    /// Write a tagged, length-prefixed field so adjacent fields cannot run together
+    fn field(&mut self, tag: u8, value: &OsStr) {
+        let bytes = value.as_encoded_bytes();
+        self.write(&[tag]);
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+}
+
+#[derive(Debug, Clone)]
+/// An owned copy of everything observable about how a [`Command`] is configured. Two specs
+/// are equal when their [fingerprints](CommandSpec::fingerprint) are; the stdio
+/// configuration is carried along but is not part of the comparison.
+pub struct CommandSpec {
+    /// The program to execute
+    pub program: OsString,
+    /// The arguments passed to the program
+    pub args: Vec<OsString>,
+    /// Whether the inherited environment is cleared before `envs` is applied. This is always
+    /// false for a spec taken from a [`Command`], which does not expose it.
+    pub env_clear: bool,
+    /// Environment variables set (`Some`) or removed (`None`), sorted by name
+    pub envs: Vec<(OsString, Option<OsString>)>,
+    /// The working directory, if it was set
+    pub current_dir: Option<PathBuf>,
+    /// The configuration of the standard input stream
+    pub stdin: StdioConfig,
+    /// The configuration of the standard output stream
+    pub stdout: StdioConfig,
+    /// The configuration of the standard error stream
+    pub stderr: StdioConfig,
+}
+
+impl CommandSpec {
+    /// A spec for running `program` with no arguments in the inherited environment
+    pub fn new<S>(program: S) -> Self
+    where
+        S: AsRef<OsStr>,
+    {
+        Self {
+            program: program.as_ref().to_owned(),
+            args: Vec::new(),
+            env_clear: false,
+            envs: Vec::new(),
+            current_dir: None,
+            stdin: StdioConfig::Default,
+            stdout: StdioConfig::Default,
+            stderr: StdioConfig::Default,
+        }
+    }
+
+    /// Replace the program to execute
+    pub fn set_program<S: AsRef<OsStr>>(&mut self, program: S) -> &mut Self {
+        self.program = program.as_ref().to_owned();
+        self
+    }
+
+    /// Add an argument
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// Add several arguments
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|a| a.as_ref().to_owned()));
+        self
+    }
+
+    /// Remove every argument
+    pub fn clear_args(&mut self) -> &mut Self {
+        self.args.clear();
+        self
+    }
+
+    /// Replace the argument at `index`
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no argument at `index`
+    pub fn replace_arg<S: AsRef<OsStr>>(&mut self, index: usize, arg: S) -> &mut Self {
+        self.args[index] = arg.as_ref().to_owned();
+        self
+    }
+
+    /// Set or remove an environment variable, replacing any earlier setting of it
+    fn set_env(&mut self, key: &OsStr, val: Option<&OsStr>) {
+        let val = val.map(OsStr::to_owned);
+        match self.envs.binary_search_by(|(k, _)| k.as_os_str().cmp(key)) {
+            Ok(i) => self.envs[i].1 = val,
+            Err(i) => self.envs.insert(i, (key.to_owned(), val)),
+        }
+    }
+
+    /// Set an environment variable
+    pub fn env<K, V>(&mut self, key: K, val: V) -> &mut Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.set_env(key.as_ref(), Some(val.as_ref()));
+        self
+    }
+
+    /// Remove an environment variable
+    pub fn env_remove<K: AsRef<OsStr>>(&mut self, key: K) -> &mut Self {
+        self.set_env(key.as_ref(), None);
+        self
+    }
+
+    /// Clear the inherited environment and every variable set so far
+    pub fn env_clear(&mut self) -> &mut Self {
+        self.env_clear = true;
+        self.envs.clear();
+        self
+    }
+
+    /// Set the working directory
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.current_dir = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// The stable fingerprint of the program, arguments, environment, whether it is cleared,
+    /// and working directory of this spec
+    pub fn fingerprint(&self) -> Fingerprint {
+        let mut hasher = Fnv(Fnv::OFFSET);
+        hasher.field(b'p', &self.program);
+        self.args.iter().for_each(|a| hasher.field(b'a', a));
+        hasher.write(&[b'c', u8::from(self.env_clear)]);
+
+        let mut envs = self.envs.iter().collect::<Vec<_>>();
+        envs.sort_by(|a, b| a.0.cmp(&b.0));
+        envs.into_iter().for_each(|(k, v)| {
+            hasher.field(b'k', k);
+            match v {
+                Some(v) => hasher.field(b'v', v),
+                None => hasher.write(b"r"),
+            }
+        });
+
+        if let Some(dir) = &self.current_dir {
+            hasher.field(b'd', dir.as_os_str());
+        }
+
+        Fingerprint(hasher.0)
+    }
+
+    /// Build a new command with this configuration. Stdio which was connected to a file
+    /// or another handle, or is unknown, cannot be recreated and is left unconfigured.
+    pub fn to_command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+
+        if self.env_clear {
+            command.env_clear();
+        }
+
+        self.envs.iter().for_each(|(k, v)| match v {
+            Some(v) => {
+                command.env(k, v);
+            }
+            None => {
+                command.env_remove(k);
+            }
+        });
+
+        if let Some(dir) = &self.current_dir {
+            command.current_dir(dir);
+        }
+
+        if let Some(stdin) = self.stdin.stdio() {
+            command.stdin(stdin);
+        }
+        if let Some(stdout) = self.stdout.stdio() {
+            command.stdout(stdout);
+        }
+        if let Some(stderr) = self.stderr.stdio() {
+            command.stderr(stderr);
+        }
+
+        command
+    }
+}
+
+/// A command does not expose whether its environment was cleared or how its streams are
+/// configured, so the spec inherits the environment and its streams are
+/// [`StdioConfig::Unknown`]. Set those on the spec to carry them over.
+impl From<&Command> for CommandSpec {
+    fn from(command: &Command) -> Self {
+        Self {
+            program: command.get_program().to_owned(),
+            args: command.get_args().map(OsStr::to_owned).collect(),
+            env_clear: false,
+            envs: command
+                .get_envs()
+                .map(|(k, v)| (k.to_owned(), v.map(OsStr::to_owned)))
+                .collect(),
+            current_dir: command.get_current_dir().map(PathBuf::from),
+            stdin: StdioConfig::Unknown,
+            stdout: StdioConfig::Unknown,
+            stderr: StdioConfig::Unknown,
+        }
+    }
+}
+
+impl From<&mut Command> for CommandSpec {
+    fn from(command: &mut Command) -> Self {
+        Self::from(&*command)
+    }
+}
+
+impl From<Command> for CommandSpec {
+    fn from(command: Command) -> Self {
+        Self::from(&command)
+    }
+}
+
+impl PartialEq for CommandSpec {
+    fn eq(&self, other: &Self) -> bool {
+        self.fingerprint() == other.fingerprint()
+    }
+}
+
+impl Eq for CommandSpec {}
+
+impl Hash for CommandSpec {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.fingerprint().hash(state)
+    }
+}
+
+/// Extension trait for [`std::process::Command`] to compute a stable fingerprint of its
+/// configuration
+pub trait CommandExtFingerprint {
+    /// A stable hash of the program, arguments, environment variables and working
+    /// directory, for deduplicating executions and keying caches. Whether the environment
+    /// is cleared is not visible on a command, so it is not part of the hash.
+    fn fingerprint(&self) -> Fingerprint;
+}
+
+impl CommandExtFingerprint for Command {
+    fn fingerprint(&self) -> Fingerprint {
+        CommandSpec::from(self).fingerprint()
+    }
+}
+
+impl<T> CommandExtFingerprint for T
+where
+    T: CommandWrap,
+{
+    fn fingerprint(&self) -> Fingerprint {
+        CommandSpec::from(self.command()).fingerprint()
+    }
+}
+
+/// A setting the [`CommandSpec`] does not model, applied again each time the command is
+/// rebuilt
+type Configure = Box<dyn FnMut(&mut Command) + Send>;
+
+/// A command which can remove and replace its arguments and program. The standard library
+/// cannot remove configuration from a [`Command`], so edits are made to an owned
+/// [`CommandSpec`] and the command is rebuilt from it before it next executes. Until then,
+/// [`HasCommand::command`] still has the old configuration; use [`CommandEdit::spec`] to see
+/// the pending one.
+///
+/// Rebuilding discards everything the spec does not model, such as stdio connected to a
+/// handle, a user or group on Unix, or `pre_exec` hooks. Apply those with
+/// [`CommandEdit::configure`], which applies them again to every rebuilt command. A command
+/// configured any other way, through [`HasCommand::command_mut`] or by setting stdio with
+/// [`CommandWrap::stdin`] and friends, is never rebuilt again: executing it after a later
+/// edit is an error rather than running without those settings.
+///
+/// # Example
+///
+/// ```rust
+/// # use command_ext::{CommandEdit, CommandSpec, CommandWrap};
+/// let mut spec = CommandSpec::new("echo");
+/// spec.args(["a", "b"]);
+///
+/// let mut edit = CommandEdit::new(spec);
+/// edit.configure(|command| {
+///     command.env("CONFIGURED", "1");
+/// });
+/// edit.clear_args().arg("c");
+/// assert_eq!(edit.output()?.stdout, b"c\n");
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct CommandEdit {
+    command: Command,
+    spec: CommandSpec,
+    configure: Vec<Configure>,
+    direct: bool,
+    dirty: bool,
+}
+
+impl Debug for CommandEdit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("CommandEdit")
+            .field("command", &self.command)
+            .field("spec", &self.spec)
+            .field("configure", &self.configure.len())
+            .field("direct", &self.direct)
+            .field("dirty", &self.dirty)
+            .finish()
+    }
+}
+
+impl CommandEdit {
+    /// A command built from `spec`
+    pub fn new(spec: CommandSpec) -> Self {
+        Self {
+            command: spec.to_command(),
+            spec,
+            configure: Vec::new(),
+            direct: false,
+            dirty: false,
+        }
+    }
+
+    /// The configuration the command will execute with
+    pub fn spec(&self) -> &CommandSpec {
+        &self.spec
+    }
+
+    /// Apply a setting the spec does not model to the command now, and again each time it
+    /// is rebuilt
+    pub fn configure<F>(&mut self, mut configure: F) -> &mut Self
+    where
+        F: FnMut(&mut Command) + Send + 'static,
+    {
+        configure(&mut self.command);
+        self.configure.push(Box::new(configure));
+        self
+    }
+
+    /// Replace the program to execute
+    pub fn set_program<S: AsRef<OsStr>>(&mut self, program: S) -> &mut Self {
+        self.spec.set_program(program);
+        self.dirty = true;
+        self
+    }
+
+    /// Remove every argument
+    pub fn clear_args(&mut self) -> &mut Self {
+        self.spec.clear_args();
+        self.dirty = true;
+        self
+    }
+
+    /// Replace the argument at `index`
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no argument at `index`
+    pub fn replace_arg<S: AsRef<OsStr>>(&mut self, index: usize, arg: S) -> &mut Self {
+        self.spec.replace_arg(index, arg);
+        self.dirty = true;
+        self
+    }
+
+    /// Rebuild the command from the spec if it was edited. A command configured directly may
+    /// carry settings which would be lost, so it is an error.
+    fn rebuild(&mut self) -> IoResult<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        if self.direct {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "an edited command cannot be rebuilt after it was configured directly, since \
+                 settings the spec does not model would be lost; use CommandEdit::configure",
+            ));
+        }
+
+        self.command = self.spec.to_command();
+        self.configure
+            .iter_mut()
+            .for_each(|configure| configure(&mut self.command));
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Configure the stdio of the command directly, which the spec cannot model
+    fn set_stdio<F>(&mut self, set: F) -> &mut Self
+    where
+        F: FnOnce(&mut Command),
+    {
+        self.rebuild().ok();
+        self.direct = true;
+        set(&mut self.command);
+        self
+    }
+}
+
+impl HasCommand for CommandEdit {
+    fn command(&self) -> &Command {
+        &self.command
+    }
+
+    /// The command, rebuilt with any pending edits first. It is configured directly from
+    /// then on, so it is not rebuilt again.
+    fn command_mut(&mut self) -> &mut Command {
+        self.rebuild().ok();
+        self.direct = true;
+        &mut self.command
+    }
+}
+
+impl CommandWrap for CommandEdit {
+    fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Self {
+        self.spec.arg(&arg);
+        self.command.arg(arg);
+        self
+    }
+
+    fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let args = args.into_iter().collect::<Vec<_>>();
+        self.spec.args(&args);
+        self.command.args(args);
+        self
+    }
+
+    fn env<K, V>(&mut self, key: K, val: V) -> &mut Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.spec.env(&key, &val);
+        self.command.env(key, val);
+        self
+    }
+
+    fn envs<I, K, V>(&mut self, vars: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        vars.into_iter().for_each(|(k, v)| {
+            self.env(k, v);
+        });
+        self
+    }
+
+    fn env_remove<K: AsRef<OsStr>>(&mut self, key: K) -> &mut Self {
+        self.spec.env_remove(&key);
+        self.command.env_remove(key);
+        self
+    }
+
+    fn env_clear(&mut self) -> &mut Self {
+        self.spec.env_clear();
+        self.command.env_clear();
+        self
+    }
+
+    fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.spec.current_dir(&dir);
+        self.command.current_dir(dir);
+        self
+    }
+
+    fn stdin<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        self.set_stdio(|command| {
+            command.stdin(cfg);
+        })
+    }
+
+    fn stdout<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        self.set_stdio(|command| {
+            command.stdout(cfg);
+        })
+    }
+
+    fn stderr<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Self {
+        self.set_stdio(|command| {
+            command.stderr(cfg);
+        })
+    }
+
+    fn spawn(&mut self) -> IoResult<Child> {
+        self.rebuild()?;
+        self.command.spawn()
+    }
+
+    fn output(&mut self) -> IoResult<Output> {
+        self.rebuild()?;
+        self.command.output()
+    }
+
+    fn status(&mut self) -> IoResult<ExitStatus> {
+        self.rebuild()?;
+        self.command.status()
+    }
+}
+
+impl From<CommandSpec> for CommandEdit {
+    fn from(value: CommandSpec) -> Self {
+        Self::new(value)
+    }
+}
+
+#[cfg(feature = "check")]
+impl CommandExtCheck for CommandEdit {
+    type Error = CommandExtError;
+
+    fn check(&mut self) -> Result<Output, Self::Error> {
+        check_wrapper(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::ErrorKind,
+        process::{Command, Stdio},
+    };
+
+    use super::{CommandEdit, CommandSpec, Fingerprint, StdioConfig};
+    use crate::{CommandExtFingerprint, CommandWrap, HasCommand};
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that the fingerprint covers each part of the configuration and is stable
+    fn test_fingerprint() {
+        let base = || {
+            let mut command = Command::new("echo");
+            command.arg("x").env("A", "b");
+            command
+        };
+
+        assert_eq!(base().fingerprint(), base().fingerprint());
+        assert_eq!(
+            base().stdout(Stdio::null()).fingerprint(),
+            base().fingerprint()
+        );
+        assert_ne!(base().arg("y").fingerprint(), base().fingerprint());
+        assert_ne!(base().env("A", "c").fingerprint(), base().fingerprint());
+        assert_ne!(base().env_remove("B").fingerprint(), base().fingerprint());
+        assert_ne!(
+            CommandSpec::from(&base()).env_clear().fingerprint(),
+            base().fingerprint()
+        );
+        // A command does not expose whether its environment was cleared
+        assert_eq!(
+            Command::new("echo").env_clear().fingerprint(),
+            Command::new("echo").fingerprint()
+        );
+        assert_ne!(base().current_dir("/").fingerprint(), base().fingerprint());
+
+        // Arguments which would join to the same string are still distinct
+        assert_ne!(
+            Command::new("echo").args(["ab", "c"]).fingerprint(),
+            Command::new("echo").args(["a", "bc"]).fingerprint()
+        );
+
+        assert_eq!(
+            CommandSpec::new("true").fingerprint(),
+            Fingerprint(0xf331_b0f8_9c73_90d4)
+        );
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a spec recreates the command it was taken from, and builds a command with
+    /// the configuration a command does not expose
+    fn test_to_command() -> anyhow::Result<()> {
+        let mut command = Command::new("echo");
+        command
+            .arg("x")
+            .env("A", "b")
+            .current_dir("/")
+            .stdout(Stdio::piped());
+
+        let spec = CommandSpec::from(&command);
+        assert_eq!(spec.stdout, StdioConfig::Unknown);
+        assert!(!spec.env_clear);
+        assert_eq!(CommandSpec::from(&spec.to_command()), spec);
+
+        let mut spec = CommandSpec::new("/bin/sh");
+        spec.args(["-c", "printf %s \"$A$HOME\""])
+            .env_clear()
+            .env("A", "b");
+        spec.stdout = StdioConfig::Piped;
+        spec.stdin = StdioConfig::Null;
+        assert_eq!(spec.to_command().status()?.code(), Some(0));
+        assert_eq!(spec.to_command().spawn()?.wait_with_output()?.stdout, b"b");
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that an edited command is rebuilt with its edits and configuration before it
+    /// executes
+    fn test_edit() -> anyhow::Result<()> {
+        let mut spec = CommandSpec::new("echo");
+        spec.args(["a", "b"]).env("A", "b");
+
+        let mut edit = CommandEdit::new(spec);
+        edit.configure(|command| {
+            command.stderr(Stdio::null());
+        });
+        edit.clear_args().arg("c").arg("d").replace_arg(0, "e");
+        assert_eq!(edit.spec().args, ["e", "d"]);
+        assert_eq!(edit.output()?.stdout, b"e d\n");
+
+        edit.set_program("sh")
+            .clear_args()
+            .args(["-c", "printf %s \"$A\"; echo lost >&2"]);
+        let output = edit.output()?;
+        assert_eq!(output.stdout, b"b");
+        assert!(output.stderr.is_empty());
+        assert_eq!(edit.command().get_program(), "sh");
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a command configured directly is not rebuilt without that configuration
+    fn test_edit_configured_directly() {
+        let mut edit = CommandEdit::new(CommandSpec::new("echo"));
+        edit.arg("a").stdout(Stdio::null());
+        edit.clear_args();
+        assert_eq!(edit.output().unwrap_err().kind(), ErrorKind::InvalidInput);
+
+        let mut edit = CommandEdit::new(CommandSpec::new("echo"));
+        edit.command_mut().env("DIRECT", "1");
+        edit.set_program("true");
+        assert_eq!(edit.status().unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
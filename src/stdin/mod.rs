@@ -0,0 +1,269 @@
+//! Extension trait to feed input to a command, optionally rendered from a template
+//!
+//! Templates contain `{name}` placeholders which are replaced by the value bound to `name`.
+//! Literal braces are written `{{` and `}}`. A placeholder without a binding is an error
+//! reported when the command executes, so a typo never feeds a half-rendered script to a
+//! tool like `psql`.
+//!
+//! Input is written from a thread of the [`crate::pool`] while the output of the command is
+//! read, so commands which produce output before consuming all of their input do not
+//! deadlock.
+//!
+//! Values are substituted verbatim, without any quoting or escaping. Do not bind untrusted
+//! values into input which the command interprets as code, such as SQL for `psql` or a
+//! script for a shell: a value containing a quote can change what the input does. Escape
+//! such values for the target language before binding them, or pass them through a
+//! channel the tool keeps separate from code, such as `psql`'s `-v` variables.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{CommandExtStdin, CommandWrap};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let output = Command::new("cat")
+//!     .stdin_template(
+//!         "[server]\nhost = {host}\nport = {port}\nlabels = {{}}\n",
+//!         [("host", "localhost"), ("port", "8080")],
+//!     )
+//!     .output()?;
+//! assert_eq!(
+//!     output.stdout,
+//!     b"[server]\nhost = localhost\nport = 8080\nlabels = {}\n"
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    io::{Error, ErrorKind, Result as IoResult, Write},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+};
+
+use crate::{
+    capture, pool,
+    wrap::{ExecutionInfo, HasCommand},
+    CommandWrap,
+};
+#[cfg(feature = "check")]
+use crate::{check::check_wrapper, CommandExtCheck, CommandExtError};
+
+/// Render `template`, replacing each `{name}` placeholder with `lookup(name)`. Fails if a
+/// placeholder has no value or a brace is unbalanced.
+pub fn render<F>(template: &str, lookup: F) -> IoResult<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let invalid = |message: String| Error::new(ErrorKind::InvalidInput, message);
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(i) = rest.find(['{', '}']) {
+        rendered.push_str(&rest[..i]);
+        let (brace, after) = (&rest[i..i + 1], &rest[i + 1..]);
+
+        if let Some(after) = after.strip_prefix(brace) {
+            rendered.push_str(brace);
+            rest = after;
+        } else if brace == "}" {
+            return Err(invalid(format!("unmatched }} at offset {i} of template")));
+        } else {
+            let end = after
+                .find('}')
+                .ok_or_else(|| invalid(format!("unclosed {{ at offset {i} of template")))?;
+            let name = &after[..end];
+            let value = lookup(name)
+                .ok_or_else(|| invalid(format!("template binding {name} is not defined")))?;
+            rendered.push_str(&value);
+            rest = &after[end + 1..];
+        }
+    }
+
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+#[derive(Debug)]
+enum Input {
+    Bytes(Vec<u8>),
+    Template {
+        template: String,
+        bindings: BTreeMap<String, String>,
+    },
+}
+
+#[derive(Debug)]
+/// A command which is fed the given input on its standard input
+pub struct CommandStdin<'a> {
+    command: &'a mut Command,
+    input: Input,
+    last_execution: Option<ExecutionInfo>,
+}
+
+impl<'a> CommandStdin<'a> {
+    /// Bind `name` to `value` in the template, replacing any earlier binding. Has no effect
+    /// if the input is not a template.
+    pub fn bind<K, V>(&mut self, name: K, value: V) -> &mut Self
+    where
+        K: Into<String>,
+        V: Display,
+    {
+        if let Input::Template { bindings, .. } = &mut self.input {
+            bindings.insert(name.into(), value.to_string());
+        }
+        self
+    }
+
+    /// The input which will be written to the command
+    pub fn input(&self) -> IoResult<Vec<u8>> {
+        match &self.input {
+            Input::Bytes(bytes) => Ok(bytes.clone()),
+            Input::Template { template, bindings } => {
+                render(template, |name| bindings.get(name).cloned()).map(String::into_bytes)
+            }
+        }
+    }
+
+    /// Spawn the command with piped stdin and start writing the input to it
+    fn start(&mut self) -> IoResult<Child> {
+        let input = self.input()?;
+        let mut child = self.command.stdin(Stdio::piped()).spawn()?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            // The command may exit without reading all of its input
+            drop(pool::spawn(move || stdin.write_all(&input).ok()));
+        }
+
+        Ok(child)
+    }
+}
+
+impl<'a> HasCommand for CommandStdin<'a> {
+    fn command(&self) -> &Command {
+        self.command
+    }
+
+    fn command_mut(&mut self) -> &mut Command {
+        self.command
+    }
+}
+
+impl<'a> CommandWrap for CommandStdin<'a> {
+    fn last_execution(&self) -> Option<ExecutionInfo> {
+        self.last_execution
+    }
+
+    fn writes_stdin(&self) -> bool {
+        true
+    }
+
+    fn spawn(&mut self) -> IoResult<Child> {
+        self.start()
+    }
+
+    fn output(&mut self) -> IoResult<Output> {
+        self.command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        let (output, info) = ExecutionInfo::measure(|| capture::output(self.start()?));
+        self.last_execution = Some(info.with_output(&output));
+        output
+    }
+
+    fn status(&mut self) -> IoResult<ExitStatus> {
+        let (status, info) = ExecutionInfo::measure(|| self.start()?.wait());
+        self.last_execution = Some(info.with_status(&status));
+        status
+    }
+}
+
+#[cfg(feature = "check")]
+impl<'a> CommandExtCheck for CommandStdin<'a> {
+    type Error = CommandExtError;
+
+    fn check(&mut self) -> Result<Output, Self::Error> {
+        check_wrapper(self)
+    }
+}
+
+pub trait CommandExtStdin {
+    /// Feed `input` to the standard input of the command
+    fn stdin_bytes<B: Into<Vec<u8>>>(&mut self, input: B) -> CommandStdin<'_>;
+
+    /// Feed `template`, rendered with `bindings`, to the standard input of the command
+    fn stdin_template<T, I, K, V>(&mut self, template: T, bindings: I) -> CommandStdin<'_>
+    where
+        T: Into<String>,
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Display;
+}
+
+impl CommandExtStdin for Command {
+    fn stdin_bytes<B: Into<Vec<u8>>>(&mut self, input: B) -> CommandStdin<'_> {
+        CommandStdin {
+            command: self,
+            input: Input::Bytes(input.into()),
+            last_execution: None,
+        }
+    }
+
+    fn stdin_template<T, I, K, V>(&mut self, template: T, bindings: I) -> CommandStdin<'_>
+    where
+        T: Into<String>,
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Display,
+    {
+        CommandStdin {
+            command: self,
+            input: Input::Template {
+                template: template.into(),
+                bindings: bindings
+                    .into_iter()
+                    .map(|(k, v)| (k.into(), v.to_string()))
+                    .collect(),
+            },
+            last_execution: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::ErrorKind, process::Command};
+
+    use super::render;
+    use crate::{CommandExtStdin, CommandWrap};
+
+    #[test]
+    fn test_render() {
+        let lookup = |name: &str| (name == "x").then(|| "1".to_string());
+
+        assert_eq!(render("a{x}b{{x}}", lookup).unwrap(), "a1b{x}");
+        assert_eq!(
+            render("{y}", lookup).unwrap_err().to_string(),
+            "template binding y is not defined"
+        );
+        assert_eq!(
+            render("{x", lookup).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert!(render("x}", lookup).is_err());
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_large_input() -> anyhow::Result<()> {
+        let input = vec![b'x'; 1 << 20];
+        let output = Command::new("cat").stdin_bytes(input.clone()).output()?;
+        assert_eq!(output.stdout, input);
+
+        let status = Command::new("true")
+            .stdin_template("{n}", [("n", 1)])
+            .bind("n", 2)
+            .status()?;
+        assert!(status.success());
+        Ok(())
+    }
+}
@@ -0,0 +1,417 @@
+//! Extension trait to kill a command which runs for too long
+//!
+//! The timeout is a layer over any [`Execute`], so it applies to a plain [`Command`], to
+//! every wrapper, and to other layers. A timeout inside a [retry](crate::retry) limits each
+//! attempt. The timeout spawns the command itself, and spawning is never retried, so a retry
+//! inside a timeout makes a single attempt; limit the total time spent retrying with
+//! [`crate::retry::CommandRetry::max_elapsed`] instead.
+//!
+//! The layer spawns the command through the wrapper it is applied to and waits for the child
+//! itself, so the wrapper's spawn, output and status hooks still run. Wrappers which replace
+//! how their command executes rather than using hooks only have their spawn behavior run.
+//!
+//! On unix, the command is started in a process group of its own, and the whole group is
+//! killed when the timeout expires. Output is read until the timeout, plus a short grace
+//! period once the command has exited or been killed, so descendants which keep the
+//! command's pipes open do not hold the layer past its timeout; they are killed along with
+//! the group when they do.
+//!
+//! To collect output, streams which are not configured through the layer are set up as
+//! [`Command::output`] sets them up: a null stdin and piped stdout and stderr. The standard
+//! library does not expose how the streams of a command are configured, so configure them
+//! on the layer rather than on the command before it is wrapped to keep them.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::{process::Command, time::Duration};
+//! # use command_ext::{CommandExtCheck, CommandExtError, CommandExtTimeout};
+//! let result = Command::new("sleep")
+//!     .arg("10")
+//!     .timeout(Duration::from_millis(100))
+//!     .check();
+//! assert!(matches!(result, Err(CommandExtError::TimedOut { .. })));
+//! ```
+
+use std::{
+    io::{Error, Result as IoResult},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    capture::{configure_for_output, Configured, Pipes, Stream},
+    check::{check_with, preflight},
+    clock::{self, Clock},
+    metrics, spawn,
+    wrap::{Execute, ExecutionInfo, HasCommand},
+    CommandExtCheck, CommandExtError, CommandWrap,
+};
+
+/// The shortest time to wait between polls of a child
+const MIN_BACKOFF: Duration = Duration::from_micros(50);
+
+/// The longest time to wait between polls of a child
+const MAX_BACKOFF: Duration = Duration::from_millis(10);
+
+/// How long past the timeout to keep reading the output of a command, since descendants of
+/// the child may keep its pipes open after it exits or is killed
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Wait for `child` to exit, killing it and returning `None` if it is still running
+/// `timeout` after `start` according to `clock`
+fn wait(
+    child: &mut Child,
+    clock: &dyn Clock,
+    start: Instant,
+    timeout: Duration,
+) -> IoResult<Option<ExitStatus>> {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+
+        let remaining = timeout.saturating_sub(clock.now().saturating_duration_since(start));
+        if remaining.is_zero() {
+            spawn::kill_group(child);
+            child.wait()?;
+            return Ok(None);
+        }
+
+        clock.sleep(backoff.min(remaining));
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Read output from `pipes` into `output` until every stream is closed or `deadline` passes
+/// according to `clock`, returning whether every stream was closed
+fn drain(
+    pipes: &Pipes,
+    clock: &dyn Clock,
+    deadline: Instant,
+    output: &mut (Vec<u8>, Vec<u8>),
+) -> IoResult<bool> {
+    loop {
+        let wait = deadline.saturating_duration_since(clock.now());
+        if wait.is_zero() {
+            return Ok(false);
+        }
+
+        match pipes.recv_timeout(clock.poll_interval(wait)) {
+            Ok(Some((Stream::Stdout, chunk))) => output.0.extend(chunk?),
+            Ok(Some((Stream::Stderr, chunk))) => output.1.extend(chunk?),
+            Ok(None) => return Ok(true),
+            Err(_) => {}
+        }
+    }
+}
+
+#[derive(Debug)]
+/// A layer which kills a command, or any wrapper around one, when it runs for too long
+pub struct CommandTimeout<'a, W = Command>
+where
+    W: Execute,
+{
+    inner: &'a mut W,
+    timeout: Duration,
+    configured: Configured,
+}
+
+impl<'a, W> CommandTimeout<'a, W>
+where
+    W: Execute,
+{
+    /// Kill `inner` if it runs for longer than `timeout`
+    pub fn new(inner: &'a mut W, timeout: Duration) -> Self {
+        Self {
+            inner,
+            timeout,
+            configured: Configured::default(),
+        }
+    }
+
+    /// Kill the command if it runs for longer than `timeout`
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How long the command may run before it is killed
+    pub fn get_timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// The command or wrapper this layer limits
+    pub fn inner(&mut self) -> &mut W {
+        self.inner
+    }
+}
+
+impl<'a, W> HasCommand for CommandTimeout<'a, W>
+where
+    W: Execute,
+{
+    fn command(&self) -> &Command {
+        self.inner.command()
+    }
+
+    fn command_mut(&mut self) -> &mut Command {
+        self.inner.command_mut()
+    }
+}
+
+impl<'a, W> CommandWrap for CommandTimeout<'a, W>
+where
+    W: Execute,
+{
+    fn describe(&self) -> Option<String> {
+        Some(format!("killed after {:?}", self.timeout))
+    }
+
+    fn reads_output(&self) -> bool {
+        true
+    }
+
+    fn on_stdin(&mut self, _cfg: &Stdio) {
+        self.configured.stdin = true;
+    }
+
+    fn on_stdout(&mut self, _cfg: &Stdio) {
+        self.configured.stdout = true;
+    }
+
+    fn on_stderr(&mut self, _cfg: &Stdio) {
+        self.configured.stderr = true;
+    }
+
+    /// Pass the result of an execution which an outer layer waited for itself on to the
+    /// wrapped command's hooks
+    fn map_output(&mut self, output: IoResult<Output>, info: &ExecutionInfo) -> IoResult<Output> {
+        self.inner.finish_output(output, info)
+    }
+
+    /// Pass the result of an execution which an outer layer waited for itself on to the
+    /// wrapped command's hooks
+    fn map_status(
+        &mut self,
+        status: IoResult<ExitStatus>,
+        info: &ExecutionInfo,
+    ) -> IoResult<ExitStatus> {
+        self.inner.finish_status(status, info)
+    }
+
+    /// Pass the output being checked to the wrapped command's hooks
+    fn on_check(&mut self, output: &Output) -> Option<CommandExtError> {
+        self.inner.finish_check(output)
+    }
+
+    /// Spawning is not limited, since the child is not waited for
+    fn spawn(&mut self) -> IoResult<Child> {
+        self.inner.execute_spawn()
+    }
+
+    fn output(&mut self) -> IoResult<Output> {
+        let _execution = metrics::execution(self.inner.command());
+        configure_for_output(self.inner.command_mut(), self.configured);
+        spawn::isolate(self.inner.command_mut());
+        let timeout = self.timeout;
+        let inner = &mut *self.inner;
+
+        let (output, info) = ExecutionInfo::measure(|| {
+            let clock = clock::current();
+            let start = clock.now();
+            let mut child = inner.execute_spawn()?;
+            drop(child.stdin.take());
+            let pipes = Pipes::take(&mut child);
+            let status = wait(&mut child, clock.as_ref(), start, timeout)?;
+
+            // Descendants of the child may keep its pipes open after it exits or is killed
+            let mut streams = (Vec::new(), Vec::new());
+            let deadline = clock.now().max(start + timeout) + DRAIN_TIMEOUT;
+            if !drain(&pipes, clock.as_ref(), deadline, &mut streams)? {
+                spawn::kill_group(&mut child);
+            }
+
+            let Some(status) = status else {
+                return Err(Error::from(CommandExtError::TimedOut {
+                    timeout,
+                    stdout: String::from_utf8_lossy(&streams.0).into_owned(),
+                    stderr: String::from_utf8_lossy(&streams.1).into_owned(),
+                }));
+            };
+
+            Ok(Output {
+                status,
+                stdout: streams.0,
+                stderr: streams.1,
+            })
+        });
+
+        let info = info.with_output(&output);
+        self.inner.finish_output(output, &info)
+    }
+
+    fn status(&mut self) -> IoResult<ExitStatus> {
+        spawn::isolate(self.inner.command_mut());
+        let timeout = self.timeout;
+        let inner = &mut *self.inner;
+
+        let (status, info) = ExecutionInfo::measure(|| {
+            let clock = clock::current();
+            let start = clock.now();
+            wait(&mut inner.execute_spawn()?, clock.as_ref(), start, timeout)?.ok_or_else(|| {
+                Error::from(CommandExtError::TimedOut {
+                    timeout,
+                    stdout: String::new(),
+                    stderr: String::new(),
+                })
+            })
+        });
+
+        let info = info.with_status(&status);
+        self.inner.finish_status(status, &info)
+    }
+}
+
+impl<'a> From<&'a mut Command> for CommandTimeout<'a> {
+    fn from(value: &'a mut Command) -> Self {
+        Self::new(value, Duration::MAX)
+    }
+}
+
+impl<'a, W> CommandExtCheck for CommandTimeout<'a, W>
+where
+    W: Execute,
+{
+    type Error = CommandExtError;
+
+    fn check(&mut self) -> Result<Output, Self::Error> {
+        preflight(self.command())?;
+        let output = self.output();
+        check_with(self, output)
+    }
+}
+
+pub trait CommandExtTimeout: Execute + Sized {
+    /// Kill the command if it runs for longer than `timeout`, failing with
+    /// [`CommandExtError::TimedOut`]
+    fn timeout(&mut self, timeout: Duration) -> CommandTimeout<'_, Self>;
+}
+
+impl<T> CommandExtTimeout for T
+where
+    T: Execute,
+{
+    fn timeout(&mut self, timeout: Duration) -> CommandTimeout<'_, Self> {
+        CommandTimeout::new(self, timeout)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::ErrorKind,
+        process::{Command, Stdio},
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        CommandExtCheck, CommandExtError, CommandExtTimeout, CommandWrap, TerminationReason,
+    };
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that commands are killed after their timeout and otherwise run normally
+    fn test_timeout() -> anyhow::Result<()> {
+        let start = Instant::now();
+        let mut command = Command::new("sleep");
+        command.arg("10");
+        let mut limited = command.timeout(Duration::from_millis(100));
+        assert_eq!(
+            limited.status().map_err(|e| e.kind()).err(),
+            Some(ErrorKind::TimedOut)
+        );
+        assert!(matches!(
+            limited.check(),
+            Err(CommandExtError::TimedOut { .. })
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let result = Command::new("sh")
+            .args(["-c", "echo started; sleep 10"])
+            .timeout(Duration::from_millis(300))
+            .check();
+        match result {
+            Err(error @ CommandExtError::TimedOut { .. }) => {
+                assert_eq!(error.termination_reason(), Some(TerminationReason::Timeout));
+                assert_eq!(error.partial_output(), Some(("started\n", "")));
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let output = Command::new("sh")
+            .args(["-c", "echo out; echo err >&2"])
+            .timeout(Duration::from_secs(10))
+            .output()?;
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+
+        // Streams configured through the layer are kept
+        let output = Command::new("sh")
+            .args(["-c", "echo out; echo err >&2"])
+            .timeout(Duration::from_secs(10))
+            .stderr(Stdio::null())
+            .output()?;
+        assert_eq!(output.stdout, b"out\n");
+        assert!(output.stderr.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a backgrounded grandchild holding the pipes open does not hold the layer
+    /// past its timeout, and that killing a command kills its grandchildren too
+    fn test_grandchild() -> anyhow::Result<()> {
+        let start = Instant::now();
+        let output = Command::new("sh")
+            .args(["-c", "sleep 5 & echo started"])
+            .timeout(Duration::from_millis(500))
+            .output()?;
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"started\n");
+
+        let start = Instant::now();
+        let error = Command::new("sh")
+            .args(["-c", "sleep 5 & echo started; sleep 5"])
+            .timeout(Duration::from_millis(200))
+            .output()
+            .unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that the timeout follows the current clock rather than real time
+    fn test_mock_clock() {
+        use std::sync::Arc;
+
+        use crate::clock::{self, MockClock};
+
+        let start = Instant::now();
+        let mock = MockClock::new();
+        let mut command = Command::new("sleep");
+        command.arg("10");
+        let result = clock::with(Arc::new(mock.clone()), || {
+            command.timeout(Duration::from_secs(3600)).check()
+        });
+
+        assert!(matches!(result, Err(CommandExtError::TimedOut { .. })));
+        assert!(mock.elapsed() >= Duration::from_secs(3600));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,891 @@
+//! A manifest of the external tools a script requires, verified before any real work starts
+//!
+//! Scripts which shell out to many tools tend to fail halfway through when one is missing
+//! or too old. A [`ToolManifest`] declares every tool up front, with an optional minimum
+//! or exact version and an optional SHA-256 hash of the executable, and
+//! [`ToolManifest::verify`] probes all of them at once, by running each with `--version`
+//! in parallel, into a single [`ToolReport`] table.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use command_ext::tools::ToolManifest;
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut manifest = ToolManifest::new();
+//! manifest
+//!     .require("sh")
+//!     .require("cargo")
+//!     .min_version("cargo", "1.70")
+//!     .require("make")
+//!     .exact_version("make", "4.3");
+//!
+//! let report = manifest.verify();
+//! // TOOL   REQUIRED  FOUND   STATUS
+//! // sh     any       -       ok
+//! // cargo  >= 1.70   1.75.0  ok
+//! // make   = 4.3     4.4.1   wrong version
+//! println!("{report}");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! ## Installing missing tools
+//!
+//! Tools can be given commands which install them, for every platform or for one
+//! [`Os`]. [`ToolManifest::install_missing`] runs the install command of each missing or
+//! outdated tool once it is confirmed, and verifies the manifest again. [`confirm`] asks on
+//! the terminal, or confirms every install when the script was passed `--yes`.
+//!
+//! ```rust,no_run
+//! # use std::process::Command;
+//! # use command_ext::{tools::{confirm, ToolManifest}, Os};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut manifest = ToolManifest::new();
+//! let mut cargo_install = Command::new("cargo");
+//! cargo_install.args(["install", "--locked", "cargo-nextest"]);
+//! let mut brew_install = Command::new("brew");
+//! brew_install.args(["install", "cargo-nextest"]);
+//! manifest
+//!     .require("cargo-nextest")
+//!     .install("cargo-nextest", cargo_install)
+//!     .install_on("cargo-nextest", Os::MacOs, brew_install);
+//!
+//! let yes = std::env::args().any(|a| a == "--yes");
+//! manifest.install_missing(confirm(yes))?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! ## Environment doctor
+//!
+//! [`ToolManifest::doctor`] combines the tool checks with the [host](crate::host) probe and
+//! a check that the working directory, the temporary directory and any directories added
+//! with [`ToolManifest::writable`] can be written to, in a report for a `cargo xtask
+//! doctor` command.
+//!
+//! ```rust
+//! # use command_ext::tools::ToolManifest;
+//! let mut manifest = ToolManifest::new();
+//! manifest.require("sh").writable("target");
+//!
+//! let report = manifest.doctor();
+//! println!("{report}");
+//! if !report.is_ok() {
+//!     // std::process::exit(1);
+//! }
+//! ```
+
+use std::{
+    cmp::Ordering,
+    collections::BTreeMap,
+    env::{current_dir, temp_dir},
+    fmt::{Display, Formatter, Result as FmtResult},
+    fs::{read, remove_file, OpenOptions},
+    io::{sink, stderr, stdin, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+};
+
+use crate::{
+    batch::run_many_output_ordered,
+    host::{host, which, HostInfo},
+    CommandExtCheck, CommandExtError, CommandExtLossy, CommandSpec, Os,
+};
+
+/// The number of `--version` probes run at once
+const PROBE_LIMIT: usize = 8;
+
+#[derive(Debug, Clone)]
+/// A dotted numeric version, such as `1.75.0`. Missing trailing components compare as
+/// zero, so `1.2` equals `1.2.0`.
+pub struct Version(pub Vec<u64>);
+
+impl Version {
+    /// Find the first dotted number in `text`, such as the version in the output of
+    /// `cargo --version`
+    pub fn find(text: &str) -> Option<Self> {
+        let start = text.find(|c: char| c.is_ascii_digit())?;
+        text[start..]
+            .split(|c: char| !c.is_ascii_digit() && c != '.')
+            .next()
+            .and_then(|v| v.trim_end_matches('.').parse().ok())
+    }
+}
+
+impl FromStr for Version {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim()
+            .split('.')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.0.len().max(other.0.len());
+        (0..len)
+            .map(|i| {
+                let a = self.0.get(i).copied().unwrap_or(0);
+                let b = other.0.get(i).copied().unwrap_or(0);
+                a.cmp(&b)
+            })
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Version {}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let parts = self.0.iter().map(ToString::to_string).collect::<Vec<_>>();
+        write!(f, "{}", parts.join("."))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The version a tool is required to have
+pub enum VersionRequirement {
+    /// Any version
+    Any,
+    /// At least this version
+    AtLeast(Version),
+    /// Exactly this version, ignoring trailing zero components
+    Exactly(Version),
+}
+
+impl VersionRequirement {
+    /// Whether `version` satisfies the requirement
+    pub fn matches(&self, version: &Version) -> bool {
+        match self {
+            Self::Any => true,
+            Self::AtLeast(min) => version >= min,
+            Self::Exactly(exact) => version == exact,
+        }
+    }
+}
+
+impl Display for VersionRequirement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Any => write!(f, "any"),
+            Self::AtLeast(v) => write!(f, ">= {v}"),
+            Self::Exactly(v) => write!(f, "= {v}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A tool declared in a [`ToolManifest`]
+pub struct Tool {
+    /// The name of the program, looked up on the `PATH`
+    pub name: String,
+    /// The version the tool must have
+    pub version: VersionRequirement,
+    /// The arguments which make the tool print its version
+    pub version_args: Vec<String>,
+    /// The SHA-256 hash the executable must have, as lowercase hex
+    pub sha256: Option<String>,
+    /// Commands which install the tool, on one platform or, for `None`, on every platform
+    pub install: Vec<(Option<Os>, CommandSpec)>,
+}
+
+impl Tool {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            version: VersionRequirement::Any,
+            version_args: vec!["--version".to_string()],
+            sha256: None,
+            install: Vec::new(),
+        }
+    }
+
+    /// The command which installs the tool on `os`, preferring one specific to `os`
+    pub fn install_command(&self, os: Os) -> Option<Command> {
+        self.install
+            .iter()
+            .find(|(o, _)| *o == Some(os))
+            .or_else(|| self.install.iter().find(|(o, _)| o.is_none()))
+            .map(|(_, spec)| spec.to_command())
+    }
+
+    /// Whether a version probe is needed to verify the tool
+    fn needs_probe(&self) -> bool {
+        self.version != VersionRequirement::Any
+    }
+}
+
+/// Parse the version required of the tool `name`, which is written in the script, so one
+/// which does not parse is a bug in it
+fn required_version(name: &str, version: &str) -> Version {
+    version
+        .parse()
+        .unwrap_or_else(|e| panic!("invalid version {version:?} required of {name}: {e}"))
+}
+
+#[derive(Debug, Clone, Default)]
+/// A set of tools a script requires, keyed by name
+pub struct ToolManifest {
+    tools: BTreeMap<String, Tool>,
+    writable: Vec<PathBuf>,
+}
+
+impl ToolManifest {
+    /// An empty manifest
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn tool(&mut self, name: &str) -> &mut Tool {
+        self.tools
+            .entry(name.to_string())
+            .or_insert_with(|| Tool::new(name.to_string()))
+    }
+
+    /// Require the tool `name` to be on the `PATH`
+    pub fn require<S: AsRef<str>>(&mut self, name: S) -> &mut Self {
+        self.tool(name.as_ref());
+        self
+    }
+
+    /// Require the tool `name` to have at least `version`, a dotted number
+    ///
+    /// # Panics
+    ///
+    /// Panics if `version` is not a dotted number
+    pub fn min_version<S: AsRef<str>, V: AsRef<str>>(&mut self, name: S, version: V) -> &mut Self {
+        self.tool(name.as_ref()).version =
+            VersionRequirement::AtLeast(required_version(name.as_ref(), version.as_ref()));
+        self
+    }
+
+    /// Require the tool `name` to have exactly `version`, a dotted number, to keep tools in
+    /// lockstep between machines
+    ///
+    /// # Panics
+    ///
+    /// Panics if `version` is not a dotted number
+    pub fn exact_version<S: AsRef<str>, V: AsRef<str>>(
+        &mut self,
+        name: S,
+        version: V,
+    ) -> &mut Self {
+        self.tool(name.as_ref()).version =
+            VersionRequirement::Exactly(required_version(name.as_ref(), version.as_ref()));
+        self
+    }
+
+    /// Require the executable of the tool `name` to have the SHA-256 hash `sha256`, given
+    /// in hex as printed by `sha256sum`
+    pub fn sha256<S: AsRef<str>, H: AsRef<str>>(&mut self, name: S, sha256: H) -> &mut Self {
+        self.tool(name.as_ref()).sha256 = Some(sha256.as_ref().trim().to_ascii_lowercase());
+        self
+    }
+
+    /// Use `args` instead of `--version` to make the tool `name` print its version
+    pub fn version_args<S, I, A>(&mut self, name: S, args: I) -> &mut Self
+    where
+        S: AsRef<str>,
+        I: IntoIterator<Item = A>,
+        A: Into<String>,
+    {
+        self.tool(name.as_ref()).version_args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Install the tool `name` with `command` when it is missing or outdated, on any
+    /// platform without a more specific [install command](ToolManifest::install_on). Pass a
+    /// [`CommandSpec`] to clear the environment or configure stdio, which a [`Command`]
+    /// does not expose.
+    pub fn install<S, C>(&mut self, name: S, command: C) -> &mut Self
+    where
+        S: AsRef<str>,
+        C: Into<CommandSpec>,
+    {
+        self.tool(name.as_ref())
+            .install
+            .push((None, command.into()));
+        self
+    }
+
+    /// Install the tool `name` with `command` when it is missing or outdated on `os`
+    pub fn install_on<S, C>(&mut self, name: S, os: Os, command: C) -> &mut Self
+    where
+        S: AsRef<str>,
+        C: Into<CommandSpec>,
+    {
+        self.tool(name.as_ref())
+            .install
+            .push((Some(os), command.into()));
+        self
+    }
+
+    /// Require the directory `path` to be writable, as checked by
+    /// [`ToolManifest::doctor`]
+    pub fn writable<P: Into<PathBuf>>(&mut self, path: P) -> &mut Self {
+        self.writable.push(path.into());
+        self
+    }
+
+    /// Check the host, the tools and the permissions the script needs, for a human-readable
+    /// report of the environment. The working directory and the temporary directory are
+    /// always checked for write access, along with the directories added with
+    /// [`ToolManifest::writable`].
+    pub fn doctor(&self) -> DoctorReport {
+        let permissions = current_dir()
+            .into_iter()
+            .chain([temp_dir()])
+            .chain(self.writable.iter().cloned())
+            .map(|path| {
+                let error = probe_writable(&path).err().map(|e| e.to_string());
+                PermissionCheck { path, error }
+            })
+            .collect();
+
+        DoctorReport {
+            host: host().clone(),
+            tools: self.verify(),
+            permissions,
+        }
+    }
+
+    /// The declared tools, by name
+    pub fn tools(&self) -> impl Iterator<Item = &Tool> {
+        self.tools.values()
+    }
+
+    /// Check every tool, probing the versions of those with a version requirement in
+    /// parallel
+    pub fn verify(&self) -> ToolReport {
+        let found = self
+            .tools
+            .values()
+            .map(|tool| (tool, which(&tool.name)))
+            .collect::<Vec<_>>();
+
+        let probed = found
+            .iter()
+            .filter(|(tool, path)| tool.needs_probe() && path.is_some())
+            .collect::<Vec<_>>();
+        let outputs = run_many_output_ordered(
+            probed.iter().map(|(tool, path)| {
+                let mut command = Command::new(path.as_ref().expect("probed tools were found"));
+                command.args(&tool.version_args);
+                command
+            }),
+            PROBE_LIMIT,
+            sink(),
+        );
+        let mut versions = probed
+            .iter()
+            .zip(outputs)
+            .map(|((tool, _), output)| {
+                let version = output.ok().and_then(|o| {
+                    Version::find(&String::from_utf8_lossy(&o.stdout))
+                        .or_else(|| Version::find(&String::from_utf8_lossy(&o.stderr)))
+                });
+                (tool.name.clone(), version)
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        ToolReport(
+            found
+                .into_iter()
+                .map(|(tool, path)| {
+                    let version = versions.remove(&tool.name).flatten();
+                    let state = match &path {
+                        None => ToolState::Missing,
+                        Some(path) => tool_state(tool, path, version.as_ref()),
+                    };
+                    ToolStatus {
+                        name: tool.name.clone(),
+                        required: tool.version.clone(),
+                        path,
+                        version,
+                        state,
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Check every tool, and return an error listing the report if any tool is missing or
+    /// does not match its requirements
+    pub fn check(&self) -> Result<ToolReport, CommandExtError> {
+        let report = self.verify();
+        if report.is_ok() {
+            Ok(report)
+        } else {
+            Err(CommandExtError::ToolsUnavailable {
+                report: report.to_string(),
+            })
+        }
+    }
+
+    /// Verify the manifest, and run the install command for this platform of each tool
+    /// which is missing, outdated or has the wrong version, if `confirm` accepts it. Install
+    /// commands are checked, so the first one which fails is returned as an error. Once the
+    /// tools are installed, the manifest is [checked](ToolManifest::check) again.
+    pub fn install_missing<F>(&self, mut confirm: F) -> Result<ToolReport, CommandExtError>
+    where
+        F: FnMut(&Tool, &Command) -> bool,
+    {
+        let report = self.verify();
+        for status in report.problems().filter(|s| {
+            matches!(
+                s.state,
+                ToolState::Missing | ToolState::Outdated | ToolState::WrongVersion
+            )
+        }) {
+            let tool = &self.tools[&status.name];
+            let Some(mut command) = tool.install_command(host().os()) else {
+                continue;
+            };
+
+            if confirm(tool, &command) {
+                command.check()?;
+            }
+        }
+
+        self.check()
+    }
+}
+
+/// A confirmation for [`ToolManifest::install_missing`] which accepts every install if
+/// `assume_yes` is set, and otherwise asks on stderr and reads the answer from stdin. When
+/// stdin is not a terminal, installs are declined.
+pub fn confirm(assume_yes: bool) -> impl FnMut(&Tool, &Command) -> bool {
+    move |tool, command| {
+        if assume_yes {
+            return true;
+        }
+        if !stdin().is_terminal() {
+            return false;
+        }
+
+        eprint!(
+            "{} is required. Install it with `{}`? [y/N] ",
+            tool.name,
+            command.get_full_command_lossy()
+        );
+        stderr().flush().ok();
+        let mut answer = String::new();
+        stdin().read_line(&mut answer).ok();
+        matches!(answer.trim(), "y" | "Y" | "yes" | "Yes")
+    }
+}
+
+/// The state of a found tool
+fn tool_state(tool: &Tool, path: &Path, version: Option<&Version>) -> ToolState {
+    if tool.needs_probe() {
+        match version {
+            None => return ToolState::UnknownVersion,
+            Some(v) if !tool.version.matches(v) => {
+                return match tool.version {
+                    VersionRequirement::AtLeast(_) => ToolState::Outdated,
+                    _ => ToolState::WrongVersion,
+                }
+            }
+            Some(_) => {}
+        }
+    }
+
+    match &tool.sha256 {
+        Some(expected) => match read(path) {
+            Ok(contents) if hex(&sha256(&contents)) == *expected => ToolState::Ok,
+            _ => ToolState::WrongHash,
+        },
+        None => ToolState::Ok,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// The outcome of checking a tool
+pub enum ToolState {
+    /// The tool matches its requirements
+    Ok,
+    /// The tool is not on the `PATH`
+    Missing,
+    /// The tool is older than its minimum version
+    Outdated,
+    /// The tool does not have its exact version
+    WrongVersion,
+    /// No version could be found in the tool's version output
+    UnknownVersion,
+    /// The tool's executable does not have the required hash
+    WrongHash,
+}
+
+impl Display for ToolState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Ok => write!(f, "ok"),
+            Self::Missing => write!(f, "missing"),
+            Self::Outdated => write!(f, "outdated"),
+            Self::WrongVersion => write!(f, "wrong version"),
+            Self::UnknownVersion => write!(f, "unknown version"),
+            Self::WrongHash => write!(f, "wrong hash"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The result of checking one tool of a [`ToolManifest`]
+pub struct ToolStatus {
+    /// The name of the tool
+    pub name: String,
+    /// The version the tool is required to have
+    pub required: VersionRequirement,
+    /// Where the tool was found, if it was
+    pub path: Option<PathBuf>,
+    /// The version the tool reported, if it was probed
+    pub version: Option<Version>,
+    /// Whether the tool matches its requirements, and why not
+    pub state: ToolState,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A printable table of the tools of a [`ToolManifest`] and whether they match their
+/// requirements
+pub struct ToolReport(pub Vec<ToolStatus>);
+
+impl ToolReport {
+    /// Whether every tool matches its requirements
+    pub fn is_ok(&self) -> bool {
+        self.0.iter().all(|s| s.state == ToolState::Ok)
+    }
+
+    /// The tools which do not match their requirements
+    pub fn problems(&self) -> impl Iterator<Item = &ToolStatus> {
+        self.0.iter().filter(|s| s.state != ToolState::Ok)
+    }
+}
+
+impl Display for ToolReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let rows = self
+            .0
+            .iter()
+            .map(|s| {
+                [
+                    s.name.clone(),
+                    s.required.to_string(),
+                    s.version
+                        .as_ref()
+                        .map_or_else(|| "-".to_string(), ToString::to_string),
+                    s.state.to_string(),
+                ]
+            })
+            .collect::<Vec<_>>();
+        let header = ["TOOL", "REQUIRED", "FOUND", "STATUS"].map(String::from);
+        let widths = [0, 1, 2].map(|i| {
+            rows.iter()
+                .chain([&header])
+                .map(|r| r[i].len())
+                .max()
+                .unwrap_or(0)
+        });
+
+        let mut lines = [&header].into_iter().chain(&rows).map(|r| {
+            format!(
+                "{:w0$}  {:w1$}  {:w2$}  {}",
+                r[0],
+                r[1],
+                r[2],
+                r[3],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2]
+            )
+        });
+        if let Some(line) = lines.next() {
+            write!(f, "{line}")?;
+        }
+        lines.try_for_each(|line| write!(f, "\n{line}"))
+    }
+}
+
+/// Check that a file can be created in the directory `path`
+fn probe_writable(path: &Path) -> std::io::Result<()> {
+    let probe = path.join(format!(".command-ext-doctor-{}", std::process::id()));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    remove_file(probe)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Whether a directory checked by [`ToolManifest::doctor`] is writable
+pub struct PermissionCheck {
+    /// The directory
+    pub path: PathBuf,
+    /// Why a file could not be created in the directory, if it could not
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A human-readable report of the environment a script runs in, produced by
+/// [`ToolManifest::doctor`]
+pub struct DoctorReport {
+    /// The host the script runs on
+    pub host: HostInfo,
+    /// The tools the script requires
+    pub tools: ToolReport,
+    /// The directories the script writes to
+    pub permissions: Vec<PermissionCheck>,
+}
+
+impl DoctorReport {
+    /// Whether every tool matches its requirements and every directory is writable
+    pub fn is_ok(&self) -> bool {
+        self.tools.is_ok() && self.permissions.iter().all(|p| p.error.is_none())
+    }
+}
+
+impl Display for DoctorReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        writeln!(f, "Host")?;
+        writeln!(f, "  os         {}", self.host.os())?;
+        writeln!(f, "  arch       {}", self.host.arch())?;
+        writeln!(f, "  container  {}", yes_no(self.host.in_container()))?;
+        writeln!(f, "  wsl        {}", yes_no(self.host.is_wsl()))?;
+        writeln!(f, "  shells     {}", self.host.shells().join(", "))?;
+
+        writeln!(f, "\nTools")?;
+        if self.tools.0.is_empty() {
+            writeln!(f, "  none required")?;
+        } else {
+            self.tools
+                .to_string()
+                .lines()
+                .try_for_each(|line| writeln!(f, "  {line}"))?;
+        }
+
+        write!(f, "\nPermissions")?;
+        self.permissions.iter().try_for_each(|p| match &p.error {
+            None => write!(f, "\n  ok    {} is writable", p.path.display()),
+            Some(e) => write!(f, "\n  FAIL  {} is not writable: {e}", p.path.display()),
+        })
+    }
+}
+
+/// Lowercase hex encoding of `bytes`
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// The SHA-256 round constants
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// The SHA-256 hash of `data`
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (s, v) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *s = s.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0; 32];
+    for (chunk, s) in digest.chunks_exact_mut(4).zip(state) {
+        chunk.copy_from_slice(&s.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env::temp_dir, fs::remove_file, process::Command};
+
+    use super::{hex, sha256, ToolManifest, ToolState, Version};
+    use crate::host::host;
+
+    #[test]
+    fn test_version() {
+        assert_eq!(
+            Version::find("cargo 1.75.0 (1d8b05cdd 2023-11-20)"),
+            Some(Version(vec![1, 75, 0]))
+        );
+        assert_eq!(
+            Version::find("GNU Make 4.3.\nBuilt for x86_64"),
+            Some(Version(vec![4, 3]))
+        );
+        assert_eq!(Version::find("no version"), None);
+        assert!("1.2".parse::<Version>().unwrap() == "1.2".parse().unwrap());
+        assert!("1.2".parse::<Version>().unwrap() < "1.10".parse().unwrap());
+        assert_eq!("1.2".parse::<Version>(), "1.2.0".parse());
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(&sha256(&[b'a'; 1000])),
+            "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+        );
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_verify() {
+        let mut manifest = ToolManifest::new();
+        manifest
+            .require("sh")
+            .require("asdfasdfasdfasdfjkljkljkl")
+            .min_version("cargo", "1.0")
+            .exact_version("rustc", "0.1")
+            .version_args("rustc", ["-V"])
+            .sha256("cargo", "00");
+
+        let report = manifest.verify();
+        let state = |name: &str| report.0.iter().find(|s| s.name == name).unwrap().state;
+        assert_eq!(state("sh"), ToolState::Ok);
+        assert_eq!(state("asdfasdfasdfasdfjkljkljkl"), ToolState::Missing);
+        assert_eq!(state("cargo"), ToolState::WrongHash);
+        assert_eq!(state("rustc"), ToolState::WrongVersion);
+        assert_eq!(report.problems().count(), 3);
+        assert!(manifest.check().is_err());
+        assert!(report.to_string().starts_with("TOOL "));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid version \"1.x\" required of cargo")]
+    /// Test that a version requirement which does not parse is reported instead of ignored
+    fn test_invalid_version() {
+        ToolManifest::new().min_version("cargo", "1.x");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    #[cfg_attr(miri, ignore)]
+    fn test_install_missing() -> anyhow::Result<()> {
+        let marker = temp_dir().join(format!("command-ext-install-{}", std::process::id()));
+        let mut install = Command::new("touch");
+        install.arg(&marker);
+        let mut never = Command::new("false");
+        never.arg("wrong platform");
+        let other = if host().os() == crate::Os::Windows {
+            crate::Os::Linux
+        } else {
+            crate::Os::Windows
+        };
+
+        let mut manifest = ToolManifest::new();
+        manifest
+            .require("asdfasdfasdfasdfjkljkljkl")
+            .install("asdfasdfasdfasdfjkljkljkl", install)
+            .install_on("asdfasdfasdfasdfjkljkljkl", other, never)
+            .require("sh");
+
+        let mut asked = Vec::new();
+        let result = manifest.install_missing(|tool, _| {
+            asked.push(tool.name.clone());
+            false
+        });
+        assert!(result.is_err());
+        assert_eq!(asked, ["asdfasdfasdfasdfjkljkljkl"]);
+        assert!(!marker.exists());
+
+        // The install command runs, but does not put the tool on the PATH
+        assert!(manifest.install_missing(|_, _| true).is_err());
+        assert!(marker.exists());
+
+        remove_file(marker)?;
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_doctor() {
+        let mut manifest = ToolManifest::new();
+        manifest.require("sh").writable("/nonexistent");
+
+        let report = manifest.doctor();
+        assert!(!report.is_ok());
+        assert_eq!(report.permissions.len(), 3);
+        assert!(report.permissions[..2].iter().all(|p| p.error.is_none()));
+
+        let text = report.to_string();
+        assert!(text.starts_with("Host\n  os "));
+        assert!(text.contains("\nTools\n  TOOL "));
+        assert!(text.contains("FAIL  /nonexistent is not writable"));
+    }
+}
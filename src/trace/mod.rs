@@ -17,36 +17,152 @@
 //! # Ok(())
 //! # }
 //! ```
+//!
+//! Nothing is reported until a subscriber is installed. Scripts which may not install one can
+//! opt in to printing to stderr instead with `fallback_to_stderr(true)`.
+//!
+//! Only a plain [`Command`] can be traced, so retries and timeouts are layered on the
+//! [`CommandTrace`] rather than under it; see [`crate::Execute`].
 
-use std::{ffi::OsStr, process::Command};
+use std::process::Command;
 use tracing::{debug, error, info, trace, warn, Level};
+#[cfg(feature = "typed-builder")]
 use typed_builder::TypedBuilder;
 
-use crate::{wrap::HasCommand, CommandWrap};
 #[cfg(feature = "check")]
-use crate::{CommandExtCheck, CommandExtError};
+use crate::{check::check_wrapper, CommandExtCheck};
+use crate::{
+    defaults::Defaults,
+    display::{fold_lines, CommandLine, EnvSummary, ExecutionSummary},
+    metrics::{self, Category},
+    wrap::{ExecutionInfo, HasCommand},
+    CommandExtError, CommandWrap, Verbosity,
+};
 
-#[derive(TypedBuilder, Debug)]
+#[cfg_attr(feature = "typed-builder", derive(TypedBuilder))]
+#[derive(Debug)]
 pub struct CommandTrace<'a> {
     command: &'a mut Command,
-    #[builder(default, setter(into, strip_option))]
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default = crate::defaults::get(), setter(skip))
+    )]
+    /// The ambient defaults when the wrapper was built, read once for every setting left
+    /// unset
+    _defaults: Defaults,
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default = _defaults.trace_args, setter(into, strip_option))
+    )]
     /// The log level for args before execution
     args: Option<Level>,
-    #[builder(default, setter(into, strip_option))]
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default = _defaults.trace_envs, setter(into, strip_option))
+    )]
     /// Whether to log the environment on execution
     envs: Option<Level>,
-    #[builder(default, setter(into, strip_option))]
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default, setter(into, strip_option))
+    )]
+    /// Whether to trace a summary of the environment and arguments, without their values
+    env_summary: Option<Level>,
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default = _defaults.trace_current_dir, setter(into, strip_option))
+    )]
     /// Whether to log the current directory on execution
     current_dir: Option<Level>,
-    #[builder(default, setter(into, strip_option))]
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default = _defaults.trace_status, setter(into, strip_option))
+    )]
     /// Whether to log the status after execution
     status: Option<Level>,
-    #[builder(default, setter(into, strip_option))]
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default = _defaults.trace_stdout, setter(into, strip_option))
+    )]
     /// Whether to log stdout after execution
     stdout: Option<Level>,
-    #[builder(default, setter(into, strip_option))]
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default = _defaults.trace_stderr, setter(into, strip_option))
+    )]
     /// Whether to log stderr after execution
     stderr: Option<Level>,
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default, setter(into, strip_option))
+    )]
+    /// Whether to trace a single line summarizing each execution
+    summary: Option<Level>,
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default, setter(into, strip_option))
+    )]
+    /// The level for stdout after a successful execution, instead of `stdout`
+    stdout_on_success: Option<Level>,
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default, setter(into, strip_option))
+    )]
+    /// The level for stdout after a failed execution, instead of `stdout`
+    stdout_on_failure: Option<Level>,
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default, setter(into, strip_option))
+    )]
+    /// The level for stderr after a successful execution, instead of `stderr`
+    stderr_on_success: Option<Level>,
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default, setter(into, strip_option))
+    )]
+    /// The level for stderr after a failed execution, instead of `stderr`
+    stderr_on_failure: Option<Level>,
+    #[cfg_attr(feature = "typed-builder", builder(default, setter(strip_option)))]
+    /// The number of lines to keep from the start and end of traced output
+    max_lines: Option<(usize, usize)>,
+    #[cfg_attr(
+        feature = "typed-builder",
+        builder(default, setter(into, strip_option))
+    )]
+    /// The level to trace at when a successful execution wrote to stderr
+    warn_on_stderr: Option<Level>,
+    #[cfg_attr(feature = "typed-builder", builder(default))]
+    /// Whether checking fails when a successful execution wrote to stderr
+    deny_stderr: bool,
+    #[cfg_attr(feature = "typed-builder", builder(default))]
+    /// Whether to print to stderr instead when no subscriber is installed
+    fallback_to_stderr: bool,
+    #[cfg_attr(feature = "typed-builder", builder(default, setter(skip)))]
+    /// The last execution of the command through this wrapper
+    last_execution: Option<ExecutionInfo>,
+}
+
+#[cfg(not(feature = "typed-builder"))]
+crate::builder::builder! {
+    CommandTrace, CommandTraceBuilder, _defaults {
+        args: Option<Level> = _defaults.trace_args, |value: Level| Some(value),
+        envs: Option<Level> = _defaults.trace_envs, |value: Level| Some(value),
+        env_summary: Option<Level> = None, |value: Level| Some(value),
+        current_dir: Option<Level> = _defaults.trace_current_dir,
+            |value: Level| Some(value),
+        status: Option<Level> = _defaults.trace_status, |value: Level| Some(value),
+        stdout: Option<Level> = _defaults.trace_stdout, |value: Level| Some(value),
+        stderr: Option<Level> = _defaults.trace_stderr, |value: Level| Some(value),
+        summary: Option<Level> = None, |value: Level| Some(value),
+        stdout_on_success: Option<Level> = None, |value: Level| Some(value),
+        stdout_on_failure: Option<Level> = None, |value: Level| Some(value),
+        stderr_on_success: Option<Level> = None, |value: Level| Some(value),
+        stderr_on_failure: Option<Level> = None, |value: Level| Some(value),
+        max_lines: Option<(usize, usize)> = None, |value: (usize, usize)| Some(value),
+        warn_on_stderr: Option<Level> = None, |value: Level| Some(value),
+        deny_stderr: bool = false, |value: bool| value,
+        fallback_to_stderr: bool = false, |value: bool| value,
+    }
 }
 
 macro_rules! log {
@@ -71,40 +187,121 @@ macro_rules! log {
     }
 }
 
+/// Whether an event at a runtime level would be recorded by the current subscriber
+macro_rules! enabled {
+    ($lvl:expr) => {
+        match $lvl {
+            Level::TRACE => tracing::enabled!(Level::TRACE),
+            Level::DEBUG => tracing::enabled!(Level::DEBUG),
+            Level::INFO => tracing::enabled!(Level::INFO),
+            Level::WARN => tracing::enabled!(Level::WARN),
+            Level::ERROR => tracing::enabled!(Level::ERROR),
+        }
+    };
+}
+
+/// Whether no subscriber is installed for the current thread, globally or scoped
+fn subscriber_absent() -> bool {
+    tracing::dispatcher::get_default(|d| d.is::<tracing::subscriber::NoSubscriber>())
+}
+
+/// The error output of a successful execution which wrote anything but whitespace to
+/// stderr
+fn unexpected_stderr(output: &std::process::Output) -> Option<String> {
+    let written = !output.stderr.iter().all(u8::is_ascii_whitespace);
+    (output.status.success() && written)
+        .then(|| String::from_utf8_lossy(&output.stderr).trim().to_string())
+}
+
+/// The level for an output stream, which may be set separately for successful and failed
+/// executions
+fn outcome_level(
+    level: Option<Level>,
+    on_success: Option<Level>,
+    on_failure: Option<Level>,
+    success: bool,
+) -> Option<Level> {
+    if success {
+        on_success.or(level)
+    } else {
+        on_failure.or(level)
+    }
+}
+
 impl<'a> CommandTrace<'a> {
-    fn trace_before(&mut self) {
-        if let Some(args) = self.args {
-            log!(
-                args,
-                "args: {} {}",
-                self.command().get_program().to_string_lossy(),
-                self.command()
-                    .get_args()
-                    .collect::<Vec<_>>()
-                    .join(OsStr::new(" "))
-                    .to_string_lossy()
+    /// Whether events are printed to stderr instead of being recorded
+    fn fallback(&self) -> bool {
+        self.fallback_to_stderr && subscriber_absent()
+    }
+
+    /// Whether an event at a level would be seen by the subscriber or the fallback
+    fn enabled(&self, level: Level) -> bool {
+        self.fallback() || enabled!(level)
+    }
+
+    /// Record an event, or print it to stderr if falling back
+    fn emit(&self, level: Level, message: std::fmt::Arguments) {
+        metrics::measure(Category::Logging, || {
+            if self.fallback() {
+                eprintln!("[{level}] {message}");
+            } else {
+                log!(level, "{}", message);
+            }
+        })
+    }
+
+    /// Fold logged output to the configured number of lines
+    fn fold<'s>(&self, text: &'s str) -> std::borrow::Cow<'s, str> {
+        match self.max_lines {
+            Some((head, tail)) => fold_lines(text, head, tail),
+            None => text.into(),
+        }
+    }
+
+    fn emit_summary(&self, info: &ExecutionInfo) {
+        if let Some(summary) = self.summary.filter(|l| self.enabled(*l)) {
+            self.emit(
+                summary,
+                format_args!("{}", ExecutionSummary(self.command(), info)),
             );
         }
+    }
+
+    fn trace_before(&mut self) {
+        if let Some(args) = self.args.filter(|l| self.enabled(*l)) {
+            self.emit(args, format_args!("args: {}", CommandLine(self.command())));
+        }
 
-        if let Some(envs) = self.envs {
+        if let Some(envs) = self.envs.filter(|l| self.enabled(*l)) {
             self.command().get_envs().for_each(|(k, v)| {
-                log!(
+                self.emit(
                     envs,
-                    "envs: {}={}",
-                    k.to_string_lossy(),
-                    v.unwrap_or_default().to_string_lossy()
+                    format_args!(
+                        "envs: {}={}",
+                        k.to_string_lossy(),
+                        v.unwrap_or_default().to_string_lossy()
+                    ),
                 );
             });
         }
 
-        if let Some(current_dir) = self.current_dir {
-            log!(
+        if let Some(summary) = self.env_summary.filter(|l| self.enabled(*l)) {
+            self.emit(
+                summary,
+                format_args!("env summary: {}", EnvSummary(self.command())),
+            );
+        }
+
+        if let Some(current_dir) = self.current_dir.filter(|l| self.enabled(*l)) {
+            self.emit(
                 current_dir,
-                "current_dir: {}",
-                self.command()
-                    .get_current_dir()
-                    .map(|d| d.to_string_lossy())
-                    .unwrap_or_default()
+                format_args!(
+                    "current_dir: {}",
+                    self.command()
+                        .get_current_dir()
+                        .map(|d| d.to_string_lossy())
+                        .unwrap_or_default()
+                ),
             );
         }
     }
@@ -121,6 +318,10 @@ impl<'a> HasCommand for CommandTrace<'a> {
 }
 
 impl<'a> CommandWrap for CommandTrace<'a> {
+    fn last_execution(&self) -> Option<ExecutionInfo> {
+        self.last_execution
+    }
+
     fn on_spawn(&mut self) {
         self.trace_before();
     }
@@ -133,34 +334,78 @@ impl<'a> CommandWrap for CommandTrace<'a> {
         self.trace_before();
     }
 
-    fn after_output(&mut self, output: &std::io::Result<std::process::Output>) {
+    fn after_output(
+        &mut self,
+        output: &std::io::Result<std::process::Output>,
+        info: &ExecutionInfo,
+    ) {
+        self.last_execution = Some(*info);
+        self.emit_summary(info);
+
         if let Ok(output) = output {
-            if let Some(status) = self.status {
-                log!(status, "status: {}", output.status);
+            let warn = self.warn_on_stderr.filter(|l| self.enabled(*l));
+            if let (Some(warn), Some(stderr)) = (warn, unexpected_stderr(output)) {
+                self.emit(
+                    warn,
+                    format_args!("succeeded but wrote to stderr: {}", self.fold(&stderr)),
+                );
+            }
+
+            if let Some(status) = self.status.filter(|l| self.enabled(*l)) {
+                self.emit(status, format_args!("status: {}", output.status));
             }
 
-            if let Some(stdout) = self.stdout {
-                let out = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            let success = output.status.success();
+            let stdout = outcome_level(
+                self.stdout,
+                self.stdout_on_success,
+                self.stdout_on_failure,
+                success,
+            );
+            let stderr = outcome_level(
+                self.stderr,
+                self.stderr_on_success,
+                self.stderr_on_failure,
+                success,
+            );
+
+            if let Some(stdout) = stdout.filter(|l| self.enabled(*l)) {
+                let out = String::from_utf8_lossy(&output.stdout);
+                let out = self.fold(out.trim());
                 if !out.is_empty() {
-                    log!(stdout, "stdout: {out}",);
+                    self.emit(stdout, format_args!("stdout: {out}"));
                 }
             }
-            if let Some(stderr) = self.stderr {
-                let err = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            if let Some(stderr) = stderr.filter(|l| self.enabled(*l)) {
+                let err = String::from_utf8_lossy(&output.stderr);
+                let err = self.fold(err.trim());
                 if !err.is_empty() {
-                    log!(stderr, "stderr: {err}",);
+                    self.emit(stderr, format_args!("stderr: {err}"));
                 }
             }
         }
     }
 
-    fn after_status(&mut self, status: &std::io::Result<std::process::ExitStatus>) {
+    fn after_status(
+        &mut self,
+        status: &std::io::Result<std::process::ExitStatus>,
+        info: &ExecutionInfo,
+    ) {
+        self.last_execution = Some(*info);
+        self.emit_summary(info);
+
         if let Ok(status) = status {
-            if let Some(status_filter) = self.status {
-                log!(status_filter, "status: {}", status);
+            if let Some(status_filter) = self.status.filter(|l| self.enabled(*l)) {
+                self.emit(status_filter, format_args!("status: {}", status));
             }
         }
     }
+
+    fn on_check(&mut self, output: &std::process::Output) -> Option<CommandExtError> {
+        unexpected_stderr(output)
+            .filter(|_| self.deny_stderr)
+            .map(|stderr| CommandExtError::UnexpectedStderr { stderr })
+    }
 }
 
 impl<'a> From<&'a mut Command> for CommandTrace<'a> {
@@ -170,42 +415,70 @@ impl<'a> From<&'a mut Command> for CommandTrace<'a> {
 }
 
 pub trait CommandExtTrace {
-    fn trace_args<L>(&mut self, filter: L) -> CommandTrace
+    fn trace_args<L>(&mut self, filter: L) -> CommandTrace<'_>
+    where
+        L: Into<Level>;
+    fn trace_envs<L>(&mut self, filter: L) -> CommandTrace<'_>
+    where
+        L: Into<Level>;
+    fn trace_env_summary<L>(&mut self, filter: L) -> CommandTrace<'_>
+    where
+        L: Into<Level>;
+    fn trace_current_dir<L>(&mut self, filter: L) -> CommandTrace<'_>
     where
         L: Into<Level>;
-    fn trace_envs<L>(&mut self, filter: L) -> CommandTrace
+    fn trace_status<L>(&mut self, filter: L) -> CommandTrace<'_>
     where
         L: Into<Level>;
-    fn trace_current_dir<L>(&mut self, filter: L) -> CommandTrace
+    fn trace_stdout<L>(&mut self, filter: L) -> CommandTrace<'_>
     where
         L: Into<Level>;
-    fn trace_status<L>(&mut self, filter: L) -> CommandTrace
+    fn trace_stderr<L>(&mut self, filter: L) -> CommandTrace<'_>
     where
         L: Into<Level>;
-    fn trace_stdout<L>(&mut self, filter: L) -> CommandTrace
+    fn trace_summary<L>(&mut self, filter: L) -> CommandTrace<'_>
     where
         L: Into<Level>;
-    fn trace_stderr<L>(&mut self, filter: L) -> CommandTrace
+    fn trace_stdout_on_success<L>(&mut self, filter: L) -> CommandTrace<'_>
+    where
+        L: Into<Level>;
+    fn trace_stdout_on_failure<L>(&mut self, filter: L) -> CommandTrace<'_>
+    where
+        L: Into<Level>;
+    fn trace_stderr_on_success<L>(&mut self, filter: L) -> CommandTrace<'_>
+    where
+        L: Into<Level>;
+    fn trace_stderr_on_failure<L>(&mut self, filter: L) -> CommandTrace<'_>
     where
         L: Into<Level>;
 }
 
 impl CommandExtTrace for Command {
-    fn trace_args<L>(&mut self, filter: L) -> CommandTrace
+    fn trace_args<L>(&mut self, filter: L) -> CommandTrace<'_>
     where
         L: Into<Level>,
     {
         CommandTrace::builder().command(self).args(filter).build()
     }
 
-    fn trace_envs<L>(&mut self, filter: L) -> CommandTrace
+    fn trace_envs<L>(&mut self, filter: L) -> CommandTrace<'_>
     where
         L: Into<Level>,
     {
         CommandTrace::builder().command(self).envs(filter).build()
     }
 
-    fn trace_current_dir<L>(&mut self, filter: L) -> CommandTrace
+    fn trace_env_summary<L>(&mut self, filter: L) -> CommandTrace<'_>
+    where
+        L: Into<Level>,
+    {
+        CommandTrace::builder()
+            .command(self)
+            .env_summary(filter)
+            .build()
+    }
+
+    fn trace_current_dir<L>(&mut self, filter: L) -> CommandTrace<'_>
     where
         L: Into<Level>,
     {
@@ -215,30 +488,80 @@ impl CommandExtTrace for Command {
             .build()
     }
 
-    fn trace_status<L>(&mut self, filter: L) -> CommandTrace
+    fn trace_status<L>(&mut self, filter: L) -> CommandTrace<'_>
     where
         L: Into<Level>,
     {
         CommandTrace::builder().command(self).status(filter).build()
     }
 
-    fn trace_stdout<L>(&mut self, filter: L) -> CommandTrace
+    fn trace_stdout<L>(&mut self, filter: L) -> CommandTrace<'_>
     where
         L: Into<Level>,
     {
         CommandTrace::builder().command(self).stdout(filter).build()
     }
 
-    fn trace_stderr<L>(&mut self, filter: L) -> CommandTrace
+    fn trace_stderr<L>(&mut self, filter: L) -> CommandTrace<'_>
     where
         L: Into<Level>,
     {
         CommandTrace::builder().command(self).stderr(filter).build()
     }
+
+    fn trace_summary<L>(&mut self, filter: L) -> CommandTrace<'_>
+    where
+        L: Into<Level>,
+    {
+        CommandTrace::builder()
+            .command(self)
+            .summary(filter)
+            .build()
+    }
+
+    fn trace_stdout_on_success<L>(&mut self, filter: L) -> CommandTrace<'_>
+    where
+        L: Into<Level>,
+    {
+        CommandTrace::builder()
+            .command(self)
+            .stdout_on_success(filter)
+            .build()
+    }
+
+    fn trace_stdout_on_failure<L>(&mut self, filter: L) -> CommandTrace<'_>
+    where
+        L: Into<Level>,
+    {
+        CommandTrace::builder()
+            .command(self)
+            .stdout_on_failure(filter)
+            .build()
+    }
+
+    fn trace_stderr_on_success<L>(&mut self, filter: L) -> CommandTrace<'_>
+    where
+        L: Into<Level>,
+    {
+        CommandTrace::builder()
+            .command(self)
+            .stderr_on_success(filter)
+            .build()
+    }
+
+    fn trace_stderr_on_failure<L>(&mut self, filter: L) -> CommandTrace<'_>
+    where
+        L: Into<Level>,
+    {
+        CommandTrace::builder()
+            .command(self)
+            .stderr_on_failure(filter)
+            .build()
+    }
 }
 
 impl<'a> CommandTrace<'a> {
-    pub fn trace_args<L>(&'a mut self, filter: L) -> &'a mut CommandTrace
+    pub fn trace_args<L>(&mut self, filter: L) -> &mut Self
     where
         L: Into<Level>,
     {
@@ -246,7 +569,7 @@ impl<'a> CommandTrace<'a> {
         self
     }
 
-    pub fn trace_envs<L>(&'a mut self, filter: L) -> &'a mut CommandTrace
+    pub fn trace_envs<L>(&mut self, filter: L) -> &mut Self
     where
         L: Into<Level>,
     {
@@ -254,7 +577,18 @@ impl<'a> CommandTrace<'a> {
         self
     }
 
-    pub fn trace_current_dir<L>(&'a mut self, filter: L) -> &'a mut CommandTrace
+    /// Trace the number and names of the environment variables the command sets and
+    /// removes, and the number and total length of its arguments, without any values. This
+    /// gives some visibility where tracing the full environment is too noisy or sensitive.
+    pub fn trace_env_summary<L>(&mut self, filter: L) -> &mut Self
+    where
+        L: Into<Level>,
+    {
+        self.env_summary = Some(filter.into());
+        self
+    }
+
+    pub fn trace_current_dir<L>(&mut self, filter: L) -> &mut Self
     where
         L: Into<Level>,
     {
@@ -262,7 +596,7 @@ impl<'a> CommandTrace<'a> {
         self
     }
 
-    pub fn trace_status<L>(&'a mut self, filter: L) -> &'a mut CommandTrace
+    pub fn trace_status<L>(&mut self, filter: L) -> &mut Self
     where
         L: Into<Level>,
     {
@@ -270,7 +604,7 @@ impl<'a> CommandTrace<'a> {
         self
     }
 
-    pub fn trace_stdout<L>(&'a mut self, filter: L) -> &'a mut CommandTrace
+    pub fn trace_stdout<L>(&mut self, filter: L) -> &mut Self
     where
         L: Into<Level>,
     {
@@ -278,13 +612,214 @@ impl<'a> CommandTrace<'a> {
         self
     }
 
-    pub fn trace_stderr<L>(&'a mut self, filter: L) -> &'a mut CommandTrace
+    pub fn trace_stderr<L>(&mut self, filter: L) -> &mut Self
     where
         L: Into<Level>,
     {
         self.stderr = Some(filter.into());
         self
     }
+
+    /// Trace one line per execution with the program, arguments, working directory, status,
+    /// duration and captured byte counts as `key=value` pairs, for example `command=git
+    /// args="log --oneline" cwd=/src status=0 duration=1.200s stdout_bytes=123
+    /// stderr_bytes=0`, which is easy to search in CI logs without dumping the streams
+    pub fn trace_summary<L>(&mut self, filter: L) -> &mut Self
+    where
+        L: Into<Level>,
+    {
+        self.summary = Some(filter.into());
+        self
+    }
+
+    /// Trace stdout at this level after a successful execution, instead of the level set with
+    /// [`CommandTrace::trace_stdout`]
+    pub fn trace_stdout_on_success<L>(&mut self, filter: L) -> &mut Self
+    where
+        L: Into<Level>,
+    {
+        self.stdout_on_success = Some(filter.into());
+        self
+    }
+
+    /// Trace stdout at this level after a failed execution, instead of the level set with
+    /// [`CommandTrace::trace_stdout`]
+    pub fn trace_stdout_on_failure<L>(&mut self, filter: L) -> &mut Self
+    where
+        L: Into<Level>,
+    {
+        self.stdout_on_failure = Some(filter.into());
+        self
+    }
+
+    /// Trace stderr at this level after a successful execution, instead of the level set with
+    /// [`CommandTrace::trace_stderr`]
+    pub fn trace_stderr_on_success<L>(&mut self, filter: L) -> &mut Self
+    where
+        L: Into<Level>,
+    {
+        self.stderr_on_success = Some(filter.into());
+        self
+    }
+
+    /// Trace stderr at this level after a failed execution, instead of the level set with
+    /// [`CommandTrace::trace_stderr`]
+    pub fn trace_stderr_on_failure<L>(&mut self, filter: L) -> &mut Self
+    where
+        L: Into<Level>,
+    {
+        self.stderr_on_failure = Some(filter.into());
+        self
+    }
+
+    /// Trace only the first `head` and last `tail` lines of stdout and stderr, with a marker
+    /// saying how many lines were omitted in between, so verbose tools keep CI logs readable
+    pub fn max_lines(&mut self, head: usize, tail: usize) -> &mut Self {
+        self.max_lines = Some((head, tail));
+        self
+    }
+
+    /// Trace at this level when the command succeeds but writes to stderr, which usually
+    /// means a tool is warning about something
+    pub fn warn_on_stderr<L>(&mut self, filter: L) -> &mut Self
+    where
+        L: Into<Level>,
+    {
+        self.warn_on_stderr = Some(filter.into());
+        self
+    }
+
+    /// Fail checking the command with [`CommandExtError::UnexpectedStderr`] when it succeeds
+    /// but writes to stderr, to keep builds free of warnings
+    pub fn deny_stderr(&mut self) -> &mut Self {
+        self.deny_stderr = true;
+        self
+    }
+
+    /// Print what would be traced to stderr when no subscriber is installed, so a script
+    /// which never sets one up does not lose its diagnostics silently
+    pub fn fallback_to_stderr(&mut self, fallback: bool) -> &mut Self {
+        self.fallback_to_stderr = fallback;
+        self
+    }
+
+    /// Replace every setting with the given defaults
+    pub fn with_defaults(&mut self, defaults: &Defaults) -> &mut Self {
+        self.args = defaults.trace_args;
+        self.envs = defaults.trace_envs;
+        self.env_summary = None;
+        self.current_dir = defaults.trace_current_dir;
+        self.status = defaults.trace_status;
+        self.stdout = defaults.trace_stdout;
+        self.stderr = defaults.trace_stderr;
+        self.summary = None;
+        self.stdout_on_success = None;
+        self.stdout_on_failure = None;
+        self.stderr_on_success = None;
+        self.stderr_on_failure = None;
+        self
+    }
+
+    /// Trace everything reported at the given verbosity, replacing any levels set so far
+    pub fn verbosity(&mut self, verbosity: Verbosity) -> &mut Self {
+        let selection = verbosity.selection();
+        let level = verbosity.trace_level();
+        self.args = selection.args.then_some(level);
+        self.envs = selection.envs.then_some(level);
+        self.env_summary = None;
+        self.current_dir = selection.current_dir.then_some(level);
+        self.status = selection.status.then_some(level);
+        self.stdout = selection.stdout.then_some(level);
+        self.stderr = selection.stderr.then_some(level);
+        self.summary = None;
+        self.stdout_on_success = None;
+        self.stdout_on_failure = None;
+        self.stderr_on_success = None;
+        self.stderr_on_failure = None;
+        self
+    }
+}
+
+impl<'a> CommandTrace<'a> {
+    /// The level the arguments are traced at, if they are traced
+    pub fn get_trace_args(&self) -> Option<Level> {
+        self.args
+    }
+
+    /// The level the environment is traced at, if it is traced
+    pub fn get_trace_envs(&self) -> Option<Level> {
+        self.envs
+    }
+
+    /// The level the environment summary is traced at, if it is traced
+    pub fn get_trace_env_summary(&self) -> Option<Level> {
+        self.env_summary
+    }
+
+    /// The level the current directory is traced at, if it is traced
+    pub fn get_trace_current_dir(&self) -> Option<Level> {
+        self.current_dir
+    }
+
+    /// The level the status is traced at, if it is traced
+    pub fn get_trace_status(&self) -> Option<Level> {
+        self.status
+    }
+
+    /// The level stdout is traced at, if it is traced
+    pub fn get_trace_stdout(&self) -> Option<Level> {
+        self.stdout
+    }
+
+    /// The level stderr is traced at, if it is traced
+    pub fn get_trace_stderr(&self) -> Option<Level> {
+        self.stderr
+    }
+
+    /// The level the execution summary is traced at, if it is traced
+    pub fn get_trace_summary(&self) -> Option<Level> {
+        self.summary
+    }
+
+    /// The level stdout is traced at after a successful execution, if it is set separately
+    pub fn get_trace_stdout_on_success(&self) -> Option<Level> {
+        self.stdout_on_success
+    }
+
+    /// The level stdout is traced at after a failed execution, if it is set separately
+    pub fn get_trace_stdout_on_failure(&self) -> Option<Level> {
+        self.stdout_on_failure
+    }
+
+    /// The level stderr is traced at after a successful execution, if it is set separately
+    pub fn get_trace_stderr_on_success(&self) -> Option<Level> {
+        self.stderr_on_success
+    }
+
+    /// The level stderr is traced at after a failed execution, if it is set separately
+    pub fn get_trace_stderr_on_failure(&self) -> Option<Level> {
+        self.stderr_on_failure
+    }
+
+    /// The number of lines traced from the start and end of stdout and stderr, if limited
+    pub fn get_max_lines(&self) -> Option<(usize, usize)> {
+        self.max_lines
+    }
+
+    /// The level a successful execution which wrote to stderr is traced at, if it is
+    pub fn get_warn_on_stderr(&self) -> Option<Level> {
+        self.warn_on_stderr
+    }
+
+    /// Whether checking fails when a successful execution wrote to stderr
+    pub fn get_deny_stderr(&self) -> bool {
+        self.deny_stderr
+    }
+
+    /// Whether output is printed to stderr when no subscriber is installed
+    pub fn get_fallback_to_stderr(&self) -> bool {
+        self.fallback_to_stderr
+    }
 }
 
 #[cfg(feature = "check")]
@@ -292,16 +827,7 @@ impl<'a> CommandExtCheck for CommandTrace<'a> {
     type Error = CommandExtError;
 
     fn check(&mut self) -> Result<std::process::Output, Self::Error> {
-        self.output().map_err(CommandExtError::from).and_then(|r| {
-            r.status
-                .success()
-                .then_some(r.clone())
-                .ok_or_else(|| CommandExtError::Check {
-                    status: r.status,
-                    stdout: String::from_utf8_lossy(&r.stdout).to_string(),
-                    stderr: String::from_utf8_lossy(&r.stderr).to_string(),
-                })
-        })
+        check_wrapper(self)
     }
 }
 
@@ -333,6 +859,104 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_outcome_levels() -> anyhow::Result<()> {
+        use super::outcome_level;
+
+        assert_eq!(
+            outcome_level(Some(Level::INFO), Some(Level::TRACE), None, true),
+            Some(Level::TRACE)
+        );
+        assert_eq!(
+            outcome_level(Some(Level::INFO), Some(Level::TRACE), None, false),
+            Some(Level::INFO)
+        );
+        assert_eq!(
+            outcome_level(None, None, Some(Level::ERROR), false),
+            Some(Level::ERROR)
+        );
+        assert_eq!(outcome_level(None, None, Some(Level::ERROR), true), None);
+
+        let mut command = Command::new("bash");
+        command.args(["-c", "echo y 1>&2; exit 1"]);
+        let mut trace = command.trace_stderr_on_failure(Level::ERROR);
+        trace.trace_stderr_on_success(Level::TRACE);
+        assert_eq!(trace.get_trace_stderr_on_failure(), Some(Level::ERROR));
+        assert_eq!(trace.get_trace_stderr_on_success(), Some(Level::TRACE));
+        assert_eq!(trace.get_trace_stderr(), None);
+        trace.output()?;
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_summary() -> anyhow::Result<()> {
+        let mut command = Command::new("echo");
+        command.arg("x");
+        let mut trace = command.trace_summary(Level::INFO);
+        assert_eq!(trace.get_trace_summary(), Some(Level::INFO));
+        trace.output()?;
+        trace.status()?;
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "check")]
+    #[cfg_attr(miri, ignore)]
+    fn test_stderr_policy() -> anyhow::Result<()> {
+        use crate::{CommandExtCheck, CommandExtError};
+
+        let mut command = Command::new("bash");
+        command.args(["-c", "echo 'warning: unused' >&2"]);
+        let mut trace = command.trace_status(Level::WARN);
+        trace.warn_on_stderr(Level::WARN);
+        assert_eq!(trace.get_warn_on_stderr(), Some(Level::WARN));
+        trace.check()?;
+
+        trace.deny_stderr();
+        assert!(trace.get_deny_stderr());
+        match trace.check() {
+            Err(CommandExtError::UnexpectedStderr { stderr }) => {
+                assert_eq!(stderr, "warning: unused")
+            }
+            other => panic!("Unexpected result from command: {:?}", other),
+        }
+
+        let mut command = Command::new("bash");
+        command.args(["-c", "echo y >&2; exit 1"]);
+        let result = command.trace_status(Level::WARN).deny_stderr().check();
+        assert!(matches!(result, Err(CommandExtError::Check { .. })));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_max_lines() -> anyhow::Result<()> {
+        let mut command = Command::new("seq");
+        command.arg("100");
+        let mut trace = command.trace_stdout(Level::ERROR);
+        trace.max_lines(3, 2);
+        assert_eq!(trace.get_max_lines(), Some((3, 2)));
+        assert_eq!(
+            trace.fold("1\n2\n3\n4\n5\n6"),
+            "1\n2\n3\n... (1 line omitted)\n5\n6"
+        );
+        trace.output()?;
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_env_summary() -> anyhow::Result<()> {
+        let mut command = Command::new("echo");
+        command.env("x", "y");
+        let mut trace = command.trace_env_summary(Level::ERROR);
+        assert_eq!(trace.get_trace_env_summary(), Some(Level::ERROR));
+        trace.output()?;
+        Ok(())
+    }
+
     #[test]
     #[cfg_attr(miri, ignore)]
     fn test_current_dir() -> anyhow::Result<()> {
@@ -389,4 +1013,23 @@ mod test {
             .output()?;
         Ok(())
     }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that the fallback is only used when enabled and no subscriber is installed
+    fn test_fallback_to_stderr() -> anyhow::Result<()> {
+        let mut command = Command::new("echo");
+        let mut trace = command.trace_status(Level::INFO);
+        assert!(!trace.get_fallback_to_stderr());
+        trace.fallback_to_stderr(true);
+        assert!(!trace.fallback());
+
+        let none = tracing::subscriber::NoSubscriber::default();
+        tracing::subscriber::with_default(none, || {
+            assert!(trace.fallback());
+            assert!(trace.enabled(Level::TRACE));
+            trace.output()
+        })?;
+        Ok(())
+    }
 }
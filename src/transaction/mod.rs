@@ -0,0 +1,294 @@
+//! Groups of commands which are rolled back when one of them fails
+//!
+//! A [`Transaction`] runs its commands in order, checking each one. A command can be
+//! registered with a compensating rollback command using [`Transaction::with_rollback`].
+//! When a command fails, the rollbacks of the commands which already succeeded run in
+//! reverse order, and the error reports both the failure and the result of every rollback
+//! as [`CommandExtError::RolledBack`]. A failed rollback does not stop the others from
+//! running.
+//!
+//! Setting [`Transaction::dry_run`] prints the [`Transaction::plan`] instead, listing the
+//! commands and the rollbacks which would run, so destructive sequences can be reviewed
+//! before they are executed.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{transaction::Transaction, CommandExtError};
+//! let mut create = Command::new("mkdir");
+//! create.arg("/tmp/command-ext-transaction");
+//! let mut remove = Command::new("rmdir");
+//! remove.arg("/tmp/command-ext-transaction");
+//!
+//! let mut transaction = Transaction::new();
+//! transaction
+//!     .then(create)
+//!     .with_rollback(remove)
+//!     .then(Command::new("false"));
+//!
+//! match transaction.run() {
+//!     Err(CommandExtError::RolledBack { rollbacks, .. }) => assert!(rollbacks[0].1.is_ok()),
+//!     other => panic!("Unexpected result: {:?}", other),
+//! }
+//! assert!(!std::path::Path::new("/tmp/command-ext-transaction").exists());
+//! ```
+
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    process::{Command, Output},
+};
+
+use crate::{display::CommandLine, CommandExtCheck, CommandExtError};
+
+#[derive(Debug)]
+/// A command of a transaction and the command which undoes it
+struct Step {
+    command: Command,
+    rollback: Option<Command>,
+}
+
+#[derive(Debug, Default)]
+/// A sequence of commands with rollbacks. See the [module documentation](self).
+pub struct Transaction {
+    steps: Vec<Step>,
+    dry_run: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// The commands a [`Transaction`] will run, displayed as a numbered list of commands
+/// followed by the rollbacks
+pub struct TransactionPlan {
+    /// The command lines of the commands, in the order they run
+    pub forward: Vec<String>,
+    /// The command lines of the rollbacks with the index of the command each one undoes, in
+    /// the order they run. Only the rollbacks of commands which succeeded before a failure
+    /// run.
+    pub rollbacks: Vec<(usize, String)>,
+}
+
+impl Display for TransactionPlan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        writeln!(f, "commands:")?;
+        self.forward
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, command)| writeln!(f, "  {}. {command}", i + 1))?;
+
+        if self.rollbacks.is_empty() {
+            return writeln!(f, "rollbacks: none");
+        }
+
+        writeln!(f, "rollbacks, if a later command fails:")?;
+        self.rollbacks
+            .iter()
+            .try_for_each(|(i, command)| writeln!(f, "  {command} (undoes {})", i + 1))
+    }
+}
+
+impl Transaction {
+    /// Create an empty transaction
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a command to run after the commands already in the transaction
+    pub fn then(&mut self, command: Command) -> &mut Self {
+        self.steps.push(Step {
+            command,
+            rollback: None,
+        });
+        self
+    }
+
+    /// Register the command which undoes the last command added to the transaction, run if
+    /// a later command fails
+    ///
+    /// # Panics
+    ///
+    /// If no command has been added to the transaction
+    pub fn with_rollback(&mut self, rollback: Command) -> &mut Self {
+        self.steps
+            .last_mut()
+            .expect("A rollback must follow the command it undoes")
+            .rollback = Some(rollback);
+        self
+    }
+
+    /// Print the plan of the transaction when it is run instead of running it
+    pub fn dry_run(&mut self, dry_run: bool) -> &mut Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Whether running the transaction only prints its plan
+    pub fn get_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// The commands and rollbacks the transaction will run
+    pub fn plan(&self) -> TransactionPlan {
+        TransactionPlan {
+            forward: self
+                .steps
+                .iter()
+                .map(|step| CommandLine(&step.command).to_string())
+                .collect(),
+            rollbacks: self
+                .steps
+                .iter()
+                .enumerate()
+                .rev()
+                .filter_map(|(i, step)| {
+                    step.rollback
+                        .as_ref()
+                        .map(|rollback| (i, CommandLine(rollback).to_string()))
+                })
+                .collect(),
+        }
+    }
+
+    /// The number of commands in the transaction, not counting rollbacks
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Whether the transaction has no commands
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Run the commands in order, returning their outputs. If one fails, roll back the
+    /// commands before it and return [`CommandExtError::RolledBack`]. In a
+    /// [dry run](Transaction::dry_run), print the plan to the standard output stream and
+    /// return no outputs.
+    pub fn run(&mut self) -> Result<Vec<Output>, CommandExtError> {
+        if self.dry_run {
+            print!("{}", self.plan());
+            return Ok(Vec::new());
+        }
+
+        let mut outputs = Vec::with_capacity(self.steps.len());
+
+        for (index, step) in self.steps.iter_mut().enumerate() {
+            match step.command.check() {
+                Ok(output) => outputs.push(output),
+                Err(error) => {
+                    let failed = CommandLine(&step.command).to_string();
+                    return Err(CommandExtError::RolledBack {
+                        failed,
+                        rollbacks: self.rollback(index),
+                        source: Box::new(error),
+                    });
+                }
+            }
+        }
+
+        Ok(outputs)
+    }
+
+    /// Run the rollbacks of the commands before `failed` in reverse order
+    fn rollback(&mut self, failed: usize) -> Vec<(String, Result<(), String>)> {
+        self.steps[..failed]
+            .iter_mut()
+            .rev()
+            .filter_map(|step| step.rollback.as_mut())
+            .map(|rollback| {
+                let result = rollback.check().map(|_| ()).map_err(|e| e.to_string());
+                (CommandLine(rollback).to_string(), result)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use super::Transaction;
+    use crate::CommandExtError;
+
+    fn echo(arg: &str) -> Command {
+        let mut command = Command::new("echo");
+        command.arg(arg);
+        command
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a successful transaction runs no rollbacks, and a failed one rolls back
+    /// the commands before the failure in reverse order
+    fn test_transaction() -> Result<(), CommandExtError> {
+        let mut transaction = Transaction::new();
+        transaction
+            .then(echo("a"))
+            .with_rollback(Command::new("false"))
+            .then(echo("b"));
+        let outputs = transaction.run()?;
+        assert_eq!(outputs[1].stdout, b"b\n");
+
+        let mut transaction = Transaction::new();
+        transaction
+            .then(echo("a"))
+            .with_rollback(echo("undo a"))
+            .then(echo("b"))
+            .then(echo("c"))
+            .with_rollback(Command::new("false"))
+            .then(Command::new("false"))
+            .with_rollback(echo("undo failed"));
+        assert_eq!(transaction.len(), 4);
+
+        let error = transaction.run().unwrap_err();
+        match &error {
+            CommandExtError::RolledBack {
+                failed,
+                rollbacks,
+                source,
+            } => {
+                assert_eq!(failed, "false");
+                assert_eq!(rollbacks.len(), 2);
+                assert_eq!(rollbacks[0].0, "false");
+                assert!(rollbacks[0].1.is_err());
+                assert_eq!(rollbacks[1], ("echo undo a".to_string(), Ok(())));
+                assert!(matches!(**source, CommandExtError::Check { .. }));
+                assert_eq!(error.exit_code_hint(), 1);
+                assert!(error.to_string().starts_with(
+                    "Command false failed and 2 commands were rolled back, 1 unsuccessfully"
+                ));
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that the plan lists the commands in order and the rollbacks in reverse, and
+    /// that a dry run executes nothing
+    fn test_plan() -> Result<(), CommandExtError> {
+        let mut transaction = Transaction::new();
+        transaction
+            .then(echo("a"))
+            .with_rollback(echo("undo a"))
+            .then(echo("b"))
+            .then(echo("c"))
+            .with_rollback(echo("undo c"))
+            .then(Command::new("false"))
+            .dry_run(true);
+
+        assert_eq!(
+            transaction.plan().to_string(),
+            "commands:\n  1. echo a\n  2. echo b\n  3. echo c\n  4. false\n\
+             rollbacks, if a later command fails:\n  echo undo c (undoes 3)\n  echo undo a (undoes 1)\n"
+        );
+        assert!(transaction.run()?.is_empty());
+
+        let mut transaction = Transaction::new();
+        transaction.then(echo("a"));
+        assert!(transaction
+            .plan()
+            .to_string()
+            .ends_with("rollbacks: none\n"));
+        Ok(())
+    }
+}
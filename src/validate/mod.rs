@@ -0,0 +1,593 @@
+//! Extension trait to catch common misconfigurations of a command before it executes
+//!
+//! Spawning a misconfigured command usually fails with a terse OS error, or succeeds and
+//! then behaves unexpectedly. [`CommandExtValidate::validate`] reports every problem it
+//! finds at once instead.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{CommandExtValidate, ValidationIssue};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut command = Command::new("asdfasdfasdfasdfjkljkljkl");
+//! command.current_dir("/nonexistent").env("KEY", "a\0b");
+//!
+//! for issue in command.validate() {
+//!     eprintln!("{issue}");
+//! }
+//! assert_eq!(command.validate().len(), 3);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    ffi::OsStr,
+    fmt::{Display, Formatter, Result as FmtResult},
+    io::{Error, ErrorKind, Result as IoResult},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Output},
+};
+
+#[cfg(feature = "check")]
+use crate::{check::check_wrapper, CommandExtCheck, CommandExtError};
+use crate::{
+    explain::{Explanation, StdioConfig},
+    wrap::HasCommand,
+    CommandWrap,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+/// A problem which will make a command fail or misbehave when it executes
+pub enum ValidationIssue {
+    /// The program could not be found on the search path the command will use
+    ProgramNotFound { program: String },
+    /// The program path names a directory
+    ProgramIsDirectory { path: PathBuf },
+    /// The working directory does not exist
+    CurrentDirNotFound { path: PathBuf },
+    /// The working directory is not a directory
+    CurrentDirNotDirectory { path: PathBuf },
+    /// The program, an argument, an environment variable or the working directory contains
+    /// a NUL byte, which cannot be passed to a process
+    ContainsNul { what: String },
+    /// An argument, environment variable or the working directory cannot be converted to
+    /// UTF-16 on Windows
+    InvalidUnicode { what: String },
+    /// An environment variable name is empty or contains `=`
+    InvalidEnvKey { key: String },
+    /// A wrapper writes to standard input, but it is configured as something other than
+    /// piped. Streams configured on a [`Command`] are not visible, so this is only reported
+    /// when the configuration of the stream is known.
+    StdinNotPiped { stdin: StdioConfig },
+    /// A wrapper reads the output of the command, but `stream` is configured as something
+    /// other than piped. Like [`ValidationIssue::StdinNotPiped`], this is only reported when
+    /// the configuration of the stream is known.
+    OutputNotPiped {
+        stream: &'static str,
+        config: StdioConfig,
+    },
+    /// The command line, together with the environment on unix, exceeds the limit of the
+    /// platform
+    CommandLineTooLong { size: usize, limit: usize },
+    /// A single argument or environment variable exceeds the per-string limit of the platform
+    ArgumentTooLong {
+        what: String,
+        size: usize,
+        limit: usize,
+    },
+}
+
+impl Display for ValidationIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::ProgramNotFound { program } => write!(f, "program {program} was not found"),
+            Self::ProgramIsDirectory { path } => {
+                write!(f, "program {} is a directory", path.display())
+            }
+            Self::CurrentDirNotFound { path } => {
+                write!(f, "working directory {} does not exist", path.display())
+            }
+            Self::CurrentDirNotDirectory { path } => {
+                write!(f, "working directory {} is not a directory", path.display())
+            }
+            Self::ContainsNul { what } => write!(f, "{what} contains a NUL byte"),
+            Self::InvalidUnicode { what } => write!(f, "{what} is not valid Unicode"),
+            Self::InvalidEnvKey { key } => {
+                write!(f, "environment variable name {key:?} is invalid")
+            }
+            Self::StdinNotPiped { stdin } => write!(
+                f,
+                "stdin is configured as {stdin}, but the wrapper writes to it"
+            ),
+            Self::OutputNotPiped { stream, config } => write!(
+                f,
+                "{stream} is configured as {config}, but the wrapper reads it"
+            ),
+            Self::CommandLineTooLong { size, limit } => write!(
+                f,
+                "the command line is {size} bytes, which exceeds the limit of {limit}"
+            ),
+            Self::ArgumentTooLong { what, size, limit } => write!(
+                f,
+                "{what} is {size} bytes, which exceeds the limit of {limit}"
+            ),
+        }
+    }
+}
+
+/// The placeholder the standard library stores on unix in place of a program, argument or
+/// working directory which contains a NUL byte
+const NUL_PLACEHOLDER: &str = "<string-with-nul>";
+
+fn has_nul(value: &OsStr) -> bool {
+    value.as_encoded_bytes().contains(&0) || (cfg!(unix) && value == NUL_PLACEHOLDER)
+}
+
+/// The problem with passing `value` to a process, if it cannot be passed as is: a NUL
+/// byte anywhere, or on Windows, a lone surrogate which cannot be converted to UTF-16
+fn encoding_issue<F>(what: F, value: &OsStr) -> Option<ValidationIssue>
+where
+    F: FnOnce() -> String,
+{
+    if has_nul(value) {
+        Some(ValidationIssue::ContainsNul { what: what() })
+    } else if cfg!(windows) && value.to_str().is_none() {
+        Some(ValidationIssue::InvalidUnicode { what: what() })
+    } else {
+        None
+    }
+}
+
+fn env_issues(key: &OsStr, value: Option<&OsStr>) -> Vec<ValidationIssue> {
+    let name = key.to_string_lossy();
+    encoding_issue(|| format!("environment variable name {name:?}"), key)
+        .into_iter()
+        .chain(value.and_then(|v| encoding_issue(|| format!("environment variable {name}"), v)))
+        .collect()
+}
+
+/// The encoding problems with the program, arguments, environment and working directory
+fn encoding_issues(command: &Command) -> Vec<ValidationIssue> {
+    encoding_issue(|| "the program".to_string(), command.get_program())
+        .into_iter()
+        .chain(
+            command
+                .get_args()
+                .enumerate()
+                .filter_map(|(i, a)| encoding_issue(|| format!("argument {i}"), a)),
+        )
+        .chain(command.get_envs().flat_map(|(k, v)| env_issues(k, v)))
+        .chain(
+            command.get_current_dir().and_then(|d| {
+                encoding_issue(|| "the working directory".to_string(), d.as_os_str())
+            }),
+        )
+        .collect()
+}
+
+/// Whether `config` may be piped. Streams configured on a [`Command`] are unknown, so they
+/// are not reported.
+fn is_piped(config: &StdioConfig) -> bool {
+    matches!(
+        config,
+        StdioConfig::Default | StdioConfig::Piped | StdioConfig::Unknown
+    )
+}
+
+#[cfg(unix)]
+/// The limit on the combined size of the arguments and environment of a new process, as
+/// reported by `sysconf(_SC_ARG_MAX)`
+pub fn command_line_limit() -> usize {
+    extern "C" {
+        fn sysconf(name: std::ffi::c_int) -> std::ffi::c_long;
+    }
+
+    /// `_SC_ARG_MAX` on Linux, the BSDs and macOS
+    const SC_ARG_MAX: std::ffi::c_int = if cfg!(target_os = "linux") { 0 } else { 1 };
+
+    // SAFETY: sysconf has no memory safety requirements
+    match unsafe { sysconf(SC_ARG_MAX) } {
+        limit if limit > 0 => limit as usize,
+        _ => 128 * 1024,
+    }
+}
+
+#[cfg(not(unix))]
+/// The limit on the length of a command line in UTF-16 code units
+pub fn command_line_limit() -> usize {
+    32767
+}
+
+/// The limit on the size of a single argument or environment variable on Linux
+#[cfg(target_os = "linux")]
+const STRING_LIMIT: Option<usize> = Some(32 * 4096);
+#[cfg(not(target_os = "linux"))]
+const STRING_LIMIT: Option<usize> = None;
+
+#[cfg(unix)]
+/// The size counted against [`command_line_limit`] for each argument and environment
+/// variable: each string, its terminating NUL, and a pointer to it
+fn string_sizes(explanation: &Explanation) -> Vec<(String, usize)> {
+    use std::env::vars_os;
+
+    let pointer = std::mem::size_of::<usize>();
+    let mut env = match explanation.env_cleared {
+        Some(true) => Vec::new(),
+        _ => vars_os().collect::<Vec<_>>(),
+    };
+    explanation.envs.iter().for_each(|(k, v)| {
+        env.retain(|(key, _)| key != k);
+        if let Some(v) = v {
+            env.push((k.clone(), v.clone()));
+        }
+    });
+
+    std::iter::once(("the program".to_string(), explanation.program.len()))
+        .chain(
+            explanation
+                .args
+                .iter()
+                .enumerate()
+                .map(|(i, a)| (format!("argument {i}"), a.len())),
+        )
+        .chain(env.iter().map(|(k, v)| {
+            (
+                format!("environment variable {}", k.to_string_lossy()),
+                k.len() + 1 + v.len(),
+            )
+        }))
+        .map(|(what, len)| (what, len + 1 + pointer))
+        .collect()
+}
+
+#[cfg(not(unix))]
+/// The length of each argument in the command line, including quoting and separating space
+fn string_sizes(explanation: &Explanation) -> Vec<(String, usize)> {
+    let quoted = |a: &OsStr| a.to_string_lossy().encode_utf16().count() + 3;
+
+    std::iter::once(("the program".to_string(), quoted(&explanation.program)))
+        .chain(
+            explanation
+                .args
+                .iter()
+                .enumerate()
+                .map(|(i, a)| (format!("argument {i}"), quoted(a))),
+        )
+        .collect()
+}
+
+/// The size of the command line of `command` as counted against [`command_line_limit`].
+/// On unix this includes the environment the command will receive.
+pub fn command_line_size(command: &Command) -> usize {
+    string_sizes(&Explanation::new(command))
+        .iter()
+        .map(|(_, size)| size)
+        .sum()
+}
+
+/// The problems with `command`, and with the stdio requirements of its wrapper if any
+fn issues(command: &Command, writes_stdin: bool, reads_output: bool) -> Vec<ValidationIssue> {
+    let explanation = Explanation::new(command);
+    let mut issues = encoding_issues(command);
+
+    explanation
+        .envs
+        .iter()
+        .filter(|(k, _)| k.is_empty() || k.as_encoded_bytes().contains(&b'='))
+        .for_each(|(k, _)| {
+            issues.push(ValidationIssue::InvalidEnvKey {
+                key: k.to_string_lossy().into_owned(),
+            })
+        });
+
+    let sizes = string_sizes(&explanation);
+    if let Some(limit) = STRING_LIMIT {
+        sizes
+            .iter()
+            .filter(|(_, size)| *size > limit)
+            .for_each(|(what, size)| {
+                issues.push(ValidationIssue::ArgumentTooLong {
+                    what: what.clone(),
+                    size: *size,
+                    limit,
+                })
+            });
+    }
+    let (size, limit) = (
+        sizes.iter().map(|(_, size)| size).sum::<usize>(),
+        command_line_limit(),
+    );
+    if size > limit {
+        issues.push(ValidationIssue::CommandLineTooLong { size, limit });
+    }
+
+    if explanation.resolved.is_none() && !has_nul(&explanation.program) {
+        let program = Path::new(&explanation.program);
+        let path = match &explanation.current_dir {
+            Some(dir) => dir.join(program),
+            None => program.to_path_buf(),
+        };
+
+        issues.push(match program.components().count() > 1 && path.is_dir() {
+            true => ValidationIssue::ProgramIsDirectory { path },
+            false => ValidationIssue::ProgramNotFound {
+                program: explanation.program.to_string_lossy().into_owned(),
+            },
+        });
+    }
+
+    if let Some(path) = explanation.current_dir.filter(|d| !has_nul(d.as_os_str())) {
+        if !path.exists() {
+            issues.push(ValidationIssue::CurrentDirNotFound { path });
+        } else if !path.is_dir() {
+            issues.push(ValidationIssue::CurrentDirNotDirectory { path });
+        }
+    }
+
+    if writes_stdin && !is_piped(&explanation.stdin) {
+        issues.push(ValidationIssue::StdinNotPiped {
+            stdin: explanation.stdin,
+        });
+    }
+
+    if reads_output {
+        [
+            ("stdout", explanation.stdout),
+            ("stderr", explanation.stderr),
+        ]
+        .into_iter()
+        .filter(|(_, config)| !is_piped(config))
+        .for_each(|(stream, config)| {
+            issues.push(ValidationIssue::OutputNotPiped { stream, config })
+        });
+    }
+
+    issues
+}
+
+#[derive(Debug)]
+/// A command which rejects arguments, environment variables and working directories that
+/// cannot be passed to a process as soon as they are added, and refuses to execute if any
+/// were. Problems are reported as [`ErrorKind::InvalidInput`] errors naming the offending
+/// argument index or variable.
+pub struct CommandStrict<'a> {
+    command: &'a mut Command,
+    next_arg: usize,
+    issues: Vec<ValidationIssue>,
+}
+
+impl<'a> CommandStrict<'a> {
+    /// The problems found so far
+    pub fn issues(&self) -> &[ValidationIssue] {
+        &self.issues
+    }
+
+    fn invalid(&self) -> Option<Error> {
+        self.issues
+            .first()
+            .map(|issue| Error::new(ErrorKind::InvalidInput, issue.to_string()))
+    }
+}
+
+impl<'a> HasCommand for CommandStrict<'a> {
+    fn command(&self) -> &Command {
+        self.command
+    }
+
+    fn command_mut(&mut self) -> &mut Command {
+        self.command
+    }
+}
+
+impl<'a> CommandWrap for CommandStrict<'a> {
+    fn on_arg<S: AsRef<OsStr>>(&mut self, arg: S) {
+        let index = self.next_arg;
+        self.next_arg += 1;
+        self.issues
+            .extend(encoding_issue(|| format!("argument {index}"), arg.as_ref()));
+    }
+
+    fn on_args<I, S>(&mut self, args: I)
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        args.into_iter().for_each(|a| self.on_arg(a));
+    }
+
+    fn on_env<K, V>(&mut self, key: K, val: V)
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.issues
+            .extend(env_issues(key.as_ref(), Some(val.as_ref())));
+    }
+
+    fn on_envs<'b, I, K, V>(&mut self, vars: I)
+    where
+        I: IntoIterator<Item = &'b (K, V)>,
+        K: AsRef<OsStr> + 'b,
+        V: AsRef<OsStr> + 'b,
+    {
+        vars.into_iter().for_each(|(k, v)| self.on_env(k, v));
+    }
+
+    fn on_env_remove<K: AsRef<OsStr>>(&mut self, key: K) {
+        self.issues.extend(env_issues(key.as_ref(), None));
+    }
+
+    fn on_current_dir<P: AsRef<Path>>(&mut self, dir: P) {
+        self.issues.extend(encoding_issue(
+            || "the working directory".to_string(),
+            dir.as_ref().as_os_str(),
+        ));
+    }
+
+    fn spawn(&mut self) -> IoResult<Child> {
+        match self.invalid() {
+            Some(e) => Err(e),
+            None => self.command.spawn(),
+        }
+    }
+
+    fn output(&mut self) -> IoResult<Output> {
+        match self.invalid() {
+            Some(e) => Err(e),
+            None => self.command.output(),
+        }
+    }
+
+    fn status(&mut self) -> IoResult<ExitStatus> {
+        match self.invalid() {
+            Some(e) => Err(e),
+            None => self.command.status(),
+        }
+    }
+}
+
+impl<'a> From<&'a mut Command> for CommandStrict<'a> {
+    fn from(value: &'a mut Command) -> Self {
+        Self {
+            next_arg: value.get_args().count(),
+            issues: encoding_issues(value),
+            command: value,
+        }
+    }
+}
+
+#[cfg(feature = "check")]
+impl<'a> CommandExtCheck for CommandStrict<'a> {
+    type Error = CommandExtError;
+
+    fn check(&mut self) -> Result<Output, Self::Error> {
+        check_wrapper(self)
+    }
+}
+
+pub trait CommandExtStrict {
+    fn strict(&mut self) -> CommandStrict<'_>;
+}
+
+impl CommandExtStrict for Command {
+    fn strict(&mut self) -> CommandStrict<'_> {
+        CommandStrict::from(self)
+    }
+}
+
+pub trait CommandExtValidate {
+    /// Every problem which will make the command fail or misbehave when it executes, or
+    /// an empty list if none were found
+    fn validate(&self) -> Vec<ValidationIssue>;
+}
+
+impl CommandExtValidate for Command {
+    fn validate(&self) -> Vec<ValidationIssue> {
+        issues(self, false, false)
+    }
+}
+
+impl<T> CommandExtValidate for T
+where
+    T: CommandWrap,
+{
+    fn validate(&self) -> Vec<ValidationIssue> {
+        issues(self.command(), self.writes_stdin(), self.reads_output())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use super::ValidationIssue;
+    use crate::CommandExtValidate;
+
+    #[test]
+    fn test_valid() {
+        assert_eq!(Command::new("sh").args(["-c", "true"]).validate(), []);
+    }
+
+    #[test]
+    fn test_issues() {
+        let mut command = Command::new("sh");
+        command.arg("a\0b").env("A=B", "c").current_dir(file!());
+        let issues = command.validate();
+
+        assert!(issues.contains(&ValidationIssue::ContainsNul {
+            what: "argument 0".to_string()
+        }));
+        assert!(issues.contains(&ValidationIssue::InvalidEnvKey {
+            key: "A=B".to_string()
+        }));
+        assert!(issues
+            .iter()
+            .any(|i| matches!(i, ValidationIssue::CurrentDirNotDirectory { .. })));
+    }
+
+    #[test]
+    fn test_strict() {
+        use std::io::ErrorKind;
+
+        use crate::{CommandExtStrict, CommandWrap};
+
+        let mut command = Command::new("echo");
+        command.arg("a");
+        let mut strict = command.strict();
+        strict.args(["b", "c\0"]).env("KEY", "v\0");
+
+        assert_eq!(
+            strict.issues(),
+            [
+                ValidationIssue::ContainsNul {
+                    what: "argument 2".to_string()
+                },
+                ValidationIssue::ContainsNul {
+                    what: "environment variable KEY".to_string()
+                }
+            ]
+        );
+
+        let error = strict.output().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert_eq!(error.to_string(), "argument 2 contains a NUL byte");
+    }
+
+    #[test]
+    fn test_command_line_too_long() {
+        let limit = super::command_line_limit();
+        let mut command = Command::new("sh");
+        command.args(std::iter::repeat_n("x".repeat(1024), limit / 1024 + 1));
+
+        assert!(super::command_line_size(&command) > limit);
+        assert!(command
+            .validate()
+            .iter()
+            .any(|i| matches!(i, ValidationIssue::CommandLineTooLong { .. })));
+    }
+
+    #[test]
+    #[cfg(all(unix, feature = "watch"))]
+    /// Test that streams configured on a command, which it does not expose, are not
+    /// reported as misconfigured
+    fn test_output_not_piped() {
+        use std::process::Stdio;
+
+        use super::StdioConfig;
+        use crate::CommandExtWatch;
+
+        let mut command = Command::new("sh");
+        command.stdout(Stdio::null());
+        assert!(command.kill_if_output_matches("x").validate().is_empty());
+
+        let issue = ValidationIssue::OutputNotPiped {
+            stream: "stdout",
+            config: StdioConfig::Null,
+        };
+        assert!(issue
+            .to_string()
+            .starts_with("stdout is configured as null"));
+    }
+}
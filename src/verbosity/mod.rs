@@ -0,0 +1,151 @@
+//! A single verbosity knob mapped onto whichever observability backend is enabled
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{CommandExtVerbosity, CommandWrap, Verbosity};
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! // e.g. from `-q` and a count of `-v` flags
+//! let verbosity = Verbosity::from_flags(false, 1);
+//! let output = Command::new("echo")
+//!     .arg("x")
+//!     .verbosity(verbosity)
+//!     .output()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! When the `tracing` feature is enabled, this produces a [`crate::trace::CommandTrace`],
+//! otherwise a [`crate::log::CommandLog`] when `log` is enabled, otherwise a
+//! [`crate::print::CommandPrint`].
+
+use std::process::Command;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// How much information to report about executed commands
+pub enum Verbosity {
+    /// Report nothing
+    Quiet,
+    #[default]
+    /// Report the command line and exit status
+    Normal,
+    /// Additionally report the output and error streams
+    Verbose,
+    /// Report everything, including the environment and working directory
+    Debug,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// The properties of a command reported at a given [`Verbosity`]
+pub struct VerbositySelection {
+    pub args: bool,
+    pub envs: bool,
+    pub current_dir: bool,
+    pub status: bool,
+    pub stdout: bool,
+    pub stderr: bool,
+}
+
+impl Verbosity {
+    /// Map conventional `-q` and `-v` (counted) command line flags to a verbosity
+    pub fn from_flags(quiet: bool, verbose: u8) -> Self {
+        match (quiet, verbose) {
+            (true, _) => Self::Quiet,
+            (false, 0) => Self::Normal,
+            (false, 1) => Self::Verbose,
+            (false, _) => Self::Debug,
+        }
+    }
+
+    /// The properties of a command reported at this verbosity
+    pub fn selection(self) -> VerbositySelection {
+        let at_least = |v: Self| self >= v;
+        VerbositySelection {
+            args: at_least(Self::Normal),
+            envs: at_least(Self::Debug),
+            current_dir: at_least(Self::Debug),
+            status: at_least(Self::Normal),
+            stdout: at_least(Self::Verbose),
+            stderr: at_least(Self::Verbose),
+        }
+    }
+
+    #[cfg(feature = "log")]
+    /// The [`log`] level reported properties are logged at
+    pub fn log_level(self) -> log::Level {
+        match self {
+            Self::Debug => log::Level::Debug,
+            _ => log::Level::Info,
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    /// The [`tracing`] level reported properties are traced at
+    pub fn trace_level(self) -> tracing::Level {
+        match self {
+            Self::Debug => tracing::Level::DEBUG,
+            _ => tracing::Level::INFO,
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+/// The wrapper produced by [`CommandExtVerbosity::verbosity`]
+pub type CommandVerbosity<'a> = crate::trace::CommandTrace<'a>;
+
+#[cfg(all(feature = "log", not(feature = "tracing")))]
+/// The wrapper produced by [`CommandExtVerbosity::verbosity`]
+pub type CommandVerbosity<'a> = crate::log::CommandLog<'a>;
+
+#[cfg(all(feature = "print", not(feature = "log"), not(feature = "tracing")))]
+/// The wrapper produced by [`CommandExtVerbosity::verbosity`]
+pub type CommandVerbosity<'a> = crate::print::CommandPrint<'a>;
+
+pub trait CommandExtVerbosity {
+    fn verbosity(&mut self, verbosity: Verbosity) -> CommandVerbosity<'_>;
+}
+
+impl CommandExtVerbosity for Command {
+    fn verbosity(&mut self, verbosity: Verbosity) -> CommandVerbosity<'_> {
+        let mut wrapper = CommandVerbosity::from(self);
+        wrapper.verbosity(verbosity);
+        wrapper
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::process::Command;
+
+    use super::{Verbosity, VerbositySelection};
+    use crate::{CommandExtVerbosity, CommandWrap};
+
+    #[test]
+    fn test_from_flags() {
+        assert_eq!(Verbosity::from_flags(true, 2), Verbosity::Quiet);
+        assert_eq!(Verbosity::from_flags(false, 0), Verbosity::Normal);
+        assert_eq!(Verbosity::from_flags(false, 1), Verbosity::Verbose);
+        assert_eq!(Verbosity::from_flags(false, 3), Verbosity::Debug);
+    }
+
+    #[test]
+    fn test_selection() {
+        assert_eq!(Verbosity::Quiet.selection(), VerbositySelection::default());
+        assert!(Verbosity::Normal.selection().args);
+        assert!(!Verbosity::Normal.selection().stdout);
+        assert!(Verbosity::Verbose.selection().stderr);
+        assert!(!Verbosity::Verbose.selection().envs);
+        assert!(Verbosity::Debug.selection().current_dir);
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_verbosity() -> anyhow::Result<()> {
+        Command::new("echo")
+            .arg("x")
+            .verbosity(Verbosity::Debug)
+            .output()?;
+        Ok(())
+    }
+}
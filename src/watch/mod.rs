@@ -0,0 +1,914 @@
+//! Extension trait to watch the output of a command while it runs
+//!
+//! The output of a watched command is streamed line by line as it is produced, so watchdogs
+//! can react to it immediately rather than after the command exits. Both output streams are
+//! captured with [`crate::capture`], so watched commands never deadlock on a full pipe.
+//!
+//! [`CommandWrap::output`] captures stdout and stderr as usual, while
+//! [`CommandWrap::status`] forwards each chunk of output to the parent's stdout and stderr
+//! as it arrives. Unlike [`Command::output`], stdin is inherited unless configured.
+//! [`CommandWrap::spawn`] is not watched.
+//!
+//! On unix, watched commands are started in a process group of their own, and a watchdog
+//! or inactivity timeout kills the whole group, so that descendants of the command do not
+//! outlive it. Since the group is not the terminal's foreground group, signals from the
+//! terminal such as Ctrl-C do not reach the command directly.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use std::process::Command;
+//! # use command_ext::{CommandExtCheck, CommandExtError, CommandExtWatch};
+//! let error = Command::new("bash")
+//!     .args(["-c", "echo 'FATAL: out of cheese'; sleep 60"])
+//!     .kill_if_output_matches("FATAL")
+//!     .check()
+//!     .unwrap_err();
+//!
+//! assert!(matches!(error, CommandExtError::OutputMatched { killed: true, .. }));
+//! ```
+//!
+//! An inactivity timeout kills commands which stop producing output, while allowing
+//! commands which take a long time but keep printing progress to run to completion:
+//!
+//! ```rust
+//! # use std::{process::Command, time::Duration};
+//! # use command_ext::{CommandExtCheck, CommandExtError, CommandExtWatch};
+//! let error = Command::new("bash")
+//!     .args(["-c", "echo compiling; sleep 60"])
+//!     .inactivity_timeout(Duration::from_millis(200))
+//!     .check()
+//!     .unwrap_err();
+//!
+//! assert!(matches!(
+//!     error,
+//!     CommandExtError::Inactive { last_line: Some(line), .. } if line == "compiling"
+//! ));
+//! ```
+
+use std::{
+    io::{stderr, stdout, Result as IoResult, Write},
+    process::{Child, Command, ExitStatus, Output, Stdio},
+    time::Duration,
+};
+
+use crate::{
+    capture::{Pipes, Stream},
+    clock, metrics, spawn,
+    wrap::{ExecutionInfo, HasCommand},
+    CommandWrap,
+};
+#[cfg(feature = "check")]
+use crate::{
+    check::{check_with, preflight},
+    CommandExtCheck, CommandExtError,
+};
+
+/// Something a line of output can be matched against
+pub trait OutputPattern: Send {
+    /// Whether `line` matches this pattern
+    fn matches(&self, line: &str) -> bool;
+
+    /// A description of the pattern for error messages
+    fn describe(&self) -> String;
+}
+
+impl OutputPattern for &str {
+    fn matches(&self, line: &str) -> bool {
+        line.contains(*self)
+    }
+
+    fn describe(&self) -> String {
+        format!("{self:?}")
+    }
+}
+
+impl OutputPattern for String {
+    fn matches(&self, line: &str) -> bool {
+        line.contains(self.as_str())
+    }
+
+    fn describe(&self) -> String {
+        format!("{self:?}")
+    }
+}
+
+#[cfg(feature = "regex")]
+impl OutputPattern for regex::Regex {
+    fn matches(&self, line: &str) -> bool {
+        self.is_match(line)
+    }
+
+    fn describe(&self) -> String {
+        format!("/{}/", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Kill,
+    Fail,
+}
+
+struct Watchdog {
+    pattern: Box<dyn OutputPattern>,
+    action: Action,
+}
+
+impl std::fmt::Debug for Watchdog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Watchdog")
+            .field("pattern", &self.pattern.describe())
+            .field("action", &self.action)
+            .finish()
+    }
+}
+
+/// Extracts a progress percentage from a line of output
+type ProgressParser = Box<dyn FnMut(&str) -> Option<f64> + Send>;
+
+/// Receives each new progress percentage
+type ProgressCallback = Box<dyn FnMut(f64) + Send>;
+
+#[derive(Default)]
+/// Progress parsed from the output of a command
+struct Progress {
+    parser: Option<ProgressParser>,
+    callbacks: Vec<ProgressCallback>,
+    /// The most recent percentage parsed during the current execution
+    last: Option<f64>,
+}
+
+impl Progress {
+    /// Parse `line`, notifying the callbacks if it reports a new percentage
+    fn parse(&mut self, line: &str) {
+        let Some(parser) = self.parser.as_mut() else {
+            return;
+        };
+
+        // Progress bars redraw themselves by returning to the start of the line
+        let Some(percent) = line.rsplit('\r').find(|l| !l.is_empty()).and_then(parser) else {
+            return;
+        };
+
+        let percent = percent.clamp(0.0, 100.0);
+        if self.last != Some(percent) {
+            self.last = Some(percent);
+            self.callbacks.iter_mut().for_each(|f| f(percent));
+        }
+    }
+}
+
+impl std::fmt::Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Progress")
+            .field("parser", &self.parser.is_some())
+            .field("callbacks", &self.callbacks.len())
+            .field("last", &self.last)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A watchdog which was triggered by a line of output
+pub struct Triggered {
+    /// A description of the pattern which matched
+    pub pattern: String,
+    /// The line which matched
+    pub line: String,
+    /// The stream the line was written to
+    pub stream: Stream,
+    /// Whether the command was killed as a result
+    pub killed: bool,
+}
+
+/// Splits the chunks of one stream into lines
+#[derive(Debug, Default)]
+struct Lines {
+    /// The line currently being received
+    pending: Vec<u8>,
+}
+
+impl Lines {
+    /// Append a chunk, returning the complete lines it finished
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(chunk);
+        let mut lines = Vec::new();
+        let mut start = 0;
+
+        while let Some(end) = self.pending[start..].iter().position(|b| *b == b'\n') {
+            let line = &self.pending[start..start + end];
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            lines.push(String::from_utf8_lossy(line).into_owned());
+            start += end + 1;
+        }
+
+        self.pending.drain(..start);
+        lines
+    }
+
+    /// The final line, if the stream did not end with a newline
+    fn finish(&self) -> Option<String> {
+        (!self.pending.is_empty()).then(|| String::from_utf8_lossy(&self.pending).into_owned())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A command which was killed because it stopped producing output
+pub struct Inactivity {
+    /// How long the command had been silent when it was killed
+    pub silence: Duration,
+    /// The last line of output seen before the silence, if any
+    pub last_line: Option<String>,
+}
+
+/// Format a duration for humans, to the second
+fn human(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m {s}s"),
+        (h, m, _) => format!("{h}h {m}m"),
+    }
+}
+
+/// Report that a command is still running, and how far along it is if that is known, with
+/// the first enabled reporting backend, or on stderr if there is none
+fn heartbeat(elapsed: Duration, pid: u32, progress: Option<f64>) {
+    let mut message = format!("still running after {}, pid {pid}", human(elapsed));
+    if let Some(percent) = progress {
+        message.push_str(&format!(", {percent:.0}% done"));
+    }
+
+    #[cfg(feature = "tracing")]
+    tracing::info!("{message}");
+    #[cfg(all(feature = "log", not(feature = "tracing")))]
+    log::info!("{message}");
+    #[cfg(not(any(feature = "log", feature = "tracing")))]
+    eprintln!("{message}");
+}
+
+#[derive(Debug)]
+/// A command whose output is watched while it runs
+pub struct CommandWatch<'a> {
+    command: &'a mut Command,
+    watchdogs: Vec<Watchdog>,
+    inactivity_timeout: Option<Duration>,
+    heartbeat: Option<Duration>,
+    progress: Progress,
+    triggered: Option<Triggered>,
+    inactive: Option<Inactivity>,
+    last_execution: Option<ExecutionInfo>,
+}
+
+impl<'a> CommandWatch<'a> {
+    /// Kill the command as soon as a line of its output matches `pattern`
+    pub fn kill_if_output_matches<P>(&mut self, pattern: P) -> &mut Self
+    where
+        P: OutputPattern + 'static,
+    {
+        self.watchdogs.push(Watchdog {
+            pattern: Box::new(pattern),
+            action: Action::Kill,
+        });
+        self
+    }
+
+    /// Fail checking the command if a line of its output matches `pattern`, even if it
+    /// exits successfully
+    pub fn fail_if_output_matches<P>(&mut self, pattern: P) -> &mut Self
+    where
+        P: OutputPattern + 'static,
+    {
+        self.watchdogs.push(Watchdog {
+            pattern: Box::new(pattern),
+            action: Action::Fail,
+        });
+        self
+    }
+
+    /// Kill the command if it produces no output on either stream for `timeout`. The
+    /// timeout restarts whenever the command produces output.
+    pub fn inactivity_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.inactivity_timeout = Some(timeout);
+        self
+    }
+
+    /// Report that the command is still running every `period` until it exits, so that CI
+    /// systems which kill jobs without output do not kill it while its output is captured.
+    /// Reports are emitted at the info level with `tracing` or `log`, or written to stderr
+    /// if neither is enabled.
+    pub fn heartbeat(&mut self, period: Duration) -> &mut Self {
+        self.heartbeat = Some(period);
+        self
+    }
+
+    /// Parse a progress percentage from each line of output with `parser`, which returns
+    /// `None` for lines which do not report progress. Lines redrawn with carriage returns
+    /// are parsed as they are drawn. Each new percentage is passed to the callbacks
+    /// registered with [`CommandWatch::on_progress`], for example to drive a progress bar,
+    /// and the latest one is included in heartbeats.
+    ///
+    /// ```rust
+    /// # use std::process::Command;
+    /// # use command_ext::{CommandExtWatch, CommandWrap};
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut command = Command::new("bash");
+    /// command.args(["-c", "echo 'Downloading 50%'; echo 'Downloading 100%'"]);
+    /// let mut watch = command.progress_from(|line| {
+    ///     line.strip_suffix('%')?.rsplit(' ').next()?.parse().ok()
+    /// });
+    /// watch.on_progress(|percent| println!("{percent}%"));
+    /// watch.output()?;
+    /// assert_eq!(watch.progress(), Some(100.0));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn progress_from<F>(&mut self, parser: F) -> &mut Self
+    where
+        F: FnMut(&str) -> Option<f64> + Send + 'static,
+    {
+        self.progress.parser = Some(Box::new(parser));
+        self
+    }
+
+    /// Call `callback` with each new progress percentage parsed by the parser set with
+    /// [`CommandWatch::progress_from`]
+    pub fn on_progress<F>(&mut self, callback: F) -> &mut Self
+    where
+        F: FnMut(f64) + Send + 'static,
+    {
+        self.progress.callbacks.push(Box::new(callback));
+        self
+    }
+
+    /// The latest progress percentage parsed during the last execution, if any
+    pub fn progress(&self) -> Option<f64> {
+        self.progress.last
+    }
+
+    /// The patterns which kill the command when a line of its output matches
+    pub fn get_kill_patterns(&self) -> Vec<String> {
+        self.patterns(Action::Kill)
+    }
+
+    /// The patterns which fail the command when a line of its output matches
+    pub fn get_fail_patterns(&self) -> Vec<String> {
+        self.patterns(Action::Fail)
+    }
+
+    fn patterns(&self, action: Action) -> Vec<String> {
+        self.watchdogs
+            .iter()
+            .filter(|w| w.action == action)
+            .map(|w| w.pattern.describe())
+            .collect()
+    }
+
+    /// How long the command may stay silent before it is killed, if it is limited
+    pub fn get_inactivity_timeout(&self) -> Option<Duration> {
+        self.inactivity_timeout
+    }
+
+    /// How often a heartbeat is reported while the command runs, if it is
+    pub fn get_heartbeat(&self) -> Option<Duration> {
+        self.heartbeat
+    }
+
+    /// The first watchdog triggered during the last execution, if any
+    pub fn triggered(&self) -> Option<&Triggered> {
+        self.triggered.as_ref()
+    }
+
+    /// How the last execution was killed for inactivity, if it was
+    pub fn inactivity(&self) -> Option<&Inactivity> {
+        self.inactive.as_ref()
+    }
+
+    /// Check a complete line against the watchdogs, returning whether to kill the command
+    fn inspect(&mut self, stream: Stream, line: &str) -> bool {
+        if self.triggered.is_some() {
+            return false;
+        }
+
+        let Some(watchdog) = self.watchdogs.iter().find(|w| w.pattern.matches(line)) else {
+            return false;
+        };
+
+        let killed = watchdog.action == Action::Kill;
+        self.triggered = Some(Triggered {
+            pattern: watchdog.pattern.describe(),
+            line: line.to_string(),
+            stream,
+            killed,
+        });
+        killed
+    }
+
+    /// Stream the output of `child` through the watchdogs until it closes its output or is
+    /// killed, forwarding it to the parent's output streams if `forward` is set and
+    /// returning it otherwise
+    fn watch(&mut self, child: &mut Child, forward: bool) -> IoResult<(Vec<u8>, Vec<u8>)> {
+        let pipes = Pipes::take(child);
+        let mut captured = (Vec::new(), Vec::new());
+        let mut stdout_lines = Lines::default();
+        let mut stderr_lines = Lines::default();
+        let clock = clock::current();
+        let start = clock.now();
+        let mut last_output = start;
+        let mut last_line = None;
+        let mut next_heartbeat = self.heartbeat.map(|period| start + period);
+
+        loop {
+            let now = clock.now();
+
+            if let (Some(period), Some(due)) = (self.heartbeat, next_heartbeat) {
+                if now >= due {
+                    heartbeat(now - start, child.id(), self.progress.last);
+                    next_heartbeat = Some(due + period);
+                }
+            }
+
+            let inactive_at = self.inactivity_timeout.map(|timeout| last_output + timeout);
+            if inactive_at.is_some_and(|at| now >= at) {
+                spawn::kill_group(child);
+                self.inactive = Some(Inactivity {
+                    silence: now - last_output,
+                    last_line,
+                });
+                return Ok(captured);
+            }
+
+            let next = match inactive_at.into_iter().chain(next_heartbeat).min() {
+                Some(wake_at) => match pipes
+                    .recv_timeout(clock.poll_interval(wake_at.saturating_duration_since(now)))
+                {
+                    Ok(next) => next,
+                    Err(_) => continue,
+                },
+                None => pipes.recv(),
+            };
+
+            let Some((stream, chunk)) = next else {
+                break;
+            };
+            let chunk = chunk?;
+            last_output = clock.now();
+
+            if forward {
+                match stream {
+                    Stream::Stdout => stdout().write_all(&chunk),
+                    Stream::Stderr => stderr().write_all(&chunk),
+                }
+                .ok();
+            } else {
+                match stream {
+                    Stream::Stdout => captured.0.extend_from_slice(&chunk),
+                    Stream::Stderr => captured.1.extend_from_slice(&chunk),
+                }
+            }
+
+            let lines = match stream {
+                Stream::Stdout => stdout_lines.push(&chunk),
+                Stream::Stderr => stderr_lines.push(&chunk),
+            };
+
+            let partial = match stream {
+                Stream::Stdout => stdout_lines.finish(),
+                Stream::Stderr => stderr_lines.finish(),
+            };
+            lines
+                .iter()
+                .chain(&partial)
+                .for_each(|l| self.progress.parse(l));
+            last_line = partial.or_else(|| lines.last().cloned()).or(last_line);
+
+            if lines.iter().any(|line| self.inspect(stream, line)) {
+                // Descendants of the child may keep the pipes open, so stop reading
+                // rather than waiting for them to close
+                spawn::kill_group(child);
+                return Ok(captured);
+            }
+        }
+
+        [
+            (Stream::Stdout, stdout_lines.finish()),
+            (Stream::Stderr, stderr_lines.finish()),
+        ]
+        .into_iter()
+        .filter_map(|(stream, line)| line.map(|line| (stream, line)))
+        .for_each(|(stream, line)| {
+            // The streams are closed, so there is nothing left to kill
+            self.inspect(stream, &line);
+        });
+
+        Ok(captured)
+    }
+
+    fn execute(&mut self, forward: bool) -> IoResult<Output> {
+        let _execution = metrics::execution(self.command);
+        self.triggered = None;
+        self.inactive = None;
+        self.progress.last = None;
+        let mut pid = None;
+
+        spawn::isolate(self.command);
+        let (output, info) = ExecutionInfo::measure(|| {
+            let mut child = self
+                .command
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?;
+            pid = Some(child.id());
+            let streams = self.watch(&mut child, forward);
+            let status = child.wait()?;
+            let (stdout, stderr) = streams?;
+
+            Ok(Output {
+                status,
+                stdout,
+                stderr,
+            })
+        });
+
+        self.last_execution = Some(ExecutionInfo {
+            pid,
+            ..info.with_output(&output)
+        });
+        output
+    }
+}
+
+impl<'a> HasCommand for CommandWatch<'a> {
+    fn command(&self) -> &Command {
+        self.command
+    }
+
+    fn command_mut(&mut self) -> &mut Command {
+        self.command
+    }
+}
+
+impl<'a> CommandWrap for CommandWatch<'a> {
+    fn last_execution(&self) -> Option<ExecutionInfo> {
+        self.last_execution
+    }
+
+    fn reads_output(&self) -> bool {
+        true
+    }
+
+    fn describe(&self) -> Option<String> {
+        let mut parts = self
+            .watchdogs
+            .iter()
+            .map(|w| match w.action {
+                Action::Kill => format!("kill on {}", w.pattern.describe()),
+                Action::Fail => format!("fail on {}", w.pattern.describe()),
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(timeout) = self.inactivity_timeout {
+            parts.push(format!("kill after {} of silence", human(timeout)));
+        }
+
+        if let Some(interval) = self.heartbeat {
+            parts.push(format!("heartbeat every {}", human(interval)));
+        }
+
+        if self.progress.parser.is_some() {
+            parts.push("parse progress".to_string());
+        }
+
+        Some(format!("watch ({})", parts.join(", ")))
+    }
+
+    fn output(&mut self) -> IoResult<Output> {
+        self.execute(false)
+    }
+
+    fn status(&mut self) -> IoResult<ExitStatus> {
+        self.execute(true).map(|output| output.status)
+    }
+}
+
+impl<'a> From<&'a mut Command> for CommandWatch<'a> {
+    fn from(value: &'a mut Command) -> Self {
+        Self {
+            command: value,
+            watchdogs: Vec::new(),
+            inactivity_timeout: None,
+            heartbeat: None,
+            progress: Progress::default(),
+            triggered: None,
+            inactive: None,
+            last_execution: None,
+        }
+    }
+}
+
+pub trait CommandExtWatch {
+    fn kill_if_output_matches<P>(&mut self, pattern: P) -> CommandWatch<'_>
+    where
+        P: OutputPattern + 'static;
+    fn fail_if_output_matches<P>(&mut self, pattern: P) -> CommandWatch<'_>
+    where
+        P: OutputPattern + 'static;
+    fn inactivity_timeout(&mut self, timeout: Duration) -> CommandWatch<'_>;
+    fn heartbeat(&mut self, period: Duration) -> CommandWatch<'_>;
+    fn progress_from<F>(&mut self, parser: F) -> CommandWatch<'_>
+    where
+        F: FnMut(&str) -> Option<f64> + Send + 'static;
+}
+
+impl CommandExtWatch for Command {
+    fn kill_if_output_matches<P>(&mut self, pattern: P) -> CommandWatch<'_>
+    where
+        P: OutputPattern + 'static,
+    {
+        let mut watch = CommandWatch::from(self);
+        watch.kill_if_output_matches(pattern);
+        watch
+    }
+
+    fn fail_if_output_matches<P>(&mut self, pattern: P) -> CommandWatch<'_>
+    where
+        P: OutputPattern + 'static,
+    {
+        let mut watch = CommandWatch::from(self);
+        watch.fail_if_output_matches(pattern);
+        watch
+    }
+
+    fn inactivity_timeout(&mut self, timeout: Duration) -> CommandWatch<'_> {
+        let mut watch = CommandWatch::from(self);
+        watch.inactivity_timeout(timeout);
+        watch
+    }
+
+    fn heartbeat(&mut self, period: Duration) -> CommandWatch<'_> {
+        let mut watch = CommandWatch::from(self);
+        watch.heartbeat(period);
+        watch
+    }
+
+    fn progress_from<F>(&mut self, parser: F) -> CommandWatch<'_>
+    where
+        F: FnMut(&str) -> Option<f64> + Send + 'static,
+    {
+        let mut watch = CommandWatch::from(self);
+        watch.progress_from(parser);
+        watch
+    }
+}
+
+#[cfg(feature = "check")]
+impl<'a> CommandExtCheck for CommandWatch<'a> {
+    type Error = CommandExtError;
+
+    fn check(&mut self) -> Result<Output, Self::Error> {
+        preflight(self.command())?;
+        let output = self.output();
+        let partial = || match &output {
+            Ok(output) => (
+                String::from_utf8_lossy(&output.stdout).into_owned(),
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ),
+            Err(_) => Default::default(),
+        };
+
+        if let Some(inactivity) = self.inactive.take() {
+            let (stdout, stderr) = partial();
+            return Err(CommandExtError::Inactive {
+                silence: inactivity.silence,
+                last_line: inactivity.last_line,
+                stdout,
+                stderr,
+            });
+        }
+
+        match self.triggered.take() {
+            Some(triggered) => {
+                let (stdout, stderr) = partial();
+                Err(CommandExtError::OutputMatched {
+                    pattern: triggered.pattern,
+                    line: triggered.line,
+                    killed: triggered.killed,
+                    stdout,
+                    stderr,
+                })
+            }
+            None => check_with(self, output),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        process::Command,
+        time::{Duration, Instant},
+    };
+
+    use super::{human, Lines, Stream};
+    use crate::{CommandExtWatch, CommandWrap};
+
+    #[test]
+    fn test_lines() {
+        let mut lines = Lines::default();
+        assert!(lines.push(b"ab").is_empty());
+        assert_eq!(lines.push(b"c\r\nd\ne"), ["abc", "d"]);
+        assert_eq!(lines.finish().as_deref(), Some("e"));
+        assert_eq!(lines.pending, b"e");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_kill() -> anyhow::Result<()> {
+        let start = Instant::now();
+        let mut command = Command::new("bash");
+        command.args(["-c", "echo starting; echo 'error: hung' >&2; sleep 30"]);
+        let mut watch = command.kill_if_output_matches("error:");
+        let output = watch.output()?;
+
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(!output.status.success());
+        let triggered = watch.triggered().expect("watchdog triggered");
+        assert_eq!(triggered.stream, Stream::Stderr);
+        assert!(triggered.killed);
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    #[cfg_attr(miri, ignore)]
+    /// Test that killing a watched command kills its descendants too
+    fn test_kill_group() -> anyhow::Result<()> {
+        let mut command = Command::new("sh");
+        command.args([
+            "-c",
+            "sleep 30 & echo $!; sleep 0.1; echo 'error: hung' >&2; wait",
+        ]);
+        let output = command.kill_if_output_matches("error:").output()?;
+        let pid = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+        let start = Instant::now();
+        let running = || {
+            std::fs::read_to_string(format!("/proc/{pid}/stat"))
+                .is_ok_and(|stat| !stat.contains(") Z"))
+        };
+        while running() && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!running());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(feature = "check")]
+    #[cfg_attr(miri, ignore)]
+    fn test_fail() {
+        use crate::{CommandExtCheck, CommandExtError};
+
+        let error = Command::new("printf")
+            .arg("ok\nERROR at end")
+            .fail_if_output_matches("ERROR")
+            .check()
+            .unwrap_err();
+
+        assert_eq!(error.termination_reason(), None);
+        assert_eq!(error.partial_output(), Some(("ok\nERROR at end", "")));
+        assert!(matches!(
+            error,
+            CommandExtError::OutputMatched { line, killed: false, .. } if line == "ERROR at end"
+        ));
+
+        let error = Command::new("bash")
+            .args(["-c", "echo starting; echo 'error: hung' >&2; sleep 30"])
+            .kill_if_output_matches("error:")
+            .check()
+            .unwrap_err();
+        assert_eq!(
+            error.termination_reason(),
+            Some(crate::TerminationReason::Watchdog)
+        );
+        assert_eq!(
+            error.partial_output(),
+            Some(("starting\n", "error: hung\n"))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    #[cfg_attr(miri, ignore)]
+    fn test_regex() -> anyhow::Result<()> {
+        let mut command = Command::new("printf");
+        command.arg("E1 not this\nE42: this");
+        let mut watch = command.fail_if_output_matches(regex::Regex::new(r"^E\d+:")?);
+        watch.output()?;
+
+        assert_eq!(
+            watch.triggered().map(|t| t.line.as_str()),
+            Some("E42: this")
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_chatty_command_is_not_inactive() -> anyhow::Result<()> {
+        let mut command = Command::new("bash");
+        command.args(["-c", "for i in 1 2 3 4 5; do echo $i; sleep 0.1; done"]);
+        let mut watch = command.inactivity_timeout(Duration::from_millis(400));
+        let output = watch.output()?;
+
+        assert!(output.status.success());
+        assert!(watch.inactivity().is_none());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_inactivity() -> anyhow::Result<()> {
+        let mut command = Command::new("bash");
+        command.args(["-c", "printf 'partial'; sleep 30"]);
+        let mut watch = command.inactivity_timeout(Duration::from_millis(200));
+        watch.output()?;
+
+        let inactivity = watch.inactivity().expect("command was inactive");
+        assert!(inactivity.silence >= Duration::from_millis(200));
+        assert_eq!(inactivity.last_line.as_deref(), Some("partial"));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that inactivity is measured with the current clock rather than real time
+    fn test_inactivity_mock_clock() -> anyhow::Result<()> {
+        use std::{
+            sync::Arc,
+            thread::{sleep, spawn},
+        };
+
+        use crate::clock::{self, MockClock};
+
+        let mock = MockClock::new();
+        let advance = mock.clone();
+        let advancer = spawn(move || {
+            sleep(Duration::from_millis(100));
+            advance.advance(Duration::from_secs(7200));
+        });
+
+        let mut command = Command::new("bash");
+        command.args(["-c", "printf 'partial'; sleep 30"]);
+        let mut watch = command.inactivity_timeout(Duration::from_secs(3600));
+        clock::with(Arc::new(mock), || watch.output())?;
+        advancer.join().ok();
+
+        let inactivity = watch.inactivity().expect("command was inactive");
+        assert!(inactivity.silence >= Duration::from_secs(3600));
+        Ok(())
+    }
+
+    #[test]
+    fn test_human() {
+        assert_eq!(human(Duration::from_millis(12500)), "12s");
+        assert_eq!(human(Duration::from_secs(300)), "5m 0s");
+        assert_eq!(human(Duration::from_secs(7260)), "2h 1m");
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn test_heartbeat() -> anyhow::Result<()> {
+        let output = Command::new("bash")
+            .args(["-c", "sleep 0.3; echo done"])
+            .heartbeat(Duration::from_millis(50))
+            .inactivity_timeout(Duration::from_secs(10))
+            .output()?;
+
+        assert_eq!(output.stdout, b"done\n");
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that progress is parsed from complete and redrawn lines on either stream
+    fn test_progress() -> anyhow::Result<()> {
+        use std::sync::{Arc, Mutex};
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut command = Command::new("bash");
+        command.args([
+            "-c",
+            "echo 10; echo x; echo 10; sleep 0.1; printf '30\\r40\\r' >&2; sleep 0.2; echo 250",
+        ]);
+        let mut watch = command.progress_from(|line| line.trim().parse().ok());
+        let callback = seen.clone();
+        watch.on_progress(move |p| callback.lock().unwrap().push(p));
+        watch.output()?;
+
+        assert_eq!(*seen.lock().unwrap(), [10.0, 40.0, 100.0]);
+        assert_eq!(watch.progress(), Some(100.0));
+        Ok(())
+    }
+}
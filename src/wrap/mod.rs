@@ -1,14 +1,243 @@
 use std::{
+    any::Any,
     ffi::OsStr,
+    io::{Error, ErrorKind, Result as IoResult},
+    ops::ControlFlow,
+    panic::{self, AssertUnwindSafe},
     path::Path,
     process::{Child, Command, CommandArgs, CommandEnvs, ExitStatus, Output, Stdio},
+    time::{Duration, SystemTime},
 };
 
+use crate::{
+    clock,
+    metrics::{self, Category},
+    CommandExtError,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Information about a single execution of a command, passed to the hooks which run after
+/// it finishes and available afterwards from [`CommandWrap::last_execution`]
+pub struct ExecutionInfo {
+    /// The wall-clock time at which the command was started
+    pub started_at: SystemTime,
+    /// How long the command took to run, including waiting for its output
+    pub duration: Duration,
+    /// The process ID of the child, when it is known. Commands executed with the standard
+    /// library's `output` and `status` do not expose it.
+    pub pid: Option<u32>,
+    /// The exit status of the command, if it ran to completion
+    pub status: Option<ExitStatus>,
+    /// The number of bytes captured from stdout, if it was captured
+    pub stdout_len: Option<usize>,
+    /// The number of bytes captured from stderr, if it was captured
+    pub stderr_len: Option<usize>,
+}
+
+impl ExecutionInfo {
+    /// Run `f`, recording when it started and how long it took
+    pub fn measure<T, F>(f: F) -> (T, Self)
+    where
+        F: FnOnce() -> T,
+    {
+        let clock = clock::current();
+        let started_at = clock.system_now();
+        let start = clock.now();
+        let result = f();
+
+        (
+            result,
+            Self {
+                started_at,
+                duration: clock.now().saturating_duration_since(start),
+                pid: None,
+                status: None,
+                stdout_len: None,
+                stderr_len: None,
+            },
+        )
+    }
+
+    /// Record the status and capture sizes of `output`
+    pub fn with_output<E>(mut self, output: &Result<Output, E>) -> Self {
+        if let Ok(output) = output {
+            self.status = Some(output.status);
+            self.stdout_len = Some(output.stdout.len());
+            self.stderr_len = Some(output.stderr.len());
+        }
+        self
+    }
+
+    /// Record `status`
+    pub fn with_status<E>(mut self, status: &Result<ExitStatus, E>) -> Self {
+        self.status = status.as_ref().ok().copied();
+        self
+    }
+}
+
+/// Run a hook, catching a panic in it so the caller can clean up before reporting it
+fn run_hook<T>(f: impl FnOnce() -> T) -> Result<T, Box<dyn Any + Send>> {
+    metrics::measure(Category::Hooks, || panic::catch_unwind(AssertUnwindSafe(f)))
+}
+
+/// Report a panic caught in `hook` as an error, or continue unwinding if the wrapper does not
+/// catch hook panics
+fn hook_panicked(catch: bool, hook: &str, payload: Box<dyn Any + Send>) -> Error {
+    if !catch {
+        panic::resume_unwind(payload);
+    }
+
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|m| m.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Box<dyn Any>".to_string());
+
+    crate::CommandExtError::HookPanicked {
+        hook: hook.to_string(),
+        message,
+    }
+    .into()
+}
+
 pub trait HasCommand {
     fn command(&self) -> &Command;
     fn command_mut(&mut self) -> &mut Command;
 }
 
+impl HasCommand for Command {
+    fn command(&self) -> &Command {
+        self
+    }
+
+    fn command_mut(&mut self) -> &mut Command {
+        self
+    }
+}
+
+/// A command, or any wrapper around one, which layers such as [`crate::retry`] and
+/// [`crate::timeout`] execute. Layers are generic over this trait, so they stack on a plain
+/// [`Command`], on every [`CommandWrap`], and on each other in any order.
+///
+/// Wrappers are not generic over it: the reporting wrappers of [`crate::log`],
+/// [`crate::print`] and [`crate::trace`], like the others, borrow a plain [`Command`]. They
+/// therefore go innermost, with layers applied to the wrapper:
+/// `command.log_status(level).timeout(duration)` rather than
+/// `command.timeout(duration).log_status(level)`.
+pub trait Execute: HasCommand {
+    /// Spawn the command, like [`CommandWrap::spawn`]
+    fn execute_spawn(&mut self) -> IoResult<Child>;
+
+    /// Execute the command and collect its output, like [`CommandWrap::output`]
+    fn execute_output(&mut self) -> IoResult<Output>;
+
+    /// Execute the command and collect its status, like [`CommandWrap::status`]
+    fn execute_status(&mut self) -> IoResult<ExitStatus>;
+
+    /// Execute the command and collect its output the way checking it does, which for a
+    /// plain [`Command`] reports it with the ambient [defaults](crate::defaults)
+    fn execute_checked(&mut self) -> IoResult<Output> {
+        self.execute_output()
+    }
+
+    /// Finish an execution which a layer spawned with [`Execute::execute_spawn`] and waited
+    /// for itself, running the hooks a wrapper runs after [`CommandWrap::output`]
+    fn finish_output(&mut self, output: IoResult<Output>, info: &ExecutionInfo)
+        -> IoResult<Output>;
+
+    /// Finish an execution which a layer spawned with [`Execute::execute_spawn`] and waited
+    /// for itself, running the hooks a wrapper runs after [`CommandWrap::status`]
+    fn finish_status(
+        &mut self,
+        status: IoResult<ExitStatus>,
+        info: &ExecutionInfo,
+    ) -> IoResult<ExitStatus>;
+
+    /// Run the hooks a wrapper runs when its output is checked, see
+    /// [`CommandWrap::on_check`]
+    fn finish_check(&mut self, output: &Output) -> Option<CommandExtError>;
+}
+
+impl Execute for Command {
+    fn execute_spawn(&mut self) -> IoResult<Child> {
+        self.spawn()
+    }
+
+    fn execute_output(&mut self) -> IoResult<Output> {
+        self.output()
+    }
+
+    fn execute_status(&mut self) -> IoResult<ExitStatus> {
+        self.status()
+    }
+
+    fn execute_checked(&mut self) -> IoResult<Output> {
+        #[cfg(any(feature = "log", feature = "print", feature = "tracing"))]
+        return crate::defaults::output(self);
+        #[cfg(not(any(feature = "log", feature = "print", feature = "tracing")))]
+        return self.output();
+    }
+
+    fn finish_output(&mut self, output: IoResult<Output>, _: &ExecutionInfo) -> IoResult<Output> {
+        output
+    }
+
+    fn finish_status(
+        &mut self,
+        status: IoResult<ExitStatus>,
+        _: &ExecutionInfo,
+    ) -> IoResult<ExitStatus> {
+        status
+    }
+
+    fn finish_check(&mut self, _: &Output) -> Option<CommandExtError> {
+        None
+    }
+}
+
+impl<T> Execute for T
+where
+    T: CommandWrap,
+{
+    fn execute_spawn(&mut self) -> IoResult<Child> {
+        CommandWrap::spawn(self)
+    }
+
+    fn execute_output(&mut self) -> IoResult<Output> {
+        CommandWrap::output(self)
+    }
+
+    fn execute_status(&mut self) -> IoResult<ExitStatus> {
+        CommandWrap::status(self)
+    }
+
+    fn finish_output(
+        &mut self,
+        output: IoResult<Output>,
+        info: &ExecutionInfo,
+    ) -> IoResult<Output> {
+        metrics::measure(Category::Hooks, || {
+            self.after_output(&output, info);
+            self.map_output(output, info)
+        })
+    }
+
+    fn finish_status(
+        &mut self,
+        status: IoResult<ExitStatus>,
+        info: &ExecutionInfo,
+    ) -> IoResult<ExitStatus> {
+        metrics::measure(Category::Hooks, || {
+            self.after_status(&status, info);
+            self.map_status(status, info)
+        })
+    }
+
+    fn finish_check(&mut self, output: &Output) -> Option<CommandExtError> {
+        metrics::measure(Category::Hooks, || self.on_check(output))
+    }
+}
+
 pub trait CommandWrap: HasCommand {
     #[allow(unused)]
     #[inline(always)]
@@ -76,6 +305,15 @@ pub trait CommandWrap: HasCommand {
     /// Called when stderr is set using [`stderr`]
     fn on_stderr(&mut self, cfg: &Stdio) {}
 
+    #[inline(always)]
+    /// Whether a panic in the `after_*` or `map_*` hooks is caught and returned as a
+    /// [`crate::CommandExtError::HookPanicked`] error. Either way, a child spawned by
+    /// [`spawn`] is killed and waited for before the panic is reported, so it is not leaked;
+    /// when this returns false the panic then continues to unwind.
+    fn catch_hook_panics(&self) -> bool {
+        true
+    }
+
     #[allow(unused)]
     #[inline(always)]
     /// Called when the child process is spawned using [`spawn`]
@@ -91,6 +329,45 @@ pub trait CommandWrap: HasCommand {
     /// Called when status is obtained using [`status`]
     fn on_status(&mut self) {}
 
+    #[inline(always)]
+    /// Information about the last time this wrapper executed its command with [`output`] or
+    /// [`status`], if it records it
+    fn last_execution(&self) -> Option<ExecutionInfo> {
+        None
+    }
+
+    #[inline(always)]
+    /// A short description of how this wrapper changes the execution of its command, if it
+    /// does, for example when explaining the command
+    fn describe(&self) -> Option<String> {
+        None
+    }
+
+    #[inline(always)]
+    /// Whether this wrapper writes to the standard input of its command, which must then
+    /// not be configured as anything other than piped
+    fn writes_stdin(&self) -> bool {
+        false
+    }
+
+    #[inline(always)]
+    /// Whether this wrapper reads the output streams of its command, which must then not be
+    /// configured as anything other than piped
+    fn reads_output(&self) -> bool {
+        false
+    }
+
+    #[allow(unused)]
+    #[inline(always)]
+    /// Called after the `on_*` hook for [`spawn`], [`output`] or [`status`], just before the
+    /// command would execute. Returning [`ControlFlow::Break`] skips executing the command and
+    /// uses the given output as its result instead, which lets caching, dry-run and mocking
+    /// wrappers return synthetic results. Since no child exists in that case, [`spawn`]
+    /// fails with [`ErrorKind::Unsupported`].
+    fn before_execute(&mut self) -> ControlFlow<Output> {
+        ControlFlow::Continue(())
+    }
+
     #[allow(unused)]
     #[inline(always)]
     /// Called when the child process is spawned using [`spawn`]
@@ -99,12 +376,42 @@ pub trait CommandWrap: HasCommand {
     #[allow(unused)]
     #[inline(always)]
     /// Called when output is created using [`output`]
-    fn after_output(&mut self, output: &std::io::Result<Output>) {}
+    fn after_output(&mut self, output: &IoResult<Output>, info: &ExecutionInfo) {}
 
     #[allow(unused)]
     #[inline(always)]
     /// Called when status is obtained using [`status`]
-    fn after_status(&mut self, status: &std::io::Result<ExitStatus>) {}
+    fn after_status(&mut self, status: &IoResult<ExitStatus>, info: &ExecutionInfo) {}
+
+    #[allow(unused)]
+    #[inline(always)]
+    /// Called after [`after_output`] to transform the result of [`output`], for example to
+    /// annotate an error or to accept a failure as success
+    fn map_output(&mut self, output: IoResult<Output>, info: &ExecutionInfo) -> IoResult<Output> {
+        output
+    }
+
+    #[allow(unused)]
+    #[inline(always)]
+    /// Called after [`after_status`] to transform the result of [`status`], for example to
+    /// annotate an error or to accept a failure as success
+    fn map_status(
+        &mut self,
+        status: IoResult<ExitStatus>,
+        info: &ExecutionInfo,
+    ) -> IoResult<ExitStatus> {
+        status
+    }
+
+    #[allow(unused)]
+    #[inline(always)]
+    /// Called with the output of the command when it is checked, before its status is.
+    /// Returning an error fails the check even if the command exited successfully, which
+    /// allows checks specific to a tool, such as failing when it warns on stderr. Returning
+    /// `None` leaves the decision to the status.
+    fn on_check(&mut self, output: &Output) -> Option<CommandExtError> {
+        None
+    }
 
     /// Adds an argument to pass to the program.
     ///
@@ -181,6 +488,8 @@ pub trait CommandWrap: HasCommand {
         I: IntoIterator<Item = S>,
         S: AsRef<OsStr>,
     {
+        let args = args.into_iter().collect::<Vec<_>>();
+        self.on_args(&args);
         self.command_mut().args(args);
         self
     }
@@ -466,10 +775,26 @@ pub trait CommandWrap: HasCommand {
     ///     .expect("ls command failed to start");
     /// ```
     fn spawn(&mut self) -> std::io::Result<Child> {
-        self.on_spawn();
-        let child = self.command_mut().spawn();
-        self.after_spawn(&child);
-        child
+        let _execution = metrics::execution(self.command());
+        metrics::measure(Category::Hooks, || self.on_spawn());
+        let child = match metrics::measure(Category::Hooks, || self.before_execute()) {
+            ControlFlow::Break(_) => Err(Error::new(
+                ErrorKind::Unsupported,
+                "execution was short-circuited, so no child was spawned",
+            )),
+            ControlFlow::Continue(()) => self.command_mut().spawn(),
+        };
+        let catch = self.catch_hook_panics();
+        match run_hook(|| self.after_spawn(&child)) {
+            Ok(()) => child,
+            Err(payload) => {
+                if let Ok(mut child) = child {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                }
+                Err(hook_panicked(catch, "after_spawn", payload))
+            }
+        }
     }
 
     /// Executes the command as a child process, waiting for it to finish and
@@ -497,10 +822,20 @@ pub trait CommandWrap: HasCommand {
     /// assert!(output.status.success());
     /// ```
     fn output(&mut self) -> std::io::Result<Output> {
-        self.on_output();
-        let output = self.command_mut().output();
-        self.after_output(&output);
-        output
+        let _execution = metrics::execution(self.command());
+        metrics::measure(Category::Hooks, || self.on_output());
+        let (output, info) = ExecutionInfo::measure(|| {
+            match metrics::measure(Category::Hooks, || self.before_execute()) {
+                ControlFlow::Break(output) => Ok(output),
+                ControlFlow::Continue(()) => self.command_mut().output(),
+            }
+        });
+        let info = info.with_output(&output);
+        let catch = self.catch_hook_panics();
+        run_hook(|| self.after_output(&output, &info))
+            .map_err(|p| hook_panicked(catch, "after_output", p))?;
+        run_hook(|| self.map_output(output, &info))
+            .unwrap_or_else(|p| Err(hook_panicked(catch, "map_output", p)))
     }
 
     /// Executes a command as a child process, waiting for it to finish and
@@ -523,10 +858,20 @@ pub trait CommandWrap: HasCommand {
     /// assert!(status.success());
     /// ```
     fn status(&mut self) -> std::io::Result<ExitStatus> {
-        self.on_status();
-        let status = self.command_mut().status();
-        self.after_status(&status);
-        status
+        let _execution = metrics::execution(self.command());
+        metrics::measure(Category::Hooks, || self.on_status());
+        let (status, info) = ExecutionInfo::measure(|| {
+            match metrics::measure(Category::Hooks, || self.before_execute()) {
+                ControlFlow::Break(output) => Ok(output.status),
+                ControlFlow::Continue(()) => self.command_mut().status(),
+            }
+        });
+        let info = info.with_status(&status);
+        let catch = self.catch_hook_panics();
+        run_hook(|| self.after_status(&status, &info))
+            .map_err(|p| hook_panicked(catch, "after_status", p))?;
+        run_hook(|| self.map_status(status, &info))
+            .unwrap_or_else(|p| Err(hook_panicked(catch, "map_status", p)))
     }
 
     /// Returns the path to the program that was given to [`Command::new`].
@@ -617,3 +962,191 @@ pub trait CommandWrap: HasCommand {
         self.command().get_current_dir()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Error, ErrorKind, Result as IoResult},
+        ops::ControlFlow,
+        panic::{self, AssertUnwindSafe},
+        process::{Child, Command, ExitStatus, Output},
+    };
+
+    use super::{CommandWrap, ExecutionInfo, HasCommand};
+
+    struct Annotate<'a> {
+        command: &'a mut Command,
+        info: Option<ExecutionInfo>,
+    }
+
+    impl HasCommand for Annotate<'_> {
+        fn command(&self) -> &Command {
+            self.command
+        }
+
+        fn command_mut(&mut self) -> &mut Command {
+            self.command
+        }
+    }
+
+    impl CommandWrap for Annotate<'_> {
+        fn after_output(&mut self, _output: &IoResult<Output>, info: &ExecutionInfo) {
+            self.info = Some(*info);
+        }
+
+        fn map_output(
+            &mut self,
+            output: IoResult<Output>,
+            _info: &ExecutionInfo,
+        ) -> IoResult<Output> {
+            #[cfg(unix)]
+            use std::os::unix::process::ExitStatusExt;
+
+            match output {
+                // grep exits with 1 when nothing matched, which is fine here
+                #[cfg(unix)]
+                Ok(mut output) if output.status.code() == Some(1) => {
+                    output.status = ExitStatusExt::from_raw(0);
+                    Ok(output)
+                }
+                Err(e) => Err(Error::new(e.kind(), format!("while searching: {e}"))),
+                output => output,
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    #[cfg_attr(miri, ignore)]
+    /// Test that the output hook sees the result and its execution info
+    fn test_map_output() -> anyhow::Result<()> {
+        let mut command = Command::new("grep");
+        command.args(["x", "/dev/null"]);
+        let mut wrapper = Annotate {
+            command: &mut command,
+            info: None,
+        };
+
+        assert!(wrapper.output()?.status.success());
+        assert!(wrapper.info.is_some());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    /// Test that the output hook can replace an error
+    fn test_map_error() {
+        let mut command = Command::new("asdfasdfasdfasdfjkljkljkl");
+        let error = Annotate {
+            command: &mut command,
+            info: None,
+        }
+        .output()
+        .unwrap_err();
+
+        assert!(error.to_string().starts_with("while searching: "));
+    }
+
+    struct Mock<'a> {
+        command: &'a mut Command,
+    }
+
+    impl HasCommand for Mock<'_> {
+        fn command(&self) -> &Command {
+            self.command
+        }
+
+        fn command_mut(&mut self) -> &mut Command {
+            self.command
+        }
+    }
+
+    impl CommandWrap for Mock<'_> {
+        fn before_execute(&mut self) -> ControlFlow<Output> {
+            ControlFlow::Break(Output {
+                status: ExitStatus::default(),
+                stdout: b"mocked".to_vec(),
+                stderr: Vec::new(),
+            })
+        }
+    }
+
+    #[test]
+    /// Test that a wrapper can answer in place of the command without running it
+    fn test_before_execute() -> anyhow::Result<()> {
+        let mut command = Command::new("asdfasdfasdfasdfjkljkljkl");
+        let mut mock = Mock {
+            command: &mut command,
+        };
+
+        assert_eq!(mock.output()?.stdout, b"mocked");
+        assert!(mock.status()?.success());
+        assert_eq!(
+            mock.spawn().map(|_| ()).unwrap_err().kind(),
+            ErrorKind::Unsupported
+        );
+        Ok(())
+    }
+
+    struct Panicking<'a> {
+        command: &'a mut Command,
+        catch: bool,
+        pid: Option<u32>,
+    }
+
+    impl HasCommand for Panicking<'_> {
+        fn command(&self) -> &Command {
+            self.command
+        }
+
+        fn command_mut(&mut self) -> &mut Command {
+            self.command
+        }
+    }
+
+    impl CommandWrap for Panicking<'_> {
+        fn catch_hook_panics(&self) -> bool {
+            self.catch
+        }
+
+        fn after_spawn(&mut self, child: &IoResult<Child>) {
+            self.pid = child.as_ref().ok().map(|c| c.id());
+            panic!("hook failed");
+        }
+
+        fn after_output(&mut self, _output: &IoResult<Output>, _info: &ExecutionInfo) {
+            panic!("hook failed");
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    #[cfg_attr(miri, ignore)]
+    /// Test that a panicking hook is reported as an error and does not leak the child
+    fn test_hook_panic() {
+        let mut command = Command::new("sleep");
+        command.arg("30");
+        let mut wrapper = Panicking {
+            command: &mut command,
+            catch: true,
+            pid: None,
+        };
+
+        let error = wrapper.spawn().map(|_| ()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Wrapper hook after_spawn panicked: hook failed"
+        );
+        let pid = wrapper.pid.expect("child was spawned");
+        assert!(!std::path::Path::new(&format!("/proc/{pid}")).exists());
+
+        let error = wrapper.output().unwrap_err();
+        assert!(error.to_string().contains("after_output"));
+
+        wrapper.catch = false;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| wrapper.spawn().map(|_| ())));
+        assert!(result.is_err());
+        let pid = wrapper.pid.expect("child was spawned");
+        assert!(!std::path::Path::new(&format!("/proc/{pid}")).exists());
+    }
+}
@@ -0,0 +1,117 @@
+//! Retries and timeouts stacked on every wrapper, and on each other in every order. The
+//! wrappers borrow a plain `Command`, so they are always innermost.
+
+#![cfg(all(
+    feature = "retry",
+    feature = "timeout",
+    feature = "log",
+    feature = "print",
+    feature = "tracing"
+))]
+
+use std::{process::Command, time::Duration};
+
+use command_ext::{
+    CommandExtCheck, CommandExtLog, CommandExtPrint, CommandExtRetry, CommandExtTimeout,
+    CommandExtTrace, CommandWrap, Execute, PrintBuffer,
+};
+use log::Level;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Execute `inner` with each method under each combination of layers, checking whether it
+/// succeeds. Returns the number of executions of the command.
+fn run_layers<W>(inner: &mut W, succeeds: bool) -> usize
+where
+    W: Execute,
+{
+    let mut executions = 0;
+
+    {
+        let mut retry = inner.retry(2);
+        assert_eq!(retry.check().is_ok(), succeeds);
+        assert_eq!(retry.output().unwrap().status.success(), succeeds);
+        assert_eq!(retry.status().unwrap().success(), succeeds);
+        executions += 3 * retry.attempts_made();
+    }
+
+    {
+        let mut timeout = inner.timeout(TIMEOUT);
+        assert_eq!(timeout.check().is_ok(), succeeds);
+        assert_eq!(timeout.output().unwrap().status.success(), succeeds);
+        assert_eq!(timeout.status().unwrap().success(), succeeds);
+        executions += 3;
+    }
+
+    {
+        let mut timeout = inner.timeout(TIMEOUT);
+        let mut retry = timeout.retry(2);
+        assert_eq!(retry.check().is_ok(), succeeds);
+        assert_eq!(retry.output().unwrap().status.success(), succeeds);
+        assert_eq!(retry.status().unwrap().success(), succeeds);
+        executions += 3 * retry.attempts_made();
+    }
+
+    {
+        let mut retry = inner.retry(2);
+        let mut timeout = retry.timeout(TIMEOUT);
+        assert_eq!(timeout.check().is_ok(), succeeds);
+        assert_eq!(timeout.output().unwrap().status.success(), succeeds);
+        assert_eq!(timeout.status().unwrap().success(), succeeds);
+        executions += 3;
+    }
+
+    executions
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_plain() {
+    assert_eq!(run_layers(&mut Command::new("true"), true), 12);
+    assert_eq!(run_layers(&mut Command::new("false"), false), 18);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_log() {
+    let mut command = Command::new("false");
+    let mut log = command.log_args(Level::Debug);
+    log.log_status(Level::Info);
+    assert_eq!(run_layers(&mut log, false), 18);
+    assert!(log.last_execution().is_some());
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_trace() {
+    let mut command = Command::new("true");
+    let mut trace = command.trace_status(tracing::Level::INFO);
+    assert_eq!(run_layers(&mut trace, true), 12);
+    assert!(trace.last_execution().is_some());
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_print() {
+    let buffer = PrintBuffer::new();
+    let mut command = Command::new("false");
+    let mut print = command.print_status();
+    print.print_to(buffer.clone());
+
+    let executions = run_layers(&mut print, false);
+    assert_eq!(buffer.contents().lines().count(), executions);
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+fn test_timeout_kills_each_attempt() {
+    let mut command = Command::new("sleep");
+    command.arg("10");
+    let mut print = command.print_args();
+    print.print_to(PrintBuffer::new());
+
+    let mut timeout = print.timeout(Duration::from_millis(50));
+    let mut retry = timeout.retry(3);
+    assert!(retry.check().is_err());
+    assert_eq!(retry.attempts_made(), 3);
+}
@@ -0,0 +1,77 @@
+//! The records the log wrapper emits, captured by a logger installed for this test binary
+//! only, since the unit tests log through `test-log`.
+
+#![cfg(feature = "log")]
+
+use std::{
+    process::Command,
+    sync::{Mutex, Once},
+};
+
+use command_ext::{CommandExtLog, CommandWrap};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// A logger which keeps the level and message of every record
+struct Capture(Mutex<Vec<(Level, String)>>);
+
+impl Log for Capture {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((record.level(), record.args().to_string()));
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+
+/// Install the capturing logger, returning the records emitted so far which start with
+/// `prefix`
+fn records(prefix: &str) -> Vec<(Level, String)> {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        log::set_logger(&CAPTURE).expect("no other logger is installed");
+        log::set_max_level(LevelFilter::Trace);
+    });
+
+    CAPTURE
+        .0
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .filter(|(_, message)| message.starts_with(prefix))
+        .cloned()
+        .collect()
+}
+
+#[test]
+#[cfg_attr(miri, ignore)]
+/// Test that a summary is logged as one line of `key=value` pairs for each execution
+fn test_summary() -> anyhow::Result<()> {
+    records("");
+    let mut command = Command::new("echo");
+    command.args(["summary", "test"]);
+    let mut log = command.log_summary(Level::Info);
+    log.output()?;
+    log.status()?;
+
+    let prefix = "command=echo args=\"summary test\" cwd=";
+    let summaries = records(prefix);
+    assert_eq!(summaries.len(), 2);
+    assert!(summaries.iter().all(|(level, _)| *level == Level::Info));
+
+    let (_, output) = &summaries[0];
+    assert!(output.contains(" status=0 duration="));
+    assert!(output.ends_with(" stdout_bytes=13 stderr_bytes=0"));
+
+    let (_, status) = &summaries[1];
+    assert!(status.contains(" status=0 duration="));
+    assert!(!status.contains("stdout_bytes"));
+    Ok(())
+}